@@ -5,7 +5,8 @@ pub fn android_main() {
 
     std::env::set_current_dir(&resource_dir).unwrap();
     
-    let options = doukutsu_rs::game::LaunchOptions { server_mode: false, editor: false };
+    let options =
+        doukutsu_rs::game::LaunchOptions { server_mode: false, editor: false, capture_audio: None, dump_frames: None };
 
     doukutsu_rs::game::init(options).unwrap();
 }
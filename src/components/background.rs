@@ -1,19 +1,118 @@
+use serde::{Deserialize, Serialize};
+
 use crate::common::{Color, Rect};
 use crate::framework::context::Context;
-use crate::framework::error::GameResult;
-use crate::framework::graphics;
+use crate::framework::error::{GameError, GameResult};
+use crate::framework::{filesystem, graphics};
 use crate::game::frame::Frame;
 use crate::game::shared_game_state::SharedGameState;
 use crate::game::stage::{BackgroundType, Stage, StageTexturePaths};
 
+/// A single image layer of a background loaded from a `Stage/<map>.bg.json` file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackgroundLayer {
+    /// Path to the texture without extension, eg. `bkBlue` or `Stage/bkClouds`.
+    pub texture: String,
+    /// How fast the layer moves along with the camera, 0 keeps it in place and 1 moves it along with the map.
+    #[serde(default)]
+    pub scroll: (f32, f32),
+    /// Automatic scrolling speed in pixels per tick.
+    #[serde(default)]
+    pub velocity: (f32, f32),
+    /// Vertical offset from the top of the screen in pixels.
+    #[serde(default)]
+    pub offset_y: f32,
+    #[serde(default = "default_true")]
+    pub tile_x: bool,
+    #[serde(default)]
+    pub tile_y: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Layers are drawn in order, the first one is at the back.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackgroundLayers {
+    pub layers: Vec<BackgroundLayer>,
+}
+
 pub struct Background {
     pub tick: usize,
     pub prev_tick: usize,
+    /// Replaces the stage's background type when present.
+    pub layers: Option<BackgroundLayers>,
 }
 
 impl Background {
     pub fn new() -> Self {
-        Background { tick: 0, prev_tick: 0 }
+        Background { tick: 0, prev_tick: 0, layers: None }
+    }
+
+    /// Loads the background layers of given map, if it has any. On error the layers are left empty,
+    /// so the stage's background type is used.
+    pub fn load_layers(&mut self, ctx: &mut Context, roots: &Vec<String>, map: &str) -> GameResult {
+        self.layers = None;
+
+        if let Ok(file) = filesystem::open_find(ctx, roots, ["Stage/", map, ".bg.json"].join("")) {
+            let layers = serde_json::from_reader::<_, BackgroundLayers>(file).map_err(|e| {
+                GameError::ResourceLoadError(format!("Failed to parse background layers of {}: {}", map, e))
+            })?;
+
+            self.layers = Some(layers);
+        }
+
+        Ok(())
+    }
+
+    fn draw_layers(
+        &self,
+        layers: &BackgroundLayers,
+        state: &mut SharedGameState,
+        ctx: &mut Context,
+        frame: &Frame,
+        stage: &Stage,
+    ) -> GameResult {
+        graphics::clear(ctx, stage.data.background_color);
+
+        let scale = state.scale;
+        let (frame_x, frame_y) = frame.xy_interpolated(state.frame_time);
+        let ticks = self.prev_tick as f32 + self.tick.wrapping_sub(self.prev_tick) as f32 * state.frame_time as f32;
+
+        for layer in layers.layers.iter() {
+            let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, &layer.texture)?;
+            let (bg_width, bg_height) = (batch.width() as f32, batch.height() as f32);
+            if bg_width < 1.0 || bg_height < 1.0 {
+                continue;
+            }
+
+            let x = layer.velocity.0 * ticks - frame_x * layer.scroll.0;
+            let y = layer.offset_y + layer.velocity.1 * ticks - frame_y * layer.scroll.1;
+            let x = (x * scale).floor() / scale;
+            let y = (y * scale).floor() / scale;
+
+            let (start_x, count_x) = if layer.tile_x {
+                (x.rem_euclid(bg_width) - bg_width, (state.canvas_size.0 / bg_width) as i32 + 2)
+            } else {
+                (x, 1)
+            };
+            let (start_y, count_y) = if layer.tile_y {
+                (y.rem_euclid(bg_height) - bg_height, (state.canvas_size.1 / bg_height) as i32 + 2)
+            } else {
+                (y, 1)
+            };
+
+            for iy in 0..count_y {
+                for ix in 0..count_x {
+                    batch.add(start_x + ix as f32 * bg_width, start_y + iy as f32 * bg_height);
+                }
+            }
+
+            batch.draw(ctx)?;
+        }
+
+        Ok(())
     }
 
     pub fn tick(&mut self) -> GameResult<()> {
@@ -36,6 +135,10 @@ impl Background {
         textures: &StageTexturePaths,
         stage: &Stage,
     ) -> GameResult {
+        if let Some(layers) = &self.layers {
+            return self.draw_layers(layers, state, ctx, frame, stage);
+        }
+
         let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, &textures.background)?;
         let scale = state.scale;
         let (frame_x, frame_y) = frame.xy_interpolated(state.frame_time);
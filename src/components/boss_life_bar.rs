@@ -45,6 +45,7 @@ impl BossLifeBar {
     }
 
     fn draw_regular(&self, state: &mut SharedGameState, ctx: &mut Context, _frame: &Frame) -> GameResult {
+        let (left, _, right, bottom) = state.viewport_insets(ctx);
         let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "TextBox")?;
 
         let box_length = 256;
@@ -59,36 +60,15 @@ impl BossLifeBar {
         rect_prev_bar.right = ((self.prev_life as u32 * bar_length) / self.max_life as u32).min(bar_length) as u16;
         rect_life_bar.right = ((self.life as u32 * bar_length) / self.max_life as u32).min(bar_length) as u16;
 
-        batch.add_rect(
-            ((state.canvas_size.0 - box_length as f32) / 2.0).floor(),
-            state.canvas_size.1 - 20.0,
-            &box_rect1,
-        );
-        batch.add_rect(
-            ((state.canvas_size.0 - box_length as f32) / 2.0).floor(),
-            state.canvas_size.1 - 12.0,
-            &box_rect2,
-        );
-        batch.add_rect(
-            ((state.canvas_size.0 - box_length as f32) / 2.0).floor(),
-            state.canvas_size.1 - 20.0,
-            &box_rect1,
-        );
-        batch.add_rect(
-            ((state.canvas_size.0 - box_length as f32) / 2.0 + 40.0).floor(),
-            state.canvas_size.1 - 16.0,
-            &rect_prev_bar,
-        );
-        batch.add_rect(
-            ((state.canvas_size.0 - box_length as f32) / 2.0 + 40.0).floor(),
-            state.canvas_size.1 - 16.0,
-            &rect_life_bar,
-        );
-        batch.add_rect(
-            ((state.canvas_size.0 - box_length as f32) / 2.0 + 8.0).floor(),
-            state.canvas_size.1 - 16.0,
-            &text_rect,
-        );
+        let x = left + ((state.canvas_size.0 - left - right - box_length as f32) / 2.0).floor();
+        let y = state.canvas_size.1 - bottom;
+
+        batch.add_rect(x, y - 20.0, &box_rect1);
+        batch.add_rect(x, y - 12.0, &box_rect2);
+        batch.add_rect(x, y - 20.0, &box_rect1);
+        batch.add_rect(x + 40.0, y - 16.0, &rect_prev_bar);
+        batch.add_rect(x + 40.0, y - 16.0, &rect_life_bar);
+        batch.add_rect(x + 8.0, y - 16.0, &text_rect);
 
         batch.draw(ctx)?;
 
@@ -96,6 +76,7 @@ impl BossLifeBar {
     }
 
     fn draw_nx(&self, state: &mut SharedGameState, ctx: &mut Context, _frame: &Frame) -> GameResult {
+        let (_, _, right, bottom) = state.viewport_insets(ctx);
         let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "TextBox")?;
 
         let box_length = 148;
@@ -112,17 +93,19 @@ impl BossLifeBar {
         rect_prev_bar.right = ((self.prev_life as u32 * bar_length) / self.max_life as u32).min(bar_length) as u16;
         rect_life_bar.right = ((self.life as u32 * bar_length) / self.max_life as u32).min(bar_length) as u16;
 
-        let base_x = state.canvas_size.0 - box_length as f32;
-
-        batch.add_rect((base_x - 6.0).floor(), state.canvas_size.1 - 20.0, &box_rect1);
-        batch.add_rect((base_x - 6.0).floor(), state.canvas_size.1 - 12.0, &box_rect2);
-        batch.add_rect((base_x - 6.0).floor(), state.canvas_size.1 - 20.0, &box_rect1);
-        batch.add_rect((state.canvas_size.0 - 18.0).floor(), state.canvas_size.1 - 20.0, &box_rect3);
-        batch.add_rect((state.canvas_size.0 - 18.0).floor(), state.canvas_size.1 - 12.0, &box_rect4);
-        batch.add_rect((state.canvas_size.0 - 18.0).floor(), state.canvas_size.1 - 20.0, &box_rect3);
-        batch.add_rect((base_x + 34.0).floor(), state.canvas_size.1 - 16.0, &rect_prev_bar);
-        batch.add_rect((base_x + 34.0).floor(), state.canvas_size.1 - 16.0, &rect_life_bar);
-        batch.add_rect((base_x + 2.0).floor(), state.canvas_size.1 - 16.0, &text_rect);
+        let right_x = state.canvas_size.0 - right;
+        let base_x = right_x - box_length as f32;
+        let y = state.canvas_size.1 - bottom;
+
+        batch.add_rect((base_x - 6.0).floor(), y - 20.0, &box_rect1);
+        batch.add_rect((base_x - 6.0).floor(), y - 12.0, &box_rect2);
+        batch.add_rect((base_x - 6.0).floor(), y - 20.0, &box_rect1);
+        batch.add_rect((right_x - 18.0).floor(), y - 20.0, &box_rect3);
+        batch.add_rect((right_x - 18.0).floor(), y - 12.0, &box_rect4);
+        batch.add_rect((right_x - 18.0).floor(), y - 20.0, &box_rect3);
+        batch.add_rect((base_x + 34.0).floor(), y - 16.0, &rect_prev_bar);
+        batch.add_rect((base_x + 34.0).floor(), y - 16.0, &rect_life_bar);
+        batch.add_rect((base_x + 2.0).floor(), y - 16.0, &text_rect);
 
         batch.draw(ctx)?;
 
@@ -3,7 +3,6 @@ use crate::components::draw_common::{Alignment, draw_number};
 use crate::entity::GameEntity;
 use crate::framework::context::Context;
 use crate::framework::error::GameResult;
-use crate::framework::graphics::screen_insets_scaled;
 use crate::game::frame::Frame;
 use crate::game::inventory::Inventory;
 use crate::game::shared_game_state::SharedGameState;
@@ -85,7 +84,7 @@ impl GameEntity<(&Player, &mut Inventory)> for HUD {
         self.current_level = inventory.get_current_level() as usize;
 
         for (a, slot) in self.weapon_types.iter_mut().enumerate() {
-            *slot = if let Some(weapon) = inventory.get_weapon(a) { weapon.wtype as u8 } else { 0 };
+            *slot = if let Some(weapon) = inventory.get_weapon(a) { weapon.wtype.id() } else { 0 };
         }
 
         // update health bar
@@ -180,7 +179,7 @@ impl GameEntity<(&Player, &mut Inventory)> for HUD {
             return Ok(());
         }
 
-        let (left, top, right, bottom) = screen_insets_scaled(ctx, state.scale);
+        let (left, top, right, bottom) = state.viewport_insets(ctx);
 
         // none
         let weap_x = self.weapon_x_pos as f32;
@@ -258,8 +257,6 @@ impl GameEntity<(&Player, &mut Inventory)> for HUD {
         let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "ArmsImage")?;
 
         if self.weapon_count != 0 {
-            let mut rect = Rect::new(0, 0, 0, 16);
-
             // First frame of animation is off by one weapon
             // There's probably a more elegant solution than this
             let first_frame_offset = if self.weapon_x_pos == 32 {
@@ -291,8 +288,7 @@ impl GameEntity<(&Player, &mut Inventory)> for HUD {
 
                 let wtype = self.weapon_types[a];
                 if wtype != 0 {
-                    rect.left = wtype as u16 * 16;
-                    rect.right = rect.left + 16;
+                    let rect = WeaponType::icon_rect(wtype, batch.width(), &state.constants);
                     batch.add_rect(pos_x + weapon_offset, 16.0 + top, &rect);
                 }
             }
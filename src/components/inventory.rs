@@ -64,7 +64,7 @@ impl InventoryUI {
     }
 
     fn get_weapon_event_number(&self, inventory: &Inventory) -> u16 {
-        inventory.get_current_weapon().map(|w| w.wtype as u16 + 1000).unwrap_or(1000)
+        inventory.get_current_weapon().map(|w| w.wtype.id() as u16 + 1000).unwrap_or(1000)
     }
 
     fn exit(&mut self, state: &mut SharedGameState, _player: &mut Player, inventory: &mut Inventory, hud: &mut HUD) {
@@ -83,7 +83,7 @@ impl GameEntity<(&mut Context, &mut Player, &mut Inventory, &mut HUD)> for Inven
         state: &mut SharedGameState,
         (ctx, player, inventory, hud): (&mut Context, &mut Player, &mut Inventory, &mut HUD),
     ) -> GameResult<()> {
-        let (off_left, off_top, off_right, _) = crate::framework::graphics::screen_insets_scaled(ctx, state.scale);
+        let (off_left, off_top, off_right, _) = state.viewport_insets(ctx);
         let mut slot_rect =
             Rect::new_size(state.canvas_size.0 as isize - 34 - off_right as isize, 8 + off_top as isize, 26, 26);
 
@@ -301,7 +301,7 @@ impl GameEntity<(&mut Context, &mut Player, &mut Inventory, &mut HUD)> for Inven
         }
 
         let mut tmp_rect = Rect { left: 0, top: 0, right: 0, bottom: 0 };
-        let (off_left, off_top, off_right, _) = crate::framework::graphics::screen_insets_scaled(ctx, state.scale);
+        let (off_left, off_top, off_right, _) = state.viewport_insets(ctx);
         let x = (((state.canvas_size.0 - off_left - off_right) - 244.0) / 2.0).floor() + off_left;
         let y = 8.0 + off_top;
 
@@ -363,12 +363,9 @@ impl GameEntity<(&mut Context, &mut Player, &mut Inventory, &mut HUD)> for Inven
                 break;
             }
 
-            tmp_rect.left = (weapon.wtype as u16 % 16) * 16;
-            tmp_rect.top = (weapon.wtype as u16 / 16) * 16;
-            tmp_rect.right = tmp_rect.left + 16;
-            tmp_rect.bottom = tmp_rect.top + 16;
+            let icon_rect = WeaponType::icon_rect(weapon.wtype.id(), batch.width(), &state.constants);
 
-            batch.add_rect(x + 12.0 + idx as f32 * 40.0, y + 16.0, &tmp_rect);
+            batch.add_rect(x + 12.0 + idx as f32 * 40.0, y + 16.0, &icon_rect);
         }
 
         batch.draw(ctx)?;
@@ -185,12 +185,15 @@ impl MapSystem {
             self.render_map(state, ctx, stage)?;
         }
 
-        let (scr_w, scr_h) = (state.canvas_size.0 * state.scale, state.canvas_size.1 * state.scale);
+        let (view_w, view_h) = state.view_size();
+        let (view_x, view_y) = state.viewport_offset;
+        let (scr_x, scr_y) = (view_x * state.scale, view_y * state.scale);
+        let (scr_w, scr_h) = (view_w * state.scale, view_h * state.scale);
         let text_height = state.font.line_height();
         let rect_black_bar = Rect::new_size(
-            0,
-            (7.0 * state.scale) as _,
-            state.screen_size.0 as _,
+            scr_x as _,
+            ((view_y + 7.0) * state.scale) as _,
+            scr_w as _,
             ((text_height + 4.0) * state.scale) as _,
         );
 
@@ -204,7 +207,7 @@ impl MapSystem {
             stage.data.name.as_str()
         };
 
-        state.font.builder().center(state.canvas_size.0).y(9.0).draw(
+        state.font.builder().x(view_x).center(view_w).y(view_y + 9.0).draw(
             map_name,
             ctx,
             &state.constants,
@@ -219,8 +222,8 @@ impl MapSystem {
                 let height = (state.scale * tick as f32 * stage.map.height as f32 / 16.0) as isize;
 
                 let rect = Rect::new_size(
-                    (scr_w / 2.0) as isize - width,
-                    (scr_h / 2.0) as isize - height,
+                    (scr_x + scr_w / 2.0) as isize - width,
+                    (scr_y + scr_h / 2.0) as isize - height,
                     width * 2,
                     height * 2,
                 );
@@ -239,8 +242,8 @@ impl MapSystem {
         let height_border = state.scale * (stage.map.height as f32 + 2.0);
 
         let rect = Rect::new_size(
-            ((scr_w - width_border) / 2.0) as isize,
-            ((scr_h - height_border) / 2.0) as isize,
+            (scr_x + (scr_w - width_border) / 2.0) as isize,
+            (scr_y + (scr_h - height_border) / 2.0) as isize,
             width_border as isize,
             height_border as isize,
         );
@@ -254,7 +257,12 @@ impl MapSystem {
             tex.clear();
             tex.add(SpriteBatchCommand::DrawRect(
                 map_rect,
-                Rect::new_size((scr_w - width) / 2.0, (scr_h - height) / 2.0, map_rect.width(), map_rect.height()),
+                Rect::new_size(
+                    scr_x + (scr_w - width) / 2.0,
+                    scr_y + (scr_h - height) / 2.0,
+                    map_rect.width(),
+                    map_rect.height(),
+                ),
             ));
             tex.draw()?;
         }
@@ -263,8 +271,8 @@ impl MapSystem {
             const PLAYER_RECT: Rect<u16> = Rect { left: 0, top: 57, right: 1, bottom: 58 };
 
             let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "TextBox")?;
-            let x_offset = (state.canvas_size.0 - stage.map.width as f32) / 2.0;
-            let y_offset = (state.canvas_size.1 - stage.map.height as f32) / 2.0;
+            let x_offset = view_x + (view_w - stage.map.width as f32) / 2.0;
+            let y_offset = view_y + (view_h - stage.map.height as f32) / 2.0;
             let tile_div = stage.map.tile_size.as_int() * 0x200;
 
             for player in &players {
@@ -166,8 +166,9 @@ impl GameEntity<(&mut Context, &mut Player)> for Replay {
     }
 
     fn draw(&self, state: &mut SharedGameState, ctx: &mut Context, _frame: &Frame) -> GameResult {
-        let x = state.canvas_size.0 - 32.0;
-        let y = 8.0 + if state.settings.fps_counter { 12.0 } else { 0.0 };
+        let (_, top, right, _) = state.viewport_insets(ctx);
+        let x = state.canvas_size.0 - right - 32.0;
+        let y = top + 8.0 + if state.settings.fps_counter { 12.0 } else { 0.0 };
 
         match state.replay_state {
             ReplayState::None => {}
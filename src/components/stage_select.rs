@@ -96,7 +96,7 @@ impl GameEntity<(&mut Context, &Player, &Player)> for StageSelect {
                 }
             }
 
-            let (_, off_top, off_right, _) = crate::framework::graphics::screen_insets_scaled(ctx, state.scale);
+            let (_, off_top, off_right, _) = state.viewport_insets(ctx);
             slot_rect = Rect::new_size(state.canvas_size.0 as isize - 34 - off_right as isize, 8 + off_top as isize, 26, 26);
 
             if state.touch_controls.consume_click_in(slot_rect) {
@@ -156,7 +156,7 @@ impl GameEntity<(&mut Context, &Player, &Player)> for StageSelect {
         batch.draw(ctx)?;
 
         if state.settings.touch_controls {
-            let (_, off_top, off_right, _) = crate::framework::graphics::screen_insets_scaled(ctx, state.scale);
+            let (_, off_top, off_right, _) = state.viewport_insets(ctx);
 
             let close_rect = Rect { left: 110, top: 110, right: 128, bottom: 128 };
             let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "builtin/touch")?;
@@ -63,16 +63,15 @@ impl GameEntity<()> for TextBoxes {
             return Ok(());
         }
 
-        let (off_left, off_top, off_right, off_bottom) =
-            crate::framework::graphics::screen_insets_scaled(ctx, state.scale);
+        let (off_left, off_top, off_right, off_bottom) = state.viewport_insets(ctx);
 
-        let center = ((state.canvas_size.0 - off_left - off_right) / 2.0).floor();
+        let center = ((state.canvas_size.0 - off_left - off_right) / 2.0).floor() + off_left;
         let top_pos = if state.textscript_vm.flags.position_top() {
             32.0 + off_top
         } else {
             state.canvas_size.1 as f32 - off_bottom - 66.0
         };
-        let left_pos = off_left + center - 122.0;
+        let left_pos = center - 122.0;
 
         {
             let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, "TextBox")?;
@@ -2,6 +2,7 @@ use crate::common::Rect;
 use crate::framework::context::Context;
 use crate::framework::error::GameResult;
 use crate::game::frame::Frame;
+use crate::game::map::MapLayerKind;
 use crate::game::shared_game_state::{SharedGameState, TileSize};
 use crate::game::stage::{BackgroundType, Stage, StageTexturePaths};
 
@@ -70,7 +71,11 @@ impl Tilemap {
         };
 
         if !uses_layers && layer == TileLayer::Middleground {
-            return Ok(());
+            return self.draw_extra_layers(state, ctx, frame, MapLayerKind::Middleground, textures, stage);
+        }
+
+        if !uses_layers && layer == TileLayer::Background {
+            self.draw_extra_layers(state, ctx, frame, MapLayerKind::Background, textures, stage)?;
         }
 
         let tile_size = state.tile_size.as_int();
@@ -161,6 +166,10 @@ impl Tilemap {
 
         batch.draw(ctx)?;
 
+        if !uses_layers && layer == TileLayer::Foreground {
+            self.draw_extra_layers(state, ctx, frame, MapLayerKind::Foreground, textures, stage)?;
+        }
+
         if !self.no_water && layer == TileLayer::Foreground && stage.data.background_type == BackgroundType::Water {
             let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, &textures.background)?;
             let rect_top = Rect { left: 0, top: 0, right: 32, bottom: 16 };
@@ -231,4 +240,55 @@ impl Tilemap {
 
         Ok(())
     }
+
+    /// Draws extra tile layers of given kind from an extended PXM map.
+    fn draw_extra_layers(
+        &self,
+        state: &mut SharedGameState,
+        ctx: &mut Context,
+        frame: &Frame,
+        kind: MapLayerKind,
+        textures: &StageTexturePaths,
+        stage: &Stage,
+    ) -> GameResult {
+        let tile_size = state.tile_size.as_int();
+        let tile_sizef = state.tile_size.as_float();
+        let halft = tile_size / 2;
+        let halftf = tile_sizef / 2.0;
+        let (frame_x, frame_y) = frame.xy_interpolated(state.frame_time);
+
+        let width = stage.map.width as usize;
+        let tile_start_x = (frame_x as i32 / tile_size).clamp(0, width as i32) as usize;
+        let tile_start_y = (frame_y as i32 / tile_size).clamp(0, stage.map.height as i32) as usize;
+        let tile_end_x =
+            ((frame_x as i32 + 8 + state.canvas_size.0 as i32) / tile_size + 1).clamp(0, width as i32) as usize;
+        let tile_end_y = ((frame_y as i32 + halft + state.canvas_size.1 as i32) / tile_size + 1)
+            .clamp(0, stage.map.height as i32) as usize;
+
+        for layer in stage.map.layers.iter().filter(|l| l.kind == kind) {
+            let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, &textures.layer_tileset(layer))?;
+
+            for y in tile_start_y..tile_end_y {
+                for x in tile_start_x..tile_end_x {
+                    let tile = match layer.tiles.get(y * width + x) {
+                        Some(&tile) if layer.is_visible(tile) => tile,
+                        _ => continue,
+                    };
+
+                    let size = tile_size as u16;
+                    let rect = Rect::new_size((tile as u16 % 16) * size, (tile as u16 / 16) * size, size, size);
+
+                    batch.add_rect(
+                        (x as f32 * tile_sizef - halftf) - frame_x,
+                        (y as f32 * tile_sizef - halftf) - frame_y,
+                        &rect,
+                    );
+                }
+            }
+
+            batch.draw(ctx)?;
+        }
+
+        Ok(())
+    }
 }
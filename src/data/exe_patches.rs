@@ -0,0 +1,691 @@
+use std::ops::Range;
+use std::str::FromStr;
+
+use byteorder::{ByteOrder, LE};
+use serde::{Deserialize, Serialize};
+
+use crate::data::exe_parser::ExeParser;
+use crate::engine_constants::{EngineConstants, PhysicsConsts};
+use crate::framework::context::Context;
+use crate::framework::filesystem;
+use crate::game::scripting::tsc::opcodes::TSCOpCode;
+
+/// Sections present in unmodified executables, `.csmap` is added by Booster's Lab for the extended stage table.
+const KNOWN_SECTIONS: [&str; 5] = [".text", ".rdata", ".data", ".rsrc", ".csmap"];
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x20000000;
+
+const BULLET_COUNT: usize = 46;
+/// Size of a bullet table entry in the executable, the `bullet.tbl` from Cave Story+ drops the padding.
+const BULLET_ENTRY_SIZE: usize = 0x2c;
+const LEVEL_TABLE_SIZE: usize = 14 * 3 * 4;
+const CARET_COUNT: usize = 18;
+const CARET_TABLE_SIZE: usize = CARET_COUNT * 2 * 4;
+
+/// `mov dword ptr [ebp+disp8], imm32`, player physics are kept in locals of `ActMyChar_Normal`.
+const PHYSICS_MOV_SIZE: usize = 7;
+const PHYSICS_BLOCK_SIZE: usize = PHYSICS_MOV_SIZE * 7;
+
+/// `movsx r32, byte ptr [r32+disp8]` followed by `cmp r32, imm8`, how `TextScriptProc` compares
+/// each character of a command name.
+const TSC_CHAR_CMP_SIZE: usize = 7;
+/// Maximum distance between the comparisons of two consecutive characters of a command name.
+const TSC_CHAR_CMP_SPAN: usize = 32;
+
+/// Player physics as they're stored in the executable, which has no equivalent of `max_move`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhysicsPatch {
+    pub max_dash: i32,
+    pub gravity_ground: i32,
+    pub gravity_air: i32,
+    pub jump: i32,
+    pub dash_ground: i32,
+    pub dash_air: i32,
+    pub resist: i32,
+}
+
+impl PhysicsPatch {
+    fn from_consts(physics: &PhysicsConsts) -> Self {
+        Self {
+            max_dash: physics.max_dash,
+            gravity_ground: physics.gravity_ground,
+            gravity_air: physics.gravity_air,
+            jump: physics.jump,
+            dash_ground: physics.dash_ground,
+            dash_air: physics.dash_air,
+            resist: physics.resist,
+        }
+    }
+
+    /// Same order as the assignments in the executable.
+    fn from_values(values: [i32; 7]) -> Self {
+        Self {
+            max_dash: values[0],
+            gravity_ground: values[1],
+            gravity_air: values[2],
+            jump: values[3],
+            dash_ground: values[4],
+            dash_air: values[5],
+            resist: values[6],
+        }
+    }
+
+    fn apply(&self, physics: &mut PhysicsConsts) {
+        physics.max_dash = self.max_dash;
+        physics.gravity_ground = self.gravity_ground;
+        physics.gravity_air = self.gravity_air;
+        physics.jump = self.jump;
+        physics.dash_ground = self.dash_ground;
+        physics.dash_air = self.dash_air;
+        physics.resist = self.resist;
+    }
+}
+
+/// Well known assembly hacks found in a modded `Doukutsu.exe`.
+///
+/// Gets stored as `exe_patches.json` next to the extracted data, weapon and caret tables
+/// are written as `bullet.tbl`, `arms_level.tbl` and `caret.tbl` instead.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ExePatches {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub air_physics: Option<PhysicsPatch>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub water_physics: Option<PhysicsPatch>,
+    /// Game resolution changed by widescreen hacks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screen_size: Option<(u16, u16)>,
+    /// Hacks that have been detected and translated.
+    #[serde(default)]
+    pub applied: Vec<String>,
+    /// Hacks that have been detected but can't be translated.
+    #[serde(default)]
+    pub unsupported: Vec<String>,
+    /// Tables found in the executable, only set if they differ from vanilla.
+    #[serde(skip)]
+    pub bullet_table: Option<Vec<u8>>,
+    #[serde(skip)]
+    pub level_table: Option<Vec<u8>>,
+    #[serde(skip)]
+    pub caret_table: Option<Vec<u8>>,
+}
+
+impl ExePatches {
+    pub const FILE_NAME: &'static str = "exe_patches.json";
+
+    /// Scans the executable for hacks by looking for the data they change.
+    pub fn detect(exe: &[u8], parser: &ExeParser) -> Self {
+        let mut patches = ExePatches::default();
+        let defaults = EngineConstants::defaults();
+
+        let mut code = Vec::new();
+        let mut data = Vec::new();
+
+        for section in parser.section_headers.iter() {
+            let name = section.name().unwrap_or("?");
+            let range = section.file_range();
+            let range = (range.start as usize).min(exe.len())..(range.end as usize).min(exe.len());
+
+            if name == ".text" || section.Characteristics & IMAGE_SCN_MEM_EXECUTE != 0 {
+                code.push(range.clone());
+            } else if name == ".data" || name == ".rdata" {
+                data.push(range.clone());
+            }
+
+            if name == ".csmap" {
+                patches.applied.push("Stage table moved to the .csmap section.".to_owned());
+            } else if !KNOWN_SECTIONS.contains(&name) {
+                if section.Characteristics & IMAGE_SCN_MEM_EXECUTE != 0 {
+                    patches.unsupported.push(format!("Code added in the {} section.", name));
+                } else {
+                    patches.unsupported.push(format!("Data added in the {} section.", name));
+                }
+            }
+        }
+
+        let mut custom_commands: Vec<String> = Vec::new();
+        for range in code.iter() {
+            for command in find_tsc_commands(&exe[range.clone()]) {
+                if TSCOpCode::from_str(&command).is_err() && !custom_commands.contains(&command) {
+                    custom_commands.push(command);
+                }
+            }
+        }
+
+        if !custom_commands.is_empty() {
+            let commands: Vec<String> = custom_commands.iter().map(|c| format!("<{}", c)).collect();
+            patches.unsupported.push(format!("Custom TSC commands: {}.", commands.join(", ")));
+        }
+
+        match code.iter().find_map(|range| find_physics(&exe[range.clone()])) {
+            Some((water, air)) => {
+                let vanilla_water = PhysicsPatch::from_consts(&defaults.player.water_physics);
+                let vanilla_air = PhysicsPatch::from_consts(&defaults.player.air_physics);
+
+                if water != vanilla_water {
+                    patches.water_physics = Some(water);
+                    patches.applied.push("Changed player physics in water.".to_owned());
+                }
+
+                if air != vanilla_air {
+                    patches.air_physics = Some(air);
+                    patches.applied.push("Changed player physics.".to_owned());
+                }
+            }
+            None => patches.unsupported.push("Player physics code has been rewritten or moved.".to_owned()),
+        }
+
+        match data.iter().find_map(|range| find_bullet_table(exe, range.clone())) {
+            Some(table) => {
+                if table != vanilla_bullet_table(&defaults) {
+                    patches.applied.push("Changed bullet table.".to_owned());
+                    patches.bullet_table = Some(table);
+                }
+            }
+            None => patches.unsupported.push("Bullet table has been moved or resized.".to_owned()),
+        }
+
+        match data.iter().find_map(|range| find_level_table(exe, range.clone())) {
+            Some(table) => {
+                if table != vanilla_level_table(&defaults) {
+                    patches.applied.push("Changed weapon experience table.".to_owned());
+                    patches.level_table = Some(table);
+                }
+            }
+            None => patches.unsupported.push("Weapon experience table has been moved.".to_owned()),
+        }
+
+        match data.iter().find_map(|range| find_caret_table(exe, range.clone())) {
+            Some(table) => {
+                if table != vanilla_caret_table(&defaults) {
+                    patches.applied.push("Changed caret offsets.".to_owned());
+                    patches.caret_table = Some(table);
+                }
+            }
+            None => patches.unsupported.push("Caret table has been moved.".to_owned()),
+        }
+
+        if let Some((width, height)) = data.iter().find_map(|range| find_screen_size(exe, range.clone())) {
+            if (width, height) != (320, 240) {
+                patches.screen_size = Some((width, height));
+                patches.applied.push(format!("Game resolution changed to {}x{}.", width, height));
+            }
+        }
+
+        patches
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.unsupported.is_empty()
+    }
+
+    pub fn load(ctx: &Context, roots: &Vec<String>) -> Option<Self> {
+        let file = filesystem::open_find(ctx, roots, Self::FILE_NAME).ok()?;
+
+        match serde_json::from_reader(file) {
+            Ok(patches) => Some(patches),
+            Err(err) => {
+                log::warn!("Failed to parse {}: {}", Self::FILE_NAME, err);
+                None
+            }
+        }
+    }
+
+    pub fn apply(&self, constants: &mut EngineConstants) {
+        if let Some(physics) = &self.air_physics {
+            physics.apply(&mut constants.player.air_physics);
+        }
+
+        if let Some(physics) = &self.water_physics {
+            physics.apply(&mut constants.player.water_physics);
+        }
+
+        if let Some((width, height)) = self.screen_size {
+            constants.viewport_size = Some((width as f32, height as f32));
+        }
+    }
+}
+
+fn read_u32s<const N: usize>(data: &[u8]) -> [u32; N] {
+    let mut values = [0u32; N];
+    LE::read_u32_into(&data[..N * 4], &mut values);
+    values
+}
+
+/// Looks for the two blocks of assignments in `ActMyChar_Normal`, one for water and one for air,
+/// separated by a short jump over the latter.
+fn find_physics(code: &[u8]) -> Option<(PhysicsPatch, PhysicsPatch)> {
+    fn parse_block(code: &[u8]) -> Option<([u8; 7], [i32; 7])> {
+        let mut disps = [0u8; 7];
+        let mut values = [0i32; 7];
+
+        for (i, mov) in code.chunks_exact(PHYSICS_MOV_SIZE).enumerate() {
+            if mov[0] != 0xc7 || mov[1] != 0x45 || mov[5] != 0 || mov[6] != 0 {
+                return None;
+            }
+
+            disps[i] = mov[2];
+            values[i] = LE::read_i32(&mov[3..]);
+        }
+
+        Some((disps, values))
+    }
+
+    let size = PHYSICS_BLOCK_SIZE * 2 + 2;
+
+    code.windows(size).find_map(|window| {
+        // jmp short over the second block
+        if window[PHYSICS_BLOCK_SIZE] != 0xeb || window[PHYSICS_BLOCK_SIZE + 1] as usize != PHYSICS_BLOCK_SIZE {
+            return None;
+        }
+
+        let (water_disps, water) = parse_block(&window[..PHYSICS_BLOCK_SIZE])?;
+        let (air_disps, air) = parse_block(&window[PHYSICS_BLOCK_SIZE + 2..])?;
+
+        let distinct = water_disps.iter().enumerate().all(|(i, d)| !water_disps[i + 1..].contains(d));
+        if water_disps != air_disps || !distinct {
+            return None;
+        }
+
+        Some((PhysicsPatch::from_values(water), PhysicsPatch::from_values(air)))
+    })
+}
+
+/// Finds names of TSC commands handled by the code, by looking for three comparisons
+/// of consecutive characters after the `<`.
+fn find_tsc_commands(code: &[u8]) -> Vec<String> {
+    fn char_cmp(code: &[u8]) -> Option<(u8, u8)> {
+        let [op0, op1, modrm, disp, cmp, cmp_modrm, imm] = code.get(..TSC_CHAR_CMP_SIZE)?.try_into().ok()?;
+        let reg = (modrm >> 3) & 7;
+
+        // mod = 01 (disp8) without SIB, compared against a printable character
+        if op0 != 0x0f || op1 != 0xbe || modrm >> 6 != 1 || modrm & 7 == 4 {
+            return None;
+        }
+
+        if cmp != 0x83 || cmp_modrm != 0xf8 | reg || !(0x21..0x7f).contains(&imm) {
+            return None;
+        }
+
+        Some((disp, imm))
+    }
+
+    fn next_char(code: &[u8], offset: usize, disp: u8) -> Option<(usize, u8)> {
+        let end = (offset + TSC_CHAR_CMP_SPAN).min(code.len());
+
+        (offset..end).find_map(|i| match char_cmp(&code[i..]) {
+            Some((d, c)) if d == disp => Some((i + TSC_CHAR_CMP_SIZE, c)),
+            _ => None,
+        })
+    }
+
+    let mut commands = Vec::new();
+
+    for offset in 0..code.len() {
+        let Some((1, c1)) = char_cmp(&code[offset..]) else {
+            continue;
+        };
+
+        let Some((offset, c2)) = next_char(code, offset + TSC_CHAR_CMP_SIZE, 2) else {
+            continue;
+        };
+
+        let Some((_, c3)) = next_char(code, offset, 3) else {
+            continue;
+        };
+
+        let command = String::from_utf8_lossy(&[c1, c2, c3]).into_owned();
+        if !commands.contains(&command) {
+            commands.push(command);
+        }
+    }
+
+    commands
+}
+
+/// Finds the bullet table by its layout, returns it converted to `bullet.tbl` format.
+fn find_bullet_table(exe: &[u8], range: Range<usize>) -> Option<Vec<u8>> {
+    fn is_bullet(entry: &[u8]) -> bool {
+        let [life_count, bbits, enemy_w, enemy_h, block_w, block_h, view_l, view_t, view_r, view_b] =
+            read_u32s::<10>(&entry[4..]);
+
+        entry[2] == 0
+            && entry[3] == 0
+            && life_count < 0x10000
+            && bbits < 0x100
+            && [enemy_w, enemy_h, block_w, block_h].iter().all(|&v| v < 0x1000)
+            && [view_l, view_t, view_r, view_b].iter().all(|&v| v < 0x100)
+    }
+
+    let table_size = BULLET_COUNT * BULLET_ENTRY_SIZE;
+    let data = &exe[range];
+
+    (0..data.len().saturating_sub(table_size)).step_by(4).find_map(|offset| {
+        let table = &data[offset..offset + table_size];
+        let (null, entries) = table.split_at(BULLET_ENTRY_SIZE);
+
+        // the first entry is unused and always empty, the next one is the first level of Snake
+        if null.iter().any(|&b| b != 0) || LE::read_u32(&entries[4..]) == 0 || LE::read_u32(&entries[28..]) == 0 {
+            return None;
+        }
+
+        if !entries.chunks_exact(BULLET_ENTRY_SIZE).all(is_bullet) {
+            return None;
+        }
+
+        let mut out = Vec::with_capacity(BULLET_COUNT * 0x2a);
+        for entry in table.chunks_exact(BULLET_ENTRY_SIZE) {
+            out.extend_from_slice(&entry[..2]);
+            out.extend_from_slice(&entry[4..]);
+        }
+
+        Some(out)
+    })
+}
+
+fn vanilla_bullet_table(constants: &EngineConstants) -> Vec<u8> {
+    let mut out = Vec::with_capacity(BULLET_COUNT * 0x2a);
+
+    for bullet in constants.weapon.bullet_table.iter() {
+        out.push(bullet.damage);
+        out.push(bullet.life);
+
+        let bounds = &bullet.display_bounds;
+        for value in [
+            bullet.lifetime as u32,
+            bullet.flags.0 as u32,
+            bullet.enemy_hit_width as u32,
+            bullet.enemy_hit_height as u32,
+            bullet.block_hit_width as u32,
+            bullet.block_hit_height as u32,
+            bounds.left as u32,
+            bounds.top as u32,
+            bounds.right as u32,
+            bounds.bottom as u32,
+        ] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    out
+}
+
+/// Finds the experience table by the empty entry for no weapon, returns it in `arms_level.tbl` format.
+fn find_level_table(exe: &[u8], range: Range<usize>) -> Option<Vec<u8>> {
+    let data = &exe[range];
+
+    (0..data.len().saturating_sub(LEVEL_TABLE_SIZE)).step_by(4).find_map(|offset| {
+        let table = &data[offset..offset + LEVEL_TABLE_SIZE];
+        let values = read_u32s::<42>(table);
+
+        if values[..3] != [0, 0, 100] || values.iter().any(|&v| v > 10000) {
+            return None;
+        }
+
+        Some(table.to_vec())
+    })
+}
+
+fn vanilla_level_table(constants: &EngineConstants) -> Vec<u8> {
+    constants.weapon.level_table.iter().flatten().flat_map(|&v| (v as u32).to_le_bytes()).collect()
+}
+
+/// Finds the caret offsets by the empty entry for no caret, returns them in `caret.tbl` format.
+fn find_caret_table(exe: &[u8], range: Range<usize>) -> Option<Vec<u8>> {
+    let data = &exe[range];
+
+    (0..data.len().saturating_sub(CARET_TABLE_SIZE)).step_by(4).find_map(|offset| {
+        let table = &data[offset..offset + CARET_TABLE_SIZE];
+        let values = read_u32s::<{ CARET_COUNT * 2 }>(table);
+
+        // offsets are always whole pixels
+        let (null, entries) = values.split_at(2);
+        if null != [0, 0] || entries.iter().any(|&v| v == 0 || v > 0x8000 || v % 0x200 != 0) {
+            return None;
+        }
+
+        Some(table.to_vec())
+    })
+}
+
+fn vanilla_caret_table(constants: &EngineConstants) -> Vec<u8> {
+    constants.caret.offsets.iter().flat_map(|&(x, y)| [x, y]).flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Finds the `grcGame` and `grcFull` rectangles which both depend on the game resolution.
+fn find_screen_size(exe: &[u8], range: Range<usize>) -> Option<(u16, u16)> {
+    let data = &exe[range];
+
+    (0..data.len().saturating_sub(32)).step_by(4).find_map(|offset| {
+        let [a_left, a_top, a_right, a_bottom, b_left, b_top, b_right, b_bottom] = read_u32s::<8>(&data[offset..]);
+
+        let (width, height) = match ((a_left, a_top), (b_left, b_top)) {
+            ((32, 32), (0, 0)) if a_right == b_right + 32 && a_bottom == b_bottom + 32 => (b_right, b_bottom),
+            ((0, 0), (32, 32)) if b_right == a_right + 32 && b_bottom == a_bottom + 32 => (a_right, a_bottom),
+            _ => return None,
+        };
+
+        if !(200..=1000).contains(&width) || !(150..=600).contains(&height) {
+            return None;
+        }
+
+        Some((width as u16, height as u16))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn physics_block(values: [i32; 7]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (i, value) in values.iter().enumerate() {
+            out.extend_from_slice(&[0xc7, 0x45, 0xd0 + i as u8 * 4]);
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out
+    }
+
+    fn physics_code(water: [i32; 7], air: [i32; 7]) -> Vec<u8> {
+        let mut code = vec![0x55, 0x8b, 0xec, 0x90];
+        code.extend(physics_block(water));
+        code.extend_from_slice(&[0xeb, PHYSICS_BLOCK_SIZE as u8]);
+        code.extend(physics_block(air));
+        code.push(0xc3);
+        code
+    }
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_find_physics() {
+        let water = [0x196, 0x28, 0x10, 0x280, 0x2a, 0x10, 0x19];
+        let air = [0x32c, 0x50, 0x20, 0x500, 0x55, 0x20, 0x33];
+        let code = physics_code(water, air);
+
+        assert_eq!(find_physics(&code), Some((PhysicsPatch::from_values(water), PhysicsPatch::from_values(air))));
+    }
+
+    #[test]
+    fn test_find_physics_rejects_other_code() {
+        let water = [0x196, 0x28, 0x10, 0x280, 0x2a, 0x10, 0x19];
+        let air = [0x32c, 0x50, 0x20, 0x500, 0x55, 0x20, 0x33];
+
+        // jump doesn't skip the second block
+        let mut code = physics_code(water, air);
+        code[4 + PHYSICS_BLOCK_SIZE + 1] = 0x10;
+        assert_eq!(find_physics(&code), None);
+
+        // value doesn't fit in the immediate of a vanilla build
+        let mut code = physics_code(water, air);
+        code[4 + PHYSICS_MOV_SIZE - 1] = 0xff;
+        assert_eq!(find_physics(&code), None);
+
+        // blocks write to different locals
+        let mut code = physics_code(water, air);
+        code[4 + PHYSICS_BLOCK_SIZE + 2 + 2] = 0x80;
+        assert_eq!(find_physics(&code), None);
+
+        assert_eq!(find_physics(&[0x90; 64]), None);
+    }
+
+    fn bullet_entry(damage: u8, life: u8, values: [u32; 10]) -> Vec<u8> {
+        let mut out = vec![damage, life, 0, 0];
+        out.extend(u32s(&values));
+        out
+    }
+
+    fn bullet_table() -> Vec<u8> {
+        let mut out = vec![0; BULLET_ENTRY_SIZE];
+        for i in 1..BULLET_COUNT as u32 {
+            out.extend(bullet_entry(i as u8, 1, [20 + i, 0x24, 4, 4, 2, 2, 8, 8, 8, 8]));
+        }
+        out
+    }
+
+    #[test]
+    fn test_find_bullet_table() {
+        let mut exe = vec![0xcc; 12];
+        exe.extend(bullet_table());
+        exe.extend_from_slice(&[0xcc; 8]);
+
+        let table = find_bullet_table(&exe, 0..exe.len()).unwrap();
+        assert_eq!(table.len(), BULLET_COUNT * 0x2a);
+        assert_eq!(&table[..0x2a], &[0; 0x2a]);
+        assert_eq!(&table[0x2a..0x2a + 6], &[1, 1, 21, 0, 0, 0]);
+        assert_eq!(&table[table.len() - 4..], &8u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_find_bullet_table_rejects_other_data() {
+        // first entry not empty
+        let mut exe = bullet_table();
+        exe[0] = 1;
+        exe.extend_from_slice(&[0xcc; 8]);
+        assert_eq!(find_bullet_table(&exe, 0..exe.len()), None);
+
+        // hit box too big to be a bullet
+        let mut exe = bullet_table();
+        exe[BULLET_ENTRY_SIZE * 3 + 12..BULLET_ENTRY_SIZE * 3 + 16].copy_from_slice(&0x10000u32.to_le_bytes());
+        exe.extend_from_slice(&[0xcc; 8]);
+        assert_eq!(find_bullet_table(&exe, 0..exe.len()), None);
+
+        // table doesn't fit in the range
+        let exe = bullet_table();
+        assert_eq!(find_bullet_table(&exe, 0..exe.len() - 4), None);
+
+        let exe = vec![0; BULLET_COUNT * BULLET_ENTRY_SIZE * 2];
+        assert_eq!(find_bullet_table(&exe, 0..exe.len()), None);
+    }
+
+    #[test]
+    fn test_find_level_table() {
+        let mut values = vec![0, 0, 100];
+        values.extend((3..42).map(|i| i * 10));
+        let table = u32s(&values);
+
+        let mut exe = u32s(&[0xffff_ffff, 1, 2]);
+        exe.extend_from_slice(&table);
+        exe.extend(u32s(&[0; 4]));
+
+        assert_eq!(find_level_table(&exe, 0..exe.len()), Some(table.clone()));
+        assert_eq!(find_level_table(&exe, 12..exe.len()), Some(table));
+    }
+
+    #[test]
+    fn test_find_level_table_rejects_other_data() {
+        let mut values = vec![0, 0, 100];
+        values.extend((3..42).map(|i| i * 10));
+
+        let mut exe = u32s(&values);
+        exe[4 * 20..4 * 21].copy_from_slice(&20000u32.to_le_bytes());
+        exe.extend(u32s(&[0; 4]));
+        assert_eq!(find_level_table(&exe, 0..exe.len()), None);
+
+        let mut exe = u32s(&values);
+        exe[4 * 2..4 * 3].copy_from_slice(&50u32.to_le_bytes());
+        exe.extend(u32s(&[0; 4]));
+        assert_eq!(find_level_table(&exe, 0..exe.len()), None);
+
+        assert_eq!(find_level_table(&[0; 16], 0..16), None);
+    }
+
+    #[test]
+    fn test_find_screen_size() {
+        let mut exe = u32s(&[7, 7]);
+        exe.extend(u32s(&[32, 32, 458, 272, 0, 0, 426, 240]));
+        exe.extend(u32s(&[0, 0]));
+        assert_eq!(find_screen_size(&exe, 0..exe.len()), Some((426, 240)));
+
+        let mut exe = u32s(&[0, 0, 320, 240, 32, 32, 352, 272]);
+        exe.extend(u32s(&[0]));
+        assert_eq!(find_screen_size(&exe, 0..exe.len()), Some((320, 240)));
+    }
+
+    #[test]
+    fn test_find_screen_size_rejects_other_data() {
+        // rectangles not 32 pixels apart
+        let mut exe = u32s(&[32, 32, 458, 272, 0, 0, 420, 240]);
+        exe.extend(u32s(&[0]));
+        assert_eq!(find_screen_size(&exe, 0..exe.len()), None);
+
+        // implausible resolution
+        let mut exe = u32s(&[32, 32, 64, 64, 0, 0, 32, 32]);
+        exe.extend(u32s(&[0]));
+        assert_eq!(find_screen_size(&exe, 0..exe.len()), None);
+
+        assert_eq!(find_screen_size(&[0; 64], 0..64), None);
+    }
+
+    fn char_cmp(disp: u8, c: u8) -> [u8; TSC_CHAR_CMP_SIZE] {
+        // movsx edx, byte ptr [ecx+disp]; cmp edx, c
+        [0x0f, 0xbe, 0x51, disp, 0x83, 0xfa, c]
+    }
+
+    fn command_cmp(name: &[u8; 3]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (i, &c) in name.iter().enumerate() {
+            out.extend_from_slice(&char_cmp(i as u8 + 1, c));
+            // jnz rel32
+            out.extend_from_slice(&[0x0f, 0x85, 0x10, 0x00, 0x00, 0x00]);
+        }
+        out
+    }
+
+    #[test]
+    fn test_find_tsc_commands() {
+        let mut code = vec![0x90; 3];
+        code.extend(command_cmp(b"END"));
+        code.extend_from_slice(&[0x90; 10]);
+        code.extend(command_cmp(b"XYZ"));
+        code.extend(command_cmp(b"END"));
+
+        assert_eq!(find_tsc_commands(&code), vec!["END".to_owned(), "XYZ".to_owned()]);
+    }
+
+    #[test]
+    fn test_find_tsc_commands_rejects_other_code() {
+        // characters compared out of order
+        let mut code = char_cmp(2, b'A').to_vec();
+        code.extend_from_slice(&char_cmp(1, b'B'));
+        code.extend_from_slice(&char_cmp(3, b'C'));
+        assert!(find_tsc_commands(&code).is_empty());
+
+        // characters too far apart
+        let mut code = char_cmp(1, b'A').to_vec();
+        code.extend_from_slice(&[0x90; TSC_CHAR_CMP_SPAN]);
+        code.extend_from_slice(&char_cmp(2, b'B'));
+        code.extend_from_slice(&char_cmp(3, b'C'));
+        assert!(find_tsc_commands(&code).is_empty());
+
+        // compared register differs from the loaded one
+        let mut code = command_cmp(b"ABC");
+        code[5] = 0xf8;
+        assert!(find_tsc_commands(&code).is_empty());
+
+        // not a printable character
+        let mut code = command_cmp(b"ABC");
+        code[6] = 0x05;
+        assert!(find_tsc_commands(&code).is_empty());
+    }
+}
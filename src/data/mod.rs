@@ -1,3 +1,4 @@
 pub mod builtin_fs;
 pub mod exe_parser;
+pub mod exe_patches;
 pub mod vanilla;
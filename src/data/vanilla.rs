@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     env,
     io::{Read, Write},
     ops::Range,
@@ -8,11 +9,13 @@ use std::{
 use byteorder::{LE, WriteBytesExt};
 
 use crate::data::exe_parser::ExeParser;
+use crate::data::exe_patches::ExePatches;
 use crate::framework::{
     context::Context,
     error::{GameError::ParseError, GameResult},
     filesystem,
 };
+use crate::sound::pixtone::{Channel, Envelope, PixToneParameters, Waveform};
 
 pub struct VanillaExtractor {
     exe_buffer: Vec<u8>,
@@ -24,6 +27,9 @@ const VANILLA_STAGE_COUNT: u32 = 95;
 const VANILLA_STAGE_ENTRY_SIZE: u32 = 0xC8;
 const VANILLA_STAGE_TABLE_SIZE: u32 = VANILLA_STAGE_COUNT * VANILLA_STAGE_ENTRY_SIZE;
 
+/// Size of a single `PIXTONEPARAMETER` channel in `gPtpTable`, including padding.
+const PIXTONE_CHANNEL_SIZE: usize = 0x70;
+
 trait RangeExt {
     fn to_usize(&self) -> std::ops::Range<usize>;
 }
@@ -86,6 +92,23 @@ impl VanillaExtractor {
         Some(Self { exe_buffer, data_base_dir, root })
     }
 
+    /// Wraps an already loaded executable, for tools that only read tables from it.
+    pub fn from_exe_buffer(exe_buffer: Vec<u8>) -> Self {
+        Self { exe_buffer, data_base_dir: String::new(), root: PathBuf::new() }
+    }
+
+    /// Returns the stage table embedded in the executable, in the same format as `stage.sect`.
+    pub fn stage_table(&self) -> GameResult<Vec<u8>> {
+        let parser = ExeParser::from(&self.exe_buffer)
+            .map_err(|_| ParseError("Failed to create vanilla parser.".to_string()))?;
+        let range = self
+            .find_stage_table_offset(&parser)
+            .and_then(|range| self.exe_buffer.get(range.to_usize()))
+            .ok_or_else(|| ParseError("Failed to retrieve stage table from executable.".to_string()))?;
+
+        Ok(range.to_vec())
+    }
+
     pub fn extract_data(&self) -> GameResult {
         let parser = ExeParser::from(&self.exe_buffer);
         if parser.is_err() {
@@ -97,6 +120,8 @@ impl VanillaExtractor {
         self.extract_organya(&parser)?;
         self.extract_bitmaps(&parser)?;
         self.extract_stage_table(&parser)?;
+        self.extract_pixtone(&parser)?;
+        self.extract_patches(&parser)?;
 
         Ok(())
     }
@@ -265,4 +290,165 @@ impl VanillaExtractor {
 
         Ok(())
     }
+
+    fn va_to_file_offset(&self, parser: &ExeParser, va: u32) -> Option<usize> {
+        let rva = va.checked_sub(parser.image_base)?;
+        let section = parser.section_headers.by_rva(rva)?;
+        let offset_inside_range = rva.checked_sub(section.VirtualAddress)?;
+
+        Some((section.file_range().start + offset_inside_range) as usize)
+    }
+
+    /// Looks for the `MakePixToneObject(&gPtpTable[n], channel_count, sound_id)` calls in `LoadGenericData`
+    /// and reads the parameters they point to.
+    fn find_pixtone_sounds(&self, parser: &ExeParser) -> Option<Vec<(u8, PixToneParameters)>> {
+        let text = parser.section_headers.by_name(".text")?;
+        let code = &self.exe_buffer[text.file_range().to_usize()];
+
+        // call target => (sound id, channel count, parameters address)
+        let mut calls: HashMap<usize, Vec<(u8, usize, u32)>> = HashMap::new();
+
+        for start in 0..code.len().saturating_sub(16) {
+            // push sound id
+            let (id, mut pos) = match code[start] {
+                0x6a => (code[start + 1] as u32, start + 2),
+                0x68 => (u32::from_le_bytes(code[start + 1..start + 5].try_into().unwrap()), start + 5),
+                _ => continue,
+            };
+
+            // push channel count; push offset gPtpTable+n; call MakePixToneObject
+            if id > 0xff || code[pos] != 0x6a || !(1..=4).contains(&code[pos + 1]) || code[pos + 2] != 0x68 {
+                continue;
+            }
+            let count = code[pos + 1] as usize;
+            let addr = u32::from_le_bytes(code[pos + 3..pos + 7].try_into().unwrap());
+            pos += 7;
+
+            if code[pos] != 0xe8 {
+                continue;
+            }
+            let rel = i32::from_le_bytes(code[pos + 1..pos + 5].try_into().unwrap());
+            let target = (pos as i64 + 5 + rel as i64) as usize;
+
+            calls.entry(target).or_default().push((id as u8, count, addr));
+        }
+
+        let (_, calls) = calls.into_iter().max_by_key(|(_, calls)| calls.len())?;
+        if calls.len() < 16 {
+            return None;
+        }
+
+        let mut sounds = Vec::new();
+        for (id, count, addr) in calls {
+            let start = self.va_to_file_offset(parser, addr)?;
+            let data = self.exe_buffer.get(start..start + count * PIXTONE_CHANNEL_SIZE)?;
+
+            let mut params = PixToneParameters::empty();
+            for (channel, data) in params.channels.iter_mut().zip(data.chunks_exact(PIXTONE_CHANNEL_SIZE)) {
+                let read_i32 = |offset: usize| i32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+                let read_waveform = |offset: usize| Waveform {
+                    waveform_type: read_i32(offset) as u8,
+                    pitch: f64::from_le_bytes(data[offset + 8..offset + 16].try_into().unwrap()) as f32,
+                    level: read_i32(offset + 16),
+                    offset: read_i32(offset + 20),
+                };
+
+                *channel = Channel {
+                    enabled: read_i32(0) != 0,
+                    length: read_i32(4) as u32,
+                    carrier: read_waveform(8),
+                    frequency: read_waveform(32),
+                    amplitude: read_waveform(56),
+                    envelope: Envelope {
+                        initial: read_i32(80),
+                        time_a: read_i32(84),
+                        value_a: read_i32(88),
+                        time_b: read_i32(92),
+                        value_b: read_i32(96),
+                        time_c: read_i32(100),
+                        value_c: read_i32(104),
+                    },
+                };
+            }
+
+            sounds.push((id, params));
+        }
+
+        Some(sounds)
+    }
+
+    fn extract_pixtone(&self, parser: &ExeParser) -> GameResult {
+        let sounds = match self.find_pixtone_sounds(parser) {
+            Some(sounds) => sounds,
+            None => {
+                log::warn!("Failed to find the PixTone table in executable, using built-in sound effects.");
+                return Ok(());
+            }
+        };
+
+        let mut pxt_path = self.root.clone();
+        pxt_path.push(self.data_base_dir.clone());
+        pxt_path.push("pxt");
+
+        if self.deep_create_dir_if_not_exists(pxt_path.clone()).is_err() {
+            return Err(ParseError("Failed to create PixTone directory structure.".to_string()));
+        }
+
+        for (id, params) in sounds.iter() {
+            let file_name = format!("fx{:02x}.pxt", id);
+            if std::fs::write(pxt_path.join(&file_name), params.to_pxt()).is_err() {
+                return Err(ParseError(format!("Failed to write {}.", file_name)));
+            }
+        }
+
+        log::info!("Extracted {} PixTone sound effects.", sounds.len());
+
+        Ok(())
+    }
+
+    fn extract_patches(&self, parser: &ExeParser) -> GameResult {
+        let patches = ExePatches::detect(&self.exe_buffer, parser);
+
+        for note in patches.applied.iter() {
+            log::info!("Detected executable hack: {}", note);
+        }
+
+        for note in patches.unsupported.iter() {
+            log::warn!("Unsupported executable hack: {}", note);
+        }
+
+        let mut data_path = self.root.clone();
+        data_path.push(self.data_base_dir.clone());
+
+        // only modified tables are written, the offsets are found heuristically and the built-in ones are exact
+        let tables = [
+            ("bullet.tbl", &patches.bullet_table),
+            ("arms_level.tbl", &patches.level_table),
+            ("caret.tbl", &patches.caret_table),
+        ];
+        for (file_name, table) in tables {
+            if let Some(table) = table {
+                if std::fs::write(data_path.join(file_name), table).is_err() {
+                    return Err(ParseError(format!("Failed to write {}.", file_name)));
+                }
+
+                log::info!("Extracted data table: {}", file_name);
+            }
+        }
+
+        if patches.is_empty() {
+            return Ok(());
+        }
+
+        let json = match serde_json::to_vec_pretty(&patches) {
+            Ok(json) => json,
+            Err(e) => return Err(ParseError(format!("Failed to serialize executable hacks: {}", e))),
+        };
+
+        if std::fs::write(data_path.join(ExePatches::FILE_NAME), json).is_err() {
+            return Err(ParseError("Failed to write executable hacks file.".to_string()));
+        }
+
+        Ok(())
+    }
 }
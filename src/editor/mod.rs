@@ -12,6 +12,7 @@ use crate::framework::error::GameResult;
 use crate::framework::graphics;
 use crate::game::shared_game_state::SharedGameState;
 use crate::game::frame::Frame;
+use crate::game::map::{MapLayer, MapLayerKind};
 use crate::game::stage::{Stage, StageTexturePaths};
 use crate::graphics::texture_set::I_MAG;
 
@@ -32,6 +33,8 @@ pub struct EditorInstance {
     pub tilemap: Tilemap,
     pub zoom: f32,
     pub current_tile: u8,
+    /// Index of the extra map layer being edited, the main layer if `None`.
+    pub current_layer: Option<usize>,
     pub mouse_pos: (f32, f32),
     pub want_capture_mouse: bool,
 }
@@ -56,6 +59,7 @@ impl EditorInstance {
             tilemap: Tilemap::new(),
             zoom: 2.0,
             current_tile: 0,
+            current_layer: None,
             mouse_pos: (0.0, 0.0),
             want_capture_mouse: true,
         }
@@ -69,6 +73,8 @@ impl EditorInstance {
 
         let mut drag = false;
 
+        self.layers_window(state, ctx, ui);
+
         match tool {
             CurrentTool::Move => {
                 if ui.io().want_capture_mouse {
@@ -99,7 +105,11 @@ impl EditorInstance {
                         && tile_x < self.stage.map.width as i32
                         && tile_y < self.stage.map.height as i32
                     {
-                        self.stage.change_tile(tile_x as usize, tile_y as usize, self.current_tile);
+                        if let Some(layer) = self.current_layer {
+                            self.stage.change_layer_tile(layer, tile_x as usize, tile_y as usize, self.current_tile);
+                        } else {
+                            self.stage.change_tile(tile_x as usize, tile_y as usize, self.current_tile);
+                        }
                     }
                 }
             }
@@ -139,9 +149,9 @@ impl EditorInstance {
             return Ok(());
         }
 
-        let name = &self.stage_textures.deref().borrow().tileset_fg;
+        let name = self.current_tileset();
 
-        if let Ok(batch) = state.texture_set.get_or_load_batch(ctx, &state.constants, name) {
+        if let Ok(batch) = state.texture_set.get_or_load_batch(ctx, &state.constants, &name) {
             let tile_size16 = tile_size as u16;
             let rect = Rect::new_size(
                 (self.current_tile as u16 % 16) * tile_size16,
@@ -170,8 +180,8 @@ impl EditorInstance {
             .position_pivot([1.0, 1.0])
             .resizable(false)
             .build(|| {
-                let name = &self.stage_textures.deref().borrow().tileset_fg;
-                let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, name);
+                let name = self.current_tileset();
+                let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, &name);
 
                 let pos = ui.cursor_screen_pos();
                 let tile_size = self.stage.map.tile_size.as_float();
@@ -211,6 +221,64 @@ impl EditorInstance {
             });
     }
 
+    /// Path to the tileset texture of the layer being edited.
+    fn current_tileset(&self) -> String {
+        let paths = self.stage_textures.deref().borrow();
+
+        match self.current_layer.and_then(|idx| self.stage.map.layers.get(idx)) {
+            Some(layer) => paths.layer_tileset(layer),
+            None => paths.tileset_fg.clone(),
+        }
+    }
+
+    fn layers_window(&mut self, state: &mut SharedGameState, ctx: &mut Context, ui: &imgui::Ui) {
+        // extra layers are only supported in PXM maps
+        if self.stage.data.pxpack_data.is_some() {
+            return;
+        }
+
+        ui.window("Layers")
+            .size([220.0, 240.0], imgui::Condition::FirstUseEver)
+            .position([0.0, ui.io().display_size[1]], imgui::Condition::FirstUseEver)
+            .position_pivot([0.0, 1.0])
+            .build(|| {
+                if ui.selectable_config("Main").selected(self.current_layer.is_none()).build() {
+                    self.current_layer = None;
+                }
+
+                for (idx, layer) in self.stage.map.layers.iter().enumerate() {
+                    let tileset = if layer.tileset.is_empty() { &self.stage.data.tileset.name } else { &layer.tileset };
+                    let label = format!("{}: {} ({})##layer{}", idx + 1, layer.kind.name(), tileset, idx);
+
+                    if ui.selectable_config(label).selected(self.current_layer == Some(idx)).build() {
+                        self.current_layer = Some(idx);
+                    }
+                }
+
+                ui.separator();
+
+                for kind in [MapLayerKind::Background, MapLayerKind::Middleground, MapLayerKind::Foreground] {
+                    if ui.button(format!("Add {}", kind.name().to_lowercase())) {
+                        let map = &mut self.stage.map;
+                        map.layers.push(MapLayer::new(kind, map.width, map.height));
+                        map.load_layer_attributes(ctx, &state.constants.base_paths, &self.stage.data.tileset.name);
+                        self.current_layer = Some(map.layers.len() - 1);
+                    }
+                }
+
+                if let Some(idx) = self.current_layer {
+                    if let Some(layer) = self.stage.map.layers.get_mut(idx) {
+                        ui.checkbox("Collision", &mut layer.collision);
+                    }
+
+                    if ui.button("Remove layer") {
+                        self.stage.map.layers.remove(idx);
+                        self.current_layer = None;
+                    }
+                }
+            });
+    }
+
     pub fn draw(&self, state: &mut SharedGameState, ctx: &mut Context, tool: CurrentTool) -> GameResult {
         let old_scale = state.scale;
         set_scale(state, self.zoom);
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Cursor, Read};
+use std::rc::Rc;
 
 use byteorder::{ReadBytesExt, LE};
 use case_insensitive_hashmap::CaseInsensitiveHashMap;
@@ -7,15 +8,20 @@ use xmltree::Element;
 
 use crate::case_insensitive_hashmap;
 use crate::common::{BulletFlag, Color, Rect};
+use crate::data::exe_patches::ExePatches;
 use crate::engine_constants::npcs::NPCConsts;
 use crate::framework::context::Context;
 use crate::framework::error::GameResult;
 use crate::framework::filesystem;
 use crate::framework::gamepad::{Axis, Button};
+use crate::game::lighting::LightTable;
+use crate::game::npc::boss::custom::{CustomBoss, CustomBossTable};
 use crate::game::player::ControlMode;
 use crate::game::scripting::tsc::text_script::TextScriptEncoding;
 use crate::game::settings::Settings;
 use crate::game::shared_game_state::{FontData, Season};
+use crate::game::weapon::custom::{remove_spawn_cycles, CustomBullet, CustomWeapon, CustomWeaponTable};
+use crate::game::weapon::WeaponType;
 use crate::i18n::Locale;
 use crate::sound::pixtone::{Channel, Envelope, PixToneParameters, Waveform};
 use crate::sound::SoundManager;
@@ -152,6 +158,8 @@ pub struct WeaponConsts {
     pub bullet_table: Vec<BulletData>,
     pub bullet_rects: BulletRects,
     pub level_table: [[u16; 3]; 14],
+    pub custom_weapons: HashMap<u8, CustomWeapon>,
+    pub custom_bullets: HashMap<u16, CustomBullet>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -274,10 +282,14 @@ pub struct EngineConstants {
     pub player_skin_paths: Vec<String>,
     pub animated_face_table: Vec<AnimatedFace>,
     pub string_table: HashMap<String, String>,
+    pub custom_bosses: HashMap<u16, Rc<CustomBoss>>,
     pub missile_flags: Vec<u16>,
     pub locales: Vec<Locale>,
     pub gamepad: GamepadConsts,
     pub stage_encoding: Option<TextScriptEncoding>,
+    pub lights: LightTable,
+    /// Game resolution set by a widescreen hack, used when the viewport follows the window size.
+    pub viewport_size: Option<(f32, f32)>,
 }
 
 impl EngineConstants {
@@ -1267,6 +1279,8 @@ impl EngineConstants {
                     [1, 1, 1],
                     [40, 60, 200],
                 ],
+                custom_weapons: HashMap::new(),
+                custom_bullets: HashMap::new(),
             },
             tex_sizes: case_insensitive_hashmap! {
                 "ArmsImage" => (256, 16),
@@ -1573,6 +1587,7 @@ impl EngineConstants {
             player_skin_paths: vec!["MyChar".to_owned()],
             animated_face_table: vec![AnimatedFace { face_id: 0, anim_id: 0, anim_frames: vec![(0, 0)] }],
             string_table: HashMap::new(),
+            custom_bosses: HashMap::new(),
             missile_flags: vec![200, 201, 202, 218, 550, 766, 880, 920, 1551],
             locales: Vec::new(),
             gamepad: {
@@ -1613,6 +1628,8 @@ impl EngineConstants {
                 holder
             },
             stage_encoding: None,
+            lights: LightTable::defaults(false),
+            viewport_size: None,
         }
     }
 
@@ -1664,7 +1681,7 @@ impl EngineConstants {
             ],
         };
 
-        let _ = sound_manager.set_sample_params(2, typewriter_sample);
+        let _ = sound_manager.set_base_sample_params(2, typewriter_sample);
     }
 
     pub fn is_base(&self) -> bool {
@@ -1705,7 +1722,8 @@ impl EngineConstants {
         self.title.logo_splash_rect = Rect { left: 224, top: 0, right: 320, bottom: 48 };
     }
 
-    pub fn rebuild_path_list(&mut self, mod_path: Option<String>, season: Season, settings: &Settings) {
+    /// Rebuilds the list of data directories, `mod_paths` are stacked in order, the last one has the highest priority.
+    pub fn rebuild_path_list(&mut self, mod_paths: &[String], season: Season, settings: &Settings) {
         self.base_paths.clear();
         self.base_paths.push("/builtin/builtin_data/".to_owned());
         self.base_paths.push("/".to_owned());
@@ -1732,11 +1750,10 @@ impl EngineConstants {
             }
         }
 
-        if let Some(mut mod_path) = mod_path {
+        for mod_path in mod_paths {
             self.base_paths.insert(0, mod_path.clone());
             if settings.original_textures {
-                mod_path.push_str("ogph/");
-                self.base_paths.insert(0, mod_path);
+                self.base_paths.insert(0, format!("{}ogph/", mod_path));
             }
 
             // Nicalis left a landmine of a file in the original graphics for the nemesis challenge
@@ -1890,6 +1907,139 @@ impl EngineConstants {
             log::info!("Loaded arms_level.tbl.");
         }
 
+        // not a part of Cave Story+, gets extracted from the freeware executable
+        if let Ok(mut file) = filesystem::open_find(ctx, &self.base_paths, "caret.tbl") {
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            let mut f = Cursor::new(data);
+
+            let mut new_offsets = EngineConstants::defaults().caret.offsets;
+            for offset in new_offsets.iter_mut() {
+                *offset = (f.read_i32::<LE>()?, f.read_i32::<LE>()?);
+            }
+
+            self.caret.offsets = new_offsets;
+            log::info!("Loaded caret.tbl.");
+        }
+
+        Ok(())
+    }
+
+    /// Applies hacks detected in the freeware executable during data extraction.
+    pub fn load_exe_patches(&mut self, ctx: &mut Context) -> GameResult {
+        self.viewport_size = None;
+
+        if let Some(patches) = ExePatches::load(ctx, &self.base_paths) {
+            patches.apply(self);
+            log::info!("Loaded {}.", ExePatches::FILE_NAME);
+        }
+
+        Ok(())
+    }
+
+    /// Loads weapon and bullet definitions from `weapons.json` files, definitions from mods take precedence.
+    pub fn load_custom_weapons(&mut self, ctx: &mut Context) -> GameResult {
+        self.weapon.custom_weapons.clear();
+        self.weapon.custom_bullets.clear();
+
+        for path in self.base_paths.iter().rev() {
+            let Ok(file) = filesystem::open(ctx, [path, "weapons.json"].join("")) else {
+                continue;
+            };
+
+            let table = match serde_json::from_reader::<_, CustomWeaponTable>(file) {
+                Ok(table) => table,
+                Err(err) => {
+                    log::warn!("Failed to deserialize {}weapons.json: {}", path, err);
+                    continue;
+                }
+            };
+
+            for weapon in table.weapons {
+                if WeaponType::is_vanilla_id(weapon.id) {
+                    log::warn!("Custom weapon id {} collides with a built-in weapon, skipping.", weapon.id);
+                    continue;
+                }
+
+                self.weapon.custom_weapons.insert(weapon.id, weapon);
+            }
+
+            for bullet in table.bullets {
+                if (bullet.id as usize) < self.weapon.bullet_table.len() {
+                    log::warn!("Custom bullet id {} collides with a built-in bullet, skipping.", bullet.id);
+                    continue;
+                }
+
+                self.weapon.custom_bullets.insert(bullet.id, bullet);
+            }
+        }
+
+        remove_spawn_cycles(&mut self.weapon.custom_bullets);
+
+        if !self.weapon.custom_weapons.is_empty() {
+            log::info!(
+                "Loaded {} custom weapons and {} custom bullets.",
+                self.weapon.custom_weapons.len(),
+                self.weapon.custom_bullets.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Loads light sources from `lights.json` files, entries from mods take precedence.
+    pub fn load_light_table(&mut self, ctx: &mut Context) -> GameResult {
+        self.lights = LightTable::defaults(self.is_cs_plus);
+
+        for path in self.base_paths.iter().rev() {
+            let Ok(file) = filesystem::open(ctx, [path, "lights.json"].join("")) else {
+                continue;
+            };
+
+            match serde_json::from_reader::<_, LightTable>(file) {
+                Ok(table) => self.lights.merge(table),
+                Err(err) => log::warn!("Failed to deserialize {}lights.json: {}", path, err),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load_custom_bosses(&mut self, ctx: &mut Context) -> GameResult {
+        self.custom_bosses.clear();
+
+        for path in self.base_paths.iter().rev() {
+            let Ok(file) = filesystem::open(ctx, [path, "bosses.json"].join("")) else {
+                continue;
+            };
+
+            let table = match serde_json::from_reader::<_, CustomBossTable>(file) {
+                Ok(table) => table,
+                Err(err) => {
+                    log::warn!("Failed to deserialize {}bosses.json: {}", path, err);
+                    continue;
+                }
+            };
+
+            for boss in table.bosses {
+                if (1..=9).contains(&boss.id) {
+                    log::warn!("Custom boss id {} collides with a built-in boss, skipping.", boss.id);
+                    continue;
+                }
+
+                if let Err(err) = boss.validate() {
+                    log::warn!("Custom boss {} is invalid, skipping: {}.", boss.id, err);
+                    continue;
+                }
+
+                self.custom_bosses.insert(boss.id, Rc::new(boss));
+            }
+        }
+
+        if !self.custom_bosses.is_empty() {
+            log::info!("Loaded {} custom bosses.", self.custom_bosses.len());
+        }
+
         Ok(())
     }
 
@@ -5,7 +5,7 @@ use imgui::DrawData;
 
 use crate::common::{Color, Rect};
 use crate::framework::context::Context;
-use crate::framework::error::GameResult;
+use crate::framework::error::{GameError, GameResult};
 use crate::framework::graphics::{BlendMode, VSyncMode};
 use crate::game::Game;
 
@@ -87,6 +87,11 @@ pub trait BackendRenderer {
         shader: BackendShader,
     ) -> GameResult;
 
+    /// Reads back the screen contents drawn so far as RGBA rows, from top to bottom.
+    fn read_pixels(&mut self) -> GameResult<(u16, u16, Vec<u8>)> {
+        Err(GameError::RenderError("Reading back pixels is not supported by this renderer.".to_owned()))
+    }
+
     fn as_any(&self) -> &dyn Any;
 }
 
@@ -405,6 +405,7 @@ impl BackendEventLoop for HorizonEventLoop {
             if state_ref.next_scene.is_some() {
                 mem::swap(&mut game.scene, &mut state_ref.next_scene);
                 state_ref.next_scene = None;
+                state_ref.sound_manager.set_sfx_listener(None);
                 game.scene.as_mut().unwrap().init(state_ref, ctx).unwrap();
                 game.loops = 0;
                 state_ref.frame_time = 0.0;
@@ -413,6 +413,7 @@ impl BackendEventLoop for SDL2EventLoop {
 
             if let Some(_) = &state.next_scene {
                 game.scene = mem::take(&mut state.next_scene);
+                state.sound_manager.set_sfx_listener(None);
                 game.scene.as_mut().unwrap().init(state, ctx).unwrap();
                 game.loops = 0;
                 state.frame_time = 0.0;
@@ -947,6 +948,17 @@ impl BackendRenderer for SDL2Renderer {
         Ok(())
     }
 
+    fn read_pixels(&mut self) -> GameResult<(u16, u16, Vec<u8>)> {
+        let mut refs = self.refs.borrow_mut();
+        let canvas = refs.window.canvas();
+
+        let (width, height) = canvas.output_size().map_err(|e| GameError::RenderError(e.to_string()))?;
+        let data =
+            canvas.read_pixels(None, PixelFormatEnum::RGBA32).map_err(|e| GameError::RenderError(e.to_string()))?;
+
+        Ok((width as u16, height as u16, data))
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -3,6 +3,7 @@ use std::io;
 use std::io::SeekFrom;
 use std::path;
 use std::path::PathBuf;
+use std::time::SystemTime;
 
 use crate::framework::context::Context;
 use crate::framework::error::{GameError, GameResult};
@@ -133,6 +134,11 @@ impl Filesystem {
         self.vfs.exists(path.as_ref())
     }
 
+    /// Returns the last modification time of a file, if it's known.
+    pub(crate) fn modified<P: AsRef<path::Path>>(&self, path: P) -> Option<SystemTime> {
+        self.vfs.metadata(path.as_ref()).ok().and_then(|m| m.modified())
+    }
+
     /// Check whether a path points at a file.
     pub(crate) fn user_is_file<P: AsRef<path::Path>>(&self, path: P) -> bool {
         self.user_vfs.metadata(path.as_ref()).map(|m| m.is_file()).unwrap_or(false)
@@ -333,6 +339,20 @@ pub fn exists_find<P: AsRef<path::Path>>(ctx: &Context, roots: &Vec<String>, pat
     false
 }
 
+/// Returns the last modification time of the file that would be opened by `open_find`, if it's known.
+pub fn modified_find<P: AsRef<path::Path>>(ctx: &Context, roots: &Vec<String>, path: P) -> Option<SystemTime> {
+    for root in roots {
+        let mut full_path = root.to_string();
+        full_path.push_str(path.as_ref().to_string_lossy().as_ref());
+
+        if ctx.filesystem.exists(&full_path) {
+            return ctx.filesystem.modified(full_path);
+        }
+    }
+
+    None
+}
+
 /// Check whether a path points at a file.
 pub fn is_file<P: AsRef<path::Path>>(ctx: &Context, path: P) -> bool {
     ctx.filesystem.is_file(path)
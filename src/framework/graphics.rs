@@ -176,6 +176,14 @@ pub fn prepare_draw(ctx: &mut Context) -> GameResult {
     Err(GameError::RenderError("Rendering backend hasn't been initialized yet.".to_string()))
 }
 
+pub fn read_pixels(ctx: &mut Context) -> GameResult<(u16, u16, Vec<u8>)> {
+    if let Some(renderer) = &mut ctx.renderer {
+        return renderer.read_pixels();
+    }
+
+    Err(GameError::RenderError("Rendering backend hasn't been initialized yet.".to_string()))
+}
+
 pub fn supports_vertex_draw(ctx: &Context) -> GameResult<bool> {
     if let Some(renderer) = ctx.renderer.as_ref() {
         return Ok(renderer.supports_vertex_draw());
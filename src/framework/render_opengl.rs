@@ -1208,6 +1208,32 @@ impl BackendRenderer for OpenGLRenderer {
         self.draw_arrays(gl::TRIANGLES, vertices, texture, shader)
     }
 
+    fn read_pixels(&mut self) -> GameResult<(u16, u16, Vec<u8>)> {
+        let (width, height) = self.render_data.last_size;
+        let surf_framebuffer = self.render_data.surf_framebuffer;
+
+        if let Some((_, gl)) = self.get_context() {
+            let stride = width as usize * 4;
+            let mut data = vec![0u8; stride * height as usize];
+
+            unsafe {
+                gl.gl.BindFramebuffer(gl::FRAMEBUFFER, surf_framebuffer);
+                gl.gl.PixelStorei(gl::PACK_ALIGNMENT, 1);
+                gl.gl.ReadPixels(0, 0, width as _, height as _, gl::RGBA, gl::UNSIGNED_BYTE, data.as_mut_ptr() as _);
+            }
+
+            // OpenGL returns the rows from bottom to top
+            let mut flipped = Vec::with_capacity(data.len());
+            for row in data.chunks_exact(stride).rev() {
+                flipped.extend_from_slice(row);
+            }
+
+            return Ok((width as u16, height as u16, flipped));
+        }
+
+        Err(RenderError("No OpenGL context available!".to_string()))
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -9,12 +9,16 @@
 //! as a trait object, and its path abstraction is not the most
 //! convenient.
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::ffi::OsStr;
 use std::fmt::{self, Debug};
 use std::fs;
-use std::io::{Read, Seek, Write};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{self, Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use byteorder::{ByteOrder, ReadBytesExt, LE};
 
 use crate::framework::error::{GameError, GameResult};
 
@@ -145,6 +149,10 @@ pub trait VMetadata {
     /// Returns the length of the thing.  If it is a directory,
     /// the result of this is undefined/platform dependent.
     fn len(&self) -> u64;
+    /// Returns the last modification time, if the backing store keeps track of it.
+    fn modified(&self) -> Option<SystemTime> {
+        None
+    }
 }
 
 /// A VFS that points to a directory and uses it as the root of its
@@ -173,6 +181,9 @@ impl VMetadata for PhysicalMetadata {
     fn len(&self) -> u64 {
         self.0.len()
     }
+    fn modified(&self) -> Option<SystemTime> {
+        self.0.modified().ok()
+    }
 }
 
 /// This takes an absolute path and returns either a sanitized relative
@@ -543,6 +554,342 @@ impl VFS for OverlayFS {
     }
 }
 
+const ZIP_LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const ZIP_CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const ZIP_END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x06054b50;
+const ZIP_END_OF_CENTRAL_DIR_SIZE: usize = 22;
+const ZIP_MAX_COMMENT_SIZE: usize = 0xffff;
+
+/// Something we can read a zip archive from.
+pub trait ZipReader: Read + Seek + Send {}
+
+impl<T> ZipReader for T where T: Read + Seek + Send {}
+
+#[derive(Debug, Clone)]
+struct ZipEntry {
+    name: String,
+    method: u16,
+    encrypted: bool,
+    header_offset: u64,
+    compressed_size: u64,
+    size: u64,
+}
+
+#[derive(Debug, Clone)]
+/// Zip FS metadata
+pub struct ZipMetadata {
+    is_dir: bool,
+    len: u64,
+}
+
+impl VMetadata for ZipMetadata {
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+    fn is_file(&self) -> bool {
+        !self.is_dir
+    }
+    fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+/// Turns a path into the lowercase, `/` separated form used as keys of a `ZipFS`.
+fn zip_key<'a>(components: impl Iterator<Item = &'a str>) -> String {
+    components.filter(|c| !c.is_empty()).map(|c| c.to_lowercase()).collect::<Vec<_>>().join("/")
+}
+
+/// A read-only VFS backed by a zip archive, so mods can be distributed
+/// as a single file without having to extract them.
+///
+/// The contents of the archive appear under the given mount point,
+/// for example an archive mounted at `/mods/foo/` with a `Stage/0.pxm`
+/// entry provides `/mods/foo/Stage/0.pxm`.
+///
+/// Only stored and deflated entries are supported, ZIP64 archives and
+/// encrypted entries are not.  Lookups are case insensitive, just like
+/// with `PhysicalFS::new_lowercase`.
+pub struct ZipFS {
+    archive: PathBuf,
+    archive_len: u64,
+    reader: Mutex<Box<dyn ZipReader>>,
+    files: HashMap<String, ZipEntry>,
+    /// Lowercase directory path -> names of its entries as they're stored in the archive.
+    dirs: HashMap<String, Vec<String>>,
+}
+
+impl ZipFS {
+    /// Opens a zip archive from disk and mounts its contents at given path.
+    pub fn new(archive: &Path, mount_point: &Path) -> GameResult<Self> {
+        let file = fs::File::open(archive)?;
+
+        Self::from_reader(archive, Box::new(io::BufReader::new(file)), mount_point)
+    }
+
+    /// Reads a zip archive from given reader, `archive` is only used to identify this VFS.
+    pub fn from_reader(archive: &Path, mut reader: Box<dyn ZipReader>, mount_point: &Path) -> GameResult<Self> {
+        let mount_point = sanitize_path(mount_point)
+            .ok_or_else(|| GameError::FilesystemError(format!("Invalid mount point: {:?}", mount_point)))?;
+        let mount_point: Vec<&str> = mount_point.iter().filter_map(|c| c.to_str()).collect();
+
+        let archive_len = reader.seek(SeekFrom::End(0))?;
+        let entries = Self::read_central_directory(&mut reader, archive_len)?;
+        let mut fs = ZipFS {
+            archive: archive.to_path_buf(),
+            archive_len,
+            reader: Mutex::new(reader),
+            files: HashMap::new(),
+            dirs: HashMap::new(),
+        };
+
+        // the mount point itself and all of its parents are directories too
+        for i in 0..mount_point.len() {
+            fs.add_dir_entry(zip_key(mount_point[..i].iter().copied()), mount_point[i]);
+        }
+        fs.dirs.entry(zip_key(mount_point.iter().copied())).or_default();
+
+        for entry in entries {
+            let name = entry.name.replace('\\', "/");
+            let components: Vec<&str> = name.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+
+            if components.is_empty() || components.contains(&"..") {
+                log::warn!("Skipping invalid zip entry {:?} in {:?}.", entry.name, archive);
+                continue;
+            }
+
+            let path: Vec<&str> = mount_point.iter().chain(components.iter()).copied().collect();
+            let file_start = mount_point.len() + components.len() - 1;
+            let is_dir = name.ends_with('/');
+
+            for i in mount_point.len()..path.len() {
+                if i == file_start && !is_dir {
+                    break;
+                }
+
+                fs.add_dir_entry(zip_key(path[..i].iter().copied()), path[i]);
+                fs.dirs.entry(zip_key(path[..=i].iter().copied())).or_default();
+            }
+
+            if !is_dir {
+                fs.add_dir_entry(zip_key(path[..file_start].iter().copied()), path[file_start]);
+                fs.files.insert(zip_key(path.iter().copied()), entry);
+            }
+        }
+
+        Ok(fs)
+    }
+
+    fn add_dir_entry(&mut self, dir: String, name: &str) {
+        let entries = self.dirs.entry(dir).or_default();
+        if !entries.iter().any(|e| e.to_lowercase() == name.to_lowercase()) {
+            entries.push(name.to_owned());
+        }
+    }
+
+    fn read_central_directory(reader: &mut Box<dyn ZipReader>, archive_len: u64) -> GameResult<Vec<ZipEntry>> {
+        let tail_len = archive_len.min((ZIP_END_OF_CENTRAL_DIR_SIZE + ZIP_MAX_COMMENT_SIZE) as u64);
+        if (tail_len as usize) < ZIP_END_OF_CENTRAL_DIR_SIZE {
+            return Err(GameError::FilesystemError("Not a zip archive.".to_owned()));
+        }
+
+        let mut tail = vec![0u8; tail_len as usize];
+        reader.seek(SeekFrom::Start(archive_len - tail_len))?;
+        reader.read_exact(&mut tail)?;
+
+        // the end of central directory record is followed by a comment of variable length, so look for it backwards
+        let eocd = (0..=tail.len() - ZIP_END_OF_CENTRAL_DIR_SIZE)
+            .rev()
+            .find(|&i| LE::read_u32(&tail[i..]) == ZIP_END_OF_CENTRAL_DIR_SIGNATURE)
+            .ok_or_else(|| GameError::FilesystemError("Not a zip archive.".to_owned()))?;
+
+        let entry_count = LE::read_u16(&tail[eocd + 10..]);
+        let cd_size = LE::read_u32(&tail[eocd + 12..]);
+        let cd_offset = LE::read_u32(&tail[eocd + 16..]);
+
+        if entry_count == 0xffff || cd_offset == 0xffffffff {
+            return Err(GameError::FilesystemError("ZIP64 archives are not supported.".to_owned()));
+        }
+
+        if cd_offset as u64 + cd_size as u64 > archive_len {
+            return Err(GameError::FilesystemError("Corrupted zip central directory.".to_owned()));
+        }
+
+        let mut cd = vec![0u8; cd_size as usize];
+        reader.seek(SeekFrom::Start(cd_offset as u64))?;
+        reader.read_exact(&mut cd)?;
+
+        let mut cd = Cursor::new(cd);
+        let mut entries = Vec::with_capacity(entry_count as usize);
+
+        for _ in 0..entry_count {
+            if cd.read_u32::<LE>()? != ZIP_CENTRAL_HEADER_SIGNATURE {
+                return Err(GameError::FilesystemError("Corrupted zip central directory.".to_owned()));
+            }
+
+            cd.seek(SeekFrom::Current(4))?; // version made by, version needed
+            let flags = cd.read_u16::<LE>()?;
+            let method = cd.read_u16::<LE>()?;
+            cd.seek(SeekFrom::Current(8))?; // time, date, crc32
+            let compressed_size = cd.read_u32::<LE>()? as u64;
+            let size = cd.read_u32::<LE>()? as u64;
+            let name_len = cd.read_u16::<LE>()?;
+            let extra_len = cd.read_u16::<LE>()?;
+            let comment_len = cd.read_u16::<LE>()?;
+            cd.seek(SeekFrom::Current(8))?; // disk number, internal and external attributes
+            let header_offset = cd.read_u32::<LE>()? as u64;
+
+            let mut name = vec![0u8; name_len as usize];
+            cd.read_exact(&mut name)?;
+            cd.seek(SeekFrom::Current(extra_len as i64 + comment_len as i64))?;
+
+            entries.push(ZipEntry {
+                name: String::from_utf8_lossy(&name).into_owned(),
+                method,
+                encrypted: flags & 1 != 0,
+                header_offset,
+                compressed_size,
+                size,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    fn to_key(&self, path: &Path) -> GameResult<String> {
+        let safe_path = sanitize_path(path)
+            .ok_or_else(|| GameError::FilesystemError(format!("Path {:?} is not valid: must be absolute", path)))?;
+
+        Ok(zip_key(safe_path.iter().filter_map(|c| c.to_str())))
+    }
+
+    fn read_entry(&self, entry: &ZipEntry) -> GameResult<Vec<u8>> {
+        if entry.encrypted {
+            return Err(GameError::FilesystemError(format!("{} in {:?} is encrypted.", entry.name, self.archive)));
+        }
+
+        let mut reader =
+            self.reader.lock().map_err(|_| GameError::FilesystemError("Zip archive reader poisoned.".to_owned()))?;
+
+        let mut header = [0u8; 30];
+        reader.seek(SeekFrom::Start(entry.header_offset))?;
+        reader.read_exact(&mut header)?;
+
+        if LE::read_u32(&header) != ZIP_LOCAL_HEADER_SIGNATURE {
+            return Err(GameError::FilesystemError(format!(
+                "Corrupted zip entry {} in {:?}.",
+                entry.name, self.archive
+            )));
+        }
+
+        let name_len = LE::read_u16(&header[26..]) as u64;
+        let extra_len = LE::read_u16(&header[28..]) as u64;
+        let data_offset = entry.header_offset + header.len() as u64 + name_len + extra_len;
+
+        // the sizes come from the archive, don't allocate more than what's actually left in it
+        if data_offset + entry.compressed_size > self.archive_len {
+            return Err(GameError::FilesystemError(format!(
+                "Corrupted zip entry {} in {:?}.",
+                entry.name, self.archive
+            )));
+        }
+
+        reader.seek(SeekFrom::Start(data_offset))?;
+
+        let mut data = vec![0u8; entry.compressed_size as usize];
+        reader.read_exact(&mut data)?;
+
+        match entry.method {
+            0 => Ok(data),
+            8 => miniz_oxide::inflate::decompress_to_vec_with_limit(&data, entry.size as usize).map_err(|e| {
+                GameError::FilesystemError(format!("Failed to inflate {} in {:?}: {:?}", entry.name, self.archive, e))
+            }),
+            method => Err(GameError::FilesystemError(format!(
+                "{} in {:?} uses unsupported compression method {}.",
+                entry.name, self.archive, method
+            ))),
+        }
+    }
+}
+
+impl Debug for ZipFS {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "<ZipFS archive: {}>", self.archive.display())
+    }
+}
+
+impl VFS for ZipFS {
+    /// Open the file at this path with the given options
+    fn open_options(&self, path: &Path, open_options: OpenOptions) -> GameResult<Box<dyn VFile>> {
+        if open_options.write || open_options.create || open_options.append || open_options.truncate {
+            let msg = format!("Cannot alter file {:?} in root {:?}, filesystem read-only", path, self);
+            return Err(GameError::FilesystemError(msg));
+        }
+
+        let key = self.to_key(path)?;
+        let entry = self
+            .files
+            .get(&key)
+            .ok_or_else(|| GameError::FilesystemError(format!("File {:?} not found in {:?}", path, self)))?;
+
+        let data = self.read_entry(entry)?;
+        Ok(Box::new(Cursor::new(data)))
+    }
+
+    /// Create a directory at the location by this path
+    fn mkdir(&self, _path: &Path) -> GameResult {
+        Err(GameError::FilesystemError("Tried to make directory {} but FS is read-only".to_string()))
+    }
+
+    /// Remove a file
+    fn rm(&self, _path: &Path) -> GameResult {
+        Err(GameError::FilesystemError("Tried to remove file {} but FS is read-only".to_string()))
+    }
+
+    /// Remove a file or directory and all its contents
+    fn rmrf(&self, _path: &Path) -> GameResult {
+        Err(GameError::FilesystemError("Tried to remove file/dir {} but FS is read-only".to_string()))
+    }
+
+    /// Check if the file exists
+    fn exists(&self, path: &Path) -> bool {
+        match self.to_key(path) {
+            Ok(key) => self.files.contains_key(&key) || self.dirs.contains_key(&key),
+            _ => false,
+        }
+    }
+
+    /// Get the file's metadata
+    fn metadata(&self, path: &Path) -> GameResult<Box<dyn VMetadata>> {
+        let key = self.to_key(path)?;
+
+        if let Some(entry) = self.files.get(&key) {
+            Ok(Box::new(ZipMetadata { is_dir: false, len: entry.size }))
+        } else if self.dirs.contains_key(&key) {
+            Ok(Box::new(ZipMetadata { is_dir: true, len: 0 }))
+        } else {
+            Err(GameError::FilesystemError(format!("File {:?} not found in {:?}", path, self)))
+        }
+    }
+
+    /// Retrieve the path entries in this path
+    fn read_dir(&self, path: &Path) -> GameResult<Box<dyn Iterator<Item = GameResult<PathBuf>>>> {
+        let key = self.to_key(path)?;
+        let entries = self
+            .dirs
+            .get(&key)
+            .ok_or_else(|| GameError::FilesystemError(format!("Directory {:?} not found in {:?}", path, self)))?;
+
+        let path = PathBuf::from(path);
+        let itr = entries.iter().map(|name| Ok(path.join(name))).collect::<Vec<_>>().into_iter();
+        Ok(Box::new(itr))
+    }
+
+    /// Retrieve the actual location of the VFS root, if available.
+    fn to_path_buf(&self) -> Option<PathBuf> {
+        Some(self.archive.clone())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::{self, BufRead};
@@ -686,5 +1033,80 @@ mod tests {
         assert!(!fs.exists(testdir));
     }
 
+    /// Builds an archive with stored entries by hand.
+    fn build_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let mut central_dir = Vec::new();
+
+        for (name, data) in files.iter() {
+            let offset = archive.len() as u32;
+            let mut header = Vec::new();
+            header.extend_from_slice(&[0x50, 0x4b, 0x03, 0x04, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            header.extend_from_slice(&[0, 0]);
+
+            archive.extend_from_slice(&header);
+            archive.extend_from_slice(name.as_bytes());
+            archive.extend_from_slice(data);
+
+            central_dir.extend_from_slice(&[0x50, 0x4b, 0x01, 0x02, 20, 0]);
+            central_dir.extend_from_slice(&header[4..]);
+            central_dir.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            central_dir.extend_from_slice(&offset.to_le_bytes());
+            central_dir.extend_from_slice(name.as_bytes());
+        }
+
+        let cd_offset = archive.len() as u32;
+        archive.extend_from_slice(&central_dir);
+        archive.extend_from_slice(&[0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0]);
+        archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(central_dir.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&cd_offset.to_le_bytes());
+        archive.extend_from_slice(&[0, 0]);
+
+        archive
+    }
+
+    #[test]
+    fn headless_test_zip() {
+        let archive = build_zip(&[("Stage/", b""), ("Stage/Cave.pxm", b"PXM"), ("mod.json", b"{}")]);
+        let fs =
+            ZipFS::from_reader(Path::new("test.zip"), Box::new(io::Cursor::new(archive)), Path::new("/mods/test/"))
+                .unwrap();
+
+        let mut buf = Vec::new();
+        fs.open(Path::new("/mods/test/stage/CAVE.pxm")).unwrap().read_to_end(&mut buf).unwrap();
+        assert_eq!(&buf[..], b"PXM");
+
+        assert!(fs.exists(Path::new("/mods")));
+        assert!(fs.exists(Path::new("/mods/test/mod.json")));
+        assert!(!fs.exists(Path::new("/mod.json")));
+        assert!(fs.metadata(Path::new("/mods/test/Stage")).unwrap().is_dir());
+        assert_eq!(fs.metadata(Path::new("/mods/test/mod.json")).unwrap().len(), 2);
+        assert_eq!(fs.read_dir(Path::new("/mods/")).unwrap().count(), 1);
+        assert_eq!(fs.read_dir(Path::new("/mods/test/")).unwrap().count(), 2);
+        assert!(fs.create(Path::new("/mods/test/mod.json")).is_err());
+    }
+
+    #[test]
+    fn headless_test_zip_corrupted_size() {
+        let mut archive = build_zip(&[("big.bin", b"data"), ("ok.bin", b"fine")]);
+
+        // the first central directory entry claims the file is almost 4 GiB large
+        let cd_offset = LE::read_u32(&archive[archive.len() - 6..]) as usize;
+        archive[cd_offset + 20..cd_offset + 24].copy_from_slice(&0xfffffff0u32.to_le_bytes());
+
+        let fs = ZipFS::from_reader(Path::new("test.zip"), Box::new(io::Cursor::new(archive)), Path::new("/")).unwrap();
+
+        assert!(fs.open(Path::new("/big.bin")).is_err());
+
+        let mut buf = Vec::new();
+        fs.open(Path::new("/ok.bin")).unwrap().read_to_end(&mut buf).unwrap();
+        assert_eq!(&buf[..], b"fine");
+    }
+
     // BUGGO: TODO: Make sure all functions are tested for OverlayFS and ZipFS!!
 }
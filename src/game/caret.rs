@@ -64,7 +64,7 @@ pub struct Caret {
     pub direction: Direction,
     pub anim_rect: Rect<u16>,
     action_num: u16,
-    anim_num: u16,
+    pub anim_num: u16,
     anim_counter: u16,
 }
 
@@ -6,7 +6,7 @@ use crate::{
         context::Context,
         error::GameResult,
         filesystem::{mount_user_vfs, mount_vfs, unmount_user_vfs},
-        vfs::PhysicalFS,
+        vfs::{PhysicalFS, ZipFS},
     },
 };
 
@@ -144,12 +144,44 @@ impl FilesystemContainer {
             }
         }
 
+        self.mount_mod_archives(context);
+
         log::info!("Mounting built-in FS");
         mount_vfs(context, Box::new(BuiltinFS::new()));
 
         Ok(())
     }
 
+    /// Mounts `.zip` files from the `mods` directory at `/mods/<name>/`, so they're loaded like extracted mods.
+    fn mount_mod_archives(&self, context: &mut Context) {
+        let entries = match std::fs::read_dir(self.game_path.join("mods")) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        let mut archives: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("zip")))
+            .collect();
+        archives.sort();
+
+        for archive in archives {
+            let name = match archive.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+
+            match ZipFS::new(&archive, &PathBuf::from(format!("/mods/{}/", name))) {
+                Ok(fs) => {
+                    log::info!("Mounting mod archive {:?}", archive);
+                    mount_vfs(context, Box::new(fs));
+                }
+                Err(err) => log::warn!("Failed to mount mod archive {:?}: {}", archive, err),
+            }
+        }
+    }
+
     pub fn open_user_directory(&self) -> GameResult {
         self.open_directory(self.user_path.clone())
     }
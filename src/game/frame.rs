@@ -47,90 +47,77 @@ impl Frame {
         (x, y)
     }
 
-    pub fn immediate_update(&mut self, state: &mut SharedGameState, stage: &Stage) {
-        let mut screen_width = state.canvas_size.0;
+    /// Size of the area the camera keeps within the stage, in pixels.
+    fn view_size(state: &SharedGameState, stage: &Stage) -> (f32, f32) {
+        let (mut width, height) = state.view_size();
         if state.constants.is_switch && stage.map.width <= 54 {
-            screen_width += 10.0; // hack for scrolling
+            width += 10.0; // hack for scrolling
         }
 
-        let tile_size = state.tile_size.as_int();
+        (width, height)
+    }
 
-        if (stage.map.width as usize).saturating_sub(1) * (tile_size as usize) < screen_width as usize {
-            self.x = -(((screen_width as i32 - (stage.map.width as i32 - 1) * tile_size) * 0x200) / 2);
-        } else {
-            self.x = self.target_x - (screen_width as i32 * 0x200 / 2);
+    /// Camera limits in fixed point units, the whole stage unless the stage has its own camera bounds.
+    fn bounds(
+        camera_bounds: Option<(u16, u16, u16, u16)>,
+        map_size: (u16, u16),
+        tile_size: i32,
+    ) -> (i32, i32, i32, i32) {
+        let (left, top, right, bottom) =
+            camera_bounds.unwrap_or((0, 0, map_size.0.saturating_sub(1), map_size.1.saturating_sub(1)));
 
-            if self.x < 0 {
-                self.x = 0;
-            }
+        (left as i32 * tile_size, top as i32 * tile_size, right as i32 * tile_size, bottom as i32 * tile_size)
+    }
 
-            let max_x = (((stage.map.width as i32 - 1) * tile_size) - screen_width as i32) * 0x200;
-            if self.x > max_x {
-                self.x = max_x;
-            }
-        }
+    fn stage_bounds(state: &SharedGameState, stage: &Stage) -> (i32, i32, i32, i32) {
+        let tile_size = state.tile_size.as_int() * 0x200;
 
-        if (stage.map.height as usize).saturating_sub(1) * (tile_size as usize) < state.canvas_size.1 as usize {
-            self.y = -(((state.canvas_size.1 as i32 - (stage.map.height as i32 - 1) * tile_size) * 0x200) / 2);
-        } else {
-            self.y = self.target_y - (state.canvas_size.1 as i32 * 0x200 / 2);
+        Self::bounds(stage.data.camera_bounds, (stage.map.width, stage.map.height), tile_size)
+    }
 
-            if self.y < 0 {
-                self.y = 0;
-            }
+    /// Keeps the view within given range along one axis, or centers it if the range is smaller than the view.
+    fn clamp_axis(pos: i32, view_size: f32, min: i32, max: i32) -> i32 {
+        let view_size = view_size as i32 * 0x200;
 
-            let max_y = (((stage.map.height as i32 - 1) * tile_size) - state.canvas_size.1 as i32) * 0x200;
-            if self.y > max_y {
-                self.y = max_y;
-            }
+        if max - min < view_size {
+            min - (view_size - (max - min)) / 2
+        } else {
+            pos.clamp(min, max - view_size)
         }
+    }
+
+    pub fn immediate_update(&mut self, state: &mut SharedGameState, stage: &Stage) {
+        let (view_width, view_height) = Self::view_size(state, stage);
+        let (min_x, min_y, max_x, max_y) = Self::stage_bounds(state, stage);
+        let offset_x = (state.viewport_offset.0 * 512.0) as i32;
+        let offset_y = (state.viewport_offset.1 * 512.0) as i32;
+
+        let x = self.target_x - (view_width as i32 * 0x200 / 2);
+        let y = self.target_y - (view_height as i32 * 0x200 / 2);
+        self.x = Self::clamp_axis(x, view_width, min_x, max_x) - offset_x;
+        self.y = Self::clamp_axis(y, view_height, min_y, max_y) - offset_y;
 
         self.prev_x = self.x;
         self.prev_y = self.y;
     }
 
     pub fn update(&mut self, state: &mut SharedGameState, stage: &Stage) {
-        let mut screen_width = state.canvas_size.0;
-        if state.constants.is_switch && stage.map.width <= 54 {
-            screen_width += 10.0;
-        }
-
         if self.wait == 0 {
             // prevent zero division
             self.wait = 1;
         }
 
-        let tile_size = state.tile_size.as_int();
+        let (view_width, view_height) = Self::view_size(state, stage);
+        let (min_x, min_y, max_x, max_y) = Self::stage_bounds(state, stage);
+        let offset_x = (state.viewport_offset.0 * 512.0) as i32;
+        let offset_y = (state.viewport_offset.1 * 512.0) as i32;
 
-        if (stage.map.width as usize).saturating_sub(1) * (tile_size as usize) < screen_width as usize {
-            self.x = -(((screen_width as i32 - (stage.map.width as i32 - 1) * tile_size) * 0x200) / 2);
-        } else {
-            self.x += (self.target_x - (screen_width as i32 * 0x200 / 2) - self.x) / self.wait;
-
-            if self.x < 0 {
-                self.x = 0;
-            }
-
-            let max_x = (((stage.map.width as i32 - 1) * tile_size) - screen_width as i32) * 0x200;
-            if self.x > max_x {
-                self.x = max_x;
-            }
-        }
-
-        if (stage.map.height as usize).saturating_sub(1) * (tile_size as usize) < state.canvas_size.1 as usize {
-            self.y = -(((state.canvas_size.1 as i32 - (stage.map.height as i32 - 1) * tile_size) * 0x200) / 2);
-        } else {
-            self.y += (self.target_y - (state.canvas_size.1 as i32 * 0x200 / 2) - self.y) / self.wait;
-
-            if self.y < 0 {
-                self.y = 0;
-            }
-
-            let max_y = (((stage.map.height as i32 - 1) * tile_size) - state.canvas_size.1 as i32) * 0x200;
-            if self.y > max_y {
-                self.y = max_y;
-            }
-        }
+        let x = self.x + offset_x;
+        let y = self.y + offset_y;
+        let x = x + (self.target_x - (view_width as i32 * 0x200 / 2) - x) / self.wait;
+        let y = y + (self.target_y - (view_height as i32 * 0x200 / 2) - y) / self.wait;
+        self.x = Self::clamp_axis(x, view_width, min_x, max_x) - offset_x;
+        self.y = Self::clamp_axis(y, view_height, min_y, max_y) - offset_y;
 
         let intensity = state.settings.screen_shake_intensity.to_val();
 
@@ -155,3 +142,52 @@ impl Frame {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clamp_axis_centers_small_range() {
+        // 10 tiles wide stage in a 320 pixels wide view
+        let (min, max) = (0, 9 * 16 * 0x200);
+        let x = Frame::clamp_axis(0x1000, 320.0, min, max);
+
+        assert_eq!(x, -(320 * 0x200 - max) / 2);
+        assert_eq!(Frame::clamp_axis(-0x10000, 320.0, min, max), x);
+    }
+
+    #[test]
+    fn test_clamp_axis_clamps_large_range() {
+        let (min, max) = (0, 100 * 16 * 0x200);
+        let view = 320 * 0x200;
+
+        assert_eq!(Frame::clamp_axis(-0x1000, 320.0, min, max), min);
+        assert_eq!(Frame::clamp_axis(0x4000, 320.0, min, max), 0x4000);
+        assert_eq!(Frame::clamp_axis(max, 320.0, min, max), max - view);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let tile_size = 16 * 0x200;
+
+        assert_eq!(Frame::bounds(None, (40, 30), tile_size), (0, 0, 39 * tile_size, 29 * tile_size));
+        assert_eq!(Frame::bounds(None, (0, 0), tile_size), (0, 0, 0, 0));
+        assert_eq!(
+            Frame::bounds(Some((2, 3, 20, 15)), (40, 30), tile_size),
+            (2 * tile_size, 3 * tile_size, 20 * tile_size, 15 * tile_size)
+        );
+    }
+
+    #[test]
+    fn test_clamp_axis_within_camera_bounds() {
+        let tile_size = 16 * 0x200;
+        let (min_x, _, max_x, _) = Frame::bounds(Some((10, 0, 50, 10)), (100, 30), tile_size);
+
+        assert_eq!(Frame::clamp_axis(0, 320.0, min_x, max_x), min_x);
+        assert_eq!(Frame::clamp_axis(90 * tile_size, 320.0, min_x, max_x), max_x - 320 * 0x200);
+
+        let (_, min_y, _, max_y) = Frame::bounds(Some((0, 4, 99, 8)), (100, 30), tile_size);
+        assert_eq!(Frame::clamp_axis(0, 240.0, min_y, max_y), min_y - (240 * 0x200 - 4 * tile_size) / 2);
+    }
+}
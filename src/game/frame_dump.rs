@@ -0,0 +1,147 @@
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::framework::context::Context;
+use crate::framework::error::{GameError, GameResult};
+use crate::framework::graphics;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameDumpFormat {
+    /// A numbered PNG file per frame, written to a directory.
+    Png,
+    /// Raw RGBA frames appended to a single file, eg. to be piped into a video encoder.
+    Raw,
+}
+
+impl FrameDumpFormat {
+    /// Files ending with `.raw` or `.rgba` are raw video streams, anything else is a directory of PNG files.
+    pub fn from_path(path: &Path) -> FrameDumpFormat {
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+            Some("raw") | Some("rgba") => FrameDumpFormat::Raw,
+            _ => FrameDumpFormat::Png,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FrameDumpConfig {
+    pub path: PathBuf,
+    /// Defaults to the tick rate of the timing mode, so every tick ends up in exactly one frame.
+    pub fps: Option<u32>,
+}
+
+/// Writes the rendered frames to disk at a fixed frame rate. While a dump is active, the game runs
+/// exactly one tick per rendered frame, so the output doesn't depend on how fast the machine is.
+pub struct FrameDump {
+    path: PathBuf,
+    format: FrameDumpFormat,
+    fps: u32,
+    ticks: u64,
+    frames: u64,
+    size: Option<(u16, u16)>,
+    raw_writer: Option<BufWriter<fs::File>>,
+    pub captures_audio: bool,
+}
+
+impl FrameDump {
+    pub fn new(config: FrameDumpConfig, tps: usize) -> GameResult<FrameDump> {
+        let fps = config.fps.unwrap_or(tps as u32).max(1);
+        let format = FrameDumpFormat::from_path(&config.path);
+
+        let raw_writer = match format {
+            FrameDumpFormat::Png => {
+                fs::create_dir_all(&config.path)?;
+                None
+            }
+            FrameDumpFormat::Raw => Some(BufWriter::new(fs::File::create(&config.path)?)),
+        };
+
+        log::info!("Dumping frames to {} at {} fps.", config.path.display(), fps);
+
+        Ok(FrameDump {
+            path: config.path,
+            format,
+            fps,
+            ticks: 0,
+            frames: 0,
+            size: None,
+            raw_writer,
+            captures_audio: false,
+        })
+    }
+
+    /// Path of the WAV file the audio is captured to alongside the frames.
+    pub fn audio_path(&self) -> PathBuf {
+        match self.format {
+            FrameDumpFormat::Png => self.path.join("audio.wav"),
+            FrameDumpFormat::Raw => self.path.with_extension("wav"),
+        }
+    }
+
+    /// Called once per tick after the frame has been drawn, before any debug overlays. Depending on the
+    /// ratio between the tick rate and the frame rate, the frame is written zero, one or several times.
+    pub fn capture(&mut self, ctx: &mut Context, tps: usize) -> GameResult {
+        self.ticks += 1;
+
+        let target_frames = self.ticks * self.fps as u64 / tps.max(1) as u64;
+        if target_frames <= self.frames {
+            return Ok(());
+        }
+
+        let (width, height, mut data) = graphics::read_pixels(ctx)?;
+
+        match self.size {
+            Some(size) if size != (width, height) && self.format == FrameDumpFormat::Raw => {
+                return Err(GameError::RenderError(format!(
+                    "Screen size changed from {}x{} to {}x{} during raw frame dump.",
+                    size.0, size.1, width, height
+                )));
+            }
+            _ => self.size = Some((width, height)),
+        }
+
+        // the screen is opaque, but the render target might have transparent areas left
+        for pixel in data.chunks_exact_mut(4) {
+            pixel[3] = 0xff;
+        }
+
+        while self.frames < target_frames {
+            match self.format {
+                FrameDumpFormat::Png => {
+                    let path = self.path.join(format!("frame_{:06}.png", self.frames));
+                    image::save_buffer(path, &data, width as u32, height as u32, image::ColorType::Rgba8)?;
+                }
+                FrameDumpFormat::Raw => {
+                    if let Some(writer) = &mut self.raw_writer {
+                        writer.write_all(&data)?;
+                    }
+                }
+            }
+
+            self.frames += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> GameResult {
+        if let Some(writer) = &mut self.raw_writer {
+            writer.flush()?;
+        }
+
+        log::info!("Frame dump finished, {} frames written to {}.", self.frames, self.path.display());
+
+        if let (FrameDumpFormat::Raw, Some((width, height))) = (self.format, self.size) {
+            log::info!(
+                "Encode with: ffmpeg -f rawvideo -pix_fmt rgba -s {}x{} -r {} -i {}",
+                width,
+                height,
+                self.fps,
+                self.path.display()
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::Direction;
+
+/// A light emitted by an NPC, bullet, caret or tile, drawn onto the lightmap.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightSource {
+    pub color: (u8, u8, u8),
+    /// Radius in pixels.
+    #[serde(default = "default_radius")]
+    pub radius: f32,
+    /// Offset from the center of the emitter in pixels.
+    #[serde(default)]
+    pub offset: (f32, f32),
+    /// Added to the color once per flicker step, the step goes from 0 to 3.
+    #[serde(default)]
+    pub flicker: (u8, u8, u8),
+    /// Uses the animation frame as the flicker step, instead of picking it pseudo-randomly.
+    #[serde(default)]
+    pub flicker_by_anim: bool,
+    /// Casts rays which are stopped by solid tiles instead of lighting up everything around.
+    #[serde(default)]
+    pub shadows: bool,
+    /// Only emit light during these animation frames, tiles cycle through frames 0 to 3 every 8 ticks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anim_num: Option<Vec<u16>>,
+    /// Only emit light in these actions, ignored for tiles and carets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action_num: Option<Vec<u16>>,
+    /// Only emit light when facing this direction (0 - left, 1 - up, 2 - right, 3 - bottom), ignored for tiles.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direction: Option<u8>,
+}
+
+fn default_radius() -> f32 {
+    32.0
+}
+
+impl LightSource {
+    fn new(color: (u8, u8, u8), size: f32) -> Self {
+        LightSource {
+            color,
+            radius: size * 32.0,
+            offset: (0.0, 0.0),
+            flicker: (0, 0, 0),
+            flicker_by_anim: false,
+            shadows: false,
+            anim_num: None,
+            action_num: None,
+            direction: None,
+        }
+    }
+
+    fn anim(mut self, anim_num: &[u16]) -> Self {
+        self.anim_num = Some(anim_num.to_vec());
+        self
+    }
+
+    fn action(mut self, action_num: &[u16]) -> Self {
+        self.action_num = Some(action_num.to_vec());
+        self
+    }
+
+    fn facing(mut self, direction: Direction) -> Self {
+        self.direction = Some(direction as u8);
+        self
+    }
+
+    fn offset(mut self, x: f32, y: f32) -> Self {
+        self.offset = (x, y);
+        self
+    }
+
+    fn flicker(mut self, flicker: (u8, u8, u8)) -> Self {
+        self.flicker = flicker;
+        self
+    }
+
+    fn flicker_by_anim(mut self) -> Self {
+        self.flicker_by_anim = true;
+        self
+    }
+
+    pub fn matches(&self, anim_num: u16, action_num: u16, direction: Direction) -> bool {
+        self.anim_num.as_ref().map_or(true, |a| a.contains(&anim_num))
+            && self.action_num.as_ref().map_or(true, |a| a.contains(&action_num))
+            && self.direction.map_or(true, |d| d == direction as u8)
+    }
+
+    /// Same as [`LightSource::matches`] for tiles, which only have an animation frame.
+    pub fn matches_tile(&self, anim_num: u16) -> bool {
+        self.anim_num.as_ref().map_or(true, |a| a.contains(&anim_num))
+    }
+
+    /// Light color with flicker applied, `seed` should change along with the emitter's animation.
+    pub fn color(&self, seed: u16, anim_num: u16) -> (u8, u8, u8) {
+        if self.flicker == (0, 0, 0) {
+            return self.color;
+        }
+
+        let step = if self.flicker_by_anim { anim_num } else { (seed ^ 5) & 3 } as u32;
+        let add = |c: u8, flicker: u8| (c as u32 + flicker as u32 * step).min(255) as u8;
+
+        (add(self.color.0, self.flicker.0), add(self.color.1, self.flicker.1), add(self.color.2, self.flicker.2))
+    }
+
+    /// Attenuation per ray step for shadow casting lights, so rays fade out at the radius.
+    pub fn attenuation(&self) -> f32 {
+        (1.0 - 5.0 / self.radius.max(1.0)).clamp(0.5, 0.98)
+    }
+}
+
+/// Light sources of things in game, keyed by NPC type, bullet type, caret type and tile attribute.
+///
+/// Mods can override entries with a `lights.json` file with the same layout.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LightTable {
+    #[serde(default)]
+    pub npcs: HashMap<u16, Vec<LightSource>>,
+    #[serde(default)]
+    pub bullets: HashMap<u16, Vec<LightSource>>,
+    /// Used for bullets which aren't listed in `bullets`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_bullet: Option<Vec<LightSource>>,
+    #[serde(default)]
+    pub carets: HashMap<u16, Vec<LightSource>>,
+    #[serde(default)]
+    pub tiles: HashMap<u8, Vec<LightSource>>,
+}
+
+impl LightTable {
+    pub fn defaults(is_cs_plus: bool) -> Self {
+        let mut npcs = HashMap::new();
+        let white = (255, 255, 255);
+
+        npcs.insert(1, vec![LightSource::new((255, 255, 50), 0.33)]);
+        npcs.insert(4, vec![LightSource::new((200, 100, 0), 1.0).facing(Direction::Up)]);
+        npcs.insert(7, vec![LightSource::new((100, 100, 100), 1.0)]);
+        npcs.insert(
+            17,
+            vec![LightSource::new((100, 0, 0), 1.25).anim(&[0]), LightSource::new((255, 10, 10), 0.5).anim(&[0])],
+        );
+        npcs.insert(
+            20,
+            vec![
+                LightSource::new((30, 30, 130), 1.5).facing(Direction::Right),
+                LightSource::new((0, 0, 20), 1.0).facing(Direction::Right).anim(&[0, 1]),
+            ],
+        );
+        npcs.insert(22, vec![LightSource::new((0, 0, 255), 3.0).action(&[1]).anim(&[1])]);
+        npcs.insert(27, vec![LightSource::new((96, 0, 0), 3.0).offset(0.5, 0.0)]);
+        npcs.insert(32, vec![LightSource::new((255, 30, 30), 0.75)]);
+        npcs.insert(38, vec![LightSource::new((150, 60, 0), 3.5).flicker((24, 24, 0))]);
+        npcs.insert(69, vec![LightSource::new((200, 200, 200), 0.5)]);
+        npcs.insert(70, vec![LightSource::new((50, 50, 50), 2.0).flicker((15, 15, 15)).flicker_by_anim()]);
+        npcs.insert(81, vec![LightSource::new((200, 200, 200), 1.0)]);
+
+        // computer screens are green in Cave Story+
+        let screen = if is_cs_plus { ((20, 100, 20), (20, 50, 20)) } else { ((20, 20, 100), (20, 20, 50)) };
+        npcs.insert(
+            85,
+            vec![
+                LightSource::new(screen.0, 0.75).action(&[1]).facing(Direction::Left),
+                LightSource::new((150, 0, 0), 0.75).action(&[1]).facing(Direction::Right),
+                LightSource::new((50, 0, 0), 2.1).action(&[1]).anim(&[0, 1]).facing(Direction::Right).offset(0.0, -8.0),
+            ],
+        );
+        npcs.insert(87, vec![LightSource::new((255, 30, 30), 0.75)]);
+        npcs.insert(101, vec![LightSource::new((100, 100, 200), 1.0)]);
+        npcs.insert(102, vec![LightSource::new((100, 100, 200), 1.0)]);
+        npcs.insert(175, vec![LightSource::new((128, 175, 200), 1.0).action(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9])]);
+        npcs.insert(189, vec![LightSource::new((10, 50, 255), 1.0)]);
+        npcs.insert(211, vec![LightSource::new((90, 0, 0), 1.0)]);
+        npcs.insert(270, vec![LightSource::new((192, 0, 0), 0.4)]);
+        npcs.insert(285, vec![LightSource::new((150, 90, 0), 1.0)]);
+        npcs.insert(287, vec![LightSource::new((150, 90, 0), 1.0)]);
+        npcs.insert(293, vec![LightSource::new(white, 4.0)]);
+        npcs.insert(312, vec![LightSource::new(white, 0.5)]);
+        npcs.insert(
+            319,
+            vec![LightSource::new((234, 157, 68), 1.0).anim(&[0, 1]), LightSource::new((255, 29, 0), 1.0).anim(&[2])],
+        );
+
+        let mut carets = HashMap::new();
+        // projectile dissipation and shoot
+        carets.insert(2, vec![LightSource::new((150, 150, 150), 0.5)]);
+        carets.insert(3, vec![LightSource::new((150, 150, 150), 0.5)]);
+
+        LightTable {
+            npcs,
+            bullets: HashMap::new(),
+            default_bullet: Some(vec![LightSource::new((200, 200, 200), 0.3)]),
+            carets,
+            tiles: HashMap::new(),
+        }
+    }
+
+    /// Replaces entries with ones from a table loaded from a mod.
+    pub fn merge(&mut self, other: LightTable) {
+        self.npcs.extend(other.npcs);
+        self.bullets.extend(other.bullets);
+        self.carets.extend(other.carets);
+        self.tiles.extend(other.tiles);
+
+        if other.default_bullet.is_some() {
+            self.default_bullet = other.default_bullet;
+        }
+    }
+
+    pub fn bullet(&self, btype: u16) -> &[LightSource] {
+        match self.bullets.get(&btype) {
+            Some(lights) => lights,
+            None => self.default_bullet.as_deref().unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_flicker_matches_vanilla() {
+        let table = LightTable::defaults(false);
+
+        let fireplace = &table.npcs[&38][0];
+        for seed in 0..16u16 {
+            let flicker = ((seed ^ 5) & 3) as u8 * 24;
+            assert_eq!(fireplace.color(seed, 0), (150 + flicker, 60 + flicker, 0));
+        }
+
+        let sparkle = &table.npcs[&70][0];
+        for anim_num in 0..4u16 {
+            let gray = 50 + anim_num as u8 * 15;
+            assert_eq!(sparkle.color(7, anim_num), (gray, gray, gray));
+        }
+
+        assert_eq!(table.npcs[&1][0].color(3, 3), (255, 255, 50));
+    }
+
+    #[test]
+    fn test_tile_filters() {
+        let mut light = LightSource::new((100, 100, 100), 1.0);
+        light.direction = Some(2);
+        assert!((0..4).all(|anim_num| light.matches_tile(anim_num)));
+
+        light.anim_num = Some(vec![1, 2]);
+        assert!(!light.matches_tile(0));
+        assert!(light.matches_tile(1));
+        assert!(light.matches_tile(2));
+        assert!(!light.matches_tile(3));
+    }
+}
@@ -1,9 +1,9 @@
 use std::collections::HashMap;
 use std::io;
-use std::io::{BufRead, BufReader, Read};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::sync::Arc;
 
-use byteorder::{ReadBytesExt, LE};
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 
 use crate::common::{Color, Rect};
 use crate::framework::context::Context;
@@ -13,7 +13,11 @@ use crate::framework::filesystem;
 use crate::game::shared_game_state::TileSize;
 use crate::game::stage::{PxPackScroll, PxPackStageData, StageData};
 
-static SUPPORTED_PXM_VERSIONS: [u8; 1] = [0x10];
+static SUPPORTED_PXM_VERSIONS: [u8; 2] = [0x10, PXM_LAYERED_VERSION];
+/// PXM version with extra tile layers appended after the main layer.
+const PXM_LAYERED_VERSION: u8 = 0x20;
+/// Layer flag making the attributes of its tiles apply to the stage, see [`Map::get_attribute`].
+const LAYER_FLAG_COLLISION: u8 = 0x01;
 static SUPPORTED_PXE_VERSIONS: [u8; 2] = [0, 0x10];
 
 #[derive(Clone)]
@@ -23,6 +27,71 @@ pub struct Map {
     pub tiles: Vec<u8>,
     pub attrib: [u8; 0x100],
     pub tile_size: TileSize,
+    /// Extra tile layers, drawn around the main layer. Attributes of the ones with collision enabled apply
+    /// where the main layer has none.
+    pub layers: Vec<MapLayer>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MapLayerKind {
+    Background,
+    Middleground,
+    Foreground,
+}
+
+impl MapLayerKind {
+    pub fn from_u8(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::Background),
+            1 => Some(Self::Middleground),
+            2 => Some(Self::Foreground),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Background => "Background",
+            Self::Middleground => "Middleground",
+            Self::Foreground => "Foreground",
+        }
+    }
+}
+
+/// Additional tile layer of an extended PXM map, with the same size as the main layer.
+#[derive(Clone)]
+pub struct MapLayer {
+    pub kind: MapLayerKind,
+    /// Tileset used by this layer, the stage's one if empty.
+    pub tileset: String,
+    pub tiles: Vec<u8>,
+    /// Attributes of the layer's tileset, see [`Map::get_attribute`].
+    pub attrib: [u8; 0x100],
+    /// Whether the layer's tiles can be collided with, otherwise it's only decorative.
+    pub collision: bool,
+}
+
+impl MapLayer {
+    pub fn new(kind: MapLayerKind, width: u16, height: u16) -> Self {
+        MapLayer {
+            kind,
+            tileset: String::new(),
+            tiles: vec![0; width as usize * height as usize],
+            attrib: [0; 0x100],
+            collision: false,
+        }
+    }
+
+    /// Same as in the main layer, background and foreground tiles are drawn, the rest is invisible.
+    pub fn is_visible(&self, tile: u8) -> bool {
+        let attr = self.attrib[tile as usize];
+        attr < 0x20 || (0x40..0x80).contains(&attr)
+    }
+}
+
+/// Plain background, hidden and foreground tiles, which have no effect on anything touching them.
+fn is_passive_attribute(attr: u8) -> bool {
+    matches!(attr, 0x00 | 0x20 | 0x40)
 }
 
 static SOLID_TILES: [u8; 8] = [0x05, 0x41, 0x43, 0x46, 0x54, 0x55, 0x56, 0x57];
@@ -63,7 +132,76 @@ impl Map {
             log::warn!("Map attribute data is shorter than 256 bytes!");
         }
 
-        Ok(Map { width, height, tiles, attrib, tile_size: TileSize::Tile16x16 })
+        let mut layers = Vec::new();
+        if version == PXM_LAYERED_VERSION {
+            let count = map_data.read_u8()?;
+
+            for _ in 0..count {
+                let kind = MapLayerKind::from_u8(map_data.read_u8()?)
+                    .ok_or_else(|| ResourceLoadError("Invalid map layer type".to_owned()))?;
+                let flags = map_data.read_u8()?;
+
+                let mut tileset = vec![0u8; map_data.read_u8()? as usize];
+                map_data.read_exact(&mut tileset)?;
+
+                let mut layer = MapLayer::new(kind, width, height);
+                layer.tileset = String::from_utf8_lossy(&tileset).into_owned();
+                layer.collision = flags & LAYER_FLAG_COLLISION != 0;
+                map_data.read_exact(&mut layer.tiles)?;
+
+                layers.push(layer);
+            }
+
+            log::info!("Map has {} extra layers.", layers.len());
+        }
+
+        Ok(Map { width, height, tiles, attrib, tile_size: TileSize::Tile16x16, layers })
+    }
+
+    /// Loads attribute tables of the extra layers' tilesets.
+    pub fn load_layer_attributes(&mut self, ctx: &mut Context, roots: &Vec<String>, stage_tileset: &str) {
+        for layer in self.layers.iter_mut() {
+            let tileset = if layer.tileset.is_empty() { stage_tileset } else { &layer.tileset };
+
+            layer.attrib = [0; 0x100];
+            if let Ok(mut attrib_data) = filesystem::open_find(ctx, roots, ["Stage/", tileset, ".pxa"].join("")) {
+                if attrib_data.read_exact(&mut layer.attrib).is_err() {
+                    log::warn!("Map attribute data is shorter than 256 bytes!");
+                }
+            }
+        }
+    }
+
+    /// Writes the map in PXM format, extra layers are stored only if there are any.
+    pub fn write_pxm<W: Write>(&self, mut out: W) -> GameResult {
+        out.write_all(b"PXM")?;
+        out.write_u8(if self.layers.is_empty() { 0x10 } else { PXM_LAYERED_VERSION })?;
+        out.write_u16::<LE>(self.width)?;
+        out.write_u16::<LE>(self.height)?;
+        out.write_all(&self.tiles)?;
+
+        if self.layers.is_empty() {
+            return Ok(());
+        }
+
+        if self.layers.len() > u8::MAX as usize {
+            return Err(GameError::InvalidValue("Too many map layers.".to_owned()));
+        }
+
+        out.write_u8(self.layers.len() as u8)?;
+        for layer in self.layers.iter() {
+            if layer.tileset.len() > u8::MAX as usize {
+                return Err(GameError::InvalidValue(format!("Tileset name {} is too long.", layer.tileset)));
+            }
+
+            out.write_u8(layer.kind as u8)?;
+            out.write_u8(if layer.collision { LAYER_FLAG_COLLISION } else { 0 })?;
+            out.write_u8(layer.tileset.len() as u8)?;
+            out.write_all(layer.tileset.as_bytes())?;
+            out.write_all(&layer.tiles)?;
+        }
+
+        Ok(())
     }
 
     pub fn load_pxpack<R: io::Read>(
@@ -279,17 +417,33 @@ impl Map {
             offset_bg: size_fg + size_mg,
         });
 
-        Ok(Map { width: width_fg, height: height_fg, tiles, attrib, tile_size: TileSize::Tile8x8 })
+        Ok(Map { width: width_fg, height: height_fg, tiles, attrib, tile_size: TileSize::Tile8x8, layers: Vec::new() })
     }
 
+    /// Returns the attribute of the tile at given position. If the main layer's tile doesn't do anything,
+    /// the attribute of the first extra layer with collision enabled and a tile that does is used instead,
+    /// so layers can add collision, water or spikes of their own.
     pub fn get_attribute(&self, x: usize, y: usize) -> u8 {
         if x >= self.width as usize || y >= self.height as usize {
             return 0;
         }
 
-        self.attrib[*self.tiles.get(self.width as usize * y + x).unwrap_or(&0u8) as usize]
+        let index = self.width as usize * y + x;
+        let attr = self.attrib[*self.tiles.get(index).unwrap_or(&0u8) as usize];
+        if !is_passive_attribute(attr) {
+            return attr;
+        }
+
+        self.layers
+            .iter()
+            .filter(|layer| layer.collision)
+            .filter_map(|layer| layer.tiles.get(index).map(|&tile| layer.attrib[tile as usize]))
+            .find(|&layer_attr| !is_passive_attribute(layer_attr))
+            .unwrap_or(attr)
     }
 
+    /// Water tiles of layers with collision are included, but the region colors are always looked up
+    /// by the main layer's tiles.
     pub fn find_water_regions(&self, water_params: &WaterParams) -> Vec<(WaterRegionType, Rect<u16>, u8)> {
         let mut result = Vec::new();
 
@@ -613,3 +767,97 @@ impl WaterParams {
         self.entries.get(&tile).unwrap_or(&DEFAULT_ENTRY)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_map(width: u16, height: u16) -> Map {
+        let tiles = (0..width * height).map(|i| i as u8).collect();
+        Map { width, height, tiles, attrib: [0; 0x100], tile_size: TileSize::Tile16x16, layers: Vec::new() }
+    }
+
+    fn round_trip(map: &Map) -> (Vec<u8>, Map) {
+        let mut data = Vec::new();
+        map.write_pxm(&mut data).unwrap();
+        let loaded = Map::load_pxm(&data[..], &[0u8; 0x100][..]).unwrap();
+
+        (data, loaded)
+    }
+
+    #[test]
+    fn test_pxm_round_trip() {
+        let map = test_map(4, 3);
+        let (data, loaded) = round_trip(&map);
+
+        assert_eq!(&data[..4], b"PXM\x10");
+        assert_eq!(data.len(), 8 + 12);
+        assert_eq!((loaded.width, loaded.height), (4, 3));
+        assert_eq!(loaded.tiles, map.tiles);
+        assert!(loaded.layers.is_empty());
+    }
+
+    #[test]
+    fn test_pxm_round_trip_layers() {
+        let mut map = test_map(4, 3);
+
+        let mut background = MapLayer::new(MapLayerKind::Background, 4, 3);
+        background.tiles[5] = 7;
+        map.layers.push(background);
+
+        let mut foreground = MapLayer::new(MapLayerKind::Foreground, 4, 3);
+        foreground.tileset = "Cave".to_owned();
+        foreground.collision = true;
+        foreground.tiles[11] = 0xff;
+        map.layers.push(foreground);
+
+        let (data, loaded) = round_trip(&map);
+
+        assert_eq!(&data[..4], b"PXM\x20");
+        assert_eq!(loaded.tiles, map.tiles);
+        assert_eq!(loaded.layers.len(), 2);
+
+        for (loaded, layer) in loaded.layers.iter().zip(map.layers.iter()) {
+            assert_eq!(loaded.kind, layer.kind);
+            assert_eq!(loaded.tileset, layer.tileset);
+            assert_eq!(loaded.tiles, layer.tiles);
+            assert_eq!(loaded.collision, layer.collision);
+        }
+
+        // removing all layers goes back to the vanilla format
+        map.layers.clear();
+        let (data, _) = round_trip(&map);
+        assert_eq!(&data[..4], b"PXM\x10");
+    }
+
+    #[test]
+    fn test_pxm_invalid_layer_kind() {
+        let mut map = test_map(2, 2);
+        map.layers.push(MapLayer::new(MapLayerKind::Middleground, 2, 2));
+
+        let mut data = Vec::new();
+        map.write_pxm(&mut data).unwrap();
+        data[8 + 4 + 1] = 3;
+
+        assert!(Map::load_pxm(&data[..], &[0u8; 0x100][..]).is_err());
+    }
+
+    #[test]
+    fn test_layer_collision() {
+        let mut map = test_map(2, 1);
+        map.attrib[0] = 0x00;
+        map.attrib[1] = 0x41;
+
+        let mut layer = MapLayer::new(MapLayerKind::Foreground, 2, 1);
+        layer.tiles = vec![1, 1];
+        layer.attrib[1] = 0x42;
+        map.layers.push(layer);
+
+        assert_eq!(map.get_attribute(0, 0), 0x00);
+        assert_eq!(map.get_attribute(1, 0), 0x41);
+
+        map.layers[0].collision = true;
+        assert_eq!(map.get_attribute(0, 0), 0x42);
+        assert_eq!(map.get_attribute(1, 0), 0x41);
+    }
+}
@@ -15,6 +15,7 @@ use crate::framework::graphics;
 use crate::framework::graphics::VSyncMode;
 use crate::framework::ui::UI;
 use crate::game::filesystem_container::FilesystemContainer;
+use crate::game::frame_dump::FrameDumpConfig;
 use crate::game::shared_game_state::{Fps, SharedGameState, TimingMode};
 use crate::graphics::texture_set::{G_MAG, I_MAG};
 use crate::scene::loading_scene::LoadingScene;
@@ -23,21 +24,27 @@ use crate::scene::Scene;
 pub mod caret;
 pub mod filesystem_container;
 pub mod frame;
+pub mod frame_dump;
 pub mod inventory;
+pub mod lighting;
 pub mod map;
 pub mod npc;
 pub mod physics;
 pub mod player;
 pub mod profile;
+pub mod save_tool;
 pub mod scripting;
 pub mod settings;
 pub mod shared_game_state;
 pub mod stage;
+pub mod stage_tool;
 pub mod weapon;
 
 pub struct LaunchOptions {
     pub server_mode: bool,
     pub editor: bool,
+    pub capture_audio: Option<String>,
+    pub dump_frames: Option<FrameDumpConfig>,
 }
 
 lazy_static! {
@@ -79,6 +86,18 @@ impl Game {
         if let Some(scene) = &mut self.scene {
             let state_ref = unsafe { &mut *self.state.get() };
 
+            state_ref.sound_manager.poll_device(&state_ref.constants, &state_ref.settings, ctx)?;
+            state_ref.update_frame_dump(ctx);
+
+            if state_ref.frame_dump.is_some() {
+                // frame dumps advance exactly one tick per drawn frame, regardless of real time
+                scene.draw_tick(state_ref)?;
+                scene.tick(state_ref, ctx)?;
+                state_ref.sound_manager.capture_tick(state_ref.settings.timing_mode.get_capture_tps())?;
+                self.loops = 1;
+                return Ok(());
+            }
+
             let speed =
                 if state_ref.textscript_vm.mode == ScriptMode::Map && state_ref.textscript_vm.flags.cutscene_skip() {
                     4.0 * state_ref.settings.speed
@@ -114,11 +133,13 @@ impl Game {
 
                     for _ in 0..self.loops {
                         scene.tick(state_ref, ctx)?;
+                        state_ref.sound_manager.capture_tick(state_ref.settings.timing_mode.get_capture_tps())?;
                     }
                     self.fps.tick_count = self.fps.tick_count.saturating_add(self.loops as u32);
                 }
                 TimingMode::FrameSynchronized => {
                     scene.tick(state_ref, ctx)?;
+                    state_ref.sound_manager.capture_tick(state_ref.settings.timing_mode.get_capture_tps())?;
                 }
             }
         }
@@ -156,6 +177,10 @@ impl Game {
             },
         }
 
+        if state_ref.frame_dump.is_some() {
+            self.present = self.loops != 0;
+        }
+
         if !self.present {
             std::thread::sleep(Duration::from_millis(2));
             self.loops = 0;
@@ -168,7 +193,9 @@ impl Game {
             return Ok(());
         }
 
-        if state_ref.settings.timing_mode != TimingMode::FrameSynchronized {
+        if state_ref.frame_dump.is_some() {
+            state_ref.frame_time = 1.0;
+        } else if state_ref.settings.timing_mode != TimingMode::FrameSynchronized {
             let mut elapsed = self.start_time.elapsed().as_nanos();
 
             // Even with the non-monotonic Instant mitigation at the start of the event loop, there's still a chance of it not working.
@@ -192,6 +219,20 @@ impl Game {
 
         if let Some(scene) = &mut self.scene {
             scene.draw(state_ref, ctx)?;
+            state_ref.draw_viewport_bars(ctx)?;
+
+            if let Err(err) = state_ref.write_pending_save(ctx, true) {
+                log::error!("Failed to save the game: {}", err);
+            }
+
+            // dumped frames only contain the game itself, without touch controls and debug overlays
+            if let Some(frame_dump) = &mut state_ref.frame_dump {
+                if let Err(err) = frame_dump.capture(ctx, state_ref.settings.timing_mode.get_capture_tps()) {
+                    log::error!("Failed to dump frame: {}", err);
+                    state_ref.stop_frame_dump();
+                }
+            }
+
             if state_ref.settings.touch_controls && state_ref.settings.display_touch_controls {
                 state_ref.touch_controls.draw(
                     state_ref.canvas_size,
@@ -321,6 +362,14 @@ pub fn init(options: LaunchOptions) -> GameResult {
     let mut game = Box::pin(Game::new(&mut context)?);
     game.state.get_mut().fs_container = Some(fs_container);
 
+    game.state.get_mut().frame_dump_config = options.dump_frames;
+
+    if let Some(path) = &options.capture_audio {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let state = game.state.get_mut();
+        state.sound_manager.start_capture(file, &state.constants, &state.settings, &mut context, true)?;
+    }
+
     #[cfg(feature = "discord-rpc")]
     if game.state.get_mut().settings.discord_rpc {
         game.state.get_mut().discord_rpc.enabled = true;
@@ -0,0 +1,645 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::{Direction, Rect};
+use crate::game::npc::boss::BossNPC;
+use crate::game::npc::list::NPCList;
+use crate::game::npc::NPC;
+use crate::game::player::Player;
+use crate::game::shared_game_state::SharedGameState;
+
+/// Contents of a `bosses.json` file provided by a mod.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CustomBossTable {
+    #[serde(default)]
+    pub bosses: Vec<CustomBoss>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomBoss {
+    /// Boss number used in the stage table. Must not collide with vanilla bosses.
+    pub id: u16,
+    /// Spritesheet used for all parts, defaults to the second NPC sheet of the stage like vanilla bosses.
+    #[serde(default)]
+    pub spritesheet: Option<String>,
+    /// Starting position of the main part in pixels.
+    #[serde(default)]
+    pub position: (i32, i32),
+    /// Up to 20 parts, the first one is the main part.
+    pub parts: Vec<CustomBossPart>,
+    #[serde(default)]
+    pub phases: Vec<CustomBossPhase>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CustomBossPart {
+    #[serde(default)]
+    pub life: u16,
+    /// Contact damage.
+    #[serde(default)]
+    pub damage: u16,
+    #[serde(default)]
+    pub exp: u16,
+    /// Size of the death smoke, 1-3.
+    #[serde(default)]
+    pub size: u8,
+    /// Raw `npc.tbl` flags.
+    #[serde(default)]
+    pub flags: u16,
+    /// Damage dealt to this part is passed to the main part instead, like the HP bar of vanilla bosses expects.
+    #[serde(default)]
+    pub damage_boss: bool,
+    /// Event run when the part dies, requires the event_when_killed flag.
+    #[serde(default)]
+    pub event_num: u16,
+    #[serde(default)]
+    pub hurt_sound: u8,
+    #[serde(default)]
+    pub death_sound: u8,
+    /// Hitbox in pixels.
+    pub hit_bounds: Rect<u16>,
+    /// Sprite bounds in pixels, relative to the part position.
+    pub display_bounds: Rect<u16>,
+    /// Whether the part is active as soon as the boss is initialized, the main part always is.
+    #[serde(default = "default_true")]
+    pub active: bool,
+    /// Offset from the main part in pixels while facing left, mirrored when facing right.
+    #[serde(default)]
+    pub offset: (i32, i32),
+    /// The part follows the main part using [`CustomBossPart::offset`], otherwise it's moved only by scripts.
+    #[serde(default = "default_true")]
+    pub attached: bool,
+    /// Named animations the phases can switch between, `default` is used until a phase picks another one.
+    #[serde(default)]
+    pub animations: HashMap<String, CustomBossAnimation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomBossAnimation {
+    pub frames: Vec<Rect<u16>>,
+    /// Frames used while facing right, defaults to [`CustomBossAnimation::frames`].
+    #[serde(default)]
+    pub frames_right: Vec<Rect<u16>>,
+    /// Ticks per frame.
+    #[serde(default = "default_anim_speed")]
+    pub speed: u16,
+    /// Stops on the last frame instead of looping.
+    #[serde(default)]
+    pub once: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomBossPhase {
+    /// `action_num` of the main part that activates this phase, `<BOA` switches phases by this number.
+    pub action: u16,
+    /// Ticks after which the boss moves on to [`CustomBossPhase::next`], 0 means the phase doesn't time out.
+    #[serde(default)]
+    pub duration: u16,
+    #[serde(default)]
+    pub next: Option<u16>,
+    /// Switches phases once the life of the main part drops below given thresholds.
+    #[serde(default)]
+    pub transitions: Vec<CustomBossTransition>,
+    #[serde(default)]
+    pub movement: CustomBossMovement,
+    #[serde(default)]
+    pub attacks: Vec<CustomBossAttack>,
+    /// Animation picked per part index when entering the phase.
+    #[serde(default)]
+    pub animations: HashMap<usize, String>,
+    /// Parts active during this phase, parts not listed are deactivated. Leaves parts untouched if missing.
+    #[serde(default)]
+    pub active_parts: Option<Vec<usize>>,
+    /// Parts that can be shot during this phase, parts not listed become invulnerable to bullets.
+    #[serde(default)]
+    pub shootable_parts: Option<Vec<usize>>,
+    /// Sound effect played when entering the phase, 0 means none.
+    #[serde(default)]
+    pub sound: u8,
+    /// Quake duration started when entering the phase.
+    #[serde(default)]
+    pub quake: u16,
+    /// Event run when entering the phase.
+    #[serde(default)]
+    pub event: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CustomBossTransition {
+    pub life_below: u16,
+    pub action: u16,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CustomBossMovement {
+    /// Horizontal velocity set when entering the phase, positive values move forward.
+    #[serde(default)]
+    pub vel_x: Option<i32>,
+    /// Vertical velocity set when entering the phase.
+    #[serde(default)]
+    pub vel_y: Option<i32>,
+    #[serde(default)]
+    pub gravity: i32,
+    /// Horizontal acceleration towards the closest player.
+    #[serde(default)]
+    pub chase_x: i32,
+    /// Vertical acceleration towards the closest player.
+    #[serde(default)]
+    pub chase_y: i32,
+    /// Maximum speed on each axis, 0 means unlimited.
+    #[serde(default)]
+    pub max_speed: i32,
+    #[serde(default)]
+    pub face_player: bool,
+    /// Turns around when touching a wall.
+    #[serde(default)]
+    pub turn_at_walls: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomBossAttack {
+    /// NPC type spawned as the projectile.
+    pub npc: u16,
+    /// Ticks between shots.
+    pub interval: u16,
+    /// Ticks from the start of the phase before the first shot.
+    #[serde(default)]
+    pub delay: u16,
+    /// Part the projectiles are fired from.
+    #[serde(default)]
+    pub part: usize,
+    /// Offset from the part in pixels while facing left, mirrored when facing right.
+    #[serde(default)]
+    pub offset: (i32, i32),
+    /// Speed in 1/512 pixel units per tick.
+    #[serde(default)]
+    pub speed: i32,
+    /// Fire towards the closest player instead of the facing direction.
+    #[serde(default)]
+    pub aimed: bool,
+    #[serde(default = "default_count")]
+    pub count: u16,
+    /// Angle in degrees the projectiles of a single shot are spread across.
+    #[serde(default)]
+    pub spread: f32,
+    #[serde(default)]
+    pub sound: u8,
+}
+
+impl CustomBoss {
+    /// Maximum number of parts, same as the number of NPCs a boss has.
+    pub const MAX_PARTS: usize = 20;
+
+    /// Checks that phases only refer to parts, animations and other phases which exist.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.parts.is_empty() || self.parts.len() > Self::MAX_PARTS {
+            return Err(format!("must have between 1 and {} parts", Self::MAX_PARTS));
+        }
+
+        let check_part = |phase: &CustomBossPhase, part: usize, what: &str| {
+            if part < self.parts.len() {
+                Ok(())
+            } else {
+                Err(format!("phase {} refers to missing part {} in {}", phase.action, part, what))
+            }
+        };
+
+        for phase in self.phases.iter() {
+            if self.phases.iter().filter(|p| p.action == phase.action).count() > 1 {
+                return Err(format!("phase {} is defined more than once", phase.action));
+            }
+
+            let targets = phase.next.iter().chain(phase.transitions.iter().map(|t| &t.action));
+            for &target in targets {
+                if !self.phases.iter().any(|p| p.action == target) {
+                    return Err(format!("phase {} switches to phase {}, which doesn't exist", phase.action, target));
+                }
+            }
+
+            for attack in phase.attacks.iter() {
+                check_part(phase, attack.part, "an attack")?;
+            }
+
+            for (&part, name) in phase.animations.iter() {
+                check_part(phase, part, "animations")?;
+
+                if !self.parts[part].animations.contains_key(name) {
+                    return Err(format!("phase {} uses missing animation {} of part {}", phase.action, name, part));
+                }
+            }
+
+            for &part in phase.active_parts.iter().chain(phase.shootable_parts.iter()).flatten() {
+                check_part(phase, part, "active or shootable parts")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_anim_speed() -> u16 {
+    4
+}
+
+fn default_count() -> u16 {
+    1
+}
+
+/// Runtime state of a custom boss that doesn't fit into the part NPCs.
+#[derive(Default)]
+pub struct CustomBossState {
+    initialized: bool,
+    /// Action of the phase that was entered last.
+    action: Option<u16>,
+    timer: u16,
+    animations: [Option<String>; 20],
+}
+
+/// Position offset in fixed point units, mirrored horizontally when facing right.
+fn offset(offset: (i32, i32), direction: Direction) -> (i32, i32) {
+    let x = if direction == Direction::Right { -offset.0 } else { offset.0 };
+
+    (x * 0x200, offset.1 * 0x200)
+}
+
+fn px_rect(rect: Rect<u16>) -> Rect<u32> {
+    Rect::new(rect.left as u32 * 0x200, rect.top as u32 * 0x200, rect.right as u32 * 0x200, rect.bottom as u32 * 0x200)
+}
+
+impl BossNPC {
+    pub(crate) fn tick_custom(&mut self, state: &mut SharedGameState, players: [&mut Player; 2], npc_list: &NPCList) {
+        // cheap to clone, lets the definition stay around while the state is mutably borrowed
+        let Some(boss) = state.constants.custom_bosses.get(&self.boss_type).cloned() else {
+            return;
+        };
+
+        if !self.custom.initialized {
+            self.init_custom(&boss);
+        }
+
+        #[cfg(feature = "scripting")]
+        if state.npc_scripts.has_boss_handler(self.boss_type) {
+            if let Err(err) = self.tick_scripted(state, [&*players[0], &*players[1]], npc_list) {
+                log::warn!("Failed to run script of boss {}: {}", self.boss_type, err);
+            }
+        }
+
+        let (player_x, player_y) = {
+            let player = self.parts[0].get_closest_player_ref(&players);
+            (player.x, player.y)
+        };
+
+        if let Some(phase) = boss.phases.iter().find(|phase| phase.action == self.parts[0].action_num) {
+            if self.custom.action != Some(phase.action) {
+                self.enter_phase(phase, &boss, state, player_x);
+            }
+
+            self.custom.timer = self.custom.timer.saturating_add(1);
+            self.tick_phase(phase, state, npc_list, player_x, player_y);
+        }
+
+        let (main_x, main_y, direction) = (self.parts[0].x, self.parts[0].y, self.parts[0].direction);
+        for (i, def) in boss.parts.iter().enumerate().take(self.parts.len()) {
+            let part = &mut self.parts[i];
+
+            if i != 0 && def.attached {
+                let (off_x, off_y) = offset(def.offset, direction);
+                part.x = main_x + off_x;
+                part.y = main_y + off_y;
+                part.direction = direction;
+            }
+
+            let name = self.custom.animations[i].as_deref().unwrap_or("default");
+            if let Some(animation) = def.animations.get(name) {
+                let frames = if part.direction == Direction::Right && !animation.frames_right.is_empty() {
+                    &animation.frames_right
+                } else {
+                    &animation.frames
+                };
+
+                if frames.is_empty() {
+                    continue;
+                }
+
+                part.anim_counter += 1;
+                if part.anim_counter >= animation.speed.max(1) {
+                    part.anim_counter = 0;
+
+                    if !animation.once || (part.anim_num as usize) < frames.len() - 1 {
+                        part.anim_num += 1;
+                    }
+                }
+
+                part.anim_num %= frames.len() as u16;
+                part.anim_rect = frames[part.anim_num as usize];
+            }
+        }
+    }
+
+    fn init_custom(&mut self, boss: &CustomBoss) {
+        self.custom.initialized = true;
+
+        for (i, def) in boss.parts.iter().enumerate().take(self.parts.len()) {
+            let part = &mut self.parts[i];
+
+            part.life = def.life;
+            part.damage = def.damage;
+            part.exp = def.exp;
+            part.size = def.size;
+            part.event_num = def.event_num;
+            part.npc_flags.0 = def.flags;
+            part.cond.set_damage_boss(def.damage_boss);
+            part.hit_bounds = px_rect(def.hit_bounds);
+            part.display_bounds = px_rect(def.display_bounds);
+            part.direction = Direction::Left;
+
+            if i == 0 {
+                part.x = boss.position.0 * 0x200;
+                part.y = boss.position.1 * 0x200;
+            } else {
+                part.cond.set_alive(def.active);
+            }
+
+            self.hurt_sound[i] = def.hurt_sound;
+            self.death_sound[i] = def.death_sound;
+        }
+    }
+
+    fn enter_phase(&mut self, phase: &CustomBossPhase, boss: &CustomBoss, state: &mut SharedGameState, player_x: i32) {
+        self.custom.action = Some(phase.action);
+        self.custom.timer = 0;
+
+        let main = &mut self.parts[0];
+        main.action_counter = 0;
+
+        if phase.movement.face_player {
+            main.direction = if main.x > player_x { Direction::Left } else { Direction::Right };
+        }
+
+        if let Some(vel_x) = phase.movement.vel_x {
+            main.vel_x = main.direction.vector_x() * vel_x;
+        }
+
+        if let Some(vel_y) = phase.movement.vel_y {
+            main.vel_y = vel_y;
+        }
+
+        for (&i, name) in phase.animations.iter().filter(|(&i, _)| i < self.parts.len()) {
+            self.custom.animations[i] = Some(name.clone());
+            self.parts[i].anim_num = 0;
+            self.parts[i].anim_counter = 0;
+        }
+
+        for i in 0..boss.parts.len().min(self.parts.len()) {
+            if let Some(active) = &phase.active_parts {
+                // deactivating the main part would kill the boss
+                if i != 0 {
+                    self.parts[i].cond.set_alive(active.contains(&i));
+                }
+            }
+
+            if let Some(shootable) = &phase.shootable_parts {
+                let shootable = shootable.contains(&i);
+                self.parts[i].npc_flags.set_shootable(shootable);
+                self.parts[i].npc_flags.set_invulnerable(!shootable);
+            }
+        }
+
+        if phase.sound != 0 {
+            state.sound_manager.play_sfx(phase.sound);
+        }
+
+        if phase.quake != 0 {
+            state.quake_counter = phase.quake;
+        }
+
+        if let Some(event) = phase.event {
+            state.textscript_vm.start_script(event);
+        }
+    }
+
+    fn tick_phase(
+        &mut self,
+        phase: &CustomBossPhase,
+        state: &mut SharedGameState,
+        npc_list: &NPCList,
+        player_x: i32,
+        player_y: i32,
+    ) {
+        let timer = self.custom.timer;
+        let movement = &phase.movement;
+        let main = &mut self.parts[0];
+        main.action_counter = timer;
+
+        if let Some(transition) = phase.transitions.iter().find(|t| main.life < t.life_below) {
+            main.action_num = transition.action;
+            return;
+        }
+
+        if phase.duration != 0 && timer >= phase.duration {
+            if let Some(next) = phase.next {
+                main.action_num = next;
+                return;
+            }
+        }
+
+        if movement.face_player {
+            main.direction = if main.x > player_x { Direction::Left } else { Direction::Right };
+        }
+
+        if movement.turn_at_walls
+            && ((main.direction == Direction::Left && main.flags.hit_left_wall())
+                || (main.direction == Direction::Right && main.flags.hit_right_wall()))
+        {
+            main.direction = main.direction.opposite();
+            main.vel_x = -main.vel_x;
+        }
+
+        main.vel_x += movement.chase_x * (player_x - main.x).signum();
+        main.vel_y += movement.chase_y * (player_y - main.y).signum() + movement.gravity;
+
+        if movement.max_speed > 0 {
+            main.vel_x = main.vel_x.clamp(-movement.max_speed, movement.max_speed);
+            main.vel_y = main.vel_y.clamp(-movement.max_speed, movement.max_speed);
+        }
+
+        main.x += main.vel_x;
+        main.y += main.vel_y;
+
+        for attack in &phase.attacks {
+            let due = match timer.checked_sub(attack.delay) {
+                Some(elapsed) => attack.interval != 0 && elapsed % attack.interval == 0,
+                None => false,
+            };
+
+            if due {
+                self.fire_attack(attack, state, npc_list, player_x, player_y);
+            }
+        }
+    }
+
+    fn fire_attack(
+        &self,
+        attack: &CustomBossAttack,
+        state: &mut SharedGameState,
+        npc_list: &NPCList,
+        player_x: i32,
+        player_y: i32,
+    ) {
+        let Some(part) = self.parts.get(attack.part).filter(|part| part.cond.alive()) else {
+            return;
+        };
+
+        let (off_x, off_y) = offset(attack.offset, part.direction);
+        let (x, y) = (part.x + off_x, part.y + off_y);
+
+        let base_angle = if attack.aimed {
+            ((player_y - y) as f32).atan2((player_x - x) as f32)
+        } else if part.direction == Direction::Right {
+            0.0
+        } else {
+            std::f32::consts::PI
+        };
+
+        let count = attack.count.max(1);
+        for i in 0..count {
+            let angle = if count > 1 {
+                base_angle + (attack.spread * (i as f32 / (count - 1) as f32 - 0.5)).to_radians()
+            } else {
+                base_angle
+            };
+
+            let mut npc = NPC::create(attack.npc, &state.npc_table);
+            npc.cond.set_alive(true);
+            npc.x = x;
+            npc.y = y;
+            npc.vel_x = (angle.cos() * attack.speed as f32) as i32;
+            npc.vel_y = (angle.sin() * attack.speed as f32) as i32;
+            npc.direction = part.direction;
+
+            let _ = npc_list.spawn(0x100, npc);
+        }
+
+        if attack.sound != 0 {
+            state.sound_manager.play_sfx(attack.sound);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOSS: &str = r#"{
+        "bosses": [{
+            "id": 20,
+            "position": [160, 120],
+            "parts": [
+                {
+                    "life": 300,
+                    "hit_bounds": [8, 8, 8, 8],
+                    "display_bounds": [16, 16, 16, 16],
+                    "animations": {
+                        "default": { "frames": [[0, 0, 32, 32]] },
+                        "angry": { "frames": [[32, 0, 64, 32], [64, 0, 96, 32]], "speed": 2 }
+                    }
+                },
+                {
+                    "hit_bounds": [4, 4, 4, 4],
+                    "display_bounds": [8, 8, 8, 8],
+                    "active": false,
+                    "offset": [0, -16]
+                }
+            ],
+            "phases": [
+                { "action": 10, "duration": 50, "next": 20, "animations": { "0": "default" } },
+                {
+                    "action": 20,
+                    "transitions": [{ "life_below": 100, "action": 30 }],
+                    "attacks": [{ "npc": 5, "interval": 20, "part": 1 }],
+                    "active_parts": [0, 1]
+                },
+                { "action": 30, "animations": { "0": "angry" }, "shootable_parts": [1] }
+            ]
+        }]
+    }"#;
+
+    fn test_boss() -> CustomBoss {
+        let table: CustomBossTable = serde_json::from_str(BOSS).unwrap();
+        table.bosses.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let boss = test_boss();
+
+        assert_eq!(boss.id, 20);
+        assert_eq!(boss.position, (160, 120));
+        assert_eq!(boss.parts.len(), 2);
+        assert!(boss.parts[0].active);
+        assert!(!boss.parts[1].active);
+        assert!(boss.parts[1].attached);
+        assert_eq!(boss.parts[1].offset, (0, -16));
+        assert_eq!(boss.parts[0].animations["default"].speed, 4);
+        assert_eq!(boss.parts[0].animations["angry"].frames.len(), 2);
+
+        assert_eq!(boss.phases.len(), 3);
+        assert_eq!(boss.phases[0].next, Some(20));
+        assert_eq!(boss.phases[1].transitions[0].action, 30);
+        assert_eq!(boss.phases[1].attacks[0].count, 1);
+        assert_eq!(boss.phases[2].animations[&0], "angry");
+
+        assert_eq!(boss.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_phase_targets() {
+        let mut boss = test_boss();
+        boss.phases[0].next = Some(40);
+        assert!(boss.validate().is_err());
+
+        let mut boss = test_boss();
+        boss.phases[1].transitions[0].action = 40;
+        assert!(boss.validate().is_err());
+
+        let mut boss = test_boss();
+        boss.phases[2].action = 10;
+        assert!(boss.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_parts() {
+        let mut boss = test_boss();
+        boss.phases[1].attacks[0].part = 2;
+        assert!(boss.validate().is_err());
+
+        let mut boss = test_boss();
+        boss.phases[1].active_parts = Some(vec![0, 5]);
+        assert!(boss.validate().is_err());
+
+        let mut boss = test_boss();
+        boss.phases[2].shootable_parts = Some(vec![2]);
+        assert!(boss.validate().is_err());
+
+        let mut boss = test_boss();
+        boss.phases[0].animations.insert(1, "default".to_owned());
+        assert!(boss.validate().is_err());
+
+        let mut boss = test_boss();
+        boss.phases[2].animations.insert(0, "missing".to_owned());
+        assert!(boss.validate().is_err());
+
+        let mut boss = test_boss();
+        boss.parts.clear();
+        assert!(boss.validate().is_err());
+
+        let mut boss = test_boss();
+        boss.parts = vec![boss.parts[1].clone(); CustomBoss::MAX_PARTS + 1];
+        assert!(boss.validate().is_err());
+    }
+}
@@ -7,6 +7,7 @@ use crate::entity::GameEntity;
 use crate::framework::context::Context;
 use crate::framework::error::GameResult;
 use crate::game::frame::Frame;
+use crate::game::npc::boss::custom::CustomBossState;
 use crate::game::npc::list::NPCList;
 use crate::game::npc::NPC;
 use crate::game::player::Player;
@@ -17,6 +18,7 @@ use crate::game::weapon::bullet::BulletManager;
 pub mod balfrog;
 pub mod ballos;
 pub mod core;
+pub mod custom;
 pub mod heavy_press;
 pub mod ironhead;
 pub mod monster_x;
@@ -29,6 +31,7 @@ pub struct BossNPC {
     pub parts: [NPC; 20],
     pub hurt_sound: [u8; 20],
     pub death_sound: [u8; 20],
+    pub(crate) custom: CustomBossState,
 }
 
 impl BossNPC {
@@ -50,7 +53,7 @@ impl BossNPC {
 
         parts[0].cond.set_alive(true);
 
-        BossNPC { boss_type: 0, parts, hurt_sound: [0; 20], death_sound: [0; 20] }
+        BossNPC { boss_type: 0, parts, hurt_sound: [0; 20], death_sound: [0; 20], custom: CustomBossState::default() }
     }
 
     pub fn init_rng(&mut self, seed: i32) {
@@ -86,6 +89,8 @@ impl GameEntity<([&mut Player; 2], &NPCList, &mut Stage, &BulletManager, &mut Fl
             return Ok(());
         }
 
+        state.sound_manager.set_sfx_emitter(Some((self.parts[0].x, self.parts[0].y)));
+
         match self.boss_type {
             1 => self.tick_b01_omega(state, players, npc_list, bullet_manager, flash),
             2 => self.tick_b02_balfrog(state, players, npc_list),
@@ -96,9 +101,11 @@ impl GameEntity<([&mut Player; 2], &NPCList, &mut Stage, &BulletManager, &mut Fl
             7 => self.tick_b07_undead_core(state, npc_list, stage, flash),
             8 => self.tick_b08_heavy_press(state, npc_list, stage),
             9 => self.tick_b09_ballos(state, players, npc_list, flash),
-            _ => {}
+            _ => self.tick_custom(state, players, npc_list),
         }
 
+        state.sound_manager.set_sfx_emitter(None);
+
         for part in &mut self.parts {
             if part.shock > 0 {
                 part.shock -= 1;
@@ -114,11 +121,13 @@ impl GameEntity<([&mut Player; 2], &NPCList, &mut Stage, &BulletManager, &mut Fl
     }
 
     fn draw(&self, state: &mut SharedGameState, ctx: &mut Context, frame: &Frame) -> GameResult {
-        let batch = state.texture_set.get_or_load_batch(
-            ctx,
-            &state.constants,
-            &state.npc_table.stage_textures.deref().borrow().npc2,
-        )?;
+        let spritesheet = state
+            .constants
+            .custom_bosses
+            .get(&self.boss_type)
+            .and_then(|boss| boss.spritesheet.clone())
+            .unwrap_or_else(|| state.npc_table.stage_textures.deref().borrow().npc2.clone());
+        let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, &spritesheet)?;
 
         for npc in self.parts.iter().rev() {
             if !npc.cond.alive() || npc.cond.hidden() {
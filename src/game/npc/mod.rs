@@ -20,6 +20,8 @@ use crate::game::npc::boss::BossNPC;
 use crate::game::npc::list::NPCList;
 use crate::game::physics::PhysicalEntity;
 use crate::game::player::Player;
+#[cfg(feature = "scripting")]
+use crate::game::scripting::npc_scripts::NPCScriptHook;
 use crate::game::shared_game_state::SharedGameState;
 use crate::game::stage::{Stage, StageTexturePaths};
 use crate::game::weapon::bullet::BulletManager;
@@ -242,11 +244,14 @@ impl GameEntity<([&mut Player; 2], &NPCList, &mut Stage, &mut BulletManager, &mu
             &mut BossNPC,
         ),
     ) -> GameResult {
-        #[allow(unused_mut, unused_assignments)]
-            let mut npc_hook_ran = false;
+        // sounds played by the NPC are emitted from its position
+        state.sound_manager.set_sfx_emitter(Some((self.x, self.y)));
 
-        match self.npc_type {
-            _ if npc_hook_ran => Ok(()),
+        let result = match self.npc_type {
+            #[cfg(feature = "scripting")]
+            _ if state.npc_scripts.has_handler(self.npc_type, NPCScriptHook::Tick) => {
+                self.tick_scripted(state, [&*players[0], &*players[1]], npc_list)
+            }
             0 => self.tick_n000_null(),
             1 => self.tick_n001_experience(state, stage),
             2 => self.tick_n002_behemoth(state, npc_list),
@@ -619,7 +624,10 @@ impl GameEntity<([&mut Player; 2], &NPCList, &mut Stage, &mut BulletManager, &mu
             369 => self.tick_n369_gclone_curly_clone(state, players, npc_list),
             370 => self.tick_n370_second_quote(state, players, npc_list),
             _ => Ok(()),
-        }?;
+        };
+
+        state.sound_manager.set_sfx_emitter(None);
+        result?;
 
         // I don't know where the best place to put this is, but let's try putting it here
         if self.shock == 0 && self.npc_flags.show_damage() && self.popup.value != 0 {
@@ -659,6 +667,15 @@ impl GameEntity<([&mut Player; 2], &NPCList, &mut Stage, &mut BulletManager, &mu
 
         let (frame_x, frame_y) = frame.xy_interpolated(state.frame_time);
 
+        #[cfg(feature = "scripting")]
+        let anim_rect = if state.npc_scripts.has_handler(self.npc_type, NPCScriptHook::Draw) {
+            state.npc_scripts.run_draw(self).unwrap_or(self.anim_rect)
+        } else {
+            self.anim_rect
+        };
+        #[cfg(not(feature = "scripting"))]
+        let anim_rect = self.anim_rect;
+
         let final_x = interpolate_fix9_scale(self.prev_x - off_x, self.x - off_x, state.frame_time) + shock - frame_x;
         let final_y = interpolate_fix9_scale(
             self.prev_y - self.display_bounds.top as i32,
@@ -668,10 +685,10 @@ impl GameEntity<([&mut Player; 2], &NPCList, &mut Stage, &mut BulletManager, &mu
 
         if self.is_sue() && state.more_rust {
             // tint sue blue
-            batch.add_rect_tinted(final_x, final_y, (200, 200, 255, 255), &self.anim_rect);
+            batch.add_rect_tinted(final_x, final_y, (200, 200, 255, 255), &anim_rect);
             batch.draw(ctx)?;
         } else {
-            batch.add_rect(final_x, final_y, &self.anim_rect);
+            batch.add_rect(final_x, final_y, &anim_rect);
             batch.draw(ctx)?;
         }
 
@@ -679,7 +696,7 @@ impl GameEntity<([&mut Player; 2], &NPCList, &mut Stage, &mut BulletManager, &mu
             // draw crab headband
             let headband_spritesheet = Self::get_headband_spritesheet(state, &*texture_ref);
             let batch = state.texture_set.get_or_load_batch(ctx, &state.constants, headband_spritesheet.as_str())?;
-            batch.add_rect(final_x, final_y, &self.anim_rect);
+            batch.add_rect(final_x, final_y, &anim_rect);
             batch.draw(ctx)?;
         }
 
@@ -1,17 +1,45 @@
 use std::io;
+use std::io::{Cursor, Read};
 
 use byteorder::{BE, LE, ReadBytesExt, WriteBytesExt};
-use num_traits::{clamp, FromPrimitive};
+use num_traits::clamp;
 
 use crate::common::{Direction, FadeState, get_timestamp};
 use crate::framework::context::Context;
 use crate::framework::error::GameError::ResourceLoadError;
 use crate::framework::error::GameResult;
-use crate::game::player::{ControlMode, TargetPlayer};
-use crate::game::shared_game_state::{GameDifficulty, SharedGameState};
+use crate::framework::graphics;
+use crate::game::inventory::Inventory;
+use crate::game::player::{ControlMode, Player, TargetPlayer};
+use crate::game::scripting::tsc::text_script::ScriptVariable;
+use crate::game::shared_game_state::{GameDifficulty, PlayerCount, SharedGameState};
 use crate::game::weapon::{WeaponLevel, WeaponType};
 use crate::scene::game_scene::GameScene;
 
+/// Do041220
+const VANILLA_MAGIC: u64 = 0x446f303431323230;
+/// Do041115
+const VANILLA_MAGIC_OLD: u64 = 0x446f303431313135;
+/// DRSSAVE1
+const EXTENDED_MAGIC: u64 = 0x4452535341564531;
+/// Version of the extended format, bumped on incompatible changes to the container itself.
+const EXTENDED_VERSION: u16 = 1;
+/// Version written for all chunks known to this build, newer chunk versions are preserved but not interpreted.
+const CHUNK_VERSION: u16 = 1;
+
+/// Layout of a save file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveFormat {
+    /// Original `Profile.dat` layout, limited to 8 weapons, 32 items, 8 teleporter slots and 8000 flags.
+    Vanilla,
+    /// Vanilla layout followed by the Cave Story+ trailer holding the save timestamp and difficulty.
+    CSPlus,
+    /// Versioned chunks without the vanilla limits, also storing TSC variables, co-op state, play time,
+    /// a thumbnail and arbitrary mod data.
+    Extended,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct WeaponData {
     pub weapon_id: u32,
     pub level: u32,
@@ -20,11 +48,92 @@ pub struct WeaponData {
     pub ammo: u32,
 }
 
+#[derive(Debug, Clone, Default)]
 pub struct TeleporterSlotData {
     pub index: u32,
     pub event_num: u32,
 }
 
+/// State of the second player in co-op, only stored in the extended format.
+#[derive(Debug, Clone)]
+pub struct CoopPlayerData {
+    pub pos_x: i32,
+    pub pos_y: i32,
+    pub direction: Direction,
+    pub max_life: u16,
+    pub stars: u16,
+    pub life: u16,
+    pub current_weapon: u32,
+    pub current_item: u32,
+    pub equipment: u32,
+    pub weapon_data: Vec<WeaponData>,
+    pub items: Vec<u32>,
+}
+
+/// Screenshot of the game at the time of saving, RGBA8.
+#[derive(Debug, Clone)]
+pub struct SaveThumbnail {
+    pub width: u16,
+    pub height: u16,
+    pub data: Vec<u8>,
+}
+
+impl SaveThumbnail {
+    /// Width of captured thumbnails, the height follows the aspect ratio of the screen.
+    pub const WIDTH: u16 = 128;
+
+    /// Downscales the frame drawn so far, has to be called before it's presented.
+    pub fn capture(ctx: &mut Context) -> GameResult<SaveThumbnail> {
+        let (src_width, src_height, src) = graphics::read_pixels(ctx)?;
+        if src_width == 0 || src_height == 0 {
+            return Err(ResourceLoadError("Screen is empty.".to_owned()));
+        }
+
+        Ok(SaveThumbnail::downscale(src_width, src_height, &src))
+    }
+
+    /// Scales an RGBA8 image down to [`SaveThumbnail::WIDTH`], smaller images keep their size.
+    pub fn downscale(src_width: u16, src_height: u16, src: &[u8]) -> SaveThumbnail {
+        let width = SaveThumbnail::WIDTH.min(src_width);
+        let height = ((src_height as u32 * width as u32 / src_width as u32) as u16).max(1);
+        let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+
+        // box filter, averages all source pixels covered by the destination pixel
+        for y in 0..height as usize {
+            let y0 = y * src_height as usize / height as usize;
+            let y1 = ((y + 1) * src_height as usize / height as usize).max(y0 + 1);
+
+            for x in 0..width as usize {
+                let x0 = x * src_width as usize / width as usize;
+                let x1 = ((x + 1) * src_width as usize / width as usize).max(x0 + 1);
+                let mut sum = [0u32; 3];
+
+                for sy in y0..y1 {
+                    for sx in x0..x1 {
+                        let idx = (sy * src_width as usize + sx) * 4;
+                        sum[0] += src[idx] as u32;
+                        sum[1] += src[idx + 1] as u32;
+                        sum[2] += src[idx + 2] as u32;
+                    }
+                }
+
+                let count = ((y1 - y0) * (x1 - x0)) as u32;
+                data.extend_from_slice(&[(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8, 0xff]);
+            }
+        }
+
+        SaveThumbnail { width, height, data }
+    }
+}
+
+/// A chunk this build doesn't understand, kept as-is so mod data and data written by newer versions survives saving.
+#[derive(Debug, Clone)]
+pub struct SaveChunk {
+    pub id: [u8; 4],
+    pub version: u16,
+    pub data: Vec<u8>,
+}
+
 pub struct GameProfile {
     pub current_map: u32,
     pub current_song: u32,
@@ -39,13 +148,84 @@ pub struct GameProfile {
     pub equipment: u32,
     pub control_mode: u32,
     pub counter: u32,
-    pub weapon_data: [WeaponData; 8],
-    pub items: [u32; 32],
-    pub teleporter_slots: [TeleporterSlotData; 8],
+    pub weapon_data: Vec<WeaponData>,
+    pub items: Vec<u32>,
+    pub teleporter_slots: Vec<TeleporterSlotData>,
     pub map_flags: [u8; 128],
-    pub flags: [u8; 1000],
+    pub flags: Vec<u8>,
     pub timestamp: u64,
     pub difficulty: u8,
+    pub variables: Vec<(ScriptVariable, i32)>,
+    pub player2: Option<CoopPlayerData>,
+    /// Play time in seconds.
+    pub play_time: u32,
+    pub thumbnail: Option<SaveThumbnail>,
+    /// Name of the current stage, shown in the save menu if the stage table doesn't have the map.
+    pub stage_name: Option<String>,
+    pub extra_chunks: Vec<SaveChunk>,
+}
+
+fn apply_inventory(
+    weapon_data: &[WeaponData],
+    items: &[u32],
+    inventory: &mut Inventory,
+    state: &mut SharedGameState,
+    ctx: &mut Context,
+) {
+    for weapon in weapon_data {
+        if weapon.weapon_id == 0 {
+            continue;
+        }
+
+        let _ = state.mod_requirements.append_weapon(ctx, weapon.weapon_id as u16);
+        let weapon_type = WeaponType::from_id(weapon.weapon_id as u8, &state.constants);
+
+        if let Some(wtype) = weapon_type {
+            inventory.add_weapon_data(
+                wtype,
+                weapon.ammo as u16,
+                weapon.max_ammo as u16,
+                weapon.exp as u16,
+                match weapon.level {
+                    2 => WeaponLevel::Level2,
+                    3 => WeaponLevel::Level3,
+                    _ => WeaponLevel::Level1,
+                },
+            );
+        }
+    }
+
+    for item in items.iter().copied() {
+        let item_id = item as u16;
+        let _ = state.mod_requirements.append_item(ctx, item_id);
+
+        let amount = (item >> 16) as u16;
+        if item_id == 0 {
+            break;
+        }
+
+        inventory.add_item_amount(item_id, amount + 1);
+    }
+}
+
+fn dump_inventory(inventory: &Inventory) -> (Vec<WeaponData>, Vec<u32>) {
+    let weapon_data = (0..inventory.get_weapon_count())
+        .filter_map(|idx| inventory.get_weapon(idx))
+        .map(|weapon| WeaponData {
+            weapon_id: weapon.wtype.id() as u32,
+            level: weapon.level as u32,
+            exp: weapon.experience as u32,
+            max_ammo: weapon.max_ammo as u32,
+            ammo: weapon.ammo as u32,
+        })
+        .collect();
+
+    let items = (0..)
+        .map_while(|idx| inventory.get_item_idx(idx))
+        .map(|item| item.0 as u32 + (((item.1 - 1) as u32) << 16))
+        .collect();
+
+    (weapon_data, items)
 }
 
 impl GameProfile {
@@ -58,40 +238,7 @@ impl GameProfile {
 
         game_scene.inventory_player1.current_weapon = self.current_weapon as u16;
         game_scene.inventory_player1.current_item = self.current_item as u16;
-        for weapon in &self.weapon_data {
-            if weapon.weapon_id == 0 {
-                continue;
-            }
-
-            let _ = state.mod_requirements.append_weapon(ctx, weapon.weapon_id as u16);
-            let weapon_type: Option<WeaponType> = FromPrimitive::from_u8(weapon.weapon_id as u8);
-
-            if let Some(wtype) = weapon_type {
-                game_scene.inventory_player1.add_weapon_data(
-                    wtype,
-                    weapon.ammo as u16,
-                    weapon.max_ammo as u16,
-                    weapon.exp as u16,
-                    match weapon.level {
-                        2 => WeaponLevel::Level2,
-                        3 => WeaponLevel::Level3,
-                        _ => WeaponLevel::Level1,
-                    },
-                );
-            }
-        }
-
-        for item in self.items.iter().copied() {
-            let item_id = item as u16;
-            let _ = state.mod_requirements.append_item(ctx, item_id);
-
-            let amount = (item >> 16) as u16;
-            if item_id == 0 {
-                break;
-            }
-
-            game_scene.inventory_player1.add_item_amount(item_id, amount + 1);
-        }
+        apply_inventory(&self.weapon_data, &self.items, &mut game_scene.inventory_player1, state, ctx);
 
         for slot in &self.teleporter_slots {
             if slot.event_num == 0 {
@@ -106,32 +253,16 @@ impl GameProfile {
         }
 
         for (idx, &flags) in self.flags.iter().enumerate() {
-            if flags & 0b00000001 != 0 {
-                state.set_flag(idx * 8, true);
-            }
-            if flags & 0b00000010 != 0 {
-                state.set_flag(idx * 8 + 1, true);
-            }
-            if flags & 0b00000100 != 0 {
-                state.set_flag(idx * 8 + 2, true);
-            }
-            if flags & 0b00001000 != 0 {
-                state.set_flag(idx * 8 + 3, true);
-            }
-            if flags & 0b00010000 != 0 {
-                state.set_flag(idx * 8 + 4, true);
-            }
-            if flags & 0b00100000 != 0 {
-                state.set_flag(idx * 8 + 5, true);
-            }
-            if flags & 0b01000000 != 0 {
-                state.set_flag(idx * 8 + 6, true);
-            }
-            if flags & 0b10000000 != 0 {
-                state.set_flag(idx * 8 + 7, true);
+            for bit in 0..8 {
+                if flags & (1 << bit) != 0 {
+                    state.set_flag(idx * 8 + bit, true);
+                }
             }
         }
 
+        state.script_variables = self.variables.iter().cloned().collect();
+        state.play_time = self.play_time as u64 * state.settings.timing_mode.get_capture_tps() as u64;
+
         state.textscript_vm.start_script(0);
 
         game_scene.player1.equip.0 = self.equipment as u16;
@@ -149,6 +280,21 @@ impl GameProfile {
         game_scene.player2 = game_scene.player1.clone();
         game_scene.inventory_player2 = game_scene.inventory_player1.clone();
 
+        if let Some(player2) = &self.player2 {
+            game_scene.inventory_player2 = Inventory::new();
+            game_scene.inventory_player2.current_weapon = player2.current_weapon as u16;
+            game_scene.inventory_player2.current_item = player2.current_item as u16;
+            apply_inventory(&player2.weapon_data, &player2.items, &mut game_scene.inventory_player2, state, ctx);
+
+            game_scene.player2.equip.0 = player2.equipment as u16;
+            game_scene.player2.x = player2.pos_x;
+            game_scene.player2.y = player2.pos_y;
+            game_scene.player2.direction = player2.direction;
+            game_scene.player2.life = player2.life;
+            game_scene.player2.max_life = player2.max_life;
+            game_scene.player2.stars = clamp(player2.stars, 0, 3) as u8;
+        }
+
         game_scene.player1.cond.0 = 0x80;
 
         state.difficulty = GameDifficulty::from_primitive(self.difficulty);
@@ -158,14 +304,14 @@ impl GameProfile {
     }
 
     pub fn dump(state: &mut SharedGameState, game_scene: &mut GameScene, target_player: Option<TargetPlayer>) -> GameProfile {
-        let player = match target_player.unwrap_or(TargetPlayer::Player1) {
-            TargetPlayer::Player1 => &game_scene.player1,
-            TargetPlayer::Player2 => &game_scene.player2,
-        };
-
-        let inventory_player = match target_player.unwrap_or(TargetPlayer::Player1) {
-            TargetPlayer::Player1 => &game_scene.inventory_player1,
-            TargetPlayer::Player2 => &game_scene.inventory_player2,
+        let target_player = target_player.unwrap_or(TargetPlayer::Player1);
+        let (player, inventory_player, partner, partner_inventory) = match target_player {
+            TargetPlayer::Player1 => {
+                (&game_scene.player1, &game_scene.inventory_player1, &game_scene.player2, &game_scene.inventory_player2)
+            }
+            TargetPlayer::Player2 => {
+                (&game_scene.player2, &game_scene.inventory_player2, &game_scene.player1, &game_scene.inventory_player1)
+            }
         };
 
         let current_map = game_scene.stage_id as u32;
@@ -181,50 +327,13 @@ impl GameProfile {
         let equipment = player.equip.0 as u32;
         let control_mode = player.control_mode as u32;
         let counter = 0; // TODO
-        let mut weapon_data = [
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-        ];
-        let mut items = [0u32; 32];
-        let mut teleporter_slots = [
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-        ];
-
-        for (idx, weap) in weapon_data.iter_mut().enumerate() {
-            if let Some(weapon) = inventory_player.get_weapon(idx) {
-                weap.weapon_id = weapon.wtype as u32;
-                weap.level = weapon.level as u32;
-                weap.exp = weapon.experience as u32;
-                weap.max_ammo = weapon.max_ammo as u32;
-                weap.ammo = weapon.ammo as u32;
-            }
-        }
-
-        for (idx, item) in items.iter_mut().enumerate() {
-            if let Some(sitem) = inventory_player.get_item_idx(idx) {
-                *item = sitem.0 as u32 + (((sitem.1 - 1) as u32) << 16);
-            }
-        }
+        let (weapon_data, items) = dump_inventory(inventory_player);
 
-        for (idx, slot) in teleporter_slots.iter_mut().enumerate() {
-            if let Some(&(index, event_num)) = state.teleporter_slots.get(idx) {
-                slot.index = index as u32;
-                slot.event_num = event_num as u32;
-            }
-        }
+        let teleporter_slots = state
+            .teleporter_slots
+            .iter()
+            .map(|&(index, event_num)| TeleporterSlotData { index: index as u32, event_num: event_num as u32 })
+            .collect();
 
         let mut map_flags = [0u8; 128];
         for (idx, map_flag) in state.map_flags.iter().enumerate() {
@@ -235,12 +344,24 @@ impl GameProfile {
             }
         }
 
-        let mut flags = [0u8; 1000];
+        let mut flags = vec![0u8; state.game_flags.len() / 8];
         state.game_flags.copy_to_slice(&mut flags);
 
         let timestamp = get_timestamp();
         let difficulty = state.difficulty as u8;
 
+        let mut variables: Vec<_> = state.script_variables.iter().map(|(k, &v)| (k.clone(), v)).collect();
+        variables.sort();
+
+        let player2 = if state.player_count == PlayerCount::Two {
+            Some(Self::dump_coop_player(partner, partner_inventory))
+        } else {
+            None
+        };
+
+        let play_time = (state.play_time / state.settings.timing_mode.get_capture_tps() as u64) as u32;
+        let stage_name = state.stages.get(game_scene.stage_id).map(|stage| stage.name.clone());
+
         GameProfile {
             current_map,
             current_song,
@@ -262,11 +383,49 @@ impl GameProfile {
             flags,
             timestamp,
             difficulty,
+            variables,
+            player2,
+            play_time,
+            thumbnail: None,
+            stage_name,
+            extra_chunks: Vec::new(),
+        }
+    }
+
+    fn dump_coop_player(player: &Player, inventory: &Inventory) -> CoopPlayerData {
+        let (weapon_data, items) = dump_inventory(inventory);
+
+        CoopPlayerData {
+            pos_x: player.x,
+            pos_y: player.y,
+            direction: player.direction,
+            max_life: player.max_life,
+            stars: player.stars as u16,
+            life: player.life,
+            current_weapon: inventory.current_weapon as u32,
+            current_item: inventory.current_item as u32,
+            equipment: player.equip.0 as u32,
+            weapon_data,
+            items,
         }
     }
 
-    pub fn write_save<W: io::Write>(&self, mut data: W) -> GameResult {
-        data.write_u64::<BE>(0x446f303431323230)?;
+    /// Writes the save in the extended format, use the save tool to export it for other versions of the game.
+    pub fn write_save<W: io::Write>(&self, data: W) -> GameResult {
+        self.write_save_as(data, SaveFormat::Extended)
+    }
+
+    /// Writes the save in given format, data not representable in the vanilla formats is dropped.
+    pub fn write_save_as<W: io::Write>(&self, mut data: W, format: SaveFormat) -> GameResult {
+        match format {
+            SaveFormat::Vanilla => self.write_vanilla(&mut data, false),
+            SaveFormat::CSPlus => self.write_vanilla(&mut data, true),
+            SaveFormat::Extended => self.write_extended(&mut data),
+        }
+    }
+
+    fn write_vanilla<W: io::Write>(&self, data: &mut W, cs_plus: bool) -> GameResult {
+        data.write_u64::<BE>(VANILLA_MAGIC)?;
 
         data.write_u32::<LE>(self.current_map)?;
         data.write_u32::<LE>(self.current_song)?;
@@ -283,7 +442,8 @@ impl GameProfile {
         data.write_u32::<LE>(self.control_mode)?;
         data.write_u32::<LE>(self.counter)?;
 
-        for weapon in &self.weapon_data {
+        for idx in 0..8 {
+            let weapon = self.weapon_data.get(idx).cloned().unwrap_or_default();
             data.write_u32::<LE>(weapon.weapon_id)?;
             data.write_u32::<LE>(weapon.level)?;
             data.write_u32::<LE>(weapon.exp)?;
@@ -291,36 +451,141 @@ impl GameProfile {
             data.write_u32::<LE>(weapon.ammo)?;
         }
 
-        for item in self.items.iter().copied() {
-            data.write_u32::<LE>(item)?;
+        for idx in 0..32 {
+            data.write_u32::<LE>(self.items.get(idx).copied().unwrap_or(0))?;
         }
 
-        for slot in &self.teleporter_slots {
+        for idx in 0..8 {
+            let slot = self.teleporter_slots.get(idx).cloned().unwrap_or_default();
             data.write_u32::<LE>(slot.index)?;
             data.write_u32::<LE>(slot.event_num)?;
         }
 
         let something = [0u8; 0x80];
-        data.write(&something)?;
+        data.write_all(&something)?;
 
         data.write_u32::<BE>(0x464c4147)?;
-        data.write(&self.flags)?;
+        let mut flags = [0u8; 1000];
+        for (out, &flag) in flags.iter_mut().zip(self.flags.iter()) {
+            *out = flag;
+        }
+        data.write_all(&flags)?;
 
-        data.write_u32::<LE>(0)?; // unused(?) CS+ space
+        if cs_plus {
+            data.write_u32::<LE>(0)?; // unused(?) CS+ space
 
-        data.write_u64::<LE>(self.timestamp)?;
-        data.write_u8(self.difficulty)?;
+            data.write_u64::<LE>(self.timestamp)?;
+            data.write_u8(self.difficulty)?;
+        }
 
         Ok(())
     }
 
-    pub fn load_from_save<R: io::Read>(mut data: R) -> GameResult<GameProfile> {
+    fn write_extended<W: io::Write>(&self, data: &mut W) -> GameResult {
+        data.write_u64::<BE>(EXTENDED_MAGIC)?;
+        data.write_u16::<LE>(EXTENDED_VERSION)?;
+
+        let mut chunk = Vec::new();
+
+        chunk.write_u32::<LE>(self.current_map)?;
+        chunk.write_u32::<LE>(self.current_song)?;
+        chunk.write_i32::<LE>(self.pos_x)?;
+        chunk.write_i32::<LE>(self.pos_y)?;
+        chunk.write_u32::<LE>(self.direction as u32)?;
+        chunk.write_u16::<LE>(self.max_life)?;
+        chunk.write_u16::<LE>(self.stars)?;
+        chunk.write_u16::<LE>(self.life)?;
+        chunk.write_u32::<LE>(self.current_weapon)?;
+        chunk.write_u32::<LE>(self.current_item)?;
+        chunk.write_u32::<LE>(self.equipment)?;
+        chunk.write_u32::<LE>(self.control_mode)?;
+        chunk.write_u32::<LE>(self.counter)?;
+        chunk.write_u64::<LE>(self.timestamp)?;
+        chunk.write_u8(self.difficulty)?;
+        write_chunk(data, b"PROF", &mut chunk)?;
+
+        write_weapons(&mut chunk, &self.weapon_data)?;
+        write_chunk(data, b"WEAP", &mut chunk)?;
+
+        write_items(&mut chunk, &self.items)?;
+        write_chunk(data, b"ITEM", &mut chunk)?;
+
+        chunk.write_u32::<LE>(self.teleporter_slots.len() as u32)?;
+        for slot in &self.teleporter_slots {
+            chunk.write_u32::<LE>(slot.index)?;
+            chunk.write_u32::<LE>(slot.event_num)?;
+        }
+        write_chunk(data, b"TELE", &mut chunk)?;
+
+        chunk.extend_from_slice(&self.map_flags);
+        write_chunk(data, b"MFLG", &mut chunk)?;
+
+        // trailing unset flags are implied
+        let flags_len = self.flags.iter().rposition(|&f| f != 0).map_or(0, |i| i + 1);
+        chunk.extend_from_slice(&self.flags[..flags_len]);
+        write_chunk(data, b"FLAG", &mut chunk)?;
+
+        if !self.variables.is_empty() {
+            write_variables(&mut chunk, &self.variables)?;
+            write_chunk(data, b"VARS", &mut chunk)?;
+        }
+
+        if let Some(player2) = &self.player2 {
+            chunk.write_i32::<LE>(player2.pos_x)?;
+            chunk.write_i32::<LE>(player2.pos_y)?;
+            chunk.write_u32::<LE>(player2.direction as u32)?;
+            chunk.write_u16::<LE>(player2.max_life)?;
+            chunk.write_u16::<LE>(player2.stars)?;
+            chunk.write_u16::<LE>(player2.life)?;
+            chunk.write_u32::<LE>(player2.current_weapon)?;
+            chunk.write_u32::<LE>(player2.current_item)?;
+            chunk.write_u32::<LE>(player2.equipment)?;
+            write_weapons(&mut chunk, &player2.weapon_data)?;
+            write_items(&mut chunk, &player2.items)?;
+            write_chunk(data, b"PLR2", &mut chunk)?;
+        }
+
+        chunk.write_u32::<LE>(self.play_time)?;
+        write_chunk(data, b"TIME", &mut chunk)?;
+
+        if let Some(thumbnail) = &self.thumbnail {
+            chunk.write_u16::<LE>(thumbnail.width)?;
+            chunk.write_u16::<LE>(thumbnail.height)?;
+            chunk.extend_from_slice(&thumbnail.data);
+            write_chunk(data, b"THMB", &mut chunk)?;
+        }
+
+        if let Some(stage_name) = &self.stage_name {
+            chunk.extend_from_slice(stage_name.as_bytes());
+            write_chunk(data, b"STGN", &mut chunk)?;
+        }
+
+        for extra in &self.extra_chunks {
+            data.write_all(&extra.id)?;
+            data.write_u16::<LE>(extra.version)?;
+            data.write_u32::<LE>(extra.data.len() as u32)?;
+            data.write_all(&extra.data)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn load_from_save<R: io::Read>(data: R) -> GameResult<GameProfile> {
+        GameProfile::load_from_save_with_format(data).map(|(profile, _)| profile)
+    }
+
+    /// Loads a save in any supported format, also returns the detected format.
+    pub fn load_from_save_with_format<R: io::Read>(mut data: R) -> GameResult<(GameProfile, SaveFormat)> {
         let magic = data.read_u64::<BE>()?;
-        // Do041220, Do041115
-        if magic != 0x446f303431323230 && magic != 0x446f303431313135 {
-            return Err(ResourceLoadError("Invalid magic".to_owned()));
+
+        match magic {
+            EXTENDED_MAGIC => Ok((GameProfile::load_extended(data)?, SaveFormat::Extended)),
+            VANILLA_MAGIC | VANILLA_MAGIC_OLD => GameProfile::load_vanilla(data),
+            _ => Err(ResourceLoadError("Invalid magic".to_owned())),
         }
+    }
 
+    fn load_vanilla<R: io::Read>(mut data: R) -> GameResult<(GameProfile, SaveFormat)> {
         let current_map = data.read_u32::<LE>()?;
         let current_song = data.read_u32::<LE>()?;
         let pos_x = data.read_i32::<LE>()?;
@@ -335,27 +600,9 @@ impl GameProfile {
         let equipment = data.read_u32::<LE>()?;
         let control_mode = data.read_u32::<LE>()?;
         let counter = data.read_u32::<LE>()?;
-        let mut weapon_data = [
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-            WeaponData { weapon_id: 0, level: 0, exp: 0, max_ammo: 0, ammo: 0 },
-        ];
-        let mut items = [0u32; 32];
-        let mut teleporter_slots = [
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-            TeleporterSlotData { index: 0, event_num: 0 },
-        ];
+        let mut weapon_data = vec![WeaponData::default(); 8];
+        let mut items = vec![0u32; 32];
+        let mut teleporter_slots = vec![TeleporterSlotData::default(); 8];
 
         for WeaponData { weapon_id, level, exp, max_ammo, ammo } in &mut weapon_data {
             *weapon_id = data.read_u32::<LE>()?;
@@ -381,15 +628,15 @@ impl GameProfile {
             return Err(ResourceLoadError("Invalid FLAG signature".to_owned()));
         }
 
-        let mut flags = [0u8; 1000];
+        let mut flags = vec![0u8; 1000];
         data.read_exact(&mut flags)?;
 
-        data.read_u32::<LE>().unwrap_or(0); // unused(?) CS+ space
+        let format = if data.read_u32::<LE>().is_ok() { SaveFormat::CSPlus } else { SaveFormat::Vanilla }; // unused(?) CS+ space
 
         let timestamp = data.read_u64::<LE>().unwrap_or(0);
         let difficulty = data.read_u8().unwrap_or(0);
 
-        Ok(GameProfile {
+        let profile = GameProfile {
             current_map,
             current_song,
             pos_x,
@@ -410,6 +657,414 @@ impl GameProfile {
             flags,
             timestamp,
             difficulty,
-        })
+            variables: Vec::new(),
+            player2: None,
+            play_time: 0,
+            thumbnail: None,
+            stage_name: None,
+            extra_chunks: Vec::new(),
+        };
+
+        Ok((profile, format))
+    }
+
+    fn load_extended<R: io::Read>(mut reader: R) -> GameResult<GameProfile> {
+        // read upfront, so chunk lengths can be checked against the size of the file
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        let mut data = Cursor::new(buf.as_slice());
+
+        let version = data.read_u16::<LE>()?;
+        if version > EXTENDED_VERSION {
+            return Err(ResourceLoadError(format!("Unsupported save version {}", version)));
+        }
+
+        let mut profile = GameProfile {
+            current_map: 0,
+            current_song: 0,
+            pos_x: 0,
+            pos_y: 0,
+            direction: Direction::Left,
+            max_life: 0,
+            stars: 0,
+            life: 0,
+            current_weapon: 0,
+            current_item: 0,
+            equipment: 0,
+            control_mode: 0,
+            counter: 0,
+            weapon_data: Vec::new(),
+            items: Vec::new(),
+            teleporter_slots: Vec::new(),
+            map_flags: [0; 128],
+            flags: Vec::new(),
+            timestamp: 0,
+            difficulty: 0,
+            variables: Vec::new(),
+            player2: None,
+            play_time: 0,
+            thumbnail: None,
+            stage_name: None,
+            extra_chunks: Vec::new(),
+        };
+        let mut has_profile = false;
+
+        loop {
+            let mut id = [0u8; 4];
+            match data.read_exact(&mut id) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            }
+
+            let chunk_version = data.read_u16::<LE>()?;
+            let chunk_len = data.read_u32::<LE>()? as usize;
+            if chunk_len > buf.len() - data.position() as usize {
+                return Err(ResourceLoadError(format!("Save chunk {} is truncated", chunk_id(&id))));
+            }
+
+            let mut chunk = vec![0u8; chunk_len];
+            data.read_exact(&mut chunk)?;
+
+            if chunk_version > CHUNK_VERSION {
+                log::warn!("Unsupported version {} of save chunk {}, keeping it as-is.", chunk_version, chunk_id(&id));
+                profile.extra_chunks.push(SaveChunk { id, version: chunk_version, data: chunk });
+                continue;
+            }
+
+            let mut cur = Cursor::new(chunk.as_slice());
+
+            match &id {
+                b"PROF" => {
+                    profile.current_map = cur.read_u32::<LE>()?;
+                    profile.current_song = cur.read_u32::<LE>()?;
+                    profile.pos_x = cur.read_i32::<LE>()?;
+                    profile.pos_y = cur.read_i32::<LE>()?;
+                    profile.direction = Direction::from_int(cur.read_u32::<LE>()? as usize).unwrap_or(Direction::Left);
+                    profile.max_life = cur.read_u16::<LE>()?;
+                    profile.stars = cur.read_u16::<LE>()?;
+                    profile.life = cur.read_u16::<LE>()?;
+                    profile.current_weapon = cur.read_u32::<LE>()?;
+                    profile.current_item = cur.read_u32::<LE>()?;
+                    profile.equipment = cur.read_u32::<LE>()?;
+                    profile.control_mode = cur.read_u32::<LE>()?;
+                    profile.counter = cur.read_u32::<LE>()?;
+                    profile.timestamp = cur.read_u64::<LE>()?;
+                    profile.difficulty = cur.read_u8()?;
+                    has_profile = true;
+                }
+                b"WEAP" => profile.weapon_data = read_weapons(&mut cur)?,
+                b"ITEM" => profile.items = read_items(&mut cur)?,
+                b"TELE" => {
+                    let count = cur.read_u32::<LE>()?;
+                    for _ in 0..count {
+                        let index = cur.read_u32::<LE>()?;
+                        let event_num = cur.read_u32::<LE>()?;
+                        profile.teleporter_slots.push(TeleporterSlotData { index, event_num });
+                    }
+                }
+                b"MFLG" => {
+                    for (out, &flag) in profile.map_flags.iter_mut().zip(chunk.iter()) {
+                        *out = flag;
+                    }
+                }
+                b"FLAG" => profile.flags = chunk.clone(),
+                b"VARS" => profile.variables = read_variables(&mut cur)?,
+                b"PLR2" => {
+                    profile.player2 = Some(CoopPlayerData {
+                        pos_x: cur.read_i32::<LE>()?,
+                        pos_y: cur.read_i32::<LE>()?,
+                        direction: Direction::from_int(cur.read_u32::<LE>()? as usize).unwrap_or(Direction::Left),
+                        max_life: cur.read_u16::<LE>()?,
+                        stars: cur.read_u16::<LE>()?,
+                        life: cur.read_u16::<LE>()?,
+                        current_weapon: cur.read_u32::<LE>()?,
+                        current_item: cur.read_u32::<LE>()?,
+                        equipment: cur.read_u32::<LE>()?,
+                        weapon_data: read_weapons(&mut cur)?,
+                        items: read_items(&mut cur)?,
+                    });
+                }
+                b"TIME" => profile.play_time = cur.read_u32::<LE>()?,
+                b"THMB" => {
+                    let width = cur.read_u16::<LE>()?;
+                    let height = cur.read_u16::<LE>()?;
+                    let pixels = chunk[4..].to_vec();
+
+                    if pixels.len() == width as usize * height as usize * 4 {
+                        profile.thumbnail = Some(SaveThumbnail { width, height, data: pixels });
+                    } else {
+                        log::warn!("Save thumbnail has invalid size, ignoring.");
+                    }
+                }
+                b"STGN" => profile.stage_name = Some(String::from_utf8_lossy(&chunk).into_owned()),
+                _ => profile.extra_chunks.push(SaveChunk { id, version: chunk_version, data: chunk }),
+            }
+        }
+
+        if !has_profile {
+            return Err(ResourceLoadError("Save is missing the PROF chunk".to_owned()));
+        }
+
+        Ok(profile)
+    }
+}
+
+fn chunk_id(id: &[u8; 4]) -> String {
+    String::from_utf8_lossy(id).into_owned()
+}
+
+fn write_chunk<W: io::Write>(data: &mut W, id: &[u8; 4], chunk: &mut Vec<u8>) -> GameResult {
+    data.write_all(id)?;
+    data.write_u16::<LE>(CHUNK_VERSION)?;
+    data.write_u32::<LE>(chunk.len() as u32)?;
+    data.write_all(chunk)?;
+    chunk.clear();
+
+    Ok(())
+}
+
+fn write_weapons(out: &mut Vec<u8>, weapon_data: &[WeaponData]) -> GameResult {
+    out.write_u32::<LE>(weapon_data.len() as u32)?;
+    for weapon in weapon_data {
+        out.write_u32::<LE>(weapon.weapon_id)?;
+        out.write_u32::<LE>(weapon.level)?;
+        out.write_u32::<LE>(weapon.exp)?;
+        out.write_u32::<LE>(weapon.max_ammo)?;
+        out.write_u32::<LE>(weapon.ammo)?;
+    }
+
+    Ok(())
+}
+
+fn read_weapons<R: io::Read>(data: &mut R) -> GameResult<Vec<WeaponData>> {
+    let count = data.read_u32::<LE>()?;
+    let mut weapon_data = Vec::new();
+
+    for _ in 0..count {
+        weapon_data.push(WeaponData {
+            weapon_id: data.read_u32::<LE>()?,
+            level: data.read_u32::<LE>()?,
+            exp: data.read_u32::<LE>()?,
+            max_ammo: data.read_u32::<LE>()?,
+            ammo: data.read_u32::<LE>()?,
+        });
+    }
+
+    Ok(weapon_data)
+}
+
+fn write_items(out: &mut Vec<u8>, items: &[u32]) -> GameResult {
+    out.write_u32::<LE>(items.len() as u32)?;
+    for &item in items {
+        out.write_u32::<LE>(item)?;
+    }
+
+    Ok(())
+}
+
+fn read_items<R: io::Read>(data: &mut R) -> GameResult<Vec<u32>> {
+    let count = data.read_u32::<LE>()?;
+    let mut items = Vec::new();
+
+    for _ in 0..count {
+        items.push(data.read_u32::<LE>()?);
+    }
+
+    Ok(items)
+}
+
+fn write_variables(out: &mut Vec<u8>, variables: &[(ScriptVariable, i32)]) -> GameResult {
+    out.write_u32::<LE>(variables.len() as u32)?;
+
+    for (variable, value) in variables {
+        match variable {
+            ScriptVariable::Indexed(index) => {
+                out.write_u8(0)?;
+                out.write_u16::<LE>(*index)?;
+            }
+            ScriptVariable::Named(name) => {
+                out.write_u8(1)?;
+                out.write_u16::<LE>(name.len() as u16)?;
+                out.extend_from_slice(name.as_bytes());
+            }
+        }
+
+        out.write_i32::<LE>(*value)?;
+    }
+
+    Ok(())
+}
+
+fn read_variables<R: io::Read>(data: &mut R) -> GameResult<Vec<(ScriptVariable, i32)>> {
+    let count = data.read_u32::<LE>()?;
+    let mut variables = Vec::new();
+
+    for _ in 0..count {
+        let variable = match data.read_u8()? {
+            0 => ScriptVariable::Indexed(data.read_u16::<LE>()?),
+            1 => {
+                let mut name = vec![0u8; data.read_u16::<LE>()? as usize];
+                data.read_exact(&mut name)?;
+
+                ScriptVariable::Named(String::from_utf8_lossy(&name).into_owned())
+            }
+            _ => return Err(ResourceLoadError("Invalid variable kind".to_owned())),
+        };
+
+        variables.push((variable, data.read_i32::<LE>()?));
+    }
+
+    Ok(variables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_profile() -> GameProfile {
+        let mut flags = vec![0u8; 1000];
+        flags[0] = 0b101;
+        flags[999] = 0x80;
+
+        GameProfile {
+            current_map: 12,
+            current_song: 8,
+            pos_x: 0x1000,
+            pos_y: -0x2000,
+            direction: Direction::Right,
+            max_life: 50,
+            stars: 3,
+            life: 42,
+            current_weapon: 1,
+            current_item: 2,
+            equipment: 0x20,
+            control_mode: 0,
+            counter: 1234,
+            weapon_data: (0..10)
+                .map(|i| WeaponData { weapon_id: i + 1, level: 2, exp: i * 3, max_ammo: 100, ammo: 50 })
+                .collect(),
+            items: (1..=40).collect(),
+            teleporter_slots: vec![TeleporterSlotData { index: 1, event_num: 1001 }],
+            map_flags: [1; 128],
+            flags,
+            timestamp: 1_600_000_000,
+            difficulty: 2,
+            variables: vec![(ScriptVariable::Indexed(7), -5), (ScriptVariable::Named("coins".to_owned()), 100)],
+            player2: Some(CoopPlayerData {
+                pos_x: 0x3000,
+                pos_y: 0x4000,
+                direction: Direction::Left,
+                max_life: 3,
+                stars: 0,
+                life: 3,
+                current_weapon: 0,
+                current_item: 0,
+                equipment: 0,
+                weapon_data: vec![WeaponData { weapon_id: 2, level: 1, exp: 0, max_ammo: 0, ammo: 0 }],
+                items: vec![5],
+            }),
+            play_time: 3725,
+            thumbnail: Some(SaveThumbnail { width: 2, height: 1, data: vec![0xff; 8] }),
+            stage_name: Some("Mimiga Village".to_owned()),
+            extra_chunks: vec![SaveChunk { id: *b"MODX", version: 3, data: vec![1, 2, 3] }],
+        }
+    }
+
+    #[test]
+    fn test_extended_save_round_trip() {
+        let profile = test_profile();
+        let mut buf = Vec::new();
+        profile.write_save_as(&mut buf, SaveFormat::Extended).unwrap();
+
+        let (loaded, format) = GameProfile::load_from_save_with_format(buf.as_slice()).unwrap();
+        assert_eq!(format, SaveFormat::Extended);
+        assert_eq!(loaded.current_map, 12);
+        assert_eq!((loaded.pos_x, loaded.pos_y), (0x1000, -0x2000));
+        assert_eq!(loaded.direction as u32, Direction::Right as u32);
+        assert_eq!((loaded.max_life, loaded.stars, loaded.life), (50, 3, 42));
+        assert_eq!(loaded.counter, 1234);
+        assert_eq!(loaded.weapon_data.len(), 10);
+        assert_eq!(loaded.weapon_data[9].weapon_id, 10);
+        assert_eq!(loaded.items, profile.items);
+        assert_eq!(loaded.teleporter_slots[0].event_num, 1001);
+        assert_eq!(loaded.map_flags, [1; 128]);
+        assert_eq!(loaded.flags, profile.flags);
+        assert_eq!((loaded.timestamp, loaded.difficulty), (1_600_000_000, 2));
+        assert_eq!(loaded.variables, profile.variables);
+        assert_eq!(loaded.player2.as_ref().map(|p| (p.pos_x, p.items.clone())), Some((0x3000, vec![5])));
+        assert_eq!(loaded.play_time, 3725);
+        assert_eq!(loaded.thumbnail.as_ref().map(|t| (t.width, t.height, t.data.len())), Some((2, 1, 8)));
+        assert_eq!(loaded.stage_name.as_deref(), Some("Mimiga Village"));
+        assert_eq!(loaded.extra_chunks.len(), 1);
+        assert_eq!(&loaded.extra_chunks[0].id, b"MODX");
+        assert_eq!((loaded.extra_chunks[0].version, loaded.extra_chunks[0].data.clone()), (3, vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_vanilla_save_round_trip() {
+        let profile = test_profile();
+
+        for format in [SaveFormat::Vanilla, SaveFormat::CSPlus] {
+            let mut buf = Vec::new();
+            profile.write_save_as(&mut buf, format).unwrap();
+
+            let (loaded, loaded_format) = GameProfile::load_from_save_with_format(buf.as_slice()).unwrap();
+            assert_eq!(loaded_format, format);
+            assert_eq!(loaded.current_map, 12);
+            assert_eq!(loaded.weapon_data.len(), 8);
+            assert_eq!(loaded.items, (1..=32).collect::<Vec<u32>>());
+            assert_eq!(loaded.flags, profile.flags);
+            assert!(loaded.variables.is_empty());
+            assert!(loaded.player2.is_none());
+
+            if format == SaveFormat::CSPlus {
+                assert_eq!((loaded.timestamp, loaded.difficulty), (1_600_000_000, 2));
+            }
+        }
+    }
+
+    #[test]
+    fn test_thumbnail_downscale() {
+        // columns alternate between two reds, rows between two greens
+        let mut src = Vec::new();
+        for y in 0..4u16 {
+            for x in 0..256u16 {
+                src.extend_from_slice(&[if x % 2 == 0 { 100 } else { 200 }, if y % 2 == 0 { 0 } else { 100 }, 255, 0]);
+            }
+        }
+
+        let thumbnail = SaveThumbnail::downscale(256, 4, &src);
+        assert_eq!((thumbnail.width, thumbnail.height), (128, 2));
+        assert_eq!(thumbnail.data.len(), 128 * 2 * 4);
+        assert!(thumbnail.data.chunks(4).all(|pixel| pixel == [150, 50, 255, 255]));
+
+        // not evenly divisible, still covers the whole image
+        let src = vec![40u8; 300 * 7 * 4];
+        let thumbnail = SaveThumbnail::downscale(300, 7, &src);
+        assert_eq!((thumbnail.width, thumbnail.height), (128, 2));
+        assert!(thumbnail.data.chunks(4).all(|pixel| pixel == [40, 40, 40, 255]));
+
+        // small images aren't scaled up
+        let src: Vec<u8> = (0..2 * 2 * 4).map(|i| i as u8).collect();
+        let thumbnail = SaveThumbnail::downscale(2, 2, &src);
+        assert_eq!((thumbnail.width, thumbnail.height), (2, 2));
+        assert_eq!(thumbnail.data, vec![0, 1, 2, 255, 4, 5, 6, 255, 8, 9, 10, 255, 12, 13, 14, 255]);
+    }
+
+    #[test]
+    fn test_extended_save_rejects_oversized_chunk() {
+        let mut buf = Vec::new();
+        test_profile().write_save_as(&mut buf, SaveFormat::Extended).unwrap();
+
+        // length of the first chunk, right after the magic, container version, chunk id and chunk version
+        buf[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(GameProfile::load_from_save(buf.as_slice()).is_err());
+
+        // a save cut in the middle of a chunk
+        let mut buf = Vec::new();
+        test_profile().write_save_as(&mut buf, SaveFormat::Extended).unwrap();
+        buf.truncate(40);
+        assert!(GameProfile::load_from_save(buf.as_slice()).is_err());
     }
 }
@@ -0,0 +1,858 @@
+//! Command line tool for inspecting and editing save files.
+//!
+//! ```text
+//! doukutsu-rs save export Profile.dat [profile.json]
+//! doukutsu-rs save import profile.json Profile.dat [--format vanilla|csplus|extended]
+//! doukutsu-rs save validate Profile.dat|profile.json
+//! ```
+//! All commands accept `--data <dir>` to use game data other than the one the game would use,
+//! it's needed to resolve map names and validate stage and weapon ids.
+//!
+//! `import` writes the format stored in the JSON unless `--format` is given. Save thumbnails
+//! aren't exported, imported saves have none until the game saves over them.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::Direction;
+use crate::data::builtin_fs::BuiltinFS;
+use crate::engine_constants::EngineConstants;
+use crate::framework::context::Context;
+use crate::framework::error::GameError::{CommandLineError, InvalidValue, ParseError};
+use crate::framework::error::GameResult;
+use crate::framework::filesystem;
+use crate::framework::vfs::PhysicalFS;
+use crate::game::filesystem_container::FilesystemContainer;
+use crate::game::profile::{CoopPlayerData, GameProfile, SaveChunk, SaveFormat, TeleporterSlotData, WeaponData};
+use crate::game::scripting::tsc::text_script::ScriptVariable;
+use crate::game::settings::Settings;
+use crate::game::shared_game_state::{Season, GAME_FLAG_COUNT};
+use crate::game::stage::StageData;
+use crate::game::weapon::WeaponType;
+
+const USAGE: &str = "Usage:
+  doukutsu-rs save export <Profile.dat> [output.json]
+  doukutsu-rs save import <input.json> <Profile.dat> [--format vanilla|csplus|extended]
+  doukutsu-rs save validate <Profile.dat|input.json>
+
+Options:
+  --data <dir>  game data directory used to resolve map names and validate ids";
+
+#[derive(Serialize, Deserialize)]
+struct SaveJson {
+    format: String,
+    map: MapJson,
+    song: u32,
+    pos_x: i32,
+    pos_y: i32,
+    direction: String,
+    life: u16,
+    max_life: u16,
+    stars: u16,
+    current_weapon: u32,
+    current_item: u32,
+    equipment: u32,
+    control_mode: u32,
+    counter: u32,
+    weapons: Vec<WeaponJson>,
+    items: Vec<ItemJson>,
+    teleporter_slots: Vec<TeleporterSlotJson>,
+    /// Indices of set map flags.
+    map_flags: Vec<usize>,
+    /// Indices of set flags.
+    flags: Vec<usize>,
+    #[serde(default)]
+    variables: BTreeMap<String, i32>,
+    #[serde(default)]
+    player2: Option<CoopPlayerJson>,
+    #[serde(default)]
+    play_time: u32,
+    timestamp: u64,
+    difficulty: u8,
+    #[serde(default)]
+    extra_chunks: Vec<ChunkJson>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MapJson {
+    id: u32,
+    /// Taken from the stage table if the map exists there, stored in extended saves.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WeaponJson {
+    id: u32,
+    /// Informational only, ignored on import.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    level: u32,
+    exp: u32,
+    ammo: u32,
+    max_ammo: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ItemJson {
+    id: u16,
+    amount: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TeleporterSlotJson {
+    index: u32,
+    event: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CoopPlayerJson {
+    pos_x: i32,
+    pos_y: i32,
+    direction: String,
+    life: u16,
+    max_life: u16,
+    stars: u16,
+    current_weapon: u32,
+    current_item: u32,
+    equipment: u32,
+    weapons: Vec<WeaponJson>,
+    items: Vec<ItemJson>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChunkJson {
+    id: String,
+    version: u16,
+    /// Hex encoded.
+    data: String,
+}
+
+/// Game data needed to describe and validate saves.
+struct SaveToolData {
+    constants: EngineConstants,
+    stages: Vec<StageData>,
+}
+
+impl SaveToolData {
+    fn load(data_dir: Option<PathBuf>) -> GameResult<SaveToolData> {
+        let mut ctx = Context::new();
+
+        if let Some(data_dir) = data_dir {
+            filesystem::mount_vfs(&mut ctx, Box::new(PhysicalFS::new(&data_dir, true)));
+            filesystem::mount_vfs(&mut ctx, Box::new(BuiltinFS::new()));
+        } else {
+            FilesystemContainer::new().mount_fs(&mut ctx)?;
+        }
+
+        let mut constants = EngineConstants::defaults();
+        constants.is_switch = filesystem::exists(&ctx, "/base/lighting.tbl");
+        constants.is_cs_plus = constants.is_switch
+            || filesystem::exists(&ctx, "/base/Nicalis.bmp")
+            || filesystem::exists(&ctx, "/base/Nicalis.png");
+        constants.rebuild_path_list(&[], Season::None, &Settings::default());
+        constants.load_custom_weapons(&mut ctx)?;
+
+        let stages =
+            StageData::load_stage_table(&mut ctx, &constants.base_paths, constants.is_switch, constants.stage_encoding)
+                .unwrap_or_else(|err| {
+                    eprintln!("Warning: failed to load the stage table: {}", err);
+                    Vec::new()
+                });
+
+        Ok(SaveToolData { constants, stages })
+    }
+
+    fn map_name(&self, id: u32) -> Option<String> {
+        self.stages.get(id as usize).map(|stage| stage.name.clone())
+    }
+
+    fn weapon_name(&self, id: u32) -> Option<String> {
+        let id = u8::try_from(id).ok()?;
+
+        match WeaponType::from_id(id, &self.constants)? {
+            WeaponType::None => None,
+            WeaponType::Custom(id) => Some(format!("Custom {}", id)),
+            wtype => Some(format!("{:?}", wtype)),
+        }
+    }
+}
+
+pub fn run(args: &[String]) -> GameResult {
+    let mut positional = Vec::new();
+    let mut data_dir = None;
+    let mut format = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--data" => {
+                data_dir = Some(PathBuf::from(args.next().ok_or_else(|| usage_error("--data requires a path"))?));
+            }
+            "--format" => {
+                format = Some(match args.next().map(String::as_str) {
+                    Some("vanilla") => SaveFormat::Vanilla,
+                    Some("csplus") => SaveFormat::CSPlus,
+                    Some("extended") => SaveFormat::Extended,
+                    _ => return Err(usage_error("--format must be one of vanilla, csplus or extended")),
+                });
+            }
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    match positional.as_slice() {
+        ["export", input] => export(&SaveToolData::load(data_dir)?, input, None),
+        ["export", input, output] => export(&SaveToolData::load(data_dir)?, input, Some(*output)),
+        ["import", input, output] => import(&SaveToolData::load(data_dir)?, input, output, format),
+        ["validate", input] => validate(&SaveToolData::load(data_dir)?, input),
+        _ => Err(usage_error("invalid arguments")),
+    }
+}
+
+fn usage_error(msg: &str) -> crate::framework::error::GameError {
+    CommandLineError(format!("{}\n\n{}", msg, USAGE))
+}
+
+fn export(data: &SaveToolData, input: &str, output: Option<&str>) -> GameResult {
+    let (profile, format) = GameProfile::load_from_save_with_format(BufReader::new(File::open(input)?))?;
+    let json = profile_to_json(data, &profile, format);
+
+    match output {
+        Some(output) => {
+            let mut writer = BufWriter::new(File::create(output)?);
+            serde_json::to_writer_pretty(&mut writer, &json).map_err(|e| ParseError(e.to_string()))?;
+            writer.write_all(b"\n")?;
+        }
+        None => {
+            let out = serde_json::to_string_pretty(&json).map_err(|e| ParseError(e.to_string()))?;
+            println!("{}", out);
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the save in `format` if given, otherwise in the format recorded in the JSON.
+fn import(data: &SaveToolData, input: &str, output: &str, format: Option<SaveFormat>) -> GameResult {
+    let json: SaveJson =
+        serde_json::from_reader(BufReader::new(File::open(input)?)).map_err(|e| ParseError(e.to_string()))?;
+    let format = match format {
+        Some(format) => format,
+        None => parse_format(&json.format)?,
+    };
+    let profile = json_to_profile(json)?;
+
+    let problems = check_profile(data, &profile, format);
+    for problem in &problems {
+        eprintln!("Warning: {}", problem);
+    }
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    profile.write_save_as(&mut writer, format)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn validate(data: &SaveToolData, input: &str) -> GameResult {
+    let (profile, format) = if input.ends_with(".json") {
+        let json: SaveJson =
+            serde_json::from_reader(BufReader::new(File::open(input)?)).map_err(|e| ParseError(e.to_string()))?;
+        let format = parse_format(&json.format)?;
+
+        (json_to_profile(json)?, format)
+    } else {
+        GameProfile::load_from_save_with_format(BufReader::new(File::open(input)?))?
+    };
+
+    let problems = check_profile(data, &profile, format);
+    if problems.is_empty() {
+        println!("{}: OK", input);
+        return Ok(());
+    }
+
+    for problem in &problems {
+        println!("{}: {}", input, problem);
+    }
+
+    Err(InvalidValue(format!("{} problem(s) found in {}", problems.len(), input)))
+}
+
+/// Returns a list of human-readable problems with the save, empty if it's valid.
+fn check_profile(data: &SaveToolData, profile: &GameProfile, format: SaveFormat) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if data.stages.is_empty() {
+        problems.push("stage table is missing, map ids can't be validated".to_owned());
+    } else if profile.current_map as usize >= data.stages.len() {
+        problems.push(format!(
+            "map {} doesn't exist, the stage table has {} maps",
+            profile.current_map,
+            data.stages.len()
+        ));
+    }
+
+    check_inventory(data, "player 1", &profile.weapon_data, &profile.items, profile.current_weapon, &mut problems);
+    if let Some(player2) = &profile.player2 {
+        check_inventory(data, "player 2", &player2.weapon_data, &player2.items, player2.current_weapon, &mut problems);
+    }
+
+    if profile.stars > 3 {
+        problems.push(format!("whimsical star count {} is out of range 0-3", profile.stars));
+    }
+
+    if profile.life > profile.max_life {
+        problems.push(format!("life {} exceeds max life {}", profile.life, profile.max_life));
+    }
+
+    if let Some(flag) = set_bits(&profile.flags).find(|&flag| flag >= GAME_FLAG_COUNT) {
+        problems.push(format!("flag {} exceeds the maximum of {} flags", flag, GAME_FLAG_COUNT));
+    }
+
+    if format != SaveFormat::Extended {
+        let weapon_count = profile.weapon_data.iter().filter(|w| w.weapon_id != 0).count();
+        let item_count = profile.items.iter().filter(|&&i| i & 0xffff != 0).count();
+        let slot_count = profile.teleporter_slots.iter().filter(|s| s.event_num != 0).count();
+
+        if weapon_count > 8 {
+            problems.push(format!("{} weapons don't fit in the vanilla format, only 8 will be kept", weapon_count));
+        }
+        if item_count > 32 {
+            problems.push(format!("{} items don't fit in the vanilla format, only 32 will be kept", item_count));
+        }
+        if slot_count > 8 {
+            problems
+                .push(format!("{} teleporter slots don't fit in the vanilla format, only 8 will be kept", slot_count));
+        }
+        if let Some(flag) = set_bits(&profile.flags).find(|&flag| flag >= 8000) {
+            problems.push(format!("flag {} doesn't fit in the vanilla format, flags above 7999 will be lost", flag));
+        }
+        if !profile.variables.is_empty() || profile.player2.is_some() || !profile.extra_chunks.is_empty() {
+            problems.push("variables, co-op and mod data can only be stored in the extended format".to_owned());
+        }
+    }
+
+    problems
+}
+
+fn check_inventory(
+    data: &SaveToolData,
+    player: &str,
+    weapon_data: &[WeaponData],
+    items: &[u32],
+    current_weapon: u32,
+    problems: &mut Vec<String>,
+) {
+    let mut weapon_count = 0;
+
+    for weapon in weapon_data.iter().filter(|w| w.weapon_id != 0) {
+        weapon_count += 1;
+
+        if data.weapon_name(weapon.weapon_id).is_none() {
+            problems.push(format!("{}: weapon {} doesn't exist in the weapon tables", player, weapon.weapon_id));
+        }
+        if !(1..=3).contains(&weapon.level) {
+            problems.push(format!("{}: weapon {} has invalid level {}", player, weapon.weapon_id, weapon.level));
+        }
+        if weapon.max_ammo != 0 && weapon.ammo > weapon.max_ammo {
+            problems.push(format!(
+                "{}: weapon {} has more ammo ({}) than its capacity ({})",
+                player, weapon.weapon_id, weapon.ammo, weapon.max_ammo
+            ));
+        }
+    }
+
+    if weapon_count != 0 && current_weapon as usize >= weapon_count {
+        problems.push(format!("{}: selected weapon slot {} is empty", player, current_weapon));
+    }
+
+    for &item in items {
+        if item & 0xffff == 0 && item != 0 {
+            problems.push(format!("{}: item entry {:#010x} has no id", player, item));
+        }
+    }
+}
+
+fn set_bits(bytes: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    bytes
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, &byte)| (0..8).filter(move |bit| byte & (1 << bit) != 0).map(move |bit| idx * 8 + bit))
+}
+
+fn profile_to_json(data: &SaveToolData, profile: &GameProfile, format: SaveFormat) -> SaveJson {
+    SaveJson {
+        format: format_name(format).to_owned(),
+        map: MapJson {
+            id: profile.current_map,
+            name: data.map_name(profile.current_map).or_else(|| profile.stage_name.clone()),
+        },
+        song: profile.current_song,
+        pos_x: profile.pos_x,
+        pos_y: profile.pos_y,
+        direction: direction_name(profile.direction).to_owned(),
+        life: profile.life,
+        max_life: profile.max_life,
+        stars: profile.stars,
+        current_weapon: profile.current_weapon,
+        current_item: profile.current_item,
+        equipment: profile.equipment,
+        control_mode: profile.control_mode,
+        counter: profile.counter,
+        weapons: weapons_to_json(data, &profile.weapon_data),
+        items: items_to_json(&profile.items),
+        teleporter_slots: profile
+            .teleporter_slots
+            .iter()
+            .filter(|slot| slot.event_num != 0)
+            .map(|slot| TeleporterSlotJson { index: slot.index, event: slot.event_num })
+            .collect(),
+        map_flags: profile.map_flags.iter().enumerate().filter(|(_, &f)| f != 0).map(|(idx, _)| idx).collect(),
+        flags: set_bits(&profile.flags).collect(),
+        variables: profile.variables.iter().map(|(var, value)| (var.to_string(), *value)).collect(),
+        player2: profile.player2.as_ref().map(|player2| CoopPlayerJson {
+            pos_x: player2.pos_x,
+            pos_y: player2.pos_y,
+            direction: direction_name(player2.direction).to_owned(),
+            life: player2.life,
+            max_life: player2.max_life,
+            stars: player2.stars,
+            current_weapon: player2.current_weapon,
+            current_item: player2.current_item,
+            equipment: player2.equipment,
+            weapons: weapons_to_json(data, &player2.weapon_data),
+            items: items_to_json(&player2.items),
+        }),
+        play_time: profile.play_time,
+        timestamp: profile.timestamp,
+        difficulty: profile.difficulty,
+        extra_chunks: profile
+            .extra_chunks
+            .iter()
+            .map(|chunk| ChunkJson {
+                id: String::from_utf8_lossy(&chunk.id).into_owned(),
+                version: chunk.version,
+                data: encode_hex(&chunk.data),
+            })
+            .collect(),
+    }
+}
+
+fn json_to_profile(json: SaveJson) -> GameResult<GameProfile> {
+    let mut map_flags = [0u8; 128];
+    for idx in json.map_flags {
+        *map_flags.get_mut(idx).ok_or_else(|| InvalidValue(format!("Map flag {} out of range.", idx)))? = 1;
+    }
+
+    let mut flags = Vec::new();
+    for flag in json.flags {
+        if flag >= GAME_FLAG_COUNT {
+            return Err(InvalidValue(format!("Flag {} exceeds the maximum of {} flags.", flag, GAME_FLAG_COUNT)));
+        }
+        if flags.len() <= flag / 8 {
+            flags.resize(flag / 8 + 1, 0);
+        }
+        flags[flag / 8] |= 1 << (flag % 8);
+    }
+
+    let mut variables = Vec::new();
+    for (name, value) in json.variables {
+        variables.push((parse_variable(&name)?, value));
+    }
+
+    let player2 = match json.player2 {
+        Some(player2) => Some(CoopPlayerData {
+            pos_x: player2.pos_x,
+            pos_y: player2.pos_y,
+            direction: parse_direction(&player2.direction)?,
+            max_life: player2.max_life,
+            stars: player2.stars,
+            life: player2.life,
+            current_weapon: player2.current_weapon,
+            current_item: player2.current_item,
+            equipment: player2.equipment,
+            weapon_data: json_to_weapons(player2.weapons),
+            items: json_to_items(player2.items),
+        }),
+        None => None,
+    };
+
+    let mut extra_chunks = Vec::new();
+    for chunk in json.extra_chunks {
+        let id: [u8; 4] = chunk
+            .id
+            .as_bytes()
+            .try_into()
+            .map_err(|_| InvalidValue(format!("Chunk id {:?} must be exactly 4 bytes long.", chunk.id)))?;
+
+        extra_chunks.push(SaveChunk { id, version: chunk.version, data: decode_hex(&chunk.data)? });
+    }
+
+    Ok(GameProfile {
+        current_map: json.map.id,
+        current_song: json.song,
+        pos_x: json.pos_x,
+        pos_y: json.pos_y,
+        direction: parse_direction(&json.direction)?,
+        max_life: json.max_life,
+        stars: json.stars,
+        life: json.life,
+        current_weapon: json.current_weapon,
+        current_item: json.current_item,
+        equipment: json.equipment,
+        control_mode: json.control_mode,
+        counter: json.counter,
+        weapon_data: json_to_weapons(json.weapons),
+        items: json_to_items(json.items),
+        teleporter_slots: json
+            .teleporter_slots
+            .into_iter()
+            .map(|slot| TeleporterSlotData { index: slot.index, event_num: slot.event })
+            .collect(),
+        map_flags,
+        flags,
+        timestamp: json.timestamp,
+        difficulty: json.difficulty,
+        variables,
+        player2,
+        play_time: json.play_time,
+        thumbnail: None,
+        stage_name: json.map.name,
+        extra_chunks,
+    })
+}
+
+fn weapons_to_json(data: &SaveToolData, weapon_data: &[WeaponData]) -> Vec<WeaponJson> {
+    weapon_data
+        .iter()
+        .filter(|weapon| weapon.weapon_id != 0)
+        .map(|weapon| WeaponJson {
+            id: weapon.weapon_id,
+            name: data.weapon_name(weapon.weapon_id),
+            level: weapon.level,
+            exp: weapon.exp,
+            ammo: weapon.ammo,
+            max_ammo: weapon.max_ammo,
+        })
+        .collect()
+}
+
+fn json_to_weapons(weapons: Vec<WeaponJson>) -> Vec<WeaponData> {
+    weapons
+        .into_iter()
+        .map(|weapon| WeaponData {
+            weapon_id: weapon.id,
+            level: weapon.level,
+            exp: weapon.exp,
+            max_ammo: weapon.max_ammo,
+            ammo: weapon.ammo,
+        })
+        .collect()
+}
+
+fn items_to_json(items: &[u32]) -> Vec<ItemJson> {
+    items
+        .iter()
+        .take_while(|&&item| item & 0xffff != 0)
+        .map(|&item| ItemJson { id: item as u16, amount: (item >> 16) as u16 + 1 })
+        .collect()
+}
+
+fn json_to_items(items: Vec<ItemJson>) -> Vec<u32> {
+    items.into_iter().map(|item| item.id as u32 | (((item.amount.max(1) - 1) as u32) << 16)).collect()
+}
+
+fn format_name(format: SaveFormat) -> &'static str {
+    match format {
+        SaveFormat::Vanilla => "vanilla",
+        SaveFormat::CSPlus => "csplus",
+        SaveFormat::Extended => "extended",
+    }
+}
+
+fn parse_format(name: &str) -> GameResult<SaveFormat> {
+    match name {
+        "vanilla" => Ok(SaveFormat::Vanilla),
+        "csplus" => Ok(SaveFormat::CSPlus),
+        "extended" => Ok(SaveFormat::Extended),
+        _ => Err(InvalidValue(format!("Unknown save format {:?}.", name))),
+    }
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Left => "left",
+        Direction::Up => "up",
+        Direction::Right => "right",
+        Direction::Bottom => "bottom",
+        Direction::FacingPlayer => "facing_player",
+    }
+}
+
+fn parse_direction(name: &str) -> GameResult<Direction> {
+    match name {
+        "left" => Ok(Direction::Left),
+        "up" => Ok(Direction::Up),
+        "right" => Ok(Direction::Right),
+        "bottom" => Ok(Direction::Bottom),
+        "facing_player" => Ok(Direction::FacingPlayer),
+        _ => Err(InvalidValue(format!("Unknown direction {:?}.", name))),
+    }
+}
+
+/// Parses a variable in the same notation TSC uses, `$0012` or `$name`.
+fn parse_variable(name: &str) -> GameResult<ScriptVariable> {
+    let name = name.strip_prefix('$').ok_or_else(|| InvalidValue(format!("Variable {:?} must start with $.", name)))?;
+
+    if name.is_empty() || !name.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_') {
+        return Err(InvalidValue(format!("Invalid variable name {:?}.", name)));
+    }
+
+    Ok(match name.parse::<u16>() {
+        Ok(index) if name.bytes().all(|c| c.is_ascii_digit()) => ScriptVariable::Indexed(index),
+        _ => ScriptVariable::Named(name.to_owned()),
+    })
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(data: &str) -> GameResult<Vec<u8>> {
+    if data.len() % 2 != 0 {
+        return Err(InvalidValue("Hex string has odd length.".to_owned()));
+    }
+
+    (0..data.len())
+        .step_by(2)
+        .map(|i| {
+            data.get(i..i + 2)
+                .filter(|byte| byte.bytes().all(|c| c.is_ascii_hexdigit()))
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| InvalidValue(format!("Invalid hex byte at offset {}.", i)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::Color;
+    use crate::game::stage::{Background, BackgroundType, NpcType, Tileset};
+
+    use super::*;
+
+    fn test_data() -> SaveToolData {
+        let stage = |name: &str| StageData {
+            name: name.to_owned(),
+            name_jp: String::new(),
+            map: name.to_owned(),
+            boss_no: 0,
+            tileset: Tileset::new("Cave"),
+            pxpack_data: None,
+            background: Background::new("bk0"),
+            background_type: BackgroundType::TiledStatic,
+            background_color: Color::from_rgb(0, 0, 0),
+            npc1: NpcType::new("0"),
+            npc2: NpcType::new("0"),
+            ambient_light: None,
+            camera_bounds: None,
+        };
+
+        SaveToolData { constants: EngineConstants::defaults(), stages: vec![stage("Null"), stage("Arthur's House")] }
+    }
+
+    fn test_profile() -> GameProfile {
+        let mut flags = vec![0u8; 10];
+        flags[0] = 0b1001;
+        flags[9] = 0x80;
+
+        GameProfile {
+            current_map: 1,
+            current_song: 8,
+            pos_x: 0x1000,
+            pos_y: -0x2000,
+            direction: Direction::Right,
+            max_life: 50,
+            stars: 3,
+            life: 42,
+            current_weapon: 1,
+            current_item: 0,
+            equipment: 0x20,
+            control_mode: 0,
+            counter: 1234,
+            weapon_data: vec![
+                WeaponData { weapon_id: 2, level: 1, exp: 5, max_ammo: 0, ammo: 0 },
+                WeaponData { weapon_id: 5, level: 3, exp: 0, max_ammo: 100, ammo: 20 },
+            ],
+            items: vec![1, 3 | (4 << 16)],
+            teleporter_slots: vec![TeleporterSlotData { index: 1, event_num: 1001 }],
+            map_flags: [0; 128],
+            flags,
+            timestamp: 1_600_000_000,
+            difficulty: 2,
+            variables: vec![(ScriptVariable::Indexed(7), -5), (ScriptVariable::Named("coins".to_owned()), 100)],
+            player2: Some(CoopPlayerData {
+                pos_x: 0x3000,
+                pos_y: 0x4000,
+                direction: Direction::Left,
+                max_life: 3,
+                stars: 0,
+                life: 3,
+                current_weapon: 0,
+                current_item: 0,
+                equipment: 0,
+                weapon_data: vec![WeaponData { weapon_id: 2, level: 1, exp: 0, max_ammo: 0, ammo: 0 }],
+                items: vec![5],
+            }),
+            play_time: 3725,
+            thumbnail: None,
+            stage_name: None,
+            extra_chunks: vec![SaveChunk { id: *b"MODX", version: 3, data: vec![0, 0xab, 0xff] }],
+        }
+    }
+
+    fn json_round_trip(profile: &GameProfile) -> GameProfile {
+        let json = profile_to_json(&test_data(), profile, SaveFormat::Extended);
+        let text = serde_json::to_string(&json).unwrap();
+
+        json_to_profile(serde_json::from_str(&text).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut profile = test_profile();
+        profile.map_flags[5] = 1;
+        let loaded = json_round_trip(&profile);
+
+        assert_eq!((loaded.current_map, loaded.current_song), (1, 8));
+        assert_eq!((loaded.pos_x, loaded.pos_y), (0x1000, -0x2000));
+        assert_eq!(loaded.direction, Direction::Right);
+        assert_eq!((loaded.max_life, loaded.stars, loaded.life), (50, 3, 42));
+        assert_eq!((loaded.current_weapon, loaded.equipment, loaded.counter), (1, 0x20, 1234));
+        assert_eq!(loaded.weapon_data.len(), 2);
+        assert_eq!(loaded.weapon_data[1].weapon_id, 5);
+        assert_eq!((loaded.weapon_data[1].level, loaded.weapon_data[1].ammo), (3, 20));
+        assert_eq!(loaded.items, profile.items);
+        assert_eq!(loaded.teleporter_slots[0].event_num, 1001);
+        assert_eq!(loaded.map_flags, profile.map_flags);
+        assert_eq!(loaded.flags, profile.flags);
+        assert_eq!((loaded.timestamp, loaded.difficulty, loaded.play_time), (1_600_000_000, 2, 3725));
+        assert_eq!(loaded.variables, profile.variables);
+        assert_eq!(
+            loaded.player2.as_ref().map(|p| (p.pos_x, p.direction, p.items.clone())),
+            Some((0x3000, Direction::Left, vec![5]))
+        );
+        assert_eq!(loaded.stage_name.as_deref(), Some("Arthur's House"));
+        assert_eq!(loaded.extra_chunks.len(), 1);
+        assert_eq!(&loaded.extra_chunks[0].id, b"MODX");
+        assert_eq!(loaded.extra_chunks[0].version, 3);
+        assert_eq!(loaded.extra_chunks[0].data, vec![0, 0xab, 0xff]);
+    }
+
+    #[test]
+    fn test_profile_to_json() {
+        let json = profile_to_json(&test_data(), &test_profile(), SaveFormat::CSPlus);
+
+        assert_eq!(json.format, "csplus");
+        assert_eq!(json.map.name.as_deref(), Some("Arthur's House"));
+        assert_eq!(json.direction, "right");
+        assert_eq!(json.flags, vec![0, 3, 79]);
+        assert!(json.map_flags.is_empty());
+        assert_eq!(json.items[1].id, 3);
+        assert_eq!(json.items[1].amount, 5);
+        assert_eq!(json.variables["$0007"], -5);
+        assert_eq!(json.variables["$coins"], 100);
+        assert_eq!(json.extra_chunks[0].data, "00abff");
+    }
+
+    #[test]
+    fn test_json_to_profile_errors() {
+        let data = test_data();
+        let json = || profile_to_json(&data, &test_profile(), SaveFormat::Extended);
+
+        let mut invalid = json();
+        invalid.flags.push(GAME_FLAG_COUNT);
+        assert!(json_to_profile(invalid).is_err());
+
+        let mut invalid = json();
+        invalid.map_flags.push(128);
+        assert!(json_to_profile(invalid).is_err());
+
+        let mut invalid = json();
+        invalid.direction = "down".to_owned();
+        assert!(json_to_profile(invalid).is_err());
+
+        let mut invalid = json();
+        invalid.variables.insert("coins".to_owned(), 1);
+        assert!(json_to_profile(invalid).is_err());
+
+        let mut invalid = json();
+        invalid.extra_chunks[0].id = "MOD".to_owned();
+        assert!(json_to_profile(invalid).is_err());
+
+        let mut invalid = json();
+        invalid.extra_chunks[0].data = "abc".to_owned();
+        assert!(json_to_profile(invalid).is_err());
+
+        assert!(json_to_profile(json()).is_ok());
+    }
+
+    #[test]
+    fn test_decode_hex() {
+        assert_eq!(decode_hex("").unwrap(), Vec::<u8>::new());
+        assert_eq!(decode_hex("00ff10").unwrap(), vec![0, 0xff, 0x10]);
+        assert_eq!(decode_hex("ABcd").unwrap(), vec![0xab, 0xcd]);
+        assert_eq!(decode_hex(&encode_hex(&[1, 2, 254])).unwrap(), vec![1, 2, 254]);
+
+        assert!(decode_hex("0").is_err());
+        assert!(decode_hex("0g").is_err());
+        assert!(decode_hex("+1").is_err());
+        assert!(decode_hex("éé").is_err());
+    }
+
+    #[test]
+    fn test_parse_variable() {
+        assert_eq!(parse_variable("$0012").unwrap(), ScriptVariable::Indexed(12));
+        assert_eq!(parse_variable("$coins").unwrap(), ScriptVariable::Named("coins".to_owned()));
+        assert_eq!(parse_variable("$12a").unwrap(), ScriptVariable::Named("12a".to_owned()));
+        assert_eq!(parse_variable("$my_var").unwrap(), ScriptVariable::Named("my_var".to_owned()));
+
+        assert_eq!(parse_variable(&ScriptVariable::Indexed(7).to_string()).unwrap(), ScriptVariable::Indexed(7));
+
+        assert!(parse_variable("0012").is_err());
+        assert!(parse_variable("$").is_err());
+        assert!(parse_variable("$a-b").is_err());
+        assert!(parse_variable("$+12").is_err());
+    }
+
+    #[test]
+    fn test_check_profile() {
+        let data = test_data();
+        let profile = test_profile();
+
+        assert!(check_profile(&data, &profile, SaveFormat::Extended).is_empty());
+
+        // co-op, variables and chunks can't be stored in vanilla saves
+        assert_eq!(check_profile(&data, &profile, SaveFormat::Vanilla).len(), 1);
+
+        let mut invalid = test_profile();
+        invalid.current_map = 2;
+        invalid.stars = 4;
+        invalid.life = 51;
+        invalid.weapon_data[0].level = 0;
+        invalid.weapon_data[1].ammo = 101;
+        invalid.current_weapon = 2;
+        invalid.items.push(5 << 16);
+        invalid.flags.resize(GAME_FLAG_COUNT / 8 + 1, 0);
+        invalid.flags[GAME_FLAG_COUNT / 8] = 1;
+        assert_eq!(check_profile(&data, &invalid, SaveFormat::Extended).len(), 8);
+
+        let no_stages = SaveToolData { constants: EngineConstants::defaults(), stages: Vec::new() };
+        assert_eq!(check_profile(&no_stages, &profile, SaveFormat::Extended).len(), 1);
+    }
+}
@@ -1 +1,3 @@
+#[cfg(feature = "scripting")]
+pub mod npc_scripts;
 pub mod tsc;
@@ -0,0 +1,617 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::rc::Rc;
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, FnPtr, FuncArgs, Map, Scope, AST};
+
+use crate::common::{Condition, Direction, Flag, Rect};
+use crate::framework::context::Context;
+use crate::framework::error::{GameError, GameResult};
+use crate::framework::filesystem;
+use crate::game::caret::CaretType;
+use crate::game::npc::boss::BossNPC;
+use crate::game::npc::list::NPCList;
+use crate::game::npc::{NPCFlag, NPC};
+use crate::game::player::Player;
+use crate::game::shared_game_state::SharedGameState;
+use crate::util::rng::{Xoroshiro32PlusPlus, RNG};
+
+/// Upper limit of operations a single handler call can perform, so a broken script can't hang the game.
+const MAX_OPERATIONS: u64 = 500_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NPCScriptHook {
+    Tick,
+    Draw,
+    Hit,
+}
+
+impl NPCScriptHook {
+    fn key(self) -> &'static str {
+        match self {
+            NPCScriptHook::Tick => "tick",
+            NPCScriptHook::Draw => "draw",
+            NPCScriptHook::Hit => "hit",
+        }
+    }
+}
+
+/// Side effects requested by a script, applied once the handler returns.
+enum ScriptCommand {
+    SpawnNPC { npc_type: u16, x: i32, y: i32, vel_x: i32, vel_y: i32, direction: Direction, parent_id: u16 },
+    CreateCaret { x: i32, y: i32, ctype: CaretType, direction: Direction },
+    PlaySfx { id: u8 },
+}
+
+#[derive(Default)]
+struct ScriptContext {
+    players: [Option<(i32, i32)>; 2],
+    commands: Vec<ScriptCommand>,
+    registrations: Vec<(u16, Map)>,
+    boss_registrations: Vec<(u16, Map)>,
+}
+
+/// Part of the NPC state exposed to scripts as `this`, so handlers don't have to copy the whole NPC.
+#[derive(Clone)]
+struct ScriptNPC {
+    id: u16,
+    npc_type: u16,
+    x: i32,
+    y: i32,
+    vel_x: i32,
+    vel_y: i32,
+    vel_x2: i32,
+    vel_y2: i32,
+    target_x: i32,
+    target_y: i32,
+    exp: u16,
+    shock: u16,
+    life: u16,
+    damage: u16,
+    cond: Condition,
+    flags: Flag,
+    npc_flags: NPCFlag,
+    direction: Direction,
+    parent_id: u16,
+    action_num: u16,
+    anim_num: u16,
+    flag_num: u16,
+    event_num: u16,
+    action_counter: u16,
+    action_counter2: u16,
+    action_counter3: u16,
+    anim_counter: u16,
+    anim_rect: Rect<u16>,
+    display_bounds: Rect<u32>,
+    hit_bounds: Rect<u32>,
+    rng: Xoroshiro32PlusPlus,
+}
+
+impl ScriptNPC {
+    fn new(npc: &NPC) -> ScriptNPC {
+        ScriptNPC {
+            id: npc.id,
+            npc_type: npc.npc_type,
+            x: npc.x,
+            y: npc.y,
+            vel_x: npc.vel_x,
+            vel_y: npc.vel_y,
+            vel_x2: npc.vel_x2,
+            vel_y2: npc.vel_y2,
+            target_x: npc.target_x,
+            target_y: npc.target_y,
+            exp: npc.exp,
+            shock: npc.shock,
+            life: npc.life,
+            damage: npc.damage,
+            cond: npc.cond,
+            flags: npc.flags,
+            npc_flags: npc.npc_flags,
+            direction: npc.direction,
+            parent_id: npc.parent_id,
+            action_num: npc.action_num,
+            anim_num: npc.anim_num,
+            flag_num: npc.flag_num,
+            event_num: npc.event_num,
+            action_counter: npc.action_counter,
+            action_counter2: npc.action_counter2,
+            action_counter3: npc.action_counter3,
+            anim_counter: npc.anim_counter,
+            anim_rect: npc.anim_rect,
+            display_bounds: npc.display_bounds,
+            hit_bounds: npc.hit_bounds,
+            rng: npc.rng.clone(),
+        }
+    }
+
+    /// Writes the fields scripts are allowed to change back to the NPC.
+    fn apply(self, npc: &mut NPC) {
+        npc.x = self.x;
+        npc.y = self.y;
+        npc.vel_x = self.vel_x;
+        npc.vel_y = self.vel_y;
+        npc.vel_x2 = self.vel_x2;
+        npc.vel_y2 = self.vel_y2;
+        npc.target_x = self.target_x;
+        npc.target_y = self.target_y;
+        npc.exp = self.exp;
+        npc.life = self.life;
+        npc.damage = self.damage;
+        npc.cond = self.cond;
+        npc.npc_flags = self.npc_flags;
+        npc.direction = self.direction;
+        npc.parent_id = self.parent_id;
+        npc.action_num = self.action_num;
+        npc.anim_num = self.anim_num;
+        npc.flag_num = self.flag_num;
+        npc.event_num = self.event_num;
+        npc.action_counter = self.action_counter;
+        npc.action_counter2 = self.action_counter2;
+        npc.action_counter3 = self.action_counter3;
+        npc.anim_counter = self.anim_counter;
+        npc.anim_rect = self.anim_rect;
+        npc.display_bounds = self.display_bounds;
+        npc.hit_bounds = self.hit_bounds;
+        npc.rng = self.rng;
+    }
+}
+
+struct NPCHandlers {
+    script: usize,
+    functions: HashMap<NPCScriptHook, String>,
+}
+
+/// Runs NPC handlers registered by mods in `scripts/*.rhai` files.
+///
+/// A script registers handlers at load time:
+/// ```rhai
+/// register_npc(361, #{ tick: "tick_gaudi", hit: "hit_gaudi" });
+///
+/// fn tick_gaudi() {
+///     this.x += this.vel_x;
+/// }
+/// ```
+/// Handlers are called with `this` bound to a view of the NPC, changes to its fields are written back after the call.
+/// A `tick` handler replaces the built-in AI of that NPC type.
+///
+/// Custom bosses can register a `tick` handler with `register_boss(id, #{ tick: "..." })`, it's called with
+/// `this` bound to an array of all boss parts before the boss' own state machine runs.
+pub struct NPCScripts {
+    engine: Engine,
+    scripts: Vec<AST>,
+    handlers: HashMap<u16, NPCHandlers>,
+    boss_handlers: HashMap<u16, NPCHandlers>,
+    context: Rc<RefCell<ScriptContext>>,
+    failed: RefCell<HashSet<u16>>,
+    failed_bosses: RefCell<HashSet<u16>>,
+}
+
+impl NPCScripts {
+    pub fn new() -> NPCScripts {
+        let context = Rc::new(RefCell::new(ScriptContext::default()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|s| log::info!("[script] {}", s));
+        engine.on_debug(|s, _, pos| log::debug!("[script] {:?}: {}", pos, s));
+
+        Self::register_npc_type(&mut engine);
+        Self::register_api(&mut engine, &context);
+
+        NPCScripts {
+            engine,
+            scripts: Vec::new(),
+            handlers: HashMap::new(),
+            boss_handlers: HashMap::new(),
+            context,
+            failed: RefCell::new(HashSet::new()),
+            failed_bosses: RefCell::new(HashSet::new()),
+        }
+    }
+
+    fn register_npc_type(engine: &mut Engine) {
+        engine.register_type_with_name::<ScriptNPC>("NPC");
+
+        macro_rules! npc_property {
+            ($name:ident: $ty:ty) => {
+                engine.register_get_set(
+                    stringify!($name),
+                    |npc: &mut ScriptNPC| npc.$name as i64,
+                    |npc: &mut ScriptNPC, value: i64| npc.$name = value as $ty,
+                );
+            };
+        }
+
+        npc_property!(x: i32);
+        npc_property!(y: i32);
+        npc_property!(vel_x: i32);
+        npc_property!(vel_y: i32);
+        npc_property!(vel_x2: i32);
+        npc_property!(vel_y2: i32);
+        npc_property!(target_x: i32);
+        npc_property!(target_y: i32);
+        npc_property!(exp: u16);
+        npc_property!(life: u16);
+        npc_property!(damage: u16);
+        npc_property!(action_num: u16);
+        npc_property!(action_counter: u16);
+        npc_property!(action_counter2: u16);
+        npc_property!(action_counter3: u16);
+        npc_property!(anim_num: u16);
+        npc_property!(anim_counter: u16);
+        npc_property!(event_num: u16);
+        npc_property!(flag_num: u16);
+        npc_property!(parent_id: u16);
+
+        engine
+            .register_get("id", |npc: &mut ScriptNPC| npc.id as i64)
+            .register_get("npc_type", |npc: &mut ScriptNPC| npc.npc_type as i64)
+            .register_get("shock", |npc: &mut ScriptNPC| npc.shock as i64)
+            .register_get("flags", |npc: &mut ScriptNPC| npc.flags.0 as i64)
+            .register_get_set(
+                "npc_flags",
+                |npc: &mut ScriptNPC| npc.npc_flags.0 as i64,
+                |npc: &mut ScriptNPC, value: i64| npc.npc_flags.0 = value as u16,
+            )
+            .register_get_set(
+                "direction",
+                |npc: &mut ScriptNPC| npc.direction as i64,
+                |npc: &mut ScriptNPC, value: i64| {
+                    npc.direction = Direction::from_int_facing(value as usize).unwrap_or(Direction::Left)
+                },
+            )
+            .register_get_set(
+                "alive",
+                |npc: &mut ScriptNPC| npc.cond.alive(),
+                |npc: &mut ScriptNPC, value: bool| {
+                    npc.cond.set_alive(value);
+                },
+            )
+            .register_get_set(
+                "hidden",
+                |npc: &mut ScriptNPC| npc.cond.hidden(),
+                |npc: &mut ScriptNPC, value: bool| {
+                    npc.cond.set_hidden(value);
+                },
+            )
+            .register_fn("set_anim_rect", |npc: &mut ScriptNPC, left: i64, top: i64, right: i64, bottom: i64| {
+                npc.anim_rect = Rect::new(left as u16, top as u16, right as u16, bottom as u16);
+            })
+            .register_fn("set_hit_bounds", |npc: &mut ScriptNPC, left: i64, top: i64, right: i64, bottom: i64| {
+                npc.hit_bounds = Rect::new(left as u32, top as u32, right as u32, bottom as u32);
+            })
+            .register_fn("set_display_bounds", |npc: &mut ScriptNPC, left: i64, top: i64, right: i64, bottom: i64| {
+                npc.display_bounds = Rect::new(left as u32, top as u32, right as u32, bottom as u32);
+            })
+            .register_fn("rand", |npc: &mut ScriptNPC, min: i64, max: i64| npc.rng.range(min as i32..max as i32) as i64)
+            .register_fn("vanish", |npc: &mut ScriptNPC| npc.cond.set_alive(false));
+    }
+
+    fn register_api(engine: &mut Engine, context: &Rc<RefCell<ScriptContext>>) {
+        let ctx = context.clone();
+        engine.register_fn("register_npc", move |npc_type: i64, handlers: Map| {
+            ctx.borrow_mut().registrations.push((npc_type as u16, handlers));
+        });
+
+        let ctx = context.clone();
+        engine.register_fn("register_boss", move |boss_type: i64, handlers: Map| {
+            ctx.borrow_mut().boss_registrations.push((boss_type as u16, handlers));
+        });
+
+        let ctx = context.clone();
+        engine.register_fn("player_x", move |idx: i64| {
+            ctx.borrow().players.get(idx as usize).copied().flatten().map_or(0, |(x, _)| x as i64)
+        });
+
+        let ctx = context.clone();
+        engine.register_fn("player_y", move |idx: i64| {
+            ctx.borrow().players.get(idx as usize).copied().flatten().map_or(0, |(_, y)| y as i64)
+        });
+
+        let ctx = context.clone();
+        engine.register_fn("player_present", move |idx: i64| {
+            ctx.borrow().players.get(idx as usize).copied().flatten().is_some()
+        });
+
+        let ctx = context.clone();
+        engine.register_fn("closest_player", move |npc: &mut ScriptNPC| {
+            let ctx = ctx.borrow();
+            let distance = |(x, y): (i32, i32)| (npc.x - x).unsigned_abs() as u64 + (npc.y - y).unsigned_abs() as u64;
+
+            match ctx.players {
+                [Some(p1), Some(p2)] if distance(p2) < distance(p1) => 1_i64,
+                [None, Some(_)] => 1,
+                _ => 0,
+            }
+        });
+
+        let ctx = context.clone();
+        engine.register_fn(
+            "spawn_npc",
+            move |npc_type: i64, x: i64, y: i64, vel_x: i64, vel_y: i64, direction: i64, parent_id: i64| {
+                ctx.borrow_mut().commands.push(ScriptCommand::SpawnNPC {
+                    npc_type: npc_type as u16,
+                    x: x as i32,
+                    y: y as i32,
+                    vel_x: vel_x as i32,
+                    vel_y: vel_y as i32,
+                    direction: Direction::from_int_facing(direction as usize).unwrap_or(Direction::Left),
+                    parent_id: parent_id as u16,
+                });
+            },
+        );
+
+        let ctx = context.clone();
+        engine.register_fn("create_caret", move |x: i64, y: i64, ctype: i64, direction: i64| {
+            if let Some(ctype) = CaretType::from_int(ctype as usize) {
+                ctx.borrow_mut().commands.push(ScriptCommand::CreateCaret {
+                    x: x as i32,
+                    y: y as i32,
+                    ctype,
+                    direction: Direction::from_int(direction as usize).unwrap_or(Direction::Left),
+                });
+            }
+        });
+
+        let ctx = context.clone();
+        engine.register_fn("play_sfx", move |id: i64| {
+            ctx.borrow_mut().commands.push(ScriptCommand::PlaySfx { id: id as u8 });
+        });
+    }
+
+    /// Loads all `scripts/*.rhai` files from the data directories, scripts from mods override the base game.
+    pub fn load(&mut self, ctx: &mut Context, roots: &Vec<String>) -> GameResult {
+        self.scripts.clear();
+        self.handlers.clear();
+        self.boss_handlers.clear();
+        self.failed.borrow_mut().clear();
+        self.failed_bosses.borrow_mut().clear();
+
+        for path in roots.iter().rev() {
+            let Ok(files) = filesystem::read_dir(ctx, [path, "scripts/"].join("")) else {
+                continue;
+            };
+
+            let mut files: Vec<_> = files.filter(|f| f.to_string_lossy().to_lowercase().ends_with(".rhai")).collect();
+            files.sort();
+
+            for filename in files {
+                let mut source = String::new();
+                let result = filesystem::open(ctx, &filename)
+                    .and_then(|mut file| file.read_to_string(&mut source).map_err(GameError::from))
+                    .and_then(|_| self.load_script(&source));
+
+                if let Err(err) = result {
+                    log::warn!("Skipping script {}: {}", filename.display(), err);
+                }
+            }
+        }
+
+        if !self.handlers.is_empty() {
+            log::info!("Loaded scripted handlers for {} NPC types.", self.handlers.len());
+        }
+
+        if !self.boss_handlers.is_empty() {
+            log::info!("Loaded scripted handlers for {} bosses.", self.boss_handlers.len());
+        }
+
+        Ok(())
+    }
+
+    fn load_script(&mut self, source: &str) -> GameResult {
+        let ast = self.engine.compile(source).map_err(|e| GameError::ParseError(e.to_string()))?;
+
+        {
+            let mut context = self.context.borrow_mut();
+            context.registrations.clear();
+            context.boss_registrations.clear();
+        }
+        self.engine.run_ast(&ast).map_err(|e| GameError::ParseError(e.to_string()))?;
+
+        let script = self.scripts.len();
+        self.scripts.push(ast);
+
+        let (registrations, boss_registrations) = {
+            let mut context = self.context.borrow_mut();
+            (std::mem::take(&mut context.registrations), std::mem::take(&mut context.boss_registrations))
+        };
+
+        for (npc_type, map) in registrations {
+            let functions = Self::parse_handlers(&map, &[NPCScriptHook::Tick, NPCScriptHook::Draw, NPCScriptHook::Hit])
+                .map_err(|err| GameError::ParseError(format!("{} for NPC {}", err, npc_type)))?;
+
+            self.handlers.insert(npc_type, NPCHandlers { script, functions });
+        }
+
+        for (boss_type, map) in boss_registrations {
+            let functions = Self::parse_handlers(&map, &[NPCScriptHook::Tick])
+                .map_err(|err| GameError::ParseError(format!("{} for boss {}", err, boss_type)))?;
+
+            self.boss_handlers.insert(boss_type, NPCHandlers { script, functions });
+        }
+
+        Ok(())
+    }
+
+    fn parse_handlers(map: &Map, hooks: &[NPCScriptHook]) -> Result<HashMap<NPCScriptHook, String>, String> {
+        let mut functions = HashMap::new();
+
+        for &hook in hooks {
+            let Some(value) = map.get(hook.key()) else {
+                continue;
+            };
+
+            let name = if value.is_fnptr() {
+                value.clone().cast::<FnPtr>().fn_name().to_owned()
+            } else {
+                value.clone().into_string().map_err(|t| format!("Invalid {} handler: {}", hook.key(), t))?
+            };
+
+            functions.insert(hook, name);
+        }
+
+        Ok(functions)
+    }
+
+    #[inline]
+    pub fn has_handler(&self, npc_type: u16, hook: NPCScriptHook) -> bool {
+        !self.handlers.is_empty()
+            && self.handlers.get(&npc_type).map_or(false, |h| h.functions.contains_key(&hook))
+            && !self.failed.borrow().contains(&npc_type)
+    }
+
+    /// Calls a handler with `this` bound to a view of the NPC, returns the modified view.
+    /// Errors are logged and disable the handlers of that NPC type, so a broken script doesn't spam the log.
+    fn call(
+        &self,
+        hook: NPCScriptHook,
+        npc: &NPC,
+        players: [Option<(i32, i32)>; 2],
+        args: impl FuncArgs,
+    ) -> Option<ScriptNPC> {
+        let handlers = self.handlers.get(&npc.npc_type)?;
+        let name = handlers.functions.get(&hook)?;
+        let ast = &self.scripts[handlers.script];
+
+        self.context.borrow_mut().players = players;
+
+        let mut this = Dynamic::from(ScriptNPC::new(npc));
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut this);
+
+        match self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), ast, name, args) {
+            Ok(_) => this.try_cast::<ScriptNPC>(),
+            Err(err) => {
+                log::error!("Script {} handler of NPC {} failed, disabling: {}", hook.key(), npc.npc_type, err);
+                self.failed.borrow_mut().insert(npc.npc_type);
+                self.context.borrow_mut().commands.clear();
+                None
+            }
+        }
+    }
+
+    #[inline]
+    pub fn has_boss_handler(&self, boss_type: u16) -> bool {
+        !self.boss_handlers.is_empty()
+            && self.boss_handlers.contains_key(&boss_type)
+            && !self.failed_bosses.borrow().contains(&boss_type)
+    }
+
+    /// Calls the tick handler of a boss with `this` bound to an array of views of its parts,
+    /// returns the modified views.
+    fn call_boss(&self, boss_type: u16, parts: &[NPC], players: [Option<(i32, i32)>; 2]) -> Option<Vec<ScriptNPC>> {
+        let handlers = self.boss_handlers.get(&boss_type)?;
+        let name = handlers.functions.get(&NPCScriptHook::Tick)?;
+        let ast = &self.scripts[handlers.script];
+
+        self.context.borrow_mut().players = players;
+
+        let mut this = Dynamic::from_array(parts.iter().map(|p| Dynamic::from(ScriptNPC::new(p))).collect());
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut this);
+
+        match self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), ast, name, ()) {
+            Ok(_) => this
+                .try_cast::<Array>()
+                .map(|parts| parts.into_iter().filter_map(|p| p.try_cast::<ScriptNPC>()).collect()),
+            Err(err) => {
+                log::error!("Script tick handler of boss {} failed, disabling: {}", boss_type, err);
+                self.failed_bosses.borrow_mut().insert(boss_type);
+                self.context.borrow_mut().commands.clear();
+                None
+            }
+        }
+    }
+
+    fn take_commands(&self) -> Vec<ScriptCommand> {
+        std::mem::take(&mut self.context.borrow_mut().commands)
+    }
+
+    /// Returns the frame the draw handler wants to render instead of the current animation rect.
+    pub fn run_draw(&self, npc: &NPC) -> Option<Rect<u16>> {
+        let result = self.call(NPCScriptHook::Draw, npc, [None, None], ()).map(|npc| npc.anim_rect);
+        // draw handlers are only meant to alter the appearance
+        self.context.borrow_mut().commands.clear();
+
+        result
+    }
+}
+
+fn player_positions(players: [&Player; 2]) -> [Option<(i32, i32)>; 2] {
+    players.map(|p| if p.cond.alive() && !p.cond.hidden() { Some((p.x, p.y)) } else { None })
+}
+
+fn apply_commands(commands: Vec<ScriptCommand>, state: &mut SharedGameState, npc_list: &NPCList) -> GameResult {
+    for command in commands {
+        match command {
+            ScriptCommand::SpawnNPC { npc_type, x, y, vel_x, vel_y, direction, parent_id } => {
+                let mut npc = NPC::create(npc_type, &state.npc_table);
+                npc.cond.set_alive(true);
+                npc.x = x;
+                npc.y = y;
+                npc.vel_x = vel_x;
+                npc.vel_y = vel_y;
+                npc.direction = direction;
+                npc.parent_id = parent_id;
+
+                let _ = npc_list.spawn(0x100, npc);
+            }
+            ScriptCommand::CreateCaret { x, y, ctype, direction } => state.create_caret(x, y, ctype, direction),
+            ScriptCommand::PlaySfx { id } => state.sound_manager.play_sfx(id),
+        }
+    }
+
+    Ok(())
+}
+
+impl NPC {
+    pub(crate) fn tick_scripted(
+        &mut self,
+        state: &mut SharedGameState,
+        players: [&Player; 2],
+        npc_list: &NPCList,
+    ) -> GameResult {
+        if let Some(npc) = state.npc_scripts.call(NPCScriptHook::Tick, self, player_positions(players), ()) {
+            npc.apply(self);
+        }
+
+        apply_commands(state.npc_scripts.take_commands(), state, npc_list)
+    }
+
+    pub(crate) fn hit_scripted(
+        &mut self,
+        state: &mut SharedGameState,
+        players: [&Player; 2],
+        npc_list: &NPCList,
+        damage: i32,
+    ) -> GameResult {
+        if !state.npc_scripts.has_handler(self.npc_type, NPCScriptHook::Hit) {
+            return Ok(());
+        }
+
+        let args = (damage as i64,);
+        if let Some(npc) = state.npc_scripts.call(NPCScriptHook::Hit, self, player_positions(players), args) {
+            npc.apply(self);
+        }
+
+        apply_commands(state.npc_scripts.take_commands(), state, npc_list)
+    }
+}
+
+impl BossNPC {
+    pub(crate) fn tick_scripted(
+        &mut self,
+        state: &mut SharedGameState,
+        players: [&Player; 2],
+        npc_list: &NPCList,
+    ) -> GameResult {
+        if let Some(parts) = state.npc_scripts.call_boss(self.boss_type, &self.parts, player_positions(players)) {
+            // scripts can't add or remove parts, so only a full array is written back
+            if parts.len() == self.parts.len() {
+                for (part, npc) in self.parts.iter_mut().zip(parts) {
+                    npc.apply(part);
+                }
+            }
+        }
+
+        apply_commands(state.npc_scripts.take_commands(), state, npc_list)
+    }
+}
@@ -2,7 +2,7 @@ use std::io::{Cursor, Read};
 
 use crate::framework::error::GameError::ParseError;
 use crate::framework::error::GameResult;
-use crate::game::scripting::tsc::text_script::TextScriptEncoding;
+use crate::game::scripting::tsc::text_script::{ScriptOperand, ScriptVariable, TextScriptEncoding};
 
 pub fn put_varint(val: i32, out: &mut Vec<u8>) {
     let mut x = ((val as u32) >> 31) ^ ((val as u32) << 1);
@@ -79,6 +79,49 @@ pub fn put_string(buffer: &mut Vec<u8>, out: &mut Vec<u8>, encoding: TextScriptE
     out.append(&mut tmp_buf);
 }
 
+/// Writes an operand of the variable opcodes, prefixed by its kind: 0 = value, 1 = indexed variable,
+/// 2 = named variable followed by the name length and characters.
+pub fn put_operand(operand: &ScriptOperand, out: &mut Vec<u8>) {
+    match operand {
+        ScriptOperand::Value(value) => {
+            put_varint(0, out);
+            put_varint(*value, out);
+        }
+        ScriptOperand::Variable(ScriptVariable::Indexed(index)) => {
+            put_varint(1, out);
+            put_varint(*index as i32, out);
+        }
+        ScriptOperand::Variable(ScriptVariable::Named(name)) => {
+            put_varint(2, out);
+            put_varint(name.chars().count() as i32, out);
+            for chr in name.chars() {
+                put_varint(chr as _, out);
+            }
+        }
+    }
+}
+
+pub fn read_cur_operand(cursor: &mut Cursor<&[u8]>) -> GameResult<ScriptOperand> {
+    match read_cur_varint(cursor)? {
+        0 => Ok(ScriptOperand::Value(read_cur_varint(cursor)?)),
+        1 => Ok(ScriptOperand::Variable(ScriptVariable::Indexed(read_cur_varint(cursor)? as u16))),
+        2 => {
+            let len = read_cur_varint(cursor)?;
+            if len < 0 {
+                return Err(ParseError(format!("Invalid variable name length: {}", len)));
+            }
+
+            let mut name = String::with_capacity((len as usize).min(cursor.get_ref().len()));
+            for _ in 0..len {
+                name.push(std::char::from_u32(read_cur_varint(cursor)? as u32).unwrap_or('?'));
+            }
+
+            Ok(ScriptOperand::Variable(ScriptVariable::Named(name)))
+        }
+        n => Err(ParseError(format!("Invalid operand kind: {}", n))),
+    }
+}
+
 #[test]
 fn test_varint() {
     for n in -4000..=4000 {
@@ -92,3 +135,36 @@ fn test_varint() {
         assert_eq!(result, n);
     }
 }
+
+#[test]
+fn test_operand() {
+    let operands = [
+        ScriptOperand::Value(0),
+        ScriptOperand::Value(i32::MIN),
+        ScriptOperand::Value(i32::MAX),
+        ScriptOperand::Variable(ScriptVariable::Indexed(0)),
+        ScriptOperand::Variable(ScriptVariable::Indexed(u16::MAX)),
+        ScriptOperand::Variable(ScriptVariable::Named("quote_hp".to_owned())),
+        ScriptOperand::Variable(ScriptVariable::Named("ミミガー".to_owned())),
+    ];
+
+    let mut out = Vec::new();
+    for operand in &operands {
+        put_operand(operand, &mut out);
+    }
+
+    let mut cur: Cursor<&[u8]> = Cursor::new(&out);
+    for operand in &operands {
+        assert_eq!(&read_cur_operand(&mut cur).unwrap(), operand);
+    }
+    assert_eq!(cur.position() as usize, out.len());
+
+    let mut invalid = Vec::new();
+    put_varint(3, &mut invalid);
+    assert!(read_cur_operand(&mut Cursor::new(&invalid)).is_err());
+
+    let mut invalid = Vec::new();
+    put_varint(2, &mut invalid);
+    put_varint(-1, &mut invalid);
+    assert!(read_cur_operand(&mut Cursor::new(&invalid)).is_err());
+}
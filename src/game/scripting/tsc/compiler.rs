@@ -1,31 +1,67 @@
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::iter::Peekable;
+use std::rc::Rc;
 use std::str::FromStr;
 
 use itertools::Itertools;
 
 use crate::framework::error::GameError::ParseError;
 use crate::framework::error::GameResult;
-use crate::game::scripting::tsc::bytecode_utils::{put_string, put_varint};
+use crate::game::scripting::tsc::bytecode_utils::{put_operand, put_string, put_varint};
 use crate::game::scripting::tsc::credit_script::CreditScript;
 use crate::game::scripting::tsc::opcodes::{CreditOpCode, TSCOpCode};
-use crate::game::scripting::tsc::parse_utils::{expect_char, read_number, skip_until};
+use crate::game::scripting::tsc::parse_utils::{expect_char, read_number, read_operand, skip_until};
+use crate::game::scripting::tsc::preprocessor::SourceMap;
 use crate::game::scripting::tsc::text_script::{TextScript, TextScriptEncoding};
 
 impl TextScript {
     /// Compiles a decrypted text script data into internal bytecode.
     pub fn compile(data: &[u8], strict: bool, encoding: TextScriptEncoding) -> GameResult<TextScript> {
+        let event_map = TextScript::compile_events(&mut data.iter().copied().peekable(), strict, encoding)?;
+
+        Ok(TextScript { event_map, source_map: None })
+    }
+
+    /// Compiles a preprocessed text script, errors point to the original location according to the source map.
+    pub fn compile_mapped(
+        data: &[u8],
+        strict: bool,
+        encoding: TextScriptEncoding,
+        source_map: SourceMap,
+    ) -> GameResult<TextScript> {
+        let position = Cell::new(0usize);
+        let mut iter = data.iter().copied().inspect(|_| position.set(position.get() + 1)).peekable();
+
+        match TextScript::compile_events(&mut iter, strict, encoding) {
+            Ok(event_map) => Ok(TextScript { event_map, source_map: Some(Rc::new(source_map)) }),
+            Err(err) => {
+                let offset = position.get().min(data.len());
+                let line = data[..offset].iter().filter(|&&c| c == b'\n').count();
+
+                match source_map.locate(line) {
+                    Some((file, line)) => Err(ParseError(format!("{}:{}: {}", file, line, err))),
+                    None => Err(err),
+                }
+            }
+        }
+    }
+
+    fn compile_events<I: Iterator<Item=u8>>(
+        iter: &mut Peekable<I>,
+        strict: bool,
+        encoding: TextScriptEncoding,
+    ) -> GameResult<HashMap<u16, Vec<u8>>> {
         let mut event_map = HashMap::new();
-        let mut iter = data.iter().copied().peekable();
         let mut last_event = 0;
 
         while let Some(&chr) = iter.peek() {
             match chr {
                 b'#' => {
                     iter.next();
-                    let event_num = read_number(&mut iter)? as u16;
+                    let event_num = read_number(iter)? as u16;
                     if iter.peek().is_some() {
-                        skip_until(b'\n', &mut iter)?;
+                        skip_until(b'\n', iter)?;
                         iter.next();
                     }
                     last_event = event_num;
@@ -35,7 +71,7 @@ impl TextScript {
                             return Err(ParseError(format!("Event {} has been defined twice.", event_num)));
                         }
 
-                        match skip_until(b'#', &mut iter).ok() {
+                        match skip_until(b'#', iter).ok() {
                             Some(_) => {
                                 continue;
                             }
@@ -45,7 +81,7 @@ impl TextScript {
                         }
                     }
 
-                    let bytecode = TextScript::compile_event(&mut iter, strict, encoding)?;
+                    let bytecode = TextScript::compile_event(iter, strict, encoding)?;
                     log::info!("Successfully compiled event #{} ({} bytes generated).", event_num, bytecode.len());
                     event_map.insert(event_num, bytecode);
                 }
@@ -64,7 +100,7 @@ impl TextScript {
             }
         }
 
-        Ok(TextScript { event_map })
+        Ok(event_map)
     }
 
     fn compile_event<I: Iterator<Item=u8>>(
@@ -303,6 +339,49 @@ impl TextScript {
                 put_varint(operand_c as i32, out);
                 put_varint(operand_d as i32, out);
             }
+            // Variable codes
+            TSCOpCode::VAs | TSCOpCode::VAp | TSCOpCode::VAm | TSCOpCode::VAx | TSCOpCode::VAd | TSCOpCode::VAr => {
+                let variable = read_operand(iter, true)?;
+                TextScript::read_separator(strict, iter)?;
+                let value = read_operand(iter, false)?;
+
+                put_varint(instr as i32, out);
+                put_operand(&variable, out);
+                put_operand(&value, out);
+            }
+            TSCOpCode::VAJ => {
+                let variable = read_operand(iter, true)?;
+                TextScript::read_separator(strict, iter)?;
+                let comparison = read_number(iter)?;
+                TextScript::read_separator(strict, iter)?;
+                let value = read_operand(iter, false)?;
+                TextScript::read_separator(strict, iter)?;
+                let event_num = read_number(iter)?;
+
+                put_varint(instr as i32, out);
+                put_operand(&variable, out);
+                put_varint(comparison, out);
+                put_operand(&value, out);
+                put_varint(event_num, out);
+            }
+            TSCOpCode::VRN => {
+                let variable = read_operand(iter, true)?;
+                TextScript::read_separator(strict, iter)?;
+                let min = read_operand(iter, false)?;
+                TextScript::read_separator(strict, iter)?;
+                let max = read_operand(iter, false)?;
+
+                put_varint(instr as i32, out);
+                put_operand(&variable, out);
+                put_operand(&min, out);
+                put_operand(&max, out);
+            }
+            TSCOpCode::VNU => {
+                let variable = read_operand(iter, true)?;
+
+                put_varint(instr as i32, out);
+                put_operand(&variable, out);
+            }
             TSCOpCode::_NOP | TSCOpCode::_UNI | TSCOpCode::_STR | TSCOpCode::_END => {
                 unreachable!()
             }
@@ -310,6 +389,14 @@ impl TextScript {
 
         Ok(())
     }
+
+    fn read_separator<I: Iterator<Item=u8>>(strict: bool, iter: &mut Peekable<I>) -> GameResult {
+        if strict {
+            expect_char(b':', iter)
+        } else {
+            iter.next().map(|_| ()).ok_or_else(|| ParseError("Script unexpectedly ended.".to_owned()))
+        }
+    }
 }
 
 impl CreditScript {
@@ -5,7 +5,7 @@ use num_traits::FromPrimitive;
 
 use crate::framework::error::GameError::InvalidValue;
 use crate::framework::error::GameResult;
-use crate::game::scripting::tsc::bytecode_utils::read_cur_varint;
+use crate::game::scripting::tsc::bytecode_utils::{read_cur_operand, read_cur_varint};
 use crate::game::scripting::tsc::opcodes::TSCOpCode;
 use crate::game::scripting::tsc::text_script::TextScript;
 
@@ -144,6 +144,39 @@ impl TextScript {
 
                             writeln!(&mut result, "{:?}({}, {}, {}, {})", op, par_a, par_b, par_c, par_d).unwrap();
                         }
+                        // Variable codes
+                        TSCOpCode::VAs
+                        | TSCOpCode::VAp
+                        | TSCOpCode::VAm
+                        | TSCOpCode::VAx
+                        | TSCOpCode::VAd
+                        | TSCOpCode::VAr => {
+                            let variable = read_cur_operand(&mut cursor)?;
+                            let value = read_cur_operand(&mut cursor)?;
+
+                            writeln!(&mut result, "{:?}({}, {})", op, variable, value).unwrap();
+                        }
+                        TSCOpCode::VAJ => {
+                            let variable = read_cur_operand(&mut cursor)?;
+                            let comparison = read_cur_varint(&mut cursor)?;
+                            let value = read_cur_operand(&mut cursor)?;
+                            let event_num = read_cur_varint(&mut cursor)?;
+
+                            writeln!(&mut result, "{:?}({}, {}, {}, {})", op, variable, comparison, value, event_num)
+                                .unwrap();
+                        }
+                        TSCOpCode::VRN => {
+                            let variable = read_cur_operand(&mut cursor)?;
+                            let min = read_cur_operand(&mut cursor)?;
+                            let max = read_cur_operand(&mut cursor)?;
+
+                            writeln!(&mut result, "{:?}({}, {}, {})", op, variable, min, max).unwrap();
+                        }
+                        TSCOpCode::VNU => {
+                            let variable = read_cur_operand(&mut cursor)?;
+
+                            writeln!(&mut result, "{:?}({})", op, variable).unwrap();
+                        }
                         TSCOpCode::_STR => {
                             let len = read_cur_varint(&mut cursor)?;
 
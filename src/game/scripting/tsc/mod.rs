@@ -5,4 +5,5 @@ mod decompiler;
 mod encryption;
 mod opcodes;
 mod parse_utils;
+pub mod preprocessor;
 pub mod text_script;
@@ -265,6 +265,153 @@ pub enum TSCOpCode {
     /// <FRE related to player 2?
     FR2,
     // ---- Custom opcodes, for use by modders ----
+    /// <VA=vvvv:xxxx, Sets variable vvvv to xxxx.
+    /// Variables are either indexed (`0000`-`9999`) or named (`$name`), values can be
+    /// a number (optionally prefixed with `-`) or another variable.
+    #[strum(serialize = "VA=")]
+    VAs,
+    /// <VA+vvvv:xxxx, Adds xxxx to variable vvvv
+    #[strum(serialize = "VA+")]
+    VAp,
+    /// <VA-vvvv:xxxx, Subtracts xxxx from variable vvvv
+    #[strum(serialize = "VA-")]
+    VAm,
+    /// <VA*vvvv:xxxx, Multiplies variable vvvv by xxxx
+    #[strum(serialize = "VA*")]
+    VAx,
+    /// <VA/vvvv:xxxx, Divides variable vvvv by xxxx, division by zero leaves the variable unchanged
+    #[strum(serialize = "VA/")]
+    VAd,
+    /// <VA%vvvv:xxxx, Sets variable vvvv to the remainder of dividing it by xxxx
+    #[strum(serialize = "VA%")]
+    VAr,
+    /// <VAJvvvv:cccc:xxxx:yyyy, Jumps to event yyyy if variable vvvv compared to xxxx using cccc is true
+    /// (0 = equal, 1 = not equal, 2 = less, 3 = less or equal, 4 = greater, 5 = greater or equal)
+    VAJ,
+    /// <VRNvvvv:xxxx:yyyy, Sets variable vvvv to a random number between xxxx and yyyy (inclusive)
+    VRN,
+    /// <VNUvvvv, Displays the value of variable vvvv
+    VNU,
+}
+
+impl TSCOpCode {
+    /// Number of colon separated operands the opcode takes in TSC source.
+    pub fn operand_count(self) -> usize {
+        match self {
+            TSCOpCode::_NOP
+            | TSCOpCode::_UNI
+            | TSCOpCode::_STR
+            | TSCOpCode::_END
+            | TSCOpCode::AEp
+            | TSCOpCode::CAT
+            | TSCOpCode::CIL
+            | TSCOpCode::CLO
+            | TSCOpCode::CLR
+            | TSCOpCode::CPS
+            | TSCOpCode::CRE
+            | TSCOpCode::CSS
+            | TSCOpCode::END
+            | TSCOpCode::ESC
+            | TSCOpCode::FLA
+            | TSCOpCode::FMU
+            | TSCOpCode::FRE
+            | TSCOpCode::HMC
+            | TSCOpCode::INI
+            | TSCOpCode::KEY
+            | TSCOpCode::LDP
+            | TSCOpCode::MLP
+            | TSCOpCode::MM0
+            | TSCOpCode::MNA
+            | TSCOpCode::MS2
+            | TSCOpCode::MS3
+            | TSCOpCode::MSG
+            | TSCOpCode::NOD
+            | TSCOpCode::PRI
+            | TSCOpCode::RMU
+            | TSCOpCode::SAT
+            | TSCOpCode::SLP
+            | TSCOpCode::SMC
+            | TSCOpCode::SPS
+            | TSCOpCode::STC
+            | TSCOpCode::SVP
+            | TSCOpCode::TUR
+            | TSCOpCode::WAS
+            | TSCOpCode::ZAM
+            | TSCOpCode::HM2
+            | TSCOpCode::POP
+            | TSCOpCode::KE2
+            | TSCOpCode::FR2 => 0,
+            TSCOpCode::BOA
+            | TSCOpCode::BSL
+            | TSCOpCode::FOM
+            | TSCOpCode::QUA
+            | TSCOpCode::UNI
+            | TSCOpCode::MYB
+            | TSCOpCode::MYD
+            | TSCOpCode::FAI
+            | TSCOpCode::FAO
+            | TSCOpCode::WAI
+            | TSCOpCode::FAC
+            | TSCOpCode::GIT
+            | TSCOpCode::NUM
+            | TSCOpCode::DNA
+            | TSCOpCode::DNP
+            | TSCOpCode::FLm
+            | TSCOpCode::FLp
+            | TSCOpCode::MPp
+            | TSCOpCode::SKm
+            | TSCOpCode::SKp
+            | TSCOpCode::EQp
+            | TSCOpCode::EQm
+            | TSCOpCode::MLp
+            | TSCOpCode::ITp
+            | TSCOpCode::ITm
+            | TSCOpCode::AMm
+            | TSCOpCode::MPJ
+            | TSCOpCode::YNJ
+            | TSCOpCode::EVE
+            | TSCOpCode::XX1
+            | TSCOpCode::SIL
+            | TSCOpCode::LIp
+            | TSCOpCode::SOU
+            | TSCOpCode::CMU
+            | TSCOpCode::SSS
+            | TSCOpCode::ACH
+            | TSCOpCode::S2MV
+            | TSCOpCode::S2PJ
+            | TSCOpCode::PSH
+            | TSCOpCode::VNU => 1,
+            TSCOpCode::FON
+            | TSCOpCode::FOB
+            | TSCOpCode::MOV
+            | TSCOpCode::AMp
+            | TSCOpCode::NCJ
+            | TSCOpCode::ECJ
+            | TSCOpCode::FLJ
+            | TSCOpCode::ITJ
+            | TSCOpCode::SKJ
+            | TSCOpCode::AMJ
+            | TSCOpCode::UNJ
+            | TSCOpCode::SMP
+            | TSCOpCode::PSp
+            | TSCOpCode::IpN
+            | TSCOpCode::FFm
+            | TSCOpCode::VAs
+            | TSCOpCode::VAp
+            | TSCOpCode::VAm
+            | TSCOpCode::VAx
+            | TSCOpCode::VAd
+            | TSCOpCode::VAr => 2,
+            TSCOpCode::ANP
+            | TSCOpCode::CNP
+            | TSCOpCode::INP
+            | TSCOpCode::TAM
+            | TSCOpCode::CMP
+            | TSCOpCode::INJ
+            | TSCOpCode::VRN => 3,
+            TSCOpCode::TRA | TSCOpCode::MNP | TSCOpCode::SNP | TSCOpCode::VAJ => 4,
+        }
+    }
 }
 
 #[derive(FromPrimitive, PartialEq, Copy, Clone)]
@@ -2,6 +2,7 @@ use std::iter::Peekable;
 
 use crate::framework::error::GameError::ParseError;
 use crate::framework::error::GameResult;
+use crate::game::scripting::tsc::text_script::{ScriptOperand, ScriptVariable};
 
 pub fn expect_char<I: Iterator<Item=u8>>(expect: u8, iter: &mut I) -> GameResult {
     let res = iter.next();
@@ -35,3 +36,40 @@ pub fn read_number<I: Iterator<Item=u8>>(iter: &mut Peekable<I>) -> GameResult<i
         .and_then(|result| iter.next().map(|v| result + v.wrapping_sub(b'0') as i32))
         .ok_or_else(|| ParseError("Script unexpectedly ended.".to_string()))
 }
+
+/// Reads an operand of the variable opcodes: a 4 digit number optionally prefixed with `-`,
+/// or a variable reference in `$name` or `$0000` form.
+/// If `variable` is set, a bare number is treated as an indexed variable instead of a value.
+pub fn read_operand<I: Iterator<Item=u8>>(iter: &mut Peekable<I>, variable: bool) -> GameResult<ScriptOperand> {
+    match iter.peek() {
+        Some(b'$') => {
+            iter.next();
+
+            let mut name = String::new();
+            while let Some(&chr) = iter.peek() {
+                if !chr.is_ascii_alphanumeric() && chr != b'_' {
+                    break;
+                }
+
+                name.push(chr as char);
+                iter.next();
+            }
+
+            if name.is_empty() {
+                return Err(ParseError("Expected a variable name after $.".to_string()));
+            }
+
+            Ok(ScriptOperand::Variable(match name.parse::<u16>() {
+                Ok(index) if name.bytes().all(|c| c.is_ascii_digit()) => ScriptVariable::Indexed(index),
+                _ => ScriptVariable::Named(name),
+            }))
+        }
+        Some(b'-') if !variable => {
+            iter.next();
+
+            Ok(ScriptOperand::Value(-read_number(iter)?))
+        }
+        _ if variable => Ok(ScriptOperand::Variable(ScriptVariable::Indexed(read_number(iter)? as u16))),
+        _ => Ok(ScriptOperand::Value(read_number(iter)?)),
+    }
+}
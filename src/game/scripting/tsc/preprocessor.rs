@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::framework::error::GameError::ParseError;
+use crate::framework::error::GameResult;
+use crate::game::scripting::tsc::opcodes::TSCOpCode;
+
+/// Maximum depth of nested `#include`s, guards against include cycles.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Line a script has to start with to be preprocessed, other scripts are compiled as they are.
+const PRAGMA: &[u8] = b"#pragma preprocess";
+
+/// Maps lines of a preprocessed script back to the files and lines they came from.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<String>,
+    /// (file index, 1-based line) for every line of the preprocessed script.
+    lines: Vec<(usize, usize)>,
+    /// Line of the preprocessed script each event header is on.
+    events: HashMap<u16, usize>,
+}
+
+impl SourceMap {
+    /// Original location of given 0-based line of the preprocessed script.
+    pub fn locate(&self, line: usize) -> Option<(&str, usize)> {
+        self.lines.get(line).map(|&(file, line)| (self.files[file].as_str(), line))
+    }
+
+    /// Original location of the header of given event.
+    pub fn locate_event(&self, event_num: u16) -> Option<(&str, usize)> {
+        self.events.get(&event_num).and_then(|&line| self.locate(line))
+    }
+}
+
+/// Expands `#include`s, `#define`d names and strips `//` comment lines before the script gets compiled.
+/// Only scripts starting with a `#pragma preprocess` line are preprocessed, see [`Preprocessor::is_enabled`].
+///
+/// ```text
+/// #pragma preprocess
+/// #include "Stage/common.tsi"
+/// #define FLAG_GOT_ARTHUR 0500
+/// #define EV_ARTHUR 0200
+///
+/// // Arthur's grave
+/// #EV_ARTHUR
+/// <PRI<FLJFLAG_GOT_ARTHUR:0201<MSGHello<NOD<END
+/// ```
+/// Names are substituted in event headers and in as many operands as the command takes, so message text stays untouched.
+/// Includes are resolved with the provided function, relative to the data directory.
+pub struct Preprocessor<'a> {
+    resolve: Box<dyn FnMut(&str) -> GameResult<Vec<u8>> + 'a>,
+    defines: HashMap<String, String>,
+    include_stack: Vec<String>,
+    output: Vec<u8>,
+    source_map: SourceMap,
+}
+
+impl<'a> Preprocessor<'a> {
+    pub fn new(resolve: impl FnMut(&str) -> GameResult<Vec<u8>> + 'a) -> Preprocessor<'a> {
+        Preprocessor {
+            resolve: Box::new(resolve),
+            defines: HashMap::new(),
+            include_stack: Vec::new(),
+            output: Vec::new(),
+            source_map: SourceMap::default(),
+        }
+    }
+
+    /// Whether the script opted into preprocessing, that is its first non-empty line is `#pragma preprocess`.
+    pub fn is_enabled(data: &[u8]) -> bool {
+        data.split(|&c| c == b'\n').map(trim).find(|line| !line.is_empty()) == Some(PRAGMA)
+    }
+
+    /// Preprocesses a script, returns the expanded source and a map back to the original lines.
+    pub fn process(mut self, name: &str, data: &[u8]) -> GameResult<(Vec<u8>, SourceMap)> {
+        self.process_file(name, data)?;
+
+        Ok((self.output, self.source_map))
+    }
+
+    fn process_file(&mut self, name: &str, data: &[u8]) -> GameResult {
+        if self.include_stack.len() >= MAX_INCLUDE_DEPTH || self.include_stack.iter().any(|f| f == name) {
+            return Err(ParseError(format!("Recursive #include of {} in {}.", name, self.include_stack.join(" -> "))));
+        }
+
+        let file = self.source_map.files.len();
+        self.source_map.files.push(name.to_owned());
+        self.include_stack.push(name.to_owned());
+
+        let mut lines = data.split(|&c| c == b'\n').peekable();
+        let mut line_num = 0;
+
+        while let Some(line) = lines.next() {
+            line_num += 1;
+            let is_last = lines.peek().is_none();
+            if is_last && line.is_empty() {
+                break;
+            }
+
+            // included files always end with a line break, so they don't run into the next line of the includer
+            let has_newline = !is_last || self.include_stack.len() > 1;
+            let trimmed = trim(line);
+
+            if let Some(args) = trimmed.strip_prefix(b"#include") {
+                let path = parse_include(args).ok_or_else(|| {
+                    ParseError(format!("{}:{}: expected a quoted path after #include.", name, line_num))
+                })?;
+                let contents = (self.resolve)(&path)
+                    .map_err(|err| ParseError(format!("{}:{}: failed to include {}: {}", name, line_num, path, err)))?;
+
+                self.process_file(&path, &contents)?;
+                continue;
+            }
+
+            if let Some(args) = trimmed.strip_prefix(b"#define") {
+                let (key, value) = parse_define(args).ok_or_else(|| {
+                    ParseError(format!("{}:{}: expected a name and value after #define.", name, line_num))
+                })?;
+                let value = self.defines.get(&value).cloned().unwrap_or(value);
+
+                self.defines.insert(key, value);
+                continue;
+            }
+
+            if trimmed.starts_with(b"//") || trimmed == PRAGMA {
+                continue;
+            }
+
+            let start = self.output.len();
+            self.substitute(line);
+
+            if let Some(event_num) = parse_event_header(&self.output[start..]) {
+                self.source_map.events.insert(event_num, self.source_map.lines.len());
+            }
+
+            self.source_map.lines.push((file, line_num));
+            if has_newline {
+                self.output.push(b'\n');
+            }
+        }
+
+        self.include_stack.pop();
+
+        Ok(())
+    }
+
+    /// Copies the line to the output, replacing defined names in event headers and command operands.
+    /// Unknown commands are treated as if they had no operands.
+    fn substitute(&mut self, line: &[u8]) {
+        if self.defines.is_empty() {
+            self.output.extend_from_slice(line);
+            return;
+        }
+
+        let mut i = 0;
+
+        if line.first() == Some(&b'#') && line.get(1).map_or(false, |&c| is_ident_start(c)) {
+            self.output.push(b'#');
+            i = self.substitute_ident(line, 1);
+        }
+
+        while i < line.len() {
+            if line[i] != b'<' || i + 4 > line.len() {
+                self.output.push(line[i]);
+                i += 1;
+                continue;
+            }
+
+            // opcode
+            let operands = std::str::from_utf8(&line[i + 1..i + 4])
+                .ok()
+                .and_then(|code| TSCOpCode::from_str(code).ok())
+                .map_or(0, TSCOpCode::operand_count);
+            self.output.extend_from_slice(&line[i..i + 4]);
+            i += 4;
+
+            // operands, separated by colons
+            for operand in 0..operands {
+                match line.get(i) {
+                    Some(&c) if is_ident_start(c) => i = self.substitute_ident(line, i),
+                    Some(&c) if c.is_ascii_digit() || c == b'-' || c == b'$' => {
+                        let end = i + 1 + line[i + 1..].iter().take_while(|&&c| is_ident(c)).count();
+                        self.output.extend_from_slice(&line[i..end]);
+                        i = end;
+                    }
+                    _ => break,
+                }
+
+                if operand + 1 == operands || line.get(i) != Some(&b':') {
+                    break;
+                }
+
+                self.output.push(b':');
+                i += 1;
+            }
+        }
+    }
+
+    /// Copies an identifier starting at given position, replaced by its value if it's defined.
+    fn substitute_ident(&mut self, line: &[u8], start: usize) -> usize {
+        let end = start + line[start..].iter().take_while(|&&c| is_ident(c)).count();
+        let ident = String::from_utf8_lossy(&line[start..end]);
+
+        match self.defines.get(ident.as_ref()) {
+            Some(value) => self.output.extend_from_slice(value.as_bytes()),
+            None => self.output.extend_from_slice(&line[start..end]),
+        }
+
+        end
+    }
+}
+
+fn is_ident_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_'
+}
+
+fn is_ident(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+fn trim(line: &[u8]) -> &[u8] {
+    let start = line.iter().position(|c| !c.is_ascii_whitespace()).unwrap_or(line.len());
+    let end = line.iter().rposition(|c| !c.is_ascii_whitespace()).map_or(start, |i| i + 1);
+
+    &line[start..end.max(start)]
+}
+
+fn parse_include(args: &[u8]) -> Option<String> {
+    let args = trim(args);
+    let path = args.strip_prefix(b"\"")?.strip_suffix(b"\"")?;
+
+    if path.is_empty() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(path).into_owned())
+}
+
+fn parse_define(args: &[u8]) -> Option<(String, String)> {
+    let args = trim(args);
+    let split = args.iter().position(|c| c.is_ascii_whitespace())?;
+    let (name, value) = (&args[..split], trim(&args[split..]));
+
+    if name.is_empty() || !is_ident_start(name[0]) || !name.iter().all(|&c| is_ident(c)) || value.is_empty() {
+        return None;
+    }
+
+    Some((String::from_utf8_lossy(name).into_owned(), String::from_utf8_lossy(value).into_owned()))
+}
+
+fn parse_event_header(line: &[u8]) -> Option<u16> {
+    let digits = line.strip_prefix(b"#")?;
+    let digits = &digits[..digits.iter().take_while(|c| c.is_ascii_digit()).count()];
+
+    if digits.len() != 4 {
+        return None;
+    }
+
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(data: &[u8]) -> (String, SourceMap) {
+        let resolve = |path: &str| match path {
+            "common.tsi" => Ok(b"#define EV_COMMON 0300\n// shared events\n".to_vec()),
+            _ => Err(ParseError(format!("missing {}", path))),
+        };
+
+        let (output, source_map) = Preprocessor::new(resolve).process("test.tsc", data).unwrap();
+        (String::from_utf8(output).unwrap(), source_map)
+    }
+
+    #[test]
+    fn test_preprocessor_opt_in() {
+        assert!(Preprocessor::is_enabled(b"\r\n#pragma preprocess\r\n#0100\r\n"));
+        assert!(!Preprocessor::is_enabled(b"#0100\n#pragma preprocess\n"));
+        assert!(!Preprocessor::is_enabled(b"// comment\n#0100\n<END\n"));
+    }
+
+    #[test]
+    fn test_preprocessor_substitution() {
+        let (output, _) = process(
+            b"#pragma preprocess\n#define FLAG 0500\n#define EV 0200\n#EV\n<FLJFLAG:EV<MSGFLAG EV<WAI0010<VAJ$v:0000:FLAG:EV",
+        );
+
+        assert_eq!(output, "#0200\n<FLJ0500:0200<MSGFLAG EV<WAI0010<VAJ$v:0000:0500:0200");
+    }
+
+    #[test]
+    fn test_preprocessor_operand_count() {
+        // only as many operands as the opcode takes are substituted, the rest is message text
+        let (output, _) = process(b"#pragma preprocess\n#define A 0001\n<FAIA:A<UNKA<KEYA");
+
+        assert_eq!(output, "<FAI0001:A<UNKA<KEYA");
+    }
+
+    #[test]
+    fn test_preprocessor_includes_and_source_map() {
+        let (output, source_map) =
+            process(b"#pragma preprocess\n#include \"common.tsi\"\n// comment\n#EV_COMMON\n<END");
+
+        assert_eq!(output, "#0300\n<END");
+        assert_eq!(source_map.locate_event(300), Some(("test.tsc", 4)));
+        assert_eq!(source_map.locate(1), Some(("test.tsc", 5)));
+    }
+
+    #[test]
+    fn test_preprocessor_include_errors() {
+        let resolve = |path: &str| Err(ParseError(format!("missing {}", path)));
+        assert!(Preprocessor::new(resolve).process("test.tsc", b"#include \"missing.tsi\"").is_err());
+
+        let resolve = |_: &str| Ok(b"#include \"self.tsi\"".to_vec());
+        assert!(Preprocessor::new(resolve).process("self.tsi", b"#include \"self.tsi\"").is_err());
+    }
+}
@@ -1,8 +1,10 @@
 use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt;
 use std::io;
 use std::io::Cursor;
+use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::ops::Not;
@@ -16,19 +18,23 @@ use crate::common::{Direction, FadeDirection, FadeState, Rect};
 use crate::engine_constants::EngineConstants;
 use crate::entity::GameEntity;
 use crate::framework::context::Context;
+use crate::framework::error::GameError::{InvalidValue, ParseError};
 use crate::framework::error::GameResult;
+use crate::framework::filesystem;
 use crate::game::frame::UpdateTarget;
 use crate::game::npc::NPC;
 use crate::game::player::{ControlMode, TargetPlayer};
-use crate::game::scripting::tsc::bytecode_utils::read_cur_varint;
+use crate::game::scripting::tsc::bytecode_utils::{read_cur_operand, read_cur_varint};
 use crate::game::scripting::tsc::encryption::decrypt_tsc;
 use crate::game::scripting::tsc::opcodes::TSCOpCode;
+use crate::game::scripting::tsc::preprocessor::{Preprocessor, SourceMap};
 use crate::game::shared_game_state::ReplayState;
 use crate::game::shared_game_state::SharedGameState;
 use crate::game::weapon::WeaponType;
 use crate::graphics::font::{Font, Symbols};
 use crate::input::touch_controls::TouchControlType;
 use crate::scene::game_scene::GameScene;
+use crate::util::rng::RNG;
 
 const TSC_SUBSTITUTION_MAP_SIZE: usize = 1;
 
@@ -176,6 +182,56 @@ impl From<TextScriptEncoding> for &'static encoding_rs::Encoding {
     }
 }
 
+/// Integer variable used by the `<VA*` family of opcodes, persisted in saves.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ScriptVariable {
+    /// `0000`-`9999` or `$0000`-`$9999`
+    Indexed(u16),
+    /// `$name`
+    Named(String),
+}
+
+impl fmt::Display for ScriptVariable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptVariable::Indexed(index) => write!(f, "${:04}", index),
+            ScriptVariable::Named(name) => write!(f, "${}", name),
+        }
+    }
+}
+
+/// Operand of the variable opcodes, either a literal value or a variable reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptOperand {
+    Value(i32),
+    Variable(ScriptVariable),
+}
+
+impl ScriptOperand {
+    pub fn eval(&self, state: &SharedGameState) -> i32 {
+        match self {
+            ScriptOperand::Value(value) => *value,
+            ScriptOperand::Variable(variable) => state.get_variable(variable),
+        }
+    }
+
+    pub fn into_variable(self) -> GameResult<ScriptVariable> {
+        match self {
+            ScriptOperand::Variable(variable) => Ok(variable),
+            ScriptOperand::Value(value) => Err(InvalidValue(format!("Expected a variable, found {}.", value))),
+        }
+    }
+}
+
+impl fmt::Display for ScriptOperand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptOperand::Value(value) => write!(f, "{}", value),
+            ScriptOperand::Variable(variable) => write!(f, "{}", variable),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 #[repr(u8)]
 pub enum TextScriptLine {
@@ -309,6 +365,19 @@ impl Scripts {
 
         None
     }
+
+    /// Original file and line of the event that would be run by [`Scripts::find_script`].
+    pub fn locate_event(&self, mode: ScriptMode, event_num: u16) -> Option<(&str, usize)> {
+        let script = match mode {
+            ScriptMode::Debug if self.debug_script.has_event(event_num) => &self.debug_script,
+            ScriptMode::Map | ScriptMode::Debug if self.scene_script.has_event(event_num) => &self.scene_script,
+            ScriptMode::Map | ScriptMode::Debug => &self.global_script,
+            ScriptMode::Inventory => &self.inventory_script,
+            ScriptMode::StageSelect => &self.stage_select_script,
+        };
+
+        script.locate_event(event_num)
+    }
 }
 
 impl TextScriptVM {
@@ -445,7 +514,13 @@ impl TextScriptVM {
                     }
 
                     state.textscript_vm.state = if let Some((_, bytecode)) = cached_event {
-                        TextScriptVM::execute(bytecode, event, ip, state, game_scene, ctx)?
+                        TextScriptVM::execute(bytecode, event, ip, state, game_scene, ctx).map_err(|err| {
+                            if let Some((file, line)) = scripts.locate_event(state.textscript_vm.mode, event) {
+                                log::error!("Error in event #{:04} ({}:{}): {}", event, file, line, err);
+                            }
+
+                            err
+                        })?
                     } else {
                         TextScriptExecutionState::Ended
                     };
@@ -630,9 +705,8 @@ impl TextScriptVM {
                     if state.settings.touch_controls && !state.control_flags.control_enabled() {
                         state.touch_controls.control_type = TouchControlType::None;
 
-                        let (off_left, _, off_right, off_bottom) =
-                            crate::framework::graphics::screen_insets_scaled(ctx, state.scale);
-                        let box_x = ((state.canvas_size.0 - off_left - off_right) / 2.0) as isize + 51;
+                        let (off_left, _, off_right, off_bottom) = state.viewport_insets(ctx);
+                        let box_x = ((state.canvas_size.0 - off_left - off_right) / 2.0 + off_left) as isize + 51;
                         let box_y = (state.canvas_size.1 - off_bottom - 96.0 - 10.0) as isize;
 
                         if state.touch_controls.consume_click_in(Rect::new_size(box_x, box_y, 40, 40)) {
@@ -1081,7 +1155,7 @@ impl TextScriptVM {
             TSCOpCode::AMJ => {
                 let weapon = read_cur_varint(&mut cursor)? as u8;
                 let event_num = read_cur_varint(&mut cursor)? as u16;
-                let weapon_type: Option<WeaponType> = FromPrimitive::from_u8(weapon);
+                let weapon_type = WeaponType::from_id(weapon, &state.constants);
 
                 if weapon_type.is_some() && game_scene.inventory_player1.has_weapon(weapon_type.unwrap()) {
                     state.textscript_vm.clear_text_box();
@@ -1291,6 +1365,72 @@ impl TextScriptVM {
 
                 exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
             }
+            TSCOpCode::VAs | TSCOpCode::VAp | TSCOpCode::VAm | TSCOpCode::VAx | TSCOpCode::VAd | TSCOpCode::VAr => {
+                let variable = read_cur_operand(&mut cursor)?.into_variable()?;
+                let value = read_cur_operand(&mut cursor)?.eval(state);
+                let current = state.get_variable(&variable);
+
+                let result = match op {
+                    TSCOpCode::VAp => current.wrapping_add(value),
+                    TSCOpCode::VAm => current.wrapping_sub(value),
+                    TSCOpCode::VAx => current.wrapping_mul(value),
+                    TSCOpCode::VAd => current.checked_div(value).unwrap_or(current),
+                    TSCOpCode::VAr => current.checked_rem(value).unwrap_or(current),
+                    _ => value,
+                };
+                state.set_variable(variable, result);
+
+                exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+            }
+            TSCOpCode::VAJ => {
+                let variable = read_cur_operand(&mut cursor)?.into_variable()?;
+                let comparison = read_cur_varint(&mut cursor)?;
+                let value = read_cur_operand(&mut cursor)?.eval(state);
+                let event_num = read_cur_varint(&mut cursor)? as u16;
+                let current = state.get_variable(&variable);
+
+                let jump = match comparison {
+                    0 => current == value,
+                    1 => current != value,
+                    2 => current < value,
+                    3 => current <= value,
+                    4 => current > value,
+                    5 => current >= value,
+                    _ => {
+                        log::warn!("Unknown <VAJ comparison: {}", comparison);
+                        false
+                    }
+                };
+
+                if jump {
+                    state.textscript_vm.clear_text_box();
+                    exec_state = TextScriptExecutionState::Running(event_num, 0);
+                } else {
+                    exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+                }
+            }
+            TSCOpCode::VRN => {
+                let variable = read_cur_operand(&mut cursor)?.into_variable()?;
+                let min = read_cur_operand(&mut cursor)?.eval(state);
+                let max = read_cur_operand(&mut cursor)?.eval(state);
+
+                let value = state.game_rng.range(min.min(max)..min.max(max));
+                state.set_variable(variable, value);
+
+                exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+            }
+            TSCOpCode::VNU => {
+                let variable = read_cur_operand(&mut cursor)?.into_variable()?;
+                let mut str = state.get_variable(&variable).to_string().chars().collect();
+
+                match state.textscript_vm.current_line {
+                    TextScriptLine::Line1 => state.textscript_vm.line_1.append(&mut str),
+                    TextScriptLine::Line2 => state.textscript_vm.line_2.append(&mut str),
+                    TextScriptLine::Line3 => state.textscript_vm.line_3.append(&mut str),
+                }
+
+                exec_state = TextScriptExecutionState::Running(event, cursor.position() as u32);
+            }
             TSCOpCode::GIT => {
                 let item = read_cur_varint(&mut cursor)? as u16;
                 state.textscript_vm.item = item;
@@ -1759,7 +1899,7 @@ impl TextScriptVM {
             TSCOpCode::AMp => {
                 let weapon_id = read_cur_varint(&mut cursor)? as u8;
                 let max_ammo = read_cur_varint(&mut cursor)? as u16;
-                let weapon_type: Option<WeaponType> = FromPrimitive::from_u8(weapon_id);
+                let weapon_type = WeaponType::from_id(weapon_id, &state.constants);
 
                 state.textscript_vm.numbers[0] = max_ammo;
 
@@ -1775,7 +1915,7 @@ impl TextScriptVM {
             }
             TSCOpCode::AMm => {
                 let weapon_id = read_cur_varint(&mut cursor)? as u8;
-                let weapon_type: Option<WeaponType> = FromPrimitive::from_u8(weapon_id);
+                let weapon_type = WeaponType::from_id(weapon_id, &state.constants);
 
                 if let Some(wtype) = weapon_type {
                     game_scene.inventory_player1.remove_weapon(wtype);
@@ -1794,8 +1934,8 @@ impl TextScriptVM {
                 let old_weapon_id = read_cur_varint(&mut cursor)? as u8;
                 let new_weapon_id = read_cur_varint(&mut cursor)? as u8;
                 let max_ammo = read_cur_varint(&mut cursor)? as u16;
-                let old_weapon_type: Option<WeaponType> = FromPrimitive::from_u8(old_weapon_id);
-                let new_weapon_type: Option<WeaponType> = FromPrimitive::from_u8(new_weapon_id);
+                let old_weapon_type = WeaponType::from_id(old_weapon_id, &state.constants);
+                let new_weapon_type = WeaponType::from_id(new_weapon_id, &state.constants);
 
                 if let Some(wtype) = new_weapon_type {
                     game_scene.inventory_player1.trade_weapon(old_weapon_type, wtype, max_ammo);
@@ -1984,6 +2124,7 @@ impl TextScriptVM {
 #[derive(Clone)]
 pub struct TextScript {
     pub(crate) event_map: HashMap<u16, Vec<u8>>,
+    pub(crate) source_map: Option<Rc<SourceMap>>,
 }
 
 impl Default for TextScript {
@@ -1994,10 +2135,11 @@ impl Default for TextScript {
 
 impl TextScript {
     pub fn new() -> TextScript {
-        Self { event_map: HashMap::new() }
+        Self { event_map: HashMap::new(), source_map: None }
     }
 
     /// Loads, decrypts and compiles a text script from specified stream.
+    /// `#include` directives are not available in scripts loaded this way, use [`TextScript::load_from_file`].
     pub fn load_from<R: io::Read>(mut data: R, constants: &EngineConstants) -> GameResult<TextScript> {
         let mut buf = Vec::new();
         data.read_to_end(&mut buf)?;
@@ -2006,7 +2148,52 @@ impl TextScript {
             decrypt_tsc(&mut buf);
         }
 
-        TextScript::compile(&buf, false, constants.textscript.encoding)
+        let preprocessor =
+            Preprocessor::new(|path: &str| Err(ParseError(format!("Cannot include {} in this script.", path))));
+        TextScript::compile_preprocessed(preprocessor, "<stream>", &buf, constants)
+    }
+
+    /// Loads, decrypts, preprocesses and compiles a text script from the data directories,
+    /// included files are looked up in the data directories too.
+    pub fn load_from_file(
+        ctx: &Context,
+        roots: &Vec<String>,
+        path: &str,
+        constants: &EngineConstants,
+    ) -> GameResult<TextScript> {
+        let read = |path: &str| -> GameResult<Vec<u8>> {
+            let mut buf = Vec::new();
+            filesystem::open_find(ctx, roots, path)?.read_to_end(&mut buf)?;
+
+            // included snippets are plain text unless they're named like regular scripts
+            if constants.textscript.encrypted && path.to_lowercase().ends_with(".tsc") {
+                decrypt_tsc(&mut buf);
+            }
+
+            Ok(buf)
+        };
+
+        let buf = read(path)?;
+        TextScript::compile_preprocessed(Preprocessor::new(read), path, &buf, constants)
+    }
+
+    fn compile_preprocessed(
+        preprocessor: Preprocessor,
+        name: &str,
+        buf: &[u8],
+        constants: &EngineConstants,
+    ) -> GameResult<TextScript> {
+        let encoding = constants.textscript.encoding;
+
+        // the preprocessor only understands ASCII compatible encodings
+        if matches!(encoding, TextScriptEncoding::UTF16BE | TextScriptEncoding::UTF16LE)
+            || !Preprocessor::is_enabled(buf)
+        {
+            return TextScript::compile(buf, false, encoding);
+        }
+
+        let (buf, source_map) = preprocessor.process(name, buf)?;
+        TextScript::compile_mapped(&buf, false, encoding, source_map)
     }
 
     pub fn get_event_ids(&self) -> Vec<u16> {
@@ -2018,4 +2205,9 @@ impl TextScript {
     pub fn has_event(&self, id: u16) -> bool {
         self.event_map.contains_key(&id)
     }
+
+    /// Original file and line of the event header, if the script was preprocessed.
+    pub fn locate_event(&self, id: u16) -> Option<(&str, usize)> {
+        self.source_map.as_ref()?.locate_event(id)
+    }
 }
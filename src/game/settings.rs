@@ -5,13 +5,13 @@ use crate::framework::gamepad::{Axis, AxisDirection, Button, PlayerControllerInp
 use crate::framework::graphics::VSyncMode;
 use crate::framework::keyboard::ScanCode;
 use crate::game::player::TargetPlayer;
-use crate::game::shared_game_state::{CutsceneSkipMode, ScreenShakeIntensity, TimingMode, WindowMode};
+use crate::game::shared_game_state::{CutsceneSkipMode, ScreenShakeIntensity, TimingMode, ViewportSize, WindowMode};
 use crate::input::combined_player_controller::CombinedPlayerController;
 use crate::input::gamepad_player_controller::GamepadController;
 use crate::input::keyboard_player_controller::KeyboardController;
 use crate::input::player_controller::PlayerController;
 use crate::input::touch_player_controller::TouchPlayerController;
-use crate::sound::InterpolationMode;
+use crate::sound::{AudioOutputConfig, InterpolationMode};
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Settings {
@@ -35,6 +35,14 @@ pub struct Settings {
     pub bgm_volume: f32,
     #[serde(default = "default_vol")]
     pub sfx_volume: f32,
+    #[serde(default = "default_positional_sfx")]
+    pub positional_sfx: bool,
+    #[serde(default)]
+    pub audio_device: Option<String>,
+    #[serde(default)]
+    pub audio_sample_rate: Option<u32>,
+    #[serde(default)]
+    pub audio_buffer_size: Option<u32>,
     #[serde(default = "default_timing")]
     pub timing_mode: TimingMode,
     #[serde(default = "default_pause_on_focus_loss")]
@@ -77,6 +85,8 @@ pub struct Settings {
     pub vsync_mode: VSyncMode,
     #[serde(default = "default_screen_shake_intensity")]
     pub screen_shake_intensity: ScreenShakeIntensity,
+    #[serde(default = "default_viewport_size")]
+    pub viewport_size: ViewportSize,
     pub debug_mode: bool,
     #[serde(skip)]
     pub noclip: bool,
@@ -95,7 +105,7 @@ fn default_true() -> bool {
 
 #[inline(always)]
 fn current_version() -> u32 {
-    25
+    28
 }
 
 #[inline(always)]
@@ -123,6 +133,11 @@ fn default_vol() -> f32 {
     1.0
 }
 
+#[inline(always)]
+fn default_positional_sfx() -> bool {
+    false
+}
+
 #[inline(always)]
 fn default_locale() -> String {
     "en".to_string()
@@ -138,6 +153,11 @@ fn default_screen_shake_intensity() -> ScreenShakeIntensity {
     ScreenShakeIntensity::Full
 }
 
+#[inline(always)]
+fn default_viewport_size() -> ViewportSize {
+    ViewportSize::Fill
+}
+
 #[inline(always)]
 fn default_p1_controller_type() -> ControllerType {
     if cfg!(any(target_os = "horizon")) {
@@ -359,6 +379,23 @@ impl Settings {
             }
         }
 
+        if self.version == 25 {
+            self.version = 26;
+            self.positional_sfx = default_positional_sfx();
+        }
+
+        if self.version == 26 {
+            self.version = 27;
+            self.audio_device = None;
+            self.audio_sample_rate = None;
+            self.audio_buffer_size = None;
+        }
+
+        if self.version == 27 {
+            self.version = 28;
+            self.viewport_size = default_viewport_size();
+        }
+
         if self.version != initial_version {
             log::info!("Upgraded configuration file from version {} to {}.", initial_version, self.version);
         }
@@ -366,6 +403,14 @@ impl Settings {
         self
     }
 
+    pub fn audio_output_config(&self) -> AudioOutputConfig {
+        AudioOutputConfig {
+            device: self.audio_device.clone(),
+            sample_rate: self.audio_sample_rate,
+            buffer_size: self.audio_buffer_size,
+        }
+    }
+
     pub fn save(&self, ctx: &Context) -> GameResult {
         let file = user_create(ctx, "/settings.json")?;
         serde_json::to_writer_pretty(file, self)?;
@@ -439,6 +484,10 @@ impl Default for Settings {
             soundtrack: "Organya".to_string(),
             bgm_volume: 1.0,
             sfx_volume: 1.0,
+            positional_sfx: default_positional_sfx(),
+            audio_device: None,
+            audio_sample_rate: None,
+            audio_buffer_size: None,
             timing_mode: default_timing(),
             pause_on_focus_loss: default_pause_on_focus_loss(),
             organya_interpolation: InterpolationMode::Linear,
@@ -461,6 +510,7 @@ impl Default for Settings {
             window_mode: WindowMode::Windowed,
             vsync_mode: VSyncMode::VSync,
             screen_shake_intensity: ScreenShakeIntensity::Full,
+            viewport_size: default_viewport_size(),
             debug_mode: false,
             noclip: false,
             more_rust: false,
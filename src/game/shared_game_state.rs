@@ -1,9 +1,11 @@
+use std::collections::HashMap;
 use std::{cmp, ops::Div};
 
 use chrono::{Datelike, Local};
 
-use crate::common::{ControlFlags, Direction, FadeState};
+use crate::common::{Color, ControlFlags, Direction, FadeState, Rect};
 use crate::components::draw_common::{draw_number, Alignment};
+use crate::data::exe_patches::ExePatches;
 use crate::data::vanilla::VanillaExtractor;
 #[cfg(feature = "discord-rpc")]
 use crate::discord::DiscordRPC;
@@ -15,12 +17,15 @@ use crate::framework::graphics::{create_texture_mutable, set_render_target};
 use crate::framework::vfs::OpenOptions;
 use crate::framework::{filesystem, graphics};
 use crate::game::caret::{Caret, CaretType};
+use crate::game::frame_dump::{FrameDump, FrameDumpConfig};
 use crate::game::npc::NPCTable;
 use crate::game::player::TargetPlayer;
-use crate::game::profile::GameProfile;
+use crate::game::profile::{GameProfile, SaveThumbnail};
+#[cfg(feature = "scripting")]
+use crate::game::scripting::npc_scripts::NPCScripts;
 use crate::game::scripting::tsc::credit_script::{CreditScript, CreditScriptVM};
 use crate::game::scripting::tsc::text_script::{
-    ScriptMode, TextScript, TextScriptEncoding, TextScriptExecutionState, TextScriptVM,
+    ScriptMode, ScriptVariable, TextScript, TextScriptEncoding, TextScriptExecutionState, TextScriptVM,
 };
 use crate::game::settings::Settings;
 use crate::game::stage::StageData;
@@ -70,6 +75,14 @@ impl TimingMode {
             TimingMode::FrameSynchronized => 0,
         }
     }
+
+    /// Tick rate assumed by audio and frame captures, frame synchronized mode is treated as 60Hz.
+    pub fn get_capture_tps(self) -> usize {
+        match self {
+            TimingMode::_50Hz => 50,
+            TimingMode::_60Hz | TimingMode::FrameSynchronized => 60,
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Copy, Clone, serde::Serialize, serde::Deserialize)]
@@ -110,6 +123,9 @@ pub enum GameDifficulty {
     Hard = 4,
 }
 
+/// Number of flags usable by scripts, only the first 8000 are stored in vanilla saves.
+pub const GAME_FLAG_COUNT: usize = 64000;
+
 #[derive(PartialEq, Eq, Copy, Clone, num_derive::FromPrimitive)]
 pub enum PlayerCount {
     One,
@@ -146,6 +162,30 @@ impl ScreenShakeIntensity {
     }
 }
 
+#[derive(PartialEq, Eq, Copy, Clone, num_derive::FromPrimitive, serde::Serialize, serde::Deserialize)]
+pub enum ViewportSize {
+    /// Fills the whole window.
+    Fill,
+    /// 320x240, same as the original game.
+    Classic,
+    /// 426x240, same as Cave Story+ on Switch.
+    Widescreen,
+    /// 560x240.
+    Ultrawide,
+}
+
+impl ViewportSize {
+    /// Logical resolution of the viewport, `None` if it follows the size of the window.
+    pub fn size(self) -> Option<(f32, f32)> {
+        match self {
+            ViewportSize::Fill => None,
+            ViewportSize::Classic => Some((320.0, 240.0)),
+            ViewportSize::Widescreen => Some((426.0, 240.0)),
+            ViewportSize::Ultrawide => Some((560.0, 240.0)),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct FontData {
     pub path: String,
@@ -305,6 +345,9 @@ pub struct SharedGameState {
     pub quake_rumble_counter: u32,
     pub super_quake_rumble_counter: u32,
     pub teleporter_slots: Vec<(u16, u16)>,
+    pub script_variables: HashMap<ScriptVariable, i32>,
+    /// Ticks spent in game since starting a new game, excluding pauses.
+    pub play_time: u64,
     pub carets: Vec<Caret>,
     pub touch_controls: TouchControls,
     pub mod_path: Option<String>,
@@ -322,6 +365,8 @@ pub struct SharedGameState {
     pub canvas_size: (f32, f32),
     pub screen_size: (f32, f32),
     pub preferred_viewport_size: (f32, f32),
+    /// Size of the black bars around the viewport if it's smaller than the canvas, in canvas pixels.
+    pub viewport_offset: (f32, f32),
     pub next_scene: Option<Box<dyn Scene>>,
     pub textscript_vm: TextScriptVM,
     pub creditscript_vm: CreditScriptVM,
@@ -340,20 +385,26 @@ pub struct SharedGameState {
     pub player_count_modified_in_game: bool,
     pub player2_skin_location: PlayerSkinLocation,
     pub replay_state: ReplayState,
+    pub frame_dump_config: Option<FrameDumpConfig>,
+    pub frame_dump: Option<FrameDump>,
+    /// Save waiting for the next drawn frame, so its thumbnail can be captured before the frame is presented.
+    pub pending_save: Option<(String, GameProfile)>,
     pub mod_requirements: ModRequirements,
     pub loc: Locale,
     pub tutorial_counter: u16,
     pub more_rust: bool,
     #[cfg(feature = "discord-rpc")]
     pub discord_rpc: DiscordRPC,
+    #[cfg(feature = "scripting")]
+    pub npc_scripts: NPCScripts,
     pub shutdown: bool,
 }
 
 impl SharedGameState {
     pub fn new(ctx: &mut Context) -> GameResult<SharedGameState> {
         let mut constants = EngineConstants::defaults();
-        let mut sound_manager = SoundManager::new(ctx)?;
         let settings = Settings::load(ctx)?;
+        let mut sound_manager = SoundManager::new(ctx, settings.audio_output_config())?;
         let mod_requirements = ModRequirements::load(ctx)?;
 
         let vanilla_ext_exe = match option_env!("VANILLA_EXT_EXE") {
@@ -402,6 +453,11 @@ impl SharedGameState {
             log::info!("CSE2E data files detected.");
         } else if filesystem::exists(ctx, "/stage.dat") {
             log::info!("NXEngine-evo data files detected.");
+        } else if let Some(patches) = ExePatches::load(ctx, &vec!["/".to_owned()]) {
+            log::info!("Patched freeware data files detected.");
+            if let Some((width, height)) = patches.screen_size {
+                log::info!("Game resolution changed to {}x{} by a widescreen hack.", width, height);
+            }
         }
 
         for soundtrack in constants.soundtracks.iter_mut() {
@@ -412,7 +468,7 @@ impl SharedGameState {
         }
 
         let season = Season::current();
-        constants.rebuild_path_list(None, season, &settings);
+        constants.rebuild_path_list(&[], season, &settings);
 
         constants.load_locales(ctx)?;
 
@@ -421,20 +477,6 @@ impl SharedGameState {
 
         let mod_list = ModList::load(ctx, &constants.string_table)?;
 
-        for i in 0..0xffu8 {
-            let path = format!("pxt/fx{:02x}.pxt", i);
-            if let Ok(file) = filesystem::open_find(ctx, &constants.base_paths, path) {
-                sound_manager.set_sample_params_from_file(i, file)?;
-                continue;
-            }
-
-            let path = format!("PixTone/{:03}.pxt", i);
-            if let Ok(file) = filesystem::open_find(ctx, &constants.base_paths, path) {
-                sound_manager.set_sample_params_from_file(i, file)?;
-                continue;
-            }
-        }
-
         sound_manager.set_song_volume(settings.bgm_volume);
         sound_manager.set_sfx_volume(settings.sfx_volume);
 
@@ -450,7 +492,7 @@ impl SharedGameState {
 
         Ok(SharedGameState {
             control_flags: ControlFlags(0),
-            game_flags: BitVec::with_size(8000),
+            game_flags: BitVec::with_size(GAME_FLAG_COUNT),
             skip_flags: BitVec::with_size(64),
             map_flags: BitVec::with_size(128),
             fade_state: FadeState::Hidden,
@@ -462,6 +504,8 @@ impl SharedGameState {
             quake_rumble_counter: 0,
             super_quake_rumble_counter: 0,
             teleporter_slots: Vec::with_capacity(8),
+            script_variables: HashMap::new(),
+            play_time: 0,
             carets: Vec::with_capacity(32),
             touch_controls: TouchControls::new(),
             mod_path: None,
@@ -479,6 +523,7 @@ impl SharedGameState {
             screen_size: (640.0, 480.0),
             canvas_size: (320.0, 240.0),
             preferred_viewport_size: (320.0, 240.0),
+            viewport_offset: (0.0, 0.0),
             next_scene: None,
             textscript_vm: TextScriptVM::new(),
             creditscript_vm: CreditScriptVM::new(),
@@ -497,12 +542,17 @@ impl SharedGameState {
             player_count_modified_in_game: false,
             player2_skin_location: PlayerSkinLocation::default(),
             replay_state: ReplayState::None,
+            frame_dump_config: None,
+            frame_dump: None,
+            pending_save: None,
             mod_requirements,
             loc: locale,
             tutorial_counter: 0,
             more_rust,
             #[cfg(feature = "discord-rpc")]
             discord_rpc: DiscordRPC::new(discord_rpc_app_id),
+            #[cfg(feature = "scripting")]
+            npc_scripts: NPCScripts::new(),
             shutdown: false,
         })
     }
@@ -518,13 +568,33 @@ impl SharedGameState {
         Ok(())
     }
 
+    /// Paths of the selected mod and the mods it depends on, in load order.
+    pub fn mod_stack(&self) -> Vec<String> {
+        self.mod_path.as_ref().map(|path| self.mod_list.load_order(path)).unwrap_or_default()
+    }
+
     pub fn reload_resources(&mut self, ctx: &mut Context) -> GameResult {
-        self.constants.rebuild_path_list(self.mod_path.clone(), self.season, &self.settings);
+        let mod_stack = self.mod_stack();
+        for conflict in self.mod_list.find_conflicts(ctx, &mod_stack) {
+            let mods = conflict.mods.join(", ");
+            log::warn!("Mod conflict: {} is provided by {}, using the last one.", conflict.path, mods);
+        }
+
+        self.constants.rebuild_path_list(&mod_stack, self.season, &self.settings);
         if !self.constants.is_demo {
             //TODO find a more elegant way to handle this
             self.constants.special_treatment_for_csplus_mods(self.mod_path.as_ref());
         }
         self.constants.load_csplus_tables(ctx)?;
+        let viewport_size = self.viewport_size();
+        self.constants.load_exe_patches(ctx)?;
+        if self.viewport_size() != viewport_size {
+            self.handle_resize(ctx)?;
+        }
+
+        self.constants.load_custom_weapons(ctx)?;
+        self.constants.load_custom_bosses(ctx)?;
+        self.constants.load_light_table(ctx)?;
         self.constants.load_animated_faces(ctx)?;
         self.constants.load_texture_size_hints(ctx)?;
         self.reload_stage_table(ctx)?;
@@ -533,16 +603,15 @@ impl SharedGameState {
         let npc_table = NPCTable::load_from(npc_tbl)?;
         self.npc_table = npc_table;
 
-        let head_tsc = filesystem::open_find(ctx, &self.constants.base_paths, "Head.tsc")?;
-        let head_script = TextScript::load_from(head_tsc, &self.constants)?;
+        let head_script = TextScript::load_from_file(ctx, &self.constants.base_paths, "Head.tsc", &self.constants)?;
         self.textscript_vm.set_global_script(head_script);
 
-        let arms_item_tsc = filesystem::open_find(ctx, &self.constants.base_paths, "ArmsItem.tsc")?;
-        let arms_item_script = TextScript::load_from(arms_item_tsc, &self.constants)?;
+        let arms_item_script =
+            TextScript::load_from_file(ctx, &self.constants.base_paths, "ArmsItem.tsc", &self.constants)?;
         self.textscript_vm.set_inventory_script(arms_item_script);
 
-        let stage_select_tsc = filesystem::open_find(ctx, &self.constants.base_paths, "StageSelect.tsc")?;
-        let stage_select_script = TextScript::load_from(stage_select_tsc, &self.constants)?;
+        let stage_select_script =
+            TextScript::load_from_file(ctx, &self.constants.base_paths, "StageSelect.tsc", &self.constants)?;
         self.textscript_vm.set_stage_select_script(stage_select_script);
 
         let substitution_rect_map = [('=', self.constants.textscript.textbox_item_marker_rect)];
@@ -558,11 +627,14 @@ impl SharedGameState {
 
         self.sound_manager.load_custom_sound_effects(ctx, &self.constants.base_paths)?;
 
+        #[cfg(feature = "scripting")]
+        self.npc_scripts.load(ctx, &self.constants.base_paths)?;
+
         Ok(())
     }
 
     pub fn reload_graphics(&mut self) {
-        self.constants.rebuild_path_list(self.mod_path.clone(), self.season, &self.settings);
+        self.constants.rebuild_path_list(&self.mod_stack(), self.season, &self.settings);
         self.texture_set.unload_all();
     }
 
@@ -676,11 +748,12 @@ impl SharedGameState {
         target_player: Option<TargetPlayer>,
     ) -> GameResult {
         if let Some(save_path) = self.get_save_filename(self.save_slot) {
-            if let Ok(data) = filesystem::open_options(ctx, save_path, OpenOptions::new().write(true).create(true)) {
-                let profile = GameProfile::dump(self, game_scene, target_player);
-                profile.write_save(data)?;
-            } else {
-                log::warn!("Cannot open save file.");
+            let profile = GameProfile::dump(self, game_scene, target_player);
+            self.pending_save = Some((save_path, profile));
+
+            // nothing gets drawn in headless mode, so there's no thumbnail to wait for
+            if ctx.headless {
+                self.write_pending_save(ctx, false)?;
             }
         } else {
             log::info!("Mod has saves disabled.");
@@ -689,7 +762,36 @@ impl SharedGameState {
         Ok(())
     }
 
+    /// Writes the save made by [`SharedGameState::save_game`], optionally with a thumbnail of the frame
+    /// drawn so far, which means it has to be called before the frame is presented.
+    pub fn write_pending_save(&mut self, ctx: &mut Context, capture_thumbnail: bool) -> GameResult {
+        let (save_path, mut profile) = match self.pending_save.take() {
+            Some(pending_save) => pending_save,
+            None => return Ok(()),
+        };
+
+        if capture_thumbnail {
+            match SaveThumbnail::capture(ctx) {
+                Ok(thumbnail) => profile.thumbnail = Some(thumbnail),
+                Err(err) => log::warn!("Failed to capture save thumbnail: {}", err),
+            }
+        }
+
+        if let Ok(data) =
+            filesystem::open_options(ctx, save_path, OpenOptions::new().write(true).create(true).truncate(true))
+        {
+            profile.write_save(data)?;
+        } else {
+            log::warn!("Cannot open save file.");
+        }
+
+        Ok(())
+    }
+
     pub fn load_or_start_game(&mut self, ctx: &mut Context) -> GameResult {
+        // a save made this frame hasn't been written yet
+        self.write_pending_save(ctx, false)?;
+
         if let Some(save_path) = self.get_save_filename(self.save_slot) {
             if let Ok(data) = filesystem::user_open(ctx, save_path) {
                 match GameProfile::load_from_save(data) {
@@ -721,10 +823,12 @@ impl SharedGameState {
 
     pub fn reset(&mut self) {
         self.control_flags.0 = 0;
-        self.game_flags = BitVec::with_size(8000);
+        self.game_flags = BitVec::with_size(GAME_FLAG_COUNT);
         self.fade_state = FadeState::Hidden;
         self.game_rng = XorShift::new(chrono::Local::now().timestamp() as i32);
         self.teleporter_slots.clear();
+        self.script_variables.clear();
+        self.play_time = 0;
         self.quake_counter = 0;
         self.carets.clear();
         self.textscript_vm.set_mode(ScriptMode::Map);
@@ -733,11 +837,21 @@ impl SharedGameState {
 
     pub fn handle_resize(&mut self, ctx: &mut Context) -> GameResult {
         self.screen_size = graphics::screen_size(ctx);
+        let viewport_size = self.viewport_size();
+        self.preferred_viewport_size = viewport_size.unwrap_or((320.0, 240.0));
+
         let scale_x = self.screen_size.1.div(self.preferred_viewport_size.1).floor().max(1.0);
         let scale_y = self.screen_size.0.div(self.preferred_viewport_size.0).floor().max(1.0);
 
         self.scale = f32::min(scale_x, scale_y);
         self.canvas_size = (self.screen_size.0 / self.scale, self.screen_size.1 / self.scale);
+        self.viewport_offset = match viewport_size {
+            Some((width, height)) => (
+                ((self.canvas_size.0 - width) / 2.0).floor().max(0.0),
+                ((self.canvas_size.1 - height) / 2.0).floor().max(0.0),
+            ),
+            None => (0.0, 0.0),
+        };
 
         let (width, height) = (self.screen_size.0 as u16, self.screen_size.1 as u16);
 
@@ -748,6 +862,63 @@ impl SharedGameState {
         Ok(())
     }
 
+    /// Logical resolution of the viewport, the one picked in settings or the one set by
+    /// a widescreen hack in the executable. `None` if it follows the size of the window.
+    pub fn viewport_size(&self) -> Option<(f32, f32)> {
+        self.settings.viewport_size.size().or(self.constants.viewport_size)
+    }
+
+    /// Size of the visible part of the canvas, excluding the black bars around the viewport.
+    pub fn view_size(&self) -> (f32, f32) {
+        match self.viewport_size() {
+            Some((width, height)) => (width.min(self.canvas_size.0), height.min(self.canvas_size.1)),
+            None => self.canvas_size,
+        }
+    }
+
+    /// Sizes of the black bars on the left, top, right and bottom of the viewport, in canvas pixels.
+    fn viewport_margins(&self) -> (f32, f32, f32, f32) {
+        let (view_width, view_height) = self.view_size();
+        let (left, top) = self.viewport_offset;
+
+        (left, top, self.canvas_size.0 - view_width - left, self.canvas_size.1 - view_height - top)
+    }
+
+    /// Screen insets in canvas pixels, extended to cover the black bars around the viewport.
+    /// HUD elements should be placed within them.
+    pub fn viewport_insets(&self, ctx: &mut Context) -> (f32, f32, f32, f32) {
+        let (left, top, right, bottom) = graphics::screen_insets_scaled(ctx, self.scale);
+        let margins = self.viewport_margins();
+
+        (left.max(margins.0), top.max(margins.1), right.max(margins.2), bottom.max(margins.3))
+    }
+
+    /// Covers the parts of the canvas outside of the viewport.
+    pub fn draw_viewport_bars(&self, ctx: &mut Context) -> GameResult {
+        let (left, top, right, bottom) = self.viewport_margins();
+        let (width, height) = (self.screen_size.0 as isize, self.screen_size.1 as isize);
+        let color = Color::from_rgb(0, 0, 0);
+        let scaled = |v: f32| (v * self.scale) as isize;
+
+        if left > 0.0 {
+            graphics::draw_rect(ctx, Rect::new(0, 0, scaled(left), height), color)?;
+        }
+
+        if right > 0.0 {
+            graphics::draw_rect(ctx, Rect::new(width - scaled(right), 0, width, height), color)?;
+        }
+
+        if top > 0.0 {
+            graphics::draw_rect(ctx, Rect::new(0, 0, width, scaled(top)), color)?;
+        }
+
+        if bottom > 0.0 {
+            graphics::draw_rect(ctx, Rect::new(0, height - scaled(bottom), width, height), color)?;
+        }
+
+        Ok(())
+    }
+
     pub fn tick_carets(&mut self) {
         for caret in &mut self.carets {
             caret.tick(&self.effect_rng, &self.constants);
@@ -772,10 +943,66 @@ impl SharedGameState {
     pub fn shutdown(&mut self) {
         self.shutdown = true;
 
+        self.stop_frame_dump();
+        if let Err(err) = self.sound_manager.stop_capture() {
+            log::error!("Failed to finish audio capture: {}", err);
+        }
+
         #[cfg(feature = "discord-rpc")]
         self.discord_rpc.dispose();
     }
 
+    /// Starts dumping frames once a replay starts playing and stops when it ends,
+    /// if a frame dump has been requested at launch.
+    pub fn update_frame_dump(&mut self, ctx: &mut Context) {
+        let replay_playing = matches!(self.replay_state, ReplayState::Playback(_));
+
+        if replay_playing && self.frame_dump.is_none() {
+            if let Some(config) = self.frame_dump_config.take() {
+                match FrameDump::new(config, self.settings.timing_mode.get_capture_tps()) {
+                    Ok(mut frame_dump) => {
+                        if !self.sound_manager.is_capturing() {
+                            let audio = std::fs::File::create(frame_dump.audio_path()).map(std::io::BufWriter::new);
+                            let result = match audio {
+                                Ok(audio) => {
+                                    self.sound_manager.start_capture(audio, &self.constants, &self.settings, ctx, false)
+                                }
+                                Err(err) => Err(err.into()),
+                            };
+
+                            match result {
+                                Ok(()) => {
+                                    frame_dump.captures_audio = true;
+                                    let _ = self.sound_manager.restart_song(&self.constants, &self.settings, ctx);
+                                }
+                                Err(err) => log::error!("Failed to capture audio for frame dump: {}", err),
+                            }
+                        }
+
+                        self.frame_dump = Some(frame_dump);
+                    }
+                    Err(err) => log::error!("Failed to start frame dump: {}", err),
+                }
+            }
+        } else if !replay_playing && self.frame_dump.is_some() {
+            self.stop_frame_dump();
+        }
+    }
+
+    pub fn stop_frame_dump(&mut self) {
+        if let Some(frame_dump) = self.frame_dump.take() {
+            if frame_dump.captures_audio {
+                if let Err(err) = self.sound_manager.stop_capture() {
+                    log::error!("Failed to finish audio capture: {}", err);
+                }
+            }
+
+            if let Err(err) = frame_dump.finish() {
+                log::error!("Failed to finish frame dump: {}", err);
+            }
+        }
+    }
+
     // Stops SFX 40/41/58 (CPS and CSS)
     pub fn stop_noise(&mut self) {
         self.sound_manager.stop_sfx(40);
@@ -799,6 +1026,14 @@ impl SharedGameState {
         }
     }
 
+    pub fn set_variable(&mut self, variable: ScriptVariable, value: i32) {
+        self.script_variables.insert(variable, value);
+    }
+
+    pub fn get_variable(&self, variable: &ScriptVariable) -> i32 {
+        self.script_variables.get(variable).copied().unwrap_or(0)
+    }
+
     pub fn reset_skip_flags(&mut self) {
         self.skip_flags = BitVec::with_size(64);
     }
@@ -1,8 +1,9 @@
 use std::io::{Cursor, Read};
 use std::str::from_utf8;
 
-use byteorder::ReadBytesExt;
 use byteorder::LE;
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
 
 use crate::common::Color;
 use crate::engine_constants::EngineConstants;
@@ -10,7 +11,7 @@ use crate::framework::context::Context;
 use crate::framework::error::GameError::ResourceLoadError;
 use crate::framework::error::{GameError, GameResult};
 use crate::framework::filesystem;
-use crate::game::map::{Map, NPCData};
+use crate::game::map::{Map, MapLayer, NPCData};
 use crate::game::scripting::tsc::text_script::{TextScript, TextScriptEncoding};
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -188,6 +189,10 @@ pub struct StageData {
     pub background_color: Color,
     pub npc1: NpcType,
     pub npc2: NpcType,
+    /// Overrides the ambient light level of the lightmap, can be only set by `stage.json`.
+    pub ambient_light: Option<(u8, u8, u8)>,
+    /// Limits the camera to given area in tiles (left, top, right, bottom), can be only set by `stage.json`.
+    pub camera_bounds: Option<(u16, u16, u16, u16)>,
 }
 
 const NXENGINE_BACKDROPS: [&str; 15] = [
@@ -249,6 +254,123 @@ fn from_csplus_stagetbl(s: &[u8], is_switch: bool, encoding: Option<TextScriptEn
     }
 }
 
+/// File formats the stage table can be stored in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StageTableFormat {
+    /// `stage.tbl` from Cave Story+, Shift-JIS encoded.
+    CSPlus,
+    /// `stage.tbl` from Cave Story+ for Nintendo Switch, UTF-8 encoded.
+    Switch,
+    /// `stage.sect`, the stage table dumped from the freeware `Doukutsu.exe`.
+    Freeware,
+    /// `mrmap.bin` from Moustache Rider.
+    MoustacheRider,
+    /// `stage.dat` from NXEngine.
+    NXEngine,
+    /// `stage.json`, meant to be edited by hand.
+    Json,
+}
+
+impl StageTableFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "csplus" => Some(Self::CSPlus),
+            "switch" => Some(Self::Switch),
+            "freeware" => Some(Self::Freeware),
+            "mrmap" => Some(Self::MoustacheRider),
+            "nxengine" => Some(Self::NXEngine),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from file name, `stage.tbl` is assumed to come from the PC version.
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+
+        match () {
+            _ if name.ends_with(".tbl") => Some(Self::CSPlus),
+            _ if name.ends_with(".sect") => Some(Self::Freeware),
+            _ if name.ends_with("mrmap.bin") => Some(Self::MoustacheRider),
+            _ if name.ends_with(".dat") => Some(Self::NXEngine),
+            _ if name.ends_with(".json") => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    /// Name of the file the game looks for in the data directory.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::CSPlus | Self::Switch => "stage.tbl",
+            Self::Freeware => "stage.sect",
+            Self::MoustacheRider => "mrmap.bin",
+            Self::NXEngine => "stage.dat",
+            Self::Json => "stage.json",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StageTableJson {
+    stages: Vec<StageJson>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StageJson {
+    name: String,
+    /// Same as `name` if not present.
+    #[serde(default)]
+    name_jp: String,
+    map: String,
+    tileset: String,
+    background: String,
+    background_type: u8,
+    npc1: String,
+    npc2: String,
+    #[serde(default)]
+    boss_no: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ambient_light: Option<(u8, u8, u8)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    camera_bounds: Option<(u16, u16, u16, u16)>,
+}
+
+fn to_encoding(s: &str, encoding: Option<TextScriptEncoding>, utf8: bool) -> Vec<u8> {
+    if let Some(encoding) = encoding {
+        let encoding: &encoding_rs::Encoding = encoding.into();
+        return encoding.encode(s).0.into_owned();
+    }
+
+    if utf8 {
+        s.as_bytes().to_vec()
+    } else {
+        encoding_rs::SHIFT_JIS.encode(s).0.into_owned()
+    }
+}
+
+/// Writes a zero padded string field, leaves room for the terminator the original engines expect.
+fn write_field(out: &mut Vec<u8>, data: &[u8], size: usize, field: &str, map: &str) -> GameResult {
+    if data.len() >= size {
+        return Err(GameError::InvalidValue(format!(
+            "{} of stage {} is too long, it can be up to {} bytes long.",
+            field,
+            map,
+            size - 1
+        )));
+    }
+
+    out.extend_from_slice(data);
+    out.resize(out.len() + size - data.len(), 0);
+
+    Ok(())
+}
+
+fn nxengine_index(names: &[&str], name: &str, field: &str, map: &str) -> GameResult<u8> {
+    names.iter().position(|&n| n == name).map(|i| i as u8).ok_or_else(|| {
+        GameError::InvalidValue(format!("{} {} of stage {} can't be stored in NXEngine stage table.", field, name, map))
+    })
+}
+
 impl StageData {
     pub fn load_stage_table(
         ctx: &mut Context,
@@ -256,70 +378,40 @@ impl StageData {
         is_switch: bool,
         encoding: Option<TextScriptEncoding>,
     ) -> GameResult<Vec<Self>> {
-        let stage_tbl_path = "/stage.tbl";
-        let stage_sect_path = "/stage.sect";
-        let mrmap_bin_path = "/mrmap.bin";
-        let stage_dat_path = "/stage.dat";
-
-        if filesystem::exists_find(ctx, roots, stage_tbl_path) {
-            // Cave Story+ stage table.
-            // Mod stage.tbl expects to overwrite from base stage.tbl
+        let tables = [
+            ("/stage.json", StageTableFormat::Json),
+            ("/stage.tbl", if is_switch { StageTableFormat::Switch } else { StageTableFormat::CSPlus }),
+            // Cave Story freeware executable dump.
+            ("/stage.sect", StageTableFormat::Freeware),
+            // Moustache Rider stage table
+            ("/mrmap.bin", StageTableFormat::MoustacheRider),
+            ("/stage.dat", StageTableFormat::NXEngine),
+        ];
+
+        // the format is picked from the highest priority root which has a stage table,
+        // so a mod shipping a stage.tbl isn't shadowed by a stage.json further down
+        let Some((root, table_path, format)) = roots.iter().find_map(|root| {
+            tables
+                .iter()
+                .find(|(path, _)| filesystem::exists(ctx, [root.as_str(), *path].join("")))
+                .map(|&(path, format)| (root, path, format))
+        }) else {
+            return Err(ResourceLoadError("No stage table found.".to_string()));
+        };
+
+        if matches!(format, StageTableFormat::Json | StageTableFormat::CSPlus | StageTableFormat::Switch) {
+            // Cave Story+ stage table or a stage.json.
+            // Mod stage tables expect to overwrite from base stage table
             let mut stages = Vec::new();
 
             for path in roots.iter().rev() {
-                if let Ok(mut file) = filesystem::open(ctx, [path, stage_tbl_path].join("")) {
-                    log::info!("Loading Cave Story+ stage table from {}", &path);
-
-                    let mut new_stages = Vec::new();
+                if let Ok(mut file) = filesystem::open(ctx, [path, table_path].join("")) {
+                    log::info!("Loading {:?} stage table from {}", format, &path);
 
                     let mut data = Vec::new();
                     file.read_to_end(&mut data)?;
 
-                    let count = data.len() / 0xe5;
-                    let mut f = Cursor::new(data);
-                    for _ in 0..count {
-                        let mut ts_buf = vec![0u8; 0x20];
-                        let mut map_buf = vec![0u8; 0x20];
-                        let mut back_buf = vec![0u8; 0x20];
-                        let mut npc1_buf = vec![0u8; 0x20];
-                        let mut npc2_buf = vec![0u8; 0x20];
-                        let mut name_jap_buf = vec![0u8; 0x20];
-                        let mut name_buf = vec![0u8; 0x20];
-
-                        f.read_exact(&mut ts_buf)?;
-                        f.read_exact(&mut map_buf)?;
-                        let bg_type = f.read_u32::<LE>()? as u8;
-                        f.read_exact(&mut back_buf)?;
-                        f.read_exact(&mut npc1_buf)?;
-                        f.read_exact(&mut npc2_buf)?;
-                        let boss_no = f.read_u8()?;
-                        f.read_exact(&mut name_jap_buf)?;
-                        f.read_exact(&mut name_buf)?;
-
-                        let tileset = from_csplus_stagetbl(&ts_buf[0..zero_index(&ts_buf)], is_switch, encoding);
-                        let map = from_csplus_stagetbl(&map_buf[0..zero_index(&map_buf)], is_switch, encoding);
-                        let background = from_csplus_stagetbl(&back_buf[0..zero_index(&back_buf)], is_switch, encoding);
-                        let npc1 = from_csplus_stagetbl(&npc1_buf[0..zero_index(&npc1_buf)], is_switch, encoding);
-                        let npc2 = from_csplus_stagetbl(&npc2_buf[0..zero_index(&npc2_buf)], is_switch, encoding);
-                        let name = from_csplus_stagetbl(&name_buf[0..zero_index(&name_buf)], is_switch, encoding);
-                        let name_jp =
-                            from_csplus_stagetbl(&name_jap_buf[0..zero_index(&name_jap_buf)], is_switch, encoding);
-
-                        let stage = StageData {
-                            name: name.clone(),
-                            name_jp: name_jp.clone(),
-                            map: map.clone(),
-                            boss_no,
-                            tileset: Tileset::new(&tileset),
-                            pxpack_data: None,
-                            background: Background::new(&background),
-                            background_type: BackgroundType::from(bg_type),
-                            background_color: Color::from_rgb(0, 0, 32),
-                            npc1: NpcType::new(&npc1),
-                            npc2: NpcType::new(&npc2),
-                        };
-                        new_stages.push(stage);
-                    }
+                    let new_stages = Self::read_stage_table(&data, format, encoding)?;
 
                     if new_stages.len() >= stages.len() {
                         stages = new_stages;
@@ -330,182 +422,358 @@ impl StageData {
             }
 
             return Ok(stages);
-        } else if let Ok(mut file) = filesystem::open_find(ctx, roots, stage_sect_path) {
-            // Cave Story freeware executable dump.
-            let mut stages = Vec::new();
-
-            log::info!("Loading Cave Story freeware exe dump stage table from {}", &stage_sect_path);
-
-            let mut data = Vec::new();
-            file.read_to_end(&mut data)?;
-
-            let count = data.len() / 0xc8;
-            let mut f = Cursor::new(data);
-            for _ in 0..count {
-                let mut ts_buf = vec![0u8; 0x20];
-                let mut map_buf = vec![0u8; 0x20];
-                let mut back_buf = vec![0u8; 0x20];
-                let mut npc1_buf = vec![0u8; 0x20];
-                let mut npc2_buf = vec![0u8; 0x20];
-                let mut name_buf = vec![0u8; 0x20];
-
-                f.read_exact(&mut ts_buf)?;
-                f.read_exact(&mut map_buf)?;
-                let bg_type = f.read_u32::<LE>()? as u8;
-                f.read_exact(&mut back_buf)?;
-                f.read_exact(&mut npc1_buf)?;
-                f.read_exact(&mut npc2_buf)?;
-                let boss_no = f.read_u8()?;
-                f.read_exact(&mut name_buf)?;
-                // alignment
-                {
-                    let mut lol = [0u8; 3];
-                    let _ = f.read(&mut lol)?;
-                }
-
-                let tileset = from_encoding(&ts_buf[0..zero_index(&ts_buf)], encoding);
-                let map = from_encoding(&map_buf[0..zero_index(&map_buf)], encoding);
-                let background = from_encoding(&back_buf[0..zero_index(&back_buf)], encoding);
-                let npc1 = from_encoding(&npc1_buf[0..zero_index(&npc1_buf)], encoding);
-                let npc2 = from_encoding(&npc2_buf[0..zero_index(&npc2_buf)], encoding);
-                let name = from_encoding(&name_buf[0..zero_index(&name_buf)], encoding);
-
-                let stage = StageData {
-                    name: name.clone(),
-                    name_jp: name.clone(),
-                    map: map.clone(),
-                    boss_no,
-                    tileset: Tileset::new(&tileset),
-                    pxpack_data: None,
-                    background: Background::new(&background),
-                    background_type: BackgroundType::from(bg_type),
-                    background_color: Color::from_rgb(0, 0, 32),
-                    npc1: NpcType::new(&npc1),
-                    npc2: NpcType::new(&npc2),
-                };
-                stages.push(stage);
-            }
-
-            return Ok(stages);
-        } else if let Ok(mut file) = filesystem::open_find(ctx, roots, mrmap_bin_path) {
-            // Moustache Rider stage table
-            let mut stages = Vec::new();
+        }
 
-            log::info!("Loading Moustache Rider stage table from {}", &mrmap_bin_path);
+        log::info!("Loading {:?} stage table from {}", format, root);
 
-            let mut data = Vec::new();
+        let mut file = filesystem::open(ctx, [root.as_str(), table_path].join(""))?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
 
-            let count = file.read_u32::<LE>()?;
-            file.read_to_end(&mut data)?;
+        Self::read_stage_table(&data, format, encoding)
+    }
 
-            if data.len() < count as usize * 0x74 {
-                return Err(ResourceLoadError(
-                    "Specified stage table size is bigger than actual number of entries.".to_string(),
-                ));
+    /// Parses a stage table stored in given format.
+    pub fn read_stage_table(
+        data: &[u8],
+        format: StageTableFormat,
+        encoding: Option<TextScriptEncoding>,
+    ) -> GameResult<Vec<Self>> {
+        let mut stages = Vec::new();
+
+        match format {
+            StageTableFormat::CSPlus | StageTableFormat::Switch => {
+                let is_switch = format == StageTableFormat::Switch;
+                let count = data.len() / 0xe5;
+                let mut f = Cursor::new(data);
+                for _ in 0..count {
+                    let mut ts_buf = vec![0u8; 0x20];
+                    let mut map_buf = vec![0u8; 0x20];
+                    let mut back_buf = vec![0u8; 0x20];
+                    let mut npc1_buf = vec![0u8; 0x20];
+                    let mut npc2_buf = vec![0u8; 0x20];
+                    let mut name_jap_buf = vec![0u8; 0x20];
+                    let mut name_buf = vec![0u8; 0x20];
+
+                    f.read_exact(&mut ts_buf)?;
+                    f.read_exact(&mut map_buf)?;
+                    let bg_type = f.read_u32::<LE>()? as u8;
+                    f.read_exact(&mut back_buf)?;
+                    f.read_exact(&mut npc1_buf)?;
+                    f.read_exact(&mut npc2_buf)?;
+                    let boss_no = f.read_u8()?;
+                    f.read_exact(&mut name_jap_buf)?;
+                    f.read_exact(&mut name_buf)?;
+
+                    let tileset = from_csplus_stagetbl(&ts_buf[0..zero_index(&ts_buf)], is_switch, encoding);
+                    let map = from_csplus_stagetbl(&map_buf[0..zero_index(&map_buf)], is_switch, encoding);
+                    let background = from_csplus_stagetbl(&back_buf[0..zero_index(&back_buf)], is_switch, encoding);
+                    let npc1 = from_csplus_stagetbl(&npc1_buf[0..zero_index(&npc1_buf)], is_switch, encoding);
+                    let npc2 = from_csplus_stagetbl(&npc2_buf[0..zero_index(&npc2_buf)], is_switch, encoding);
+                    let name = from_csplus_stagetbl(&name_buf[0..zero_index(&name_buf)], is_switch, encoding);
+                    let name_jp =
+                        from_csplus_stagetbl(&name_jap_buf[0..zero_index(&name_jap_buf)], is_switch, encoding);
+
+                    let stage = StageData {
+                        name: name.clone(),
+                        name_jp: name_jp.clone(),
+                        map: map.clone(),
+                        boss_no,
+                        tileset: Tileset::new(&tileset),
+                        pxpack_data: None,
+                        background: Background::new(&background),
+                        background_type: BackgroundType::from(bg_type),
+                        background_color: Color::from_rgb(0, 0, 32),
+                        npc1: NpcType::new(&npc1),
+                        npc2: NpcType::new(&npc2),
+                        ambient_light: None,
+                        camera_bounds: None,
+                    };
+                    stages.push(stage);
+                }
             }
+            StageTableFormat::Freeware => {
+                let count = data.len() / 0xc8;
+                let mut f = Cursor::new(data);
+                for _ in 0..count {
+                    let mut ts_buf = vec![0u8; 0x20];
+                    let mut map_buf = vec![0u8; 0x20];
+                    let mut back_buf = vec![0u8; 0x20];
+                    let mut npc1_buf = vec![0u8; 0x20];
+                    let mut npc2_buf = vec![0u8; 0x20];
+                    let mut name_buf = vec![0u8; 0x20];
+
+                    f.read_exact(&mut ts_buf)?;
+                    f.read_exact(&mut map_buf)?;
+                    let bg_type = f.read_u32::<LE>()? as u8;
+                    f.read_exact(&mut back_buf)?;
+                    f.read_exact(&mut npc1_buf)?;
+                    f.read_exact(&mut npc2_buf)?;
+                    let boss_no = f.read_u8()?;
+                    f.read_exact(&mut name_buf)?;
+                    // alignment
+                    {
+                        let mut lol = [0u8; 3];
+                        let _ = f.read(&mut lol)?;
+                    }
 
-            let mut f = Cursor::new(data);
-            for _ in 0..count {
-                let mut ts_buf = vec![0u8; 0x10];
-                let mut map_buf = vec![0u8; 0x10];
-                let mut back_buf = vec![0u8; 0x10];
-                let mut npc1_buf = vec![0u8; 0x10];
-                let mut npc2_buf = vec![0u8; 0x10];
-                let mut name_buf = vec![0u8; 0x22];
-
-                f.read_exact(&mut ts_buf)?;
-                f.read_exact(&mut map_buf)?;
-                let bg_type = f.read_u8()?;
-                f.read_exact(&mut back_buf)?;
-                f.read_exact(&mut npc1_buf)?;
-                f.read_exact(&mut npc2_buf)?;
-                let boss_no = f.read_u8()?;
-                f.read_exact(&mut name_buf)?;
-
-                let tileset = from_encoding(&ts_buf[0..zero_index(&ts_buf)], encoding);
-                let map = from_encoding(&map_buf[0..zero_index(&map_buf)], encoding);
-                let background = from_encoding(&back_buf[0..zero_index(&back_buf)], encoding);
-                let npc1 = from_encoding(&npc1_buf[0..zero_index(&npc1_buf)], encoding);
-                let npc2 = from_encoding(&npc2_buf[0..zero_index(&npc2_buf)], encoding);
-                let name = from_encoding(&name_buf[0..zero_index(&name_buf)], encoding);
-
-                let stage = StageData {
-                    name: name.clone(),
-                    name_jp: name.clone(),
-                    map: map.clone(),
-                    boss_no,
-                    tileset: Tileset::new(&tileset),
-                    pxpack_data: None,
-                    background: Background::new(&background),
-                    background_type: BackgroundType::from(bg_type),
-                    background_color: Color::from_rgb(0, 0, 32),
-                    npc1: NpcType::new(&npc1),
-                    npc2: NpcType::new(&npc2),
-                };
-                stages.push(stage);
+                    let tileset = from_encoding(&ts_buf[0..zero_index(&ts_buf)], encoding);
+                    let map = from_encoding(&map_buf[0..zero_index(&map_buf)], encoding);
+                    let background = from_encoding(&back_buf[0..zero_index(&back_buf)], encoding);
+                    let npc1 = from_encoding(&npc1_buf[0..zero_index(&npc1_buf)], encoding);
+                    let npc2 = from_encoding(&npc2_buf[0..zero_index(&npc2_buf)], encoding);
+                    let name = from_encoding(&name_buf[0..zero_index(&name_buf)], encoding);
+
+                    let stage = StageData {
+                        name: name.clone(),
+                        name_jp: name.clone(),
+                        map: map.clone(),
+                        boss_no,
+                        tileset: Tileset::new(&tileset),
+                        pxpack_data: None,
+                        background: Background::new(&background),
+                        background_type: BackgroundType::from(bg_type),
+                        background_color: Color::from_rgb(0, 0, 32),
+                        npc1: NpcType::new(&npc1),
+                        npc2: NpcType::new(&npc2),
+                        ambient_light: None,
+                        camera_bounds: None,
+                    };
+                    stages.push(stage);
+                }
             }
+            StageTableFormat::MoustacheRider => {
+                let mut f = Cursor::new(data);
+                let count = f.read_u32::<LE>()?;
+
+                if data.len() - 4 < count as usize * 0x74 {
+                    return Err(ResourceLoadError(
+                        "Specified stage table size is bigger than actual number of entries.".to_string(),
+                    ));
+                }
 
-            return Ok(stages);
-        } else if let Ok(mut file) = filesystem::open_find(ctx, roots, stage_dat_path) {
-            let mut stages = Vec::new();
+                for _ in 0..count {
+                    let mut ts_buf = vec![0u8; 0x10];
+                    let mut map_buf = vec![0u8; 0x10];
+                    let mut back_buf = vec![0u8; 0x10];
+                    let mut npc1_buf = vec![0u8; 0x10];
+                    let mut npc2_buf = vec![0u8; 0x10];
+                    let mut name_buf = vec![0u8; 0x22];
+
+                    f.read_exact(&mut ts_buf)?;
+                    f.read_exact(&mut map_buf)?;
+                    let bg_type = f.read_u8()?;
+                    f.read_exact(&mut back_buf)?;
+                    f.read_exact(&mut npc1_buf)?;
+                    f.read_exact(&mut npc2_buf)?;
+                    let boss_no = f.read_u8()?;
+                    f.read_exact(&mut name_buf)?;
+
+                    let tileset = from_encoding(&ts_buf[0..zero_index(&ts_buf)], encoding);
+                    let map = from_encoding(&map_buf[0..zero_index(&map_buf)], encoding);
+                    let background = from_encoding(&back_buf[0..zero_index(&back_buf)], encoding);
+                    let npc1 = from_encoding(&npc1_buf[0..zero_index(&npc1_buf)], encoding);
+                    let npc2 = from_encoding(&npc2_buf[0..zero_index(&npc2_buf)], encoding);
+                    let name = from_encoding(&name_buf[0..zero_index(&name_buf)], encoding);
+
+                    let stage = StageData {
+                        name: name.clone(),
+                        name_jp: name.clone(),
+                        map: map.clone(),
+                        boss_no,
+                        tileset: Tileset::new(&tileset),
+                        pxpack_data: None,
+                        background: Background::new(&background),
+                        background_type: BackgroundType::from(bg_type),
+                        background_color: Color::from_rgb(0, 0, 32),
+                        npc1: NpcType::new(&npc1),
+                        npc2: NpcType::new(&npc2),
+                        ambient_light: None,
+                        camera_bounds: None,
+                    };
+                    stages.push(stage);
+                }
+            }
+            StageTableFormat::NXEngine => {
+                let mut f = Cursor::new(data);
+                let count = f.read_u8()? as usize;
+
+                if data.len() - 1 < count * 0x49 {
+                    return Err(ResourceLoadError(
+                        "Specified stage table size is bigger than actual number of entries.".to_string(),
+                    ));
+                }
 
-            log::info!("Loading NXEngine stage table from {}", &stage_dat_path);
+                for _ in 0..count {
+                    let mut map_buf = vec![0u8; 0x20];
+                    let mut name_buf = vec![0u8; 0x23];
+
+                    f.read_exact(&mut map_buf)?;
+                    f.read_exact(&mut name_buf)?;
+
+                    let tileset_id = f.read_u8()? as usize;
+                    let bg_id = f.read_u8()? as usize;
+                    let bg_type = f.read_u8()?;
+                    let boss_no = f.read_u8()?;
+                    let npc1 = f.read_u8()? as usize;
+                    let npc2 = f.read_u8()? as usize;
+
+                    let map = from_utf8(&map_buf)
+                        .map_err(|_| ResourceLoadError("UTF-8 error in map field".to_string()))?
+                        .trim_matches('\0')
+                        .to_owned();
+                    let name = from_utf8(&name_buf)
+                        .map_err(|_| ResourceLoadError("UTF-8 error in name field".to_string()))?
+                        .trim_matches('\0')
+                        .to_owned();
+
+                    let stage = StageData {
+                        name: name.clone(),
+                        name_jp: name.clone(),
+                        map: map.clone(),
+                        boss_no,
+                        tileset: Tileset::new(NXENGINE_TILESETS.get(tileset_id).unwrap_or(&"0")),
+                        pxpack_data: None,
+                        background: Background::new(NXENGINE_BACKDROPS.get(bg_id).unwrap_or(&"0")),
+                        background_type: BackgroundType::from(bg_type),
+                        background_color: Color::from_rgb(0, 0, 32),
+                        npc1: NpcType::new(NXENGINE_NPCS.get(npc1).unwrap_or(&"0")),
+                        npc2: NpcType::new(NXENGINE_NPCS.get(npc2).unwrap_or(&"0")),
+                        ambient_light: None,
+                        camera_bounds: None,
+                    };
+                    stages.push(stage);
+                }
+            }
+            StageTableFormat::Json => {
+                let table: StageTableJson = serde_json::from_slice(data)
+                    .map_err(|e| ResourceLoadError(format!("Failed to parse stage table: {}", e)))?;
+
+                for stage in table.stages {
+                    let name_jp = if stage.name_jp.is_empty() { stage.name.clone() } else { stage.name_jp };
+
+                    stages.push(StageData {
+                        name: stage.name,
+                        name_jp,
+                        map: stage.map,
+                        boss_no: stage.boss_no,
+                        tileset: Tileset::new(&stage.tileset),
+                        pxpack_data: None,
+                        background: Background::new(&stage.background),
+                        background_type: BackgroundType::from(stage.background_type),
+                        background_color: Color::from_rgb(0, 0, 32),
+                        npc1: NpcType::new(&stage.npc1),
+                        npc2: NpcType::new(&stage.npc2),
+                        ambient_light: stage.ambient_light,
+                        camera_bounds: stage.camera_bounds,
+                    });
+                }
+            }
+        }
 
-            let mut data = Vec::new();
+        Ok(stages)
+    }
 
-            let count = file.read_u8()? as usize;
-            file.read_to_end(&mut data)?;
+    /// Serializes the stage table in given format, fails if something doesn't fit into it.
+    pub fn write_stage_table(
+        stages: &[Self],
+        format: StageTableFormat,
+        encoding: Option<TextScriptEncoding>,
+    ) -> GameResult<Vec<u8>> {
+        let mut out = Vec::new();
+        let utf8 = format == StageTableFormat::Switch;
+        let encode = |s: &str| to_encoding(s, encoding, utf8);
+
+        match format {
+            StageTableFormat::MoustacheRider => out.write_u32::<LE>(stages.len() as u32)?,
+            StageTableFormat::NXEngine => {
+                if stages.len() > u8::MAX as usize {
+                    return Err(GameError::InvalidValue(format!(
+                        "NXEngine stage table can hold up to {} stages.",
+                        u8::MAX
+                    )));
+                }
 
-            if data.len() < count * 0x49 {
-                return Err(ResourceLoadError(
-                    "Specified stage table size is bigger than actual number of entries.".to_string(),
-                ));
+                out.write_u8(stages.len() as u8)?;
             }
-
-            let mut f = Cursor::new(data);
-            for _ in 0..count {
-                let mut map_buf = vec![0u8; 0x20];
-                let mut name_buf = vec![0u8; 0x23];
-
-                f.read_exact(&mut map_buf)?;
-                f.read_exact(&mut name_buf)?;
-
-                let tileset_id = f.read_u8()? as usize;
-                let bg_id = f.read_u8()? as usize;
-                let bg_type = f.read_u8()?;
-                let boss_no = f.read_u8()?;
-                let npc1 = f.read_u8()? as usize;
-                let npc2 = f.read_u8()? as usize;
-
-                let map = from_utf8(&map_buf)
-                    .map_err(|_| ResourceLoadError("UTF-8 error in map field".to_string()))?
-                    .trim_matches('\0')
-                    .to_owned();
-                let name = from_utf8(&name_buf)
-                    .map_err(|_| ResourceLoadError("UTF-8 error in name field".to_string()))?
-                    .trim_matches('\0')
-                    .to_owned();
-
-                let stage = StageData {
-                    name: name.clone(),
-                    name_jp: name.clone(),
-                    map: map.clone(),
-                    boss_no,
-                    tileset: Tileset::new(NXENGINE_TILESETS.get(tileset_id).unwrap_or(&"0")),
-                    pxpack_data: None,
-                    background: Background::new(NXENGINE_BACKDROPS.get(bg_id).unwrap_or(&"0")),
-                    background_type: BackgroundType::from(bg_type),
-                    background_color: Color::from_rgb(0, 0, 32),
-                    npc1: NpcType::new(NXENGINE_NPCS.get(npc1).unwrap_or(&"0")),
-                    npc2: NpcType::new(NXENGINE_NPCS.get(npc2).unwrap_or(&"0")),
+            StageTableFormat::Json => {
+                let table = StageTableJson {
+                    stages: stages
+                        .iter()
+                        .map(|stage| StageJson {
+                            name: stage.name.clone(),
+                            name_jp: if stage.name_jp == stage.name { String::new() } else { stage.name_jp.clone() },
+                            map: stage.map.clone(),
+                            tileset: stage.tileset.name.clone(),
+                            background: stage.background.name.clone(),
+                            background_type: stage.background_type as u8,
+                            npc1: stage.npc1.name.clone(),
+                            npc2: stage.npc2.name.clone(),
+                            boss_no: stage.boss_no,
+                            ambient_light: stage.ambient_light,
+                            camera_bounds: stage.camera_bounds,
+                        })
+                        .collect(),
                 };
-                stages.push(stage);
+
+                return serde_json::to_vec_pretty(&table)
+                    .map_err(|e| GameError::InvalidValue(format!("Failed to serialize stage table: {}", e)));
             }
+            _ => (),
+        }
 
-            return Ok(stages);
+        for stage in stages {
+            let map = stage.map.as_str();
+
+            match format {
+                StageTableFormat::CSPlus | StageTableFormat::Switch => {
+                    write_field(&mut out, &encode(&stage.tileset.name), 0x20, "Tileset", map)?;
+                    write_field(&mut out, &encode(map), 0x20, "Map", map)?;
+                    out.write_u32::<LE>(stage.background_type as u32)?;
+                    write_field(&mut out, &encode(&stage.background.name), 0x20, "Background", map)?;
+                    write_field(&mut out, &encode(&stage.npc1.name), 0x20, "NPC sheet 1", map)?;
+                    write_field(&mut out, &encode(&stage.npc2.name), 0x20, "NPC sheet 2", map)?;
+                    out.write_u8(stage.boss_no)?;
+                    write_field(&mut out, &encode(&stage.name_jp), 0x20, "Japanese name", map)?;
+                    write_field(&mut out, &encode(&stage.name), 0x20, "Name", map)?;
+                }
+                StageTableFormat::Freeware => {
+                    write_field(&mut out, &encode(&stage.tileset.name), 0x20, "Tileset", map)?;
+                    write_field(&mut out, &encode(map), 0x20, "Map", map)?;
+                    out.write_u32::<LE>(stage.background_type as u32)?;
+                    write_field(&mut out, &encode(&stage.background.name), 0x20, "Background", map)?;
+                    write_field(&mut out, &encode(&stage.npc1.name), 0x20, "NPC sheet 1", map)?;
+                    write_field(&mut out, &encode(&stage.npc2.name), 0x20, "NPC sheet 2", map)?;
+                    out.write_u8(stage.boss_no)?;
+                    write_field(&mut out, &encode(&stage.name), 0x20, "Name", map)?;
+                    // alignment
+                    out.extend_from_slice(&[0u8; 3]);
+                }
+                StageTableFormat::MoustacheRider => {
+                    write_field(&mut out, &encode(&stage.tileset.name), 0x10, "Tileset", map)?;
+                    write_field(&mut out, &encode(map), 0x10, "Map", map)?;
+                    out.write_u8(stage.background_type as u8)?;
+                    write_field(&mut out, &encode(&stage.background.name), 0x10, "Background", map)?;
+                    write_field(&mut out, &encode(&stage.npc1.name), 0x10, "NPC sheet 1", map)?;
+                    write_field(&mut out, &encode(&stage.npc2.name), 0x10, "NPC sheet 2", map)?;
+                    out.write_u8(stage.boss_no)?;
+                    write_field(&mut out, &encode(&stage.name), 0x22, "Name", map)?;
+                }
+                StageTableFormat::NXEngine => {
+                    write_field(&mut out, map.as_bytes(), 0x20, "Map", map)?;
+                    write_field(&mut out, stage.name.as_bytes(), 0x23, "Name", map)?;
+                    out.write_u8(nxengine_index(&NXENGINE_TILESETS, &stage.tileset.name, "Tileset", map)?)?;
+                    out.write_u8(nxengine_index(&NXENGINE_BACKDROPS, &stage.background.name, "Background", map)?)?;
+                    out.write_u8(stage.background_type as u8)?;
+                    out.write_u8(stage.boss_no)?;
+                    out.write_u8(nxengine_index(&NXENGINE_NPCS, &stage.npc1.name, "NPC sheet", map)?)?;
+                    out.write_u8(nxengine_index(&NXENGINE_NPCS, &stage.npc2.name, "NPC sheet", map)?)?;
+                }
+                StageTableFormat::Json => unreachable!(),
+            }
         }
 
-        Err(ResourceLoadError("No stage table found.".to_string()))
+        Ok(out)
     }
 }
 
@@ -527,7 +795,8 @@ impl Stage {
         } else if let Ok(map_file) = filesystem::open_find(ctx, roots, ["Stage/", &data.map, ".pxm"].join("")) {
             let attrib_file = filesystem::open_find(ctx, roots, ["Stage/", &data.tileset.name, ".pxa"].join(""))?;
 
-            let map = Map::load_pxm(map_file, attrib_file)?;
+            let mut map = Map::load_pxm(map_file, attrib_file)?;
+            map.load_layer_attributes(ctx, roots, &data.tileset.name);
 
             let stage = Self { map, data };
 
@@ -543,8 +812,8 @@ impl Stage {
         constants: &EngineConstants,
         ctx: &mut Context,
     ) -> GameResult<TextScript> {
-        let tsc_file = filesystem::open_find(ctx, roots, ["Stage/", &self.data.map, ".tsc"].join(""))?;
-        let text_script = TextScript::load_from(tsc_file, constants)?;
+        let path = ["Stage/", &self.data.map, ".tsc"].join("");
+        let text_script = TextScript::load_from_file(ctx, roots, &path, constants)?;
 
         Ok(text_script)
     }
@@ -576,6 +845,23 @@ impl Stage {
 
         false
     }
+
+    /// Changes map tile on one of the extra layers of the map, returns true if it was changed.
+    pub fn change_layer_tile(&mut self, layer: usize, x: usize, y: usize, tile_type: u8) -> bool {
+        let width = self.map.width as usize;
+        if x >= width || y >= self.map.height as usize {
+            return false;
+        }
+
+        if let Some(ptr) = self.map.layers.get_mut(layer).and_then(|l| l.tiles.get_mut(y * width + x)) {
+            if *ptr != tile_type {
+                *ptr = tile_type;
+                return true;
+            }
+        }
+
+        false
+    }
 }
 
 pub struct StageTexturePaths {
@@ -630,4 +916,90 @@ impl StageTexturePaths {
         self.npc1 = ["Npc/", &stage.data.npc1.filename()].join("");
         self.npc2 = ["Npc/", &stage.data.npc2.filename()].join("");
     }
+
+    /// Path to the tileset texture of an extra map layer.
+    pub fn layer_tileset(&self, layer: &MapLayer) -> String {
+        if layer.tileset.is_empty() {
+            self.tileset_fg.clone()
+        } else {
+            ["Stage/", &Tileset::new(&layer.tileset).filename()].join("")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stage(name: &str, name_jp: &str, map: &str, tileset: &str, background: &str, npcs: (&str, &str)) -> StageData {
+        StageData {
+            name: name.to_owned(),
+            name_jp: name_jp.to_owned(),
+            map: map.to_owned(),
+            boss_no: 3,
+            tileset: Tileset::new(tileset),
+            pxpack_data: None,
+            background: Background::new(background),
+            background_type: BackgroundType::TiledParallax,
+            background_color: Color::from_rgb(0, 0, 32),
+            npc1: NpcType::new(npcs.0),
+            npc2: NpcType::new(npcs.1),
+            ambient_light: None,
+            camera_bounds: None,
+        }
+    }
+
+    fn assert_round_trip(stages: &[StageData], format: StageTableFormat) {
+        let data = StageData::write_stage_table(stages, format, None).unwrap();
+        let parsed = StageData::read_stage_table(&data, format, None).unwrap();
+
+        assert_eq!(parsed.len(), stages.len());
+        for (a, b) in stages.iter().zip(parsed.iter()) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.name_jp, b.name_jp);
+            assert_eq!(a.map, b.map);
+            assert_eq!(a.boss_no, b.boss_no);
+            assert_eq!(a.tileset, b.tileset);
+            assert_eq!(a.background, b.background);
+            assert_eq!(a.background_type, b.background_type);
+            assert_eq!(a.npc1, b.npc1);
+            assert_eq!(a.npc2, b.npc2);
+            assert_eq!(a.ambient_light, b.ambient_light);
+            assert_eq!(a.camera_bounds, b.camera_bounds);
+        }
+    }
+
+    #[test]
+    fn test_stage_table_round_trip() {
+        let mimi = stage("Mimiga Village", "ミミガーの村", "Mimi", "Mimi", "bkBlue", ("Guest", "Mimi"));
+        let null = stage("", "", "0", "0", "bk0", ("Guest", "0"));
+
+        assert_round_trip(&[null.clone(), mimi.clone()], StageTableFormat::CSPlus);
+        assert_round_trip(&[null.clone(), mimi.clone()], StageTableFormat::Switch);
+
+        let mut lit = mimi.clone();
+        lit.ambient_light = Some((40, 40, 60));
+        lit.camera_bounds = Some((2, 2, 40, 20));
+        assert_round_trip(&[null.clone(), mimi.clone(), lit], StageTableFormat::Json);
+
+        // these only have a single name
+        let mimi = stage("Mimiga Village", "Mimiga Village", "Mimi", "Mimi", "bkBlue", ("Guest", "Cemet"));
+        let null = stage("", "", "0", "0", "bk0", ("Guest", "0"));
+        assert_round_trip(&[null.clone(), mimi.clone()], StageTableFormat::Freeware);
+        assert_round_trip(&[null.clone(), mimi.clone()], StageTableFormat::MoustacheRider);
+        assert_round_trip(&[null, mimi], StageTableFormat::NXEngine);
+    }
+
+    #[test]
+    fn test_stage_table_write_limits() {
+        let long_name = stage("Mimiga Village, but longer", "", "Mimi", "Mimi", "bkBlue", ("Guest", "0"));
+        assert!(StageData::write_stage_table(&[long_name.clone()], StageTableFormat::CSPlus, None).is_ok());
+        assert!(StageData::write_stage_table(&[long_name], StageTableFormat::MoustacheRider, None).is_ok());
+
+        let long_map = stage("Mimiga Village", "", "MimigaVillageMap", "Mimi", "bkBlue", ("Guest", "0"));
+        assert!(StageData::write_stage_table(&[long_map], StageTableFormat::MoustacheRider, None).is_err());
+
+        let custom_sheet = stage("Mimiga Village", "", "Mimi", "Mimi", "bkBlue", ("Guest", "Custom"));
+        assert!(StageData::write_stage_table(&[custom_sheet], StageTableFormat::NXEngine, None).is_err());
+    }
 }
@@ -0,0 +1,118 @@
+//! Command line tool for converting stage tables between formats.
+//!
+//! ```text
+//! doukutsu-rs stage convert stage.tbl stage.json
+//! doukutsu-rs stage convert Doukutsu.exe stage.tbl --to switch
+//! ```
+//! Formats are guessed from file names unless given with `--from` and `--to`,
+//! `Doukutsu.exe` is read as the stage table embedded in the freeware executable.
+
+use std::fs;
+
+use crate::data::vanilla::VanillaExtractor;
+use crate::framework::error::GameError::CommandLineError;
+use crate::framework::error::GameResult;
+use crate::game::scripting::tsc::text_script::TextScriptEncoding;
+use crate::game::stage::{StageData, StageTableFormat};
+
+const USAGE: &str = "Usage:
+  doukutsu-rs stage convert <input> <output> [--from <format>] [--to <format>] [--encoding <encoding>]
+
+Formats:
+  csplus    stage.tbl from Cave Story+
+  switch    stage.tbl from Cave Story+ for Nintendo Switch
+  freeware  stage.sect dumped from Doukutsu.exe
+  mrmap     mrmap.bin from Moustache Rider
+  nxengine  stage.dat from NXEngine
+  json      stage.json
+  exe       stage table embedded in Doukutsu.exe, input only
+
+Options:
+  --encoding <encoding>  text encoding of the binary tables, Shift-JIS by default (UTF-8 on Switch)";
+
+/// Where the stage table is read from.
+enum InputFormat {
+    Table(StageTableFormat),
+    Executable,
+}
+
+pub fn run(args: &[String]) -> GameResult {
+    let mut positional = Vec::new();
+    let mut from = None;
+    let mut to = None;
+    let mut encoding = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" => {
+                from = match args.next().map(String::as_str) {
+                    Some("exe") => Some(InputFormat::Executable),
+                    Some(name) => match StageTableFormat::from_name(name) {
+                        Some(format) => Some(InputFormat::Table(format)),
+                        None => return Err(usage_error("unknown input format")),
+                    },
+                    None => return Err(usage_error("--from requires a format")),
+                };
+            }
+            "--to" => {
+                let name = args.next().ok_or_else(|| usage_error("--to requires a format"))?;
+                to = Some(StageTableFormat::from_name(name).ok_or_else(|| usage_error("unknown output format"))?);
+            }
+            "--encoding" => {
+                let name = args.next().ok_or_else(|| usage_error("--encoding requires an encoding name"))?;
+                encoding = Some(TextScriptEncoding::from(name.as_str()));
+            }
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    match positional.as_slice() {
+        ["convert", input, output] => {
+            let from = match from {
+                Some(from) => from,
+                None if input.to_lowercase().ends_with(".exe") => InputFormat::Executable,
+                None => InputFormat::Table(
+                    StageTableFormat::from_file_name(input)
+                        .ok_or_else(|| usage_error("can't guess the input format, use --from"))?,
+                ),
+            };
+            let to = match to {
+                Some(to) => to,
+                None => StageTableFormat::from_file_name(output)
+                    .ok_or_else(|| usage_error("can't guess the output format, use --to"))?,
+            };
+
+            convert(input, from, output, to, encoding)
+        }
+        _ => Err(usage_error("invalid arguments")),
+    }
+}
+
+fn usage_error(msg: &str) -> crate::framework::error::GameError {
+    CommandLineError(format!("{}\n\n{}", msg, USAGE))
+}
+
+fn convert(
+    input: &str,
+    from: InputFormat,
+    output: &str,
+    to: StageTableFormat,
+    encoding: Option<TextScriptEncoding>,
+) -> GameResult {
+    let data = fs::read(input)?;
+    let stages = match from {
+        InputFormat::Table(format) => StageData::read_stage_table(&data, format, encoding)?,
+        InputFormat::Executable => {
+            let table = VanillaExtractor::from_exe_buffer(data).stage_table()?;
+            StageData::read_stage_table(&table, StageTableFormat::Freeware, encoding)?
+        }
+    };
+
+    let data = StageData::write_stage_table(&stages, to, encoding)?;
+    fs::write(output, data)?;
+
+    println!("Converted {} stages to {}.", stages.len(), output);
+
+    Ok(())
+}
@@ -9,8 +9,13 @@ use crate::game::physics::{OFFSETS, PhysicalEntity};
 use crate::game::player::{Player, TargetPlayer};
 use crate::game::shared_game_state::{SharedGameState, TileSize};
 use crate::game::stage::Stage;
+use crate::game::weapon::bullet_behaviour::BulletMovement;
+use crate::game::weapon::custom::CustomBullet;
 use crate::util::rng::{RNG, Xoroshiro32PlusPlus, XorShift};
 
+/// Upper limit of bullets spawned by other bullets in a single tick, stops runaway chains of splitting bullets.
+const MAX_SPAWNED_BULLETS: usize = 256;
+
 pub struct BulletManager {
     pub bullets: Vec<Bullet>,
     pub new_bullets: Vec<Bullet>,
@@ -48,6 +53,7 @@ impl BulletManager {
     }
 
     pub fn tick_bullets(&mut self, state: &mut SharedGameState, players: [&Player; 2], npc_list: &NPCList) {
+        let mut spawned = 0;
         let mut i = 0;
         while i < self.bullets.len() {
             {
@@ -57,6 +63,9 @@ impl BulletManager {
                 bullet.tick(state, players, npc_list, &mut self.new_bullets);
             }
 
+            self.new_bullets.truncate(MAX_SPAWNED_BULLETS - spawned);
+            spawned += self.new_bullets.len();
+
             for bullet in &mut self.new_bullets {
                 bullet.rng = Xoroshiro32PlusPlus::new(self.seeder.next_u32());
             }
@@ -134,24 +143,43 @@ impl Bullet {
         direction: Direction,
         constants: &EngineConstants,
     ) -> Bullet {
-        let bullet = constants.weapon.bullet_table.get(btype as usize).unwrap_or_else(|| &BulletData {
-            damage: 0,
-            life: 0,
-            lifetime: 0,
-            flags: BulletFlag(0),
-            enemy_hit_width: 0,
-            enemy_hit_height: 0,
-            block_hit_width: 0,
-            block_hit_height: 0,
-            display_bounds: Rect { left: 0, top: 0, right: 0, bottom: 0 },
-        });
+        let custom = constants.weapon.custom_bullets.get(&btype);
+        let custom_data = custom.map(CustomBullet::bullet_data);
+        let bullet =
+            custom_data.as_ref().or_else(|| constants.weapon.bullet_table.get(btype as usize)).unwrap_or_else(|| {
+                &BulletData {
+                    damage: 0,
+                    life: 0,
+                    lifetime: 0,
+                    flags: BulletFlag(0),
+                    enemy_hit_width: 0,
+                    enemy_hit_height: 0,
+                    block_hit_width: 0,
+                    block_hit_height: 0,
+                    display_bounds: Rect { left: 0, top: 0, right: 0, bottom: 0 },
+                }
+            });
+
+        // custom bullets are launched by the constructor, so weapons can rotate them afterwards
+        let (vel_x, vel_y) = match (custom, direction) {
+            (Some(custom), Direction::Left) => (-custom.speed, 0),
+            (Some(custom), Direction::Up) => (0, -custom.speed),
+            (Some(custom), Direction::Right) => (custom.speed, 0),
+            (Some(custom), Direction::Bottom) => (0, custom.speed),
+            _ => (0, 0),
+        };
+
+        let mut weapon_flags = bullet.flags;
+        if custom.map_or(false, |custom| matches!(custom.movement, BulletMovement::Bouncing { .. })) {
+            weapon_flags.set_bounce_from_walls(true);
+        }
 
         Bullet {
             btype,
             x,
             y,
-            vel_x: 0,
-            vel_y: 0,
+            vel_x,
+            vel_y,
             target_x: 0,
             target_y: 0,
             prev_x: x,
@@ -164,7 +192,7 @@ impl Bullet {
             rng: Xoroshiro32PlusPlus::new(1),
             owner,
             cond: Condition(0x80),
-            weapon_flags: bullet.flags,
+            weapon_flags,
             flags: Flag(0),
             direction,
             anim_rect: Rect::new(0, 0, 0, 0),
@@ -1657,6 +1685,12 @@ impl Bullet {
         npc_list: &NPCList,
         new_bullets: &mut Vec<Bullet>,
     ) {
+        // custom bullets handle running out of life on their own, see `BulletMovement`
+        if state.constants.weapon.custom_bullets.contains_key(&self.btype) {
+            self.tick_custom(state, npc_list, new_bullets);
+            return;
+        }
+
         if self.life == 0 {
             self.cond.set_alive(false);
             return;
@@ -0,0 +1,305 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::Direction;
+use crate::engine_constants::EngineConstants;
+use crate::game::caret::{Caret, CaretType};
+use crate::game::npc::list::NPCList;
+use crate::game::npc::NPC;
+use crate::game::shared_game_state::SharedGameState;
+use crate::game::weapon::bullet::Bullet;
+use crate::game::weapon::custom::CustomBullet;
+
+/// Movement preset of a custom bullet.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BulletMovement {
+    /// Flies in a straight line.
+    #[default]
+    Straight,
+    /// Steers towards the closest shootable NPC.
+    Homing {
+        /// Velocity change per tick towards the target.
+        acceleration: i32,
+        /// Search range in pixels, 0 means unlimited.
+        #[serde(default)]
+        range: i32,
+        /// Ticks before the bullet starts steering.
+        #[serde(default)]
+        delay: u16,
+    },
+    /// Oscillates perpendicularly to the direction of travel.
+    SineWave {
+        /// Maximum offset in 1/512 pixel units.
+        amplitude: i32,
+        /// Length of a full wave in ticks.
+        period: u16,
+    },
+    /// Bounces off walls, optionally affected by gravity.
+    Bouncing {
+        #[serde(default)]
+        gravity: i32,
+        /// Number of bounces before the bullet disappears, 0 means unlimited.
+        #[serde(default)]
+        max_bounces: u16,
+    },
+}
+
+/// Splits the bullet into several other bullets once it expires.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulletSplit {
+    pub bullet: u16,
+    pub count: u16,
+    /// Angle in degrees the new bullets are spread across, 360 makes a full ring.
+    #[serde(default = "default_split_spread")]
+    pub spread: f32,
+    /// Also split when the bullet runs out of life by hitting something.
+    #[serde(default)]
+    pub on_hit: bool,
+}
+
+/// Spawns an NPC and/or a bullet at the bullet position after given amount of ticks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulletSpawn {
+    #[serde(default)]
+    pub delay: u16,
+    /// Repeat interval in ticks after the first spawn, 0 spawns only once.
+    #[serde(default)]
+    pub interval: u16,
+    #[serde(default)]
+    pub npc: Option<u16>,
+    #[serde(default)]
+    pub bullet: Option<u16>,
+}
+
+impl BulletSpawn {
+    fn is_due(&self, tick: u16) -> bool {
+        match tick.checked_sub(self.delay) {
+            Some(0) => true,
+            Some(elapsed) => self.interval != 0 && elapsed % self.interval == 0,
+            None => false,
+        }
+    }
+}
+
+fn default_split_spread() -> f32 {
+    360.0
+}
+
+/// Length of a vector in fixed point units, computed in floating point to avoid overflows.
+fn length(x: i32, y: i32) -> f32 {
+    (x as f32).hypot(y as f32)
+}
+
+impl Bullet {
+    /// Sets the velocity to given speed at given angle in radians.
+    fn launch(&mut self, angle: f32, speed: i32) {
+        self.vel_x = (angle.cos() * speed as f32) as i32;
+        self.vel_y = (angle.sin() * speed as f32) as i32;
+    }
+
+    fn travel_angle(&self) -> f32 {
+        if self.vel_x == 0 && self.vel_y == 0 {
+            return match self.direction {
+                Direction::Up => -PI / 2.0,
+                Direction::Right => 0.0,
+                Direction::Bottom => PI / 2.0,
+                _ => PI,
+            };
+        }
+
+        (self.vel_y as f32).atan2(self.vel_x as f32)
+    }
+
+    pub(crate) fn tick_custom(
+        &mut self,
+        state: &mut SharedGameState,
+        npc_list: &NPCList,
+        new_bullets: &mut Vec<Bullet>,
+    ) {
+        // the definition stays borrowed, so only disjoint fields of the state are touched below
+        let Some(def) = state.constants.weapon.custom_bullets.get(&self.btype) else {
+            self.cond.set_alive(false);
+            return;
+        };
+
+        if self.life == 0 {
+            if def.piercing {
+                self.life = (def.life as u16).max(1);
+            } else {
+                if let Some(split) = def.split.as_ref().filter(|split| split.on_hit) {
+                    self.split(split, &state.constants, new_bullets);
+                }
+
+                self.cond.set_alive(false);
+                return;
+            }
+        }
+
+        self.action_counter += 1;
+        if self.action_counter > self.lifetime {
+            if let Some(split) = &def.split {
+                self.split(split, &state.constants, new_bullets);
+            }
+
+            self.cond.set_alive(false);
+            state.carets.push(Caret::new(self.x, self.y, CaretType::Shoot, Direction::Left, &state.constants));
+            return;
+        }
+
+        match def.movement {
+            BulletMovement::Straight => {
+                self.x += self.vel_x;
+                self.y += self.vel_y;
+            }
+            BulletMovement::Homing { acceleration, range, delay } => {
+                if self.action_counter > delay {
+                    self.steer_to_closest_npc(npc_list, acceleration, range, def.speed);
+                }
+
+                self.x += self.vel_x;
+                self.y += self.vel_y;
+            }
+            BulletMovement::SineWave { amplitude, period } => {
+                // target_x/y hold the position on the center line of the wave
+                if self.action_num == 0 {
+                    self.action_num = 1;
+                    self.target_x = self.x;
+                    self.target_y = self.y;
+                }
+
+                self.target_x += self.vel_x;
+                self.target_y += self.vel_y;
+
+                let speed = length(self.vel_x, self.vel_y);
+                if speed > 0.0 && period != 0 {
+                    let offset =
+                        (self.action_counter as f32 * 2.0 * PI / period as f32).sin() * amplitude as f32 / speed;
+
+                    self.x = self.target_x - (self.vel_y as f32 * offset) as i32;
+                    self.y = self.target_y + (self.vel_x as f32 * offset) as i32;
+                } else {
+                    self.x = self.target_x;
+                    self.y = self.target_y;
+                }
+            }
+            BulletMovement::Bouncing { gravity, max_bounces } => {
+                let mut bounced = false;
+
+                if (self.flags.hit_left_wall() && self.vel_x < 0) || (self.flags.hit_right_wall() && self.vel_x > 0) {
+                    self.vel_x = -self.vel_x;
+                    bounced = true;
+                }
+
+                if (self.flags.hit_top_wall() && self.vel_y < 0) || (self.flags.hit_bottom_wall() && self.vel_y > 0) {
+                    self.vel_y = -self.vel_y;
+                    bounced = true;
+                }
+
+                if bounced {
+                    self.counter1 += 1;
+
+                    if max_bounces != 0 && self.counter1 > max_bounces {
+                        self.cond.set_alive(false);
+                        state.carets.push(Caret::new(
+                            self.x,
+                            self.y,
+                            CaretType::ProjectileDissipation,
+                            Direction::Left,
+                            &state.constants,
+                        ));
+                        return;
+                    }
+                }
+
+                self.vel_y += gravity;
+                self.x += self.vel_x;
+                self.y += self.vel_y;
+            }
+        }
+
+        for spawn in def.spawns.iter().filter(|spawn| spawn.is_due(self.action_counter)) {
+            if let Some(npc_type) = spawn.npc {
+                let mut npc = NPC::create(npc_type, &state.npc_table);
+                npc.cond.set_alive(true);
+                npc.x = self.x;
+                npc.y = self.y;
+                npc.direction = self.direction;
+
+                let _ = npc_list.spawn(0x100, npc);
+            }
+
+            if let Some(btype) = spawn.bullet {
+                new_bullets.push(Bullet::new(self.x, self.y, btype, self.owner, self.direction, &state.constants));
+            }
+        }
+
+        self.animate(def);
+    }
+
+    fn steer_to_closest_npc(&mut self, npc_list: &NPCList, acceleration: i32, range: i32, speed: i32) {
+        let max_distance = if range > 0 { range as f32 * 512.0 } else { f32::MAX };
+
+        let target = npc_list
+            .iter_alive()
+            .filter(|npc| npc.npc_flags.shootable() && !npc.npc_flags.invulnerable())
+            .map(|npc| (npc.x, npc.y, length(npc.x - self.x, npc.y - self.y)))
+            .filter(|&(_, _, distance)| distance <= max_distance)
+            .min_by(|a, b| a.2.total_cmp(&b.2));
+
+        let Some((x, y, distance)) = target else {
+            return;
+        };
+
+        if distance > 0.0 {
+            self.vel_x += ((x - self.x) as f32 / distance * acceleration as f32) as i32;
+            self.vel_y += ((y - self.y) as f32 / distance * acceleration as f32) as i32;
+        }
+
+        let current_speed = length(self.vel_x, self.vel_y);
+        if current_speed > speed as f32 && current_speed > 0.0 {
+            let scale = speed as f32 / current_speed;
+            self.vel_x = (self.vel_x as f32 * scale) as i32;
+            self.vel_y = (self.vel_y as f32 * scale) as i32;
+        }
+    }
+
+    fn split(&self, split: &BulletSplit, constants: &EngineConstants, new_bullets: &mut Vec<Bullet>) {
+        let base_angle = self.travel_angle();
+        let speed = constants.weapon.custom_bullets.get(&split.bullet).map(|def| def.speed);
+
+        for i in 0..split.count {
+            let offset = if split.spread >= 360.0 {
+                360.0 * i as f32 / split.count as f32
+            } else if split.count > 1 {
+                split.spread * (i as f32 / (split.count - 1) as f32 - 0.5)
+            } else {
+                0.0
+            };
+
+            let mut bullet = Bullet::new(self.x, self.y, split.bullet, self.owner, self.direction, constants);
+            if let Some(speed) = speed {
+                bullet.launch(base_angle + offset.to_radians(), speed);
+            }
+
+            new_bullets.push(bullet);
+        }
+    }
+
+    fn animate(&mut self, def: &CustomBullet) {
+        let rects = def.rects.get(self.direction);
+        if rects.is_empty() {
+            return;
+        }
+
+        self.anim_counter += 1;
+        if self.anim_counter >= def.anim_speed {
+            self.anim_counter = 0;
+            self.anim_num += 1;
+        }
+
+        self.anim_num %= rects.len() as u16;
+        self.anim_rect = rects[self.anim_num as usize];
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::{BulletFlag, Direction, Rect};
+use crate::engine_constants::BulletData;
+use crate::game::caret::CaretType;
+use crate::game::player::{Player, TargetPlayer};
+use crate::game::shared_game_state::SharedGameState;
+use crate::game::weapon::bullet::{Bullet, BulletManager};
+use crate::game::weapon::bullet_behaviour::{BulletMovement, BulletSpawn, BulletSplit};
+use crate::game::weapon::{Weapon, WeaponType};
+
+/// Contents of a `weapons.json` file provided by a mod.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CustomWeaponTable {
+    #[serde(default)]
+    pub weapons: Vec<CustomWeapon>,
+    #[serde(default)]
+    pub bullets: Vec<CustomBullet>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FireMode {
+    /// One shot per press of the shoot button.
+    #[default]
+    Single,
+    /// Fires continuously while the shoot button is held, see [`CustomWeaponLevel::rate`].
+    Auto,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomWeaponLevel {
+    /// Experience required to reach the next level (or to max out the last one).
+    pub exp: u16,
+    /// Bullet type fired at this level, either a vanilla id or one from [`CustomWeaponTable::bullets`].
+    pub bullet: u16,
+    #[serde(default)]
+    pub fire_mode: FireMode,
+    /// Delay in ticks between shots in auto fire mode.
+    #[serde(default = "default_rate")]
+    pub rate: u16,
+    /// Maximum number of this level's bullets a player can have on screen, 0 means unlimited.
+    #[serde(default)]
+    pub max_bullets: u16,
+    /// Number of bullets fired at once.
+    #[serde(default = "default_count")]
+    pub count: u16,
+    /// Angle in degrees the bullets of a single shot are spread across.
+    #[serde(default)]
+    pub spread: f32,
+    #[serde(default = "default_count")]
+    pub ammo_cost: u16,
+    /// Sound effect played on shot, 0 means none.
+    #[serde(default)]
+    pub sound: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomWeapon {
+    /// Weapon id used by `<AM+`, `<AM-`, `<TAM` etc. Must not collide with vanilla weapons.
+    pub id: u8,
+    /// Icon in `ArmsImage`, defaults to the slot matching the id.
+    #[serde(default)]
+    pub icon_rect: Option<Rect<u16>>,
+    /// Index of the sprite in `Arms` the player holds, defaults to the id.
+    #[serde(default)]
+    pub arms_sprite: Option<u8>,
+    /// Ticks between refilling a single unit of ammo while not shooting, 0 disables recharging.
+    #[serde(default)]
+    pub recharge: u16,
+    pub levels: [CustomWeaponLevel; 3],
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CustomBulletRects {
+    pub left: Vec<Rect<u16>>,
+    #[serde(default)]
+    pub up: Vec<Rect<u16>>,
+    #[serde(default)]
+    pub right: Vec<Rect<u16>>,
+    #[serde(default)]
+    pub down: Vec<Rect<u16>>,
+}
+
+impl CustomBulletRects {
+    /// Animation frames for given direction, missing directions fall back to the left-facing frames.
+    pub fn get(&self, direction: Direction) -> &[Rect<u16>] {
+        let rects = match direction {
+            Direction::Up => &self.up,
+            Direction::Right => &self.right,
+            Direction::Bottom => &self.down,
+            _ => &self.left,
+        };
+
+        if rects.is_empty() {
+            &self.left
+        } else {
+            rects
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomBullet {
+    pub id: u16,
+    pub damage: u8,
+    /// Number of hits the bullet survives.
+    pub life: u8,
+    /// Lifetime in ticks.
+    pub lifetime: u16,
+    /// Raw `bullet.tbl` flags, see [`BulletFlag`].
+    #[serde(default)]
+    pub flags: u8,
+    pub enemy_hit_width: u16,
+    pub enemy_hit_height: u16,
+    pub block_hit_width: u16,
+    pub block_hit_height: u16,
+    pub display_bounds: Rect<u8>,
+    /// Speed in 1/512 pixel units per tick.
+    pub speed: i32,
+    /// Ticks per animation frame.
+    #[serde(default = "default_rate")]
+    pub anim_speed: u16,
+    /// Bullet spritesheet rects.
+    pub rects: CustomBulletRects,
+    #[serde(default)]
+    pub movement: BulletMovement,
+    /// Hits don't use up the bullet's life, it only disappears when its lifetime runs out.
+    #[serde(default)]
+    pub piercing: bool,
+    #[serde(default)]
+    pub split: Option<BulletSplit>,
+    #[serde(default)]
+    pub spawns: Vec<BulletSpawn>,
+}
+
+impl CustomBullet {
+    pub fn bullet_data(&self) -> BulletData {
+        BulletData {
+            damage: self.damage,
+            life: self.life,
+            lifetime: self.lifetime,
+            flags: BulletFlag(self.flags),
+            enemy_hit_width: self.enemy_hit_width,
+            enemy_hit_height: self.enemy_hit_height,
+            block_hit_width: self.block_hit_width,
+            block_hit_height: self.block_hit_height,
+            display_bounds: self.display_bounds,
+        }
+    }
+}
+
+/// Edge of the graph of bullets creating other bullets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SpawnEdge {
+    Split,
+    /// Index in [`CustomBullet::spawns`].
+    Spawn(usize),
+}
+
+fn spawn_edges(bullet: &CustomBullet) -> Vec<(SpawnEdge, u16)> {
+    let split = bullet.split.iter().map(|split| (SpawnEdge::Split, split.bullet));
+    let spawns = bullet.spawns.iter().enumerate().filter_map(|(i, spawn)| Some((SpawnEdge::Spawn(i), spawn.bullet?)));
+
+    split.chain(spawns).collect()
+}
+
+/// Drops splits and bullet spawns which lead back to the bullet that creates them, directly or through
+/// other bullets, as they would keep creating bullets forever.
+pub fn remove_spawn_cycles(bullets: &mut HashMap<u16, CustomBullet>) {
+    fn visit(id: u16, bullets: &mut HashMap<u16, CustomBullet>, path: &mut Vec<u16>, done: &mut HashSet<u16>) {
+        let Some(edges) = bullets.get(&id).map(spawn_edges) else {
+            return;
+        };
+
+        path.push(id);
+
+        for (edge, target) in edges {
+            if path.contains(&target) {
+                let bullet = bullets.get_mut(&id).unwrap();
+                match edge {
+                    SpawnEdge::Split => {
+                        log::warn!("Custom bullet {} splits back into bullet {}, ignoring its split.", id, target);
+                        bullet.split = None;
+                    }
+                    SpawnEdge::Spawn(i) => {
+                        log::warn!("Custom bullet {} spawns back bullet {}, ignoring that spawn.", id, target);
+                        bullet.spawns[i].bullet = None;
+                    }
+                }
+            } else if !done.contains(&target) {
+                visit(target, bullets, path, done);
+            }
+        }
+
+        path.pop();
+        done.insert(id);
+    }
+
+    let mut ids: Vec<u16> = bullets.keys().copied().collect();
+    ids.sort_unstable();
+
+    let mut done = HashSet::new();
+    for id in ids {
+        if !done.contains(&id) {
+            visit(id, bullets, &mut Vec::new(), &mut done);
+        }
+    }
+}
+
+fn default_rate() -> u16 {
+    1
+}
+
+fn default_count() -> u16 {
+    1
+}
+
+impl Weapon {
+    pub(crate) fn tick_custom(
+        &mut self,
+        player: &mut Player,
+        player_id: TargetPlayer,
+        bullet_manager: &mut BulletManager,
+        state: &mut SharedGameState,
+    ) {
+        let WeaponType::Custom(id) = self.wtype else {
+            return;
+        };
+        let Some(weapon) = state.constants.weapon.custom_weapons.get(&id) else {
+            return;
+        };
+        let recharge = weapon.recharge;
+        let level = weapon.levels[(self.level as usize).clamp(1, 3) - 1].clone();
+
+        let shoot = match level.fire_mode {
+            FireMode::Single => player.controller.trigger_shoot(),
+            FireMode::Auto => player.controller.shoot(),
+        };
+
+        if !shoot {
+            self.counter1 = level.rate;
+
+            if recharge != 0 {
+                self.counter2 += 1;
+
+                if self.counter2 >= recharge {
+                    self.counter2 = 0;
+                    self.refill_ammo(1);
+                }
+            }
+            return;
+        }
+
+        if level.fire_mode == FireMode::Auto {
+            self.counter1 += 1;
+
+            if self.counter1 < level.rate {
+                return;
+            }
+
+            self.counter1 = 0;
+        }
+
+        if level.max_bullets != 0 && bullet_manager.count_bullets(level.bullet, player_id) >= level.max_bullets as usize
+        {
+            return;
+        }
+
+        if !self.consume_ammo(level.ammo_cost) {
+            self.draw_empty(state, player.x, player.y);
+            return;
+        }
+
+        let (x, y, direction) = match player.direction {
+            Direction::Left if player.up => (player.x - 0x200, player.y - 0x1000, Direction::Up),
+            Direction::Right if player.up => (player.x + 0x200, player.y - 0x1000, Direction::Up),
+            Direction::Left if player.down => (player.x - 0x200, player.y + 0x1000, Direction::Bottom),
+            Direction::Right if player.down => (player.x + 0x200, player.y + 0x1000, Direction::Bottom),
+            Direction::Left => (player.x - 0xc00, player.y + 0x600, Direction::Left),
+            Direction::Right => (player.x + 0xc00, player.y + 0x600, Direction::Right),
+            _ => return,
+        };
+
+        let count = level.count.max(1);
+        for i in 0..count {
+            let mut bullet = Bullet::new(x, y, level.bullet, player_id, direction, &state.constants);
+
+            if count > 1 && level.spread != 0.0 {
+                let angle = (level.spread * (i as f32 / (count - 1) as f32 - 0.5)).to_radians();
+                let (sin, cos) = angle.sin_cos();
+                let (vel_x, vel_y) = (bullet.vel_x as f32, bullet.vel_y as f32);
+
+                bullet.vel_x = (vel_x * cos - vel_y * sin) as i32;
+                bullet.vel_y = (vel_x * sin + vel_y * cos) as i32;
+            }
+
+            bullet_manager.push_bullet(bullet);
+        }
+
+        state.create_caret(x, y, CaretType::Shoot, Direction::Left);
+
+        if level.sound != 0 {
+            state.sound_manager.play_sfx(level.sound);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bullet(id: u16, split: Option<u16>, spawns: &[u16]) -> CustomBullet {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "damage": 1,
+            "life": 1,
+            "lifetime": 10,
+            "enemy_hit_width": 4,
+            "enemy_hit_height": 4,
+            "block_hit_width": 2,
+            "block_hit_height": 2,
+            "display_bounds": [8, 8, 8, 8],
+            "speed": 0x400,
+            "rects": { "left": [] },
+            "split": split.map(|bullet| serde_json::json!({ "bullet": bullet, "count": 2 })),
+            "spawns": spawns.iter().map(|&bullet| serde_json::json!({ "bullet": bullet, "npc": 4 })).collect::<Vec<_>>(),
+        }))
+        .unwrap()
+    }
+
+    fn table(bullets: Vec<CustomBullet>) -> HashMap<u16, CustomBullet> {
+        bullets.into_iter().map(|bullet| (bullet.id, bullet)).collect()
+    }
+
+    fn edges(bullets: &HashMap<u16, CustomBullet>, id: u16) -> Vec<u16> {
+        spawn_edges(&bullets[&id]).into_iter().map(|(_, target)| target).collect()
+    }
+
+    #[test]
+    fn test_remove_spawn_cycles_keeps_trees() {
+        let mut bullets =
+            table(vec![bullet(100, Some(101), &[102, 102]), bullet(101, None, &[102]), bullet(102, None, &[1])]);
+        remove_spawn_cycles(&mut bullets);
+
+        assert_eq!(edges(&bullets, 100), vec![101, 102, 102]);
+        assert_eq!(edges(&bullets, 101), vec![102]);
+        assert_eq!(edges(&bullets, 102), vec![1]);
+    }
+
+    #[test]
+    fn test_remove_spawn_cycles_through_splits_and_spawns() {
+        let mut bullets =
+            table(vec![bullet(100, Some(101), &[]), bullet(101, None, &[102]), bullet(102, Some(100), &[])]);
+        remove_spawn_cycles(&mut bullets);
+
+        assert_eq!(edges(&bullets, 100), vec![101]);
+        assert_eq!(edges(&bullets, 101), vec![102]);
+        assert_eq!(edges(&bullets, 102), Vec::<u16>::new());
+    }
+
+    #[test]
+    fn test_remove_spawn_cycles_keeps_npc_spawns() {
+        let mut bullets = table(vec![bullet(100, None, &[100, 101]), bullet(101, None, &[])]);
+        remove_spawn_cycles(&mut bullets);
+
+        assert_eq!(edges(&bullets, 100), vec![101]);
+        assert_eq!(bullets[&100].spawns.len(), 2);
+        assert_eq!(bullets[&100].spawns[0].npc, Some(4));
+    }
+}
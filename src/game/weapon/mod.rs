@@ -1,6 +1,4 @@
-use num_derive::FromPrimitive;
-
-use crate::common::Direction;
+use crate::common::{Direction, Rect};
 use crate::engine_constants::EngineConstants;
 use crate::game::caret::CaretType;
 use crate::game::player::{Player, TargetPlayer};
@@ -10,6 +8,8 @@ use crate::game::weapon::bullet::BulletManager;
 mod blade;
 mod bubbler;
 pub mod bullet;
+pub mod bullet_behaviour;
+pub mod custom;
 mod fireball;
 mod machine_gun;
 mod missile_launcher;
@@ -19,20 +19,99 @@ mod snake;
 mod spur;
 mod super_missile_launcher;
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone, FromPrimitive)]
-#[repr(u8)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum WeaponType {
-    None = 0,
-    Snake = 1,
-    PolarStar = 2,
-    Fireball = 3,
-    MachineGun = 4,
-    MissileLauncher = 5,
-    Bubbler = 7,
-    Blade = 9,
-    SuperMissileLauncher = 10,
-    Nemesis = 12,
-    Spur = 13,
+    None,
+    Snake,
+    PolarStar,
+    Fireball,
+    MachineGun,
+    MissileLauncher,
+    Bubbler,
+    Blade,
+    SuperMissileLauncher,
+    Nemesis,
+    Spur,
+    /// Weapon defined by a mod in `weapons.json`, see [`custom::CustomWeapon`].
+    Custom(u8),
+}
+
+impl WeaponType {
+    /// Returns the weapon type for given id, or `None` if there's neither a vanilla nor a custom weapon with this id.
+    pub fn from_id(id: u8, constants: &EngineConstants) -> Option<WeaponType> {
+        match id {
+            0 => Some(WeaponType::None),
+            1 => Some(WeaponType::Snake),
+            2 => Some(WeaponType::PolarStar),
+            3 => Some(WeaponType::Fireball),
+            4 => Some(WeaponType::MachineGun),
+            5 => Some(WeaponType::MissileLauncher),
+            7 => Some(WeaponType::Bubbler),
+            9 => Some(WeaponType::Blade),
+            10 => Some(WeaponType::SuperMissileLauncher),
+            12 => Some(WeaponType::Nemesis),
+            13 => Some(WeaponType::Spur),
+            _ if constants.weapon.custom_weapons.contains_key(&id) => Some(WeaponType::Custom(id)),
+            _ => None,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            WeaponType::None => 0,
+            WeaponType::Snake => 1,
+            WeaponType::PolarStar => 2,
+            WeaponType::Fireball => 3,
+            WeaponType::MachineGun => 4,
+            WeaponType::MissileLauncher => 5,
+            WeaponType::Bubbler => 7,
+            WeaponType::Blade => 9,
+            WeaponType::SuperMissileLauncher => 10,
+            WeaponType::Nemesis => 12,
+            WeaponType::Spur => 13,
+            WeaponType::Custom(id) => id,
+        }
+    }
+
+    /// Returns true if given id belongs to one of the built-in weapons.
+    pub fn is_vanilla_id(id: u8) -> bool {
+        matches!(id, 0..=5 | 7 | 9 | 10 | 12 | 13)
+    }
+
+    /// Experience required for each level.
+    pub fn level_table(self, constants: &EngineConstants) -> [u16; 3] {
+        match self {
+            WeaponType::Custom(id) => constants
+                .weapon
+                .custom_weapons
+                .get(&id)
+                .map_or([0, 0, 0], |weapon| [weapon.levels[0].exp, weapon.levels[1].exp, weapon.levels[2].exp]),
+            _ => constants.weapon.level_table.get(self.id() as usize).copied().unwrap_or([0, 0, 0]),
+        }
+    }
+
+    /// Index of the sprite in `Arms` the player is holding.
+    pub fn arms_sprite(self, constants: &EngineConstants) -> u8 {
+        match self {
+            WeaponType::Custom(id) => {
+                constants.weapon.custom_weapons.get(&id).and_then(|weapon| weapon.arms_sprite).unwrap_or(id)
+            }
+            _ => self.id(),
+        }
+    }
+
+    /// Rect of the weapon icon in `ArmsImage`, `sheet_width` is the width of the texture in pixels.
+    /// Icons wrap to the next row once a row is full, vanilla has all of them in a single row.
+    pub fn icon_rect(id: u8, sheet_width: usize, constants: &EngineConstants) -> Rect<u16> {
+        if let Some(rect) = constants.weapon.custom_weapons.get(&id).and_then(|weapon| weapon.icon_rect) {
+            return rect;
+        }
+
+        let per_row = (sheet_width / 16).max(1) as u16;
+        let left = (id as u16 % per_row) * 16;
+        let top = (id as u16 / per_row) * 16;
+        Rect::new(left, top, left + 16, top + 16)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -124,7 +203,7 @@ impl Weapon {
         }
 
         let level_idx = self.level as usize - 1;
-        let max_exp = constants.weapon.level_table[self.wtype as usize][level_idx];
+        let max_exp = self.wtype.level_table(constants)[level_idx];
         let max = self.level == WeaponLevel::Level3 && self.experience == max_exp;
 
         (self.experience, max_exp, max)
@@ -132,7 +211,7 @@ impl Weapon {
 
     pub fn add_xp(&mut self, exp: u16, player: &mut Player, state: &mut SharedGameState) {
         let curr_level_idx = self.level as usize - 1;
-        let lvl_table = state.constants.weapon.level_table[self.wtype as usize];
+        let lvl_table = self.wtype.level_table(&state.constants);
 
         self.experience = self.experience.saturating_add(exp);
 
@@ -199,6 +278,7 @@ impl Weapon {
             }
             WeaponType::Nemesis => self.tick_nemesis(player, player_id, bullet_manager, state),
             WeaponType::Spur => self.tick_spur(player, player_id, bullet_manager, state),
+            WeaponType::Custom(_) => self.tick_custom(player, player_id, bullet_manager, state),
         }
     }
 }
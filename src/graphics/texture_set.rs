@@ -17,6 +17,55 @@ use crate::framework::graphics::{create_texture, FilterMode};
 pub static mut I_MAG: f32 = 1.0;
 pub static mut G_MAG: f32 = 1.0;
 
+/// Scales of sheets from texture packs relative to the original ones, which are recognized without size hints.
+const KNOWN_SCALES: [u16; 4] = [1, 2, 3, 4];
+
+/// Directories scanned for textures when validating texture packs.
+const TEXTURE_DIRS: [&str; 3] = ["", "Npc/", "Stage/"];
+
+/// How a sheet relates to the original sheet it replaces.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextureScale {
+    /// Both dimensions are a multiple of the original ones.
+    Exact(u16),
+    /// Only one dimension is a multiple of the original one, sprites outside of the sheet
+    /// are taken from the original sheet.
+    Partial(u16),
+    Unknown,
+}
+
+impl TextureScale {
+    /// Finds the scale of a sheet. If only one dimension matches and the dimensions match different scales,
+    /// the scale is ambiguous and the sheet needs a size hint in `texture_sizes.json`.
+    pub fn detect(size: (u16, u16), original: (u16, u16)) -> TextureScale {
+        let (width, height) = (size.0 as u32, size.1 as u32);
+        let (orig_width, orig_height) = (original.0 as u32, original.1 as u32);
+
+        if let Some(&scale) =
+            KNOWN_SCALES.iter().find(|&&s| width == orig_width * s as u32 && height == orig_height * s as u32)
+        {
+            return TextureScale::Exact(scale);
+        }
+
+        let mut partial =
+            KNOWN_SCALES.iter().filter(|&&s| width == orig_width * s as u32 || height == orig_height * s as u32);
+
+        match (partial.next(), partial.next()) {
+            (Some(&scale), None) => TextureScale::Partial(scale),
+            _ => TextureScale::Unknown,
+        }
+    }
+}
+
+/// Sheet whose dimensions don't match the original sheet at any known scale.
+#[derive(Debug, Clone)]
+pub struct TextureScaleMismatch {
+    pub name: String,
+    pub path: String,
+    pub size: (u32, u32),
+    pub original_size: (u16, u16),
+}
+
 pub trait SpriteBatch {
     fn width(&self) -> usize;
 
@@ -169,11 +218,48 @@ pub struct SubBatch {
     real_height: u16,
     scale_x: f32,
     scale_y: f32,
+    /// Commands held back until [`CombinedBatch`] draws them in order, `None` if they go straight to the texture.
+    queue: Option<Vec<SpriteBatchCommand>>,
+}
+
+impl SubBatch {
+    fn push(&mut self, command: SpriteBatchCommand) {
+        match &mut self.queue {
+            Some(queue) => queue.push(command),
+            None => self.batch.add(command),
+        }
+    }
+
+    fn queued(&self) -> usize {
+        self.queue.as_ref().map_or(0, |queue| queue.len())
+    }
 }
 
 pub struct CombinedBatch {
     main_batch: SubBatch,
     glow_batch: Option<SubBatch>,
+    /// Original sheet, used for sprites which lie outside of a cropped sheet from a texture pack.
+    fallback_batch: Option<SubBatch>,
+    /// Whether each sprite queued since the last draw went to the fallback sheet, used to draw
+    /// sprites of both sheets in the order they were added in.
+    order: Vec<bool>,
+}
+
+impl CombinedBatch {
+    fn add_to(&mut self, rect: &Rect<u16>, add: impl FnOnce(&mut SubBatch)) {
+        let outside_main = rect.right > self.main_batch.width || rect.bottom > self.main_batch.height;
+        let (batch, is_fallback) = match &mut self.fallback_batch {
+            Some(fallback) if outside_main => (fallback, true),
+            _ => (&mut self.main_batch, false),
+        };
+
+        let queued = batch.queued();
+        add(batch);
+
+        if batch.queued() > queued {
+            self.order.push(is_fallback);
+        }
+    }
 }
 
 impl SpriteBatch for SubBatch {
@@ -220,12 +306,15 @@ impl SpriteBatch for SubBatch {
     #[inline(always)]
     fn clear(&mut self) {
         self.batch.clear();
+        if let Some(queue) = &mut self.queue {
+            queue.clear();
+        }
     }
 
     fn add(&mut self, x: f32, y: f32) {
         let mag = unsafe { I_MAG };
 
-        self.batch.add(SpriteBatchCommand::DrawRect(
+        self.push(SpriteBatchCommand::DrawRect(
             Rect { left: 0 as f32, top: 0 as f32, right: self.real_width as f32, bottom: self.real_height as f32 },
             Rect {
                 left: x * mag,
@@ -248,7 +337,7 @@ impl SpriteBatch for SubBatch {
 
         let mag = unsafe { I_MAG };
 
-        self.batch.add(SpriteBatchCommand::DrawRectFlip(
+        self.push(SpriteBatchCommand::DrawRectFlip(
             Rect {
                 left: rect.left as f32 / self.scale_x,
                 top: rect.top as f32 / self.scale_y,
@@ -286,7 +375,7 @@ impl SpriteBatch for SubBatch {
 
         let mag = unsafe { I_MAG };
 
-        self.batch.add(SpriteBatchCommand::DrawRectFlipTinted(
+        self.push(SpriteBatchCommand::DrawRectFlipTinted(
             Rect {
                 left: rect.left as f32 / self.scale_x,
                 top: rect.top as f32 / self.scale_y,
@@ -312,7 +401,7 @@ impl SpriteBatch for SubBatch {
 
         let mag = unsafe { I_MAG };
 
-        self.batch.add(SpriteBatchCommand::DrawRect(
+        self.push(SpriteBatchCommand::DrawRect(
             Rect {
                 left: rect.left as f32 / self.scale_x,
                 top: rect.top as f32 / self.scale_y,
@@ -343,7 +432,7 @@ impl SpriteBatch for SubBatch {
 
         let mag = unsafe { I_MAG };
 
-        self.batch.add(SpriteBatchCommand::DrawRectTinted(
+        self.push(SpriteBatchCommand::DrawRectTinted(
             Rect {
                 left: rect.left as f32 / self.scale_x,
                 top: rect.top as f32 / self.scale_y,
@@ -367,6 +456,9 @@ impl SpriteBatch for SubBatch {
 
     fn draw_filtered(&mut self, _filter: FilterMode, _ctx: &mut Context) -> GameResult {
         //self.batch.set_filter(filter);
+        for command in self.queue.iter_mut().flat_map(|queue| queue.drain(..)) {
+            self.batch.add(command);
+        }
         self.batch.draw()?;
         self.batch.clear();
         Ok(())
@@ -379,15 +471,20 @@ impl SpriteBatch for SubBatch {
 
 impl SpriteBatch for CombinedBatch {
     fn width(&self) -> usize {
-        self.main_batch.width as _
+        self.dimensions().0
     }
 
     fn height(&self) -> usize {
-        self.main_batch.height as _
+        self.dimensions().1
     }
 
     fn dimensions(&self) -> (usize, usize) {
-        self.main_batch.dimensions()
+        let (width, height) = self.main_batch.dimensions();
+
+        match &self.fallback_batch {
+            Some(fallback) => (width.max(fallback.width as usize), height.max(fallback.height as usize)),
+            None => (width, height),
+        }
     }
 
     fn real_dimensions(&self) -> (usize, usize) {
@@ -411,27 +508,33 @@ impl SpriteBatch for CombinedBatch {
     }
 
     fn to_rect(&self) -> Rect<usize> {
-        self.main_batch.to_rect()
+        let (width, height) = self.dimensions();
+        Rect::new(0, 0, width, height)
     }
 
     fn clear(&mut self) {
-        self.main_batch.clear()
+        self.main_batch.clear();
+        if let Some(fallback) = &mut self.fallback_batch {
+            fallback.clear();
+        }
+        self.order.clear();
     }
 
     fn add(&mut self, x: f32, y: f32) {
-        self.main_batch.add(x, y)
+        let rect = Rect::new(0, 0, self.main_batch.width, self.main_batch.height);
+        self.add_to(&rect, |batch| batch.add(x, y))
     }
 
     fn add_rect(&mut self, x: f32, y: f32, rect: &Rect<u16>) {
-        self.main_batch.add_rect(x, y, rect)
+        self.add_to(rect, |batch| batch.add_rect(x, y, rect))
     }
 
     fn add_rect_flip(&mut self, x: f32, y: f32, flip_x: bool, flip_y: bool, rect: &Rect<u16>) {
-        self.main_batch.add_rect_flip(x, y, flip_x, flip_y, rect)
+        self.add_to(rect, |batch| batch.add_rect_flip(x, y, flip_x, flip_y, rect))
     }
 
     fn add_rect_tinted(&mut self, x: f32, y: f32, color: (u8, u8, u8, u8), rect: &Rect<u16>) {
-        self.main_batch.add_rect_tinted(x, y, color, rect)
+        self.add_to(rect, |batch| batch.add_rect_tinted(x, y, color, rect))
     }
 
     fn add_rect_flip_tinted(
@@ -443,11 +546,11 @@ impl SpriteBatch for CombinedBatch {
         color: (u8, u8, u8, u8),
         rect: &common::Rect<u16>,
     ) {
-        self.main_batch.add_rect_flip_tinted(x, y, flip_x, flip_y, color, rect)
+        self.add_to(rect, |batch| batch.add_rect_flip_tinted(x, y, flip_x, flip_y, color, rect))
     }
 
     fn add_rect_scaled(&mut self, x: f32, y: f32, scale_x: f32, scale_y: f32, rect: &Rect<u16>) {
-        self.main_batch.add_rect_scaled(x, y, scale_x, scale_y, rect)
+        self.add_to(rect, |batch| batch.add_rect_scaled(x, y, scale_x, scale_y, rect))
     }
 
     fn add_rect_scaled_tinted(
@@ -459,15 +562,57 @@ impl SpriteBatch for CombinedBatch {
         scale_y: f32,
         rect: &Rect<u16>,
     ) {
-        self.main_batch.add_rect_scaled_tinted(x, y, color, scale_x, scale_y, rect)
+        self.add_to(rect, |batch| batch.add_rect_scaled_tinted(x, y, color, scale_x, scale_y, rect))
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        self.main_batch.draw(ctx)
+        self.draw_filtered(FilterMode::Nearest, ctx)
     }
 
     fn draw_filtered(&mut self, filter: FilterMode, ctx: &mut Context) -> GameResult {
-        self.main_batch.draw_filtered(filter, ctx)
+        let Some(fallback) = &mut self.fallback_batch else {
+            return self.main_batch.draw_filtered(filter, ctx);
+        };
+
+        // each sheet is a separate texture, so they're drawn in turns, one run of sprites at a time
+        let main = &mut self.main_batch;
+        let mut main_queue = std::mem::take(main.queue.get_or_insert_with(Vec::new)).into_iter();
+        let mut fallback_queue = std::mem::take(fallback.queue.get_or_insert_with(Vec::new)).into_iter();
+        let mut current = None;
+
+        for &is_fallback in &self.order {
+            if current != Some(is_fallback) {
+                match current {
+                    Some(true) => fallback.batch.draw()?,
+                    Some(false) => main.batch.draw()?,
+                    None => {}
+                }
+                main.batch.clear();
+                fallback.batch.clear();
+                current = Some(is_fallback);
+            }
+
+            let (batch, queue) = if is_fallback {
+                (&mut fallback.batch, &mut fallback_queue)
+            } else {
+                (&mut main.batch, &mut main_queue)
+            };
+
+            if let Some(command) = queue.next() {
+                batch.add(command);
+            }
+        }
+
+        match current {
+            Some(true) => fallback.batch.draw()?,
+            Some(false) => main.batch.draw()?,
+            None => {}
+        }
+        main.batch.clear();
+        fallback.batch.clear();
+        self.order.clear();
+
+        Ok(())
     }
 
     fn get_texture(&self) -> Option<&Box<dyn BackendTexture>> {
@@ -477,16 +622,19 @@ impl SpriteBatch for CombinedBatch {
 
 pub struct TextureSet {
     pub tex_map: HashMap<String, Box<dyn SpriteBatch>>,
+    /// Loaded sheets which don't match the original ones at any known scale.
+    pub scale_mismatches: Vec<TextureScaleMismatch>,
     dummy_batch: Box<dyn SpriteBatch>,
 }
 
 impl TextureSet {
     pub fn new() -> TextureSet {
-        TextureSet { tex_map: HashMap::new(), dummy_batch: Box::new(DummyBatch) }
+        TextureSet { tex_map: HashMap::new(), scale_mismatches: Vec::new(), dummy_batch: Box::new(DummyBatch) }
     }
 
     pub fn unload_all(&mut self) {
         self.tex_map.clear();
+        self.scale_mismatches.clear();
     }
 
     fn make_transparent(rgba: &mut RgbaImage) {
@@ -520,37 +668,154 @@ impl TextureSet {
         FILE_TYPES.iter().map(|ext| [name, ext].join("")).find(|path| filesystem::exists_find(ctx, roots, path))
     }
 
-    pub fn load_texture(
+    /// Same as `find_texture`, but skips roots before `first_root` and returns the index of the root it was found in.
+    fn find_texture_root(
         &self,
         ctx: &mut Context,
+        roots: &Vec<String>,
+        name: &str,
+        first_root: usize,
+    ) -> Option<(usize, String)> {
+        roots.iter().enumerate().skip(first_root).find_map(|(idx, root)| {
+            FILE_TYPES
+                .iter()
+                .map(|ext| [name, ext].join(""))
+                .find(|path| filesystem::exists(ctx, [root.as_str(), path].join("")))
+                .map(|path| (idx, path))
+        })
+    }
+
+    fn image_dimensions(ctx: &mut Context, root: &str, path: &str) -> Option<(u32, u32)> {
+        let file = filesystem::open(ctx, [root, path].join("")).ok()?;
+        let reader = image::io::Reader::new(BufReader::new(file)).with_guessed_format().ok()?;
+
+        reader.into_dimensions().ok()
+    }
+
+    /// Sheets larger than 65535 pixels can't be used, so they don't match any original sheet.
+    fn sheet_size((width, height): (u32, u32)) -> Option<(u16, u16)> {
+        Some((u16::try_from(width).ok()?, u16::try_from(height).ok()?))
+    }
+
+    /// Finds the sheet replaced by a texture from a texture pack, that is the same texture in a lower priority
+    /// data directory. Returns its root index, path and dimensions.
+    fn find_original(
+        &self,
+        ctx: &mut Context,
+        roots: &Vec<String>,
+        name: &str,
+        root_idx: usize,
+    ) -> Option<(usize, String, (u16, u16))> {
+        let (idx, path) = self.find_texture_root(ctx, roots, name, root_idx + 1)?;
+        let size = Self::sheet_size(Self::image_dimensions(ctx, &roots[idx], &path)?)?;
+
+        Some((idx, path, size))
+    }
+
+    /// Checks all sheets within the data directories against the original ones and returns those
+    /// which don't match at any known scale.
+    pub fn validate_scales(&self, ctx: &mut Context, constants: &EngineConstants) -> Vec<TextureScaleMismatch> {
+        let roots = &constants.base_paths;
+        let mut names = Vec::new();
+
+        for dir in TEXTURE_DIRS {
+            for root in roots.iter() {
+                let files = match filesystem::read_dir(ctx, [root.as_str(), dir].join("")) {
+                    Ok(files) => files,
+                    Err(_) => continue,
+                };
+
+                for file in files {
+                    let is_texture = file
+                        .extension()
+                        .map_or(false, |ext| FILE_TYPES.contains(&[".", &ext.to_string_lossy()].join("").as_str()));
+
+                    if let (true, Some(stem)) = (is_texture, file.file_stem()) {
+                        let stem = stem.to_string_lossy();
+                        if !stem.ends_with(".glow") {
+                            names.push([dir, &stem].join(""));
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut mismatches = Vec::new();
+        for name in names.into_iter().unique() {
+            let (root_idx, path) = match self.find_texture_root(ctx, roots, &name, 0) {
+                Some(found) => found,
+                None => continue,
+            };
+            let size = match Self::image_dimensions(ctx, &roots[root_idx], &path) {
+                Some(size) => size,
+                None => continue,
+            };
+            let original_size = match constants.tex_sizes.get(&name) {
+                Some(&hint) => hint,
+                None => match self.find_original(ctx, roots, &name, root_idx) {
+                    Some((_, _, original_size)) => original_size,
+                    None => continue,
+                },
+            };
+
+            let scale =
+                Self::sheet_size(size).map_or(TextureScale::Unknown, |s| TextureScale::detect(s, original_size));
+            if scale == TextureScale::Unknown {
+                mismatches.push(TextureScaleMismatch { name, path, size, original_size });
+            }
+        }
+
+        mismatches
+    }
+
+    pub fn load_texture(
+        &mut self,
+        ctx: &mut Context,
         constants: &EngineConstants,
         name: &str,
     ) -> GameResult<Box<dyn SpriteBatch>> {
-        let path = self
-            .find_texture(ctx, &constants.base_paths, name)
+        let roots = &constants.base_paths;
+        let (root_idx, path) = self
+            .find_texture_root(ctx, roots, name, 0)
             .ok_or_else(|| GameError::ResourceLoadError(format!("Texture \"{}\" is missing.", name)))?;
 
-        let glow_path = self.find_texture(ctx, &constants.base_paths, &[name, ".glow"].join(""));
+        let glow_path = self.find_texture(ctx, roots, &[name, ".glow"].join(""));
+
+        // texture packs don't need size hints, the scale is inferred from the sheet they replace
+        let original =
+            if constants.tex_sizes.contains_key(name) { None } else { self.find_original(ctx, roots, name, root_idx) };
 
         info!("Loading texture: {} -> {}", name, path);
 
-        fn make_batch(name: &str, constants: &EngineConstants, batch: Box<dyn BackendTexture>) -> SubBatch {
+        fn make_batch(
+            name: &str,
+            constants: &EngineConstants,
+            batch: Box<dyn BackendTexture>,
+            original_size: Option<(u16, u16)>,
+        ) -> SubBatch {
             let size = batch.dimensions();
 
-            let orig_dimensions = constants.tex_sizes.get(name).unwrap_or(&size);
-
-            let scale =
-                if f32::abs((orig_dimensions.0 as f32 / size.0 as f32) - (orig_dimensions.1 as f32 / size.1 as f32))
-                    <= f32::EPSILON
-                {
-                    orig_dimensions.0 as f32 / size.0 as f32
-                } else if constants.is_cs_plus && constants.base_paths.iter().any(|p| p.contains("/ogph")) {
-                    1.0
-                } else if constants.is_cs_plus {
-                    0.5
-                } else {
-                    1.0
-                };
+            let scale = match (constants.tex_sizes.get(name), original_size) {
+                (None, Some(original_size)) => match TextureScale::detect(size, original_size) {
+                    TextureScale::Exact(scale) | TextureScale::Partial(scale) => 1.0 / scale as f32,
+                    TextureScale::Unknown => 1.0,
+                },
+                (hint, _) => {
+                    let orig_dimensions = hint.unwrap_or(&size);
+
+                    if f32::abs((orig_dimensions.0 as f32 / size.0 as f32) - (orig_dimensions.1 as f32 / size.1 as f32))
+                        <= f32::EPSILON
+                    {
+                        orig_dimensions.0 as f32 / size.0 as f32
+                    } else if constants.is_cs_plus && constants.base_paths.iter().any(|p| p.contains("/ogph")) {
+                        1.0
+                    } else if constants.is_cs_plus {
+                        0.5
+                    } else {
+                        1.0
+                    }
+                }
+            };
 
             let width = (size.0 as f32 * scale) as _;
             let height = (size.1 as f32 * scale) as _;
@@ -563,17 +828,60 @@ impl TextureSet {
                 scale_y: scale,
                 real_width: size.0 as _,
                 real_height: size.1 as _,
+                queue: None,
             }
         }
 
-        let main_batch = make_batch(name, constants, self.load_image(ctx, &constants.base_paths, &path)?);
+        let original_size = original.as_ref().map(|(_, _, size)| *size);
+        let mut main_batch = make_batch(name, constants, self.load_image(ctx, roots, &path)?, original_size);
         let glow_batch = if let Some(glow_path) = glow_path {
-            self.load_image(ctx, &constants.base_paths, &glow_path).ok().map(|b| make_batch(name, constants, b))
+            self.load_image(ctx, roots, &glow_path).ok().map(|b| make_batch(name, constants, b, original_size))
         } else {
             None
         };
 
-        Ok(Box::new(CombinedBatch { main_batch, glow_batch }))
+        let mut fallback_batch = None;
+        if let Some((original_root, original_path, original_size)) = original {
+            let size = (main_batch.real_width, main_batch.real_height);
+
+            match TextureScale::detect(size, original_size) {
+                TextureScale::Exact(_) => (),
+                TextureScale::Partial(_) => {
+                    if main_batch.width < original_size.0 || main_batch.height < original_size.1 {
+                        let roots = vec![roots[original_root].clone()];
+                        fallback_batch = self
+                            .load_image(ctx, &roots, &original_path)
+                            .ok()
+                            .map(|b| make_batch(name, constants, b, Some(original_size)));
+                    }
+                }
+                TextureScale::Unknown => {
+                    log::warn!(
+                        "Texture {} is {}x{}, which doesn't match the original {}x{} sheet at a single known scale.",
+                        path,
+                        size.0,
+                        size.1,
+                        original_size.0,
+                        original_size.1
+                    );
+
+                    self.scale_mismatches.retain(|m| m.name != name);
+                    self.scale_mismatches.push(TextureScaleMismatch {
+                        name: name.to_owned(),
+                        path: path.clone(),
+                        size: (size.0 as u32, size.1 as u32),
+                        original_size,
+                    });
+                }
+            }
+        }
+
+        if let Some(fallback) = &mut fallback_batch {
+            main_batch.queue = Some(Vec::new());
+            fallback.queue = Some(Vec::new());
+        }
+
+        Ok(Box::new(CombinedBatch { main_batch, glow_batch, fallback_batch, order: Vec::new() }))
     }
 
     pub fn get_or_load_batch(
@@ -594,3 +902,29 @@ impl TextureSet {
         Ok(self.tex_map.get_mut(name).unwrap())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_texture_scale_detect() {
+        assert_eq!(TextureScale::detect((320, 240), (320, 240)), TextureScale::Exact(1));
+        assert_eq!(TextureScale::detect((640, 480), (320, 240)), TextureScale::Exact(2));
+        assert_eq!(TextureScale::detect((1280, 960), (320, 240)), TextureScale::Exact(4));
+
+        // cropped or extended sheets keep the scale of the matching dimension
+        assert_eq!(TextureScale::detect((640, 300), (320, 240)), TextureScale::Partial(2));
+        assert_eq!(TextureScale::detect((500, 720), (320, 240)), TextureScale::Partial(3));
+        // dimensions matching different scales are ambiguous
+        assert_eq!(TextureScale::detect((640, 240), (320, 240)), TextureScale::Unknown);
+        assert_eq!(TextureScale::detect((320, 720), (320, 240)), TextureScale::Unknown);
+
+        assert_eq!(TextureScale::detect((500, 500), (320, 240)), TextureScale::Unknown);
+        assert_eq!(TextureScale::detect((1600, 1200), (320, 240)), TextureScale::Unknown);
+        assert_eq!(TextureScale::detect((16, 16), (0, 0)), TextureScale::Unknown);
+
+        assert_eq!(TextureSet::sheet_size((65535, 16)), Some((65535, 16)));
+        assert_eq!(TextureSet::sheet_size((65536, 16)), None);
+    }
+}
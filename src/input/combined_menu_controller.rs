@@ -109,4 +109,44 @@ impl CombinedMenuController {
 
         false
     }
+
+    pub fn trigger_map(&self) -> bool {
+        for cont in &self.controllers {
+            if cont.trigger_map() {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn trigger_inventory(&self) -> bool {
+        for cont in &self.controllers {
+            if cont.trigger_inventory() {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn trigger_skip(&self) -> bool {
+        for cont in &self.controllers {
+            if cont.trigger_skip() {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn strafe(&self) -> bool {
+        for cont in &self.controllers {
+            if cont.strafe() {
+                return true;
+            }
+        }
+
+        false
+    }
 }
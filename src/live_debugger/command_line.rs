@@ -1,5 +1,3 @@
-use num_traits::FromPrimitive;
-
 use crate::framework::error::{GameError::CommandLineError, GameResult};
 use crate::game::npc::NPC;
 use crate::game::scripting::tsc::text_script::{ScriptMode, TextScript, TextScriptEncoding};
@@ -182,14 +180,14 @@ impl CommandLineCommand {
                 game_scene.inventory_player1.remove_item(item_id);
             }
             CommandLineCommand::AddWeapon(weapon_id, ammo_count) => {
-                let weapon_type: Option<WeaponType> = FromPrimitive::from_u16(weapon_id);
+                let weapon_type = u8::try_from(weapon_id).ok().and_then(|id| WeaponType::from_id(id, &state.constants));
                 match weapon_type {
                     Some(weapon_type) => game_scene.inventory_player1.add_weapon(weapon_type, ammo_count),
                     None => return Err(CommandLineError(format!("Invalid weapon id {}", weapon_id))),
                 }
             }
             CommandLineCommand::RemoveWeapon(weapon_id) => {
-                let weapon_type: Option<WeaponType> = FromPrimitive::from_u16(weapon_id);
+                let weapon_type = u8::try_from(weapon_id).ok().and_then(|id| WeaponType::from_id(id, &state.constants));
                 match weapon_type {
                     Some(weapon_type) => {
                         if !game_scene.inventory_player1.has_weapon(weapon_type) {
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::framework::context::Context;
+use crate::framework::error::GameResult;
+use crate::framework::filesystem;
+use crate::game::npc::{NPCTable, NPC};
+use crate::game::scripting::tsc::text_script::{TextScript, TextScriptExecutionState};
+use crate::game::shared_game_state::SharedGameState;
+use crate::game::stage::Stage;
+use crate::live_debugger::ScriptType;
+use crate::scene::game_scene::GameScene;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Asset {
+    Script(ScriptType),
+    Map,
+    Entities,
+    Background,
+    NPCTable,
+    Texture(String),
+}
+
+/// Watches files the current stage was loaded from and reloads them in place once they're modified,
+/// without touching the player's position or game flags.
+pub struct HotReload {
+    pub enabled: bool,
+    last_poll: Instant,
+    stage_id: usize,
+    timestamps: HashMap<String, Option<SystemTime>>,
+    /// Slots of the NPCs spawned from the stage's .pxe file.
+    stage_npcs: Vec<u16>,
+}
+
+impl HotReload {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            last_poll: Instant::now(),
+            stage_id: usize::MAX,
+            timestamps: HashMap::new(),
+            stage_npcs: Vec::new(),
+        }
+    }
+
+    /// Checks watched files for modifications, returns true if anything has been reloaded.
+    pub fn poll(
+        &mut self,
+        game_scene: &mut GameScene,
+        state: &mut SharedGameState,
+        ctx: &mut Context,
+    ) -> GameResult<bool> {
+        if !self.enabled {
+            self.timestamps.clear();
+            return Ok(false);
+        }
+
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Ok(false);
+        }
+        self.last_poll = Instant::now();
+
+        if self.stage_id != game_scene.stage_id {
+            self.stage_id = game_scene.stage_id;
+            self.timestamps.clear();
+            self.stage_npcs = match game_scene.stage.load_npcs(&state.constants.base_paths, ctx) {
+                Ok(npcs) => npcs.iter().map(|npc| npc.id).collect(),
+                Err(_) => Vec::new(),
+            };
+        }
+
+        let files: Vec<_> = self
+            .watched_files(game_scene, state, ctx)
+            .into_iter()
+            .map(|(path, asset)| {
+                let modified = filesystem::modified_find(ctx, &state.constants.base_paths, &path);
+                (path, asset, modified)
+            })
+            .collect();
+
+        let scripts_running = state.textscript_vm.state != TextScriptExecutionState::Ended;
+        let changed = detect_changes(&mut self.timestamps, files, scripts_running);
+
+        for (path, asset) in changed.iter() {
+            log::info!("Hot reloading {}", path);
+
+            if let Err(err) = self.reload(asset, path, game_scene, state, ctx) {
+                log::warn!("Failed to hot reload {}: {}", path, err);
+            }
+        }
+
+        Ok(!changed.is_empty())
+    }
+
+    fn watched_files(
+        &self,
+        game_scene: &GameScene,
+        state: &SharedGameState,
+        ctx: &mut Context,
+    ) -> Vec<(String, Asset)> {
+        let data = &game_scene.stage.data;
+        let mut files = vec![
+            (["Stage/", &data.map, ".tsc"].join(""), Asset::Script(ScriptType::Scene)),
+            ("Head.tsc".to_owned(), Asset::Script(ScriptType::Global)),
+            ("ArmsItem.tsc".to_owned(), Asset::Script(ScriptType::Inventory)),
+            ("StageSelect.tsc".to_owned(), Asset::Script(ScriptType::StageSelect)),
+            (["Stage/", &data.map, ".pxm"].join(""), Asset::Map),
+            (["Stage/", &data.map, ".pxpack"].join(""), Asset::Map),
+            (["Stage/", &data.tileset.name, ".pxa"].join(""), Asset::Map),
+            (["Stage/", &data.map, ".pxe"].join(""), Asset::Entities),
+            (["Stage/", &data.map, ".bg.json"].join(""), Asset::Background),
+            ("npc.tbl".to_owned(), Asset::NPCTable),
+        ];
+
+        for layer in game_scene.stage.map.layers.iter().filter(|l| !l.tileset.is_empty()) {
+            files.push((["Stage/", &layer.tileset, ".pxa"].join(""), Asset::Map));
+        }
+
+        for tex_name in state.texture_set.tex_map.keys() {
+            for file_name in [tex_name.to_owned(), [tex_name.as_str(), ".glow"].join("")] {
+                if let Some(path) = state.texture_set.find_texture(ctx, &state.constants.base_paths, &file_name) {
+                    files.push((path, Asset::Texture(tex_name.to_owned())));
+                }
+            }
+        }
+
+        files
+    }
+
+    fn reload(
+        &mut self,
+        asset: &Asset,
+        path: &str,
+        game_scene: &mut GameScene,
+        state: &mut SharedGameState,
+        ctx: &mut Context,
+    ) -> GameResult {
+        match asset {
+            Asset::Script(script_type) => {
+                let script = TextScript::load_from_file(ctx, &state.constants.base_paths, path, &state.constants)?;
+
+                match script_type {
+                    ScriptType::Scene => state.textscript_vm.set_scene_script(script),
+                    ScriptType::Global => state.textscript_vm.set_global_script(script),
+                    ScriptType::Inventory => state.textscript_vm.set_inventory_script(script),
+                    ScriptType::StageSelect => state.textscript_vm.set_stage_select_script(script),
+                }
+            }
+            Asset::Map => {
+                let stage = Stage::load(&state.constants.base_paths, &state.stages[game_scene.stage_id], ctx)?;
+                game_scene.stage.map = stage.map;
+                state.tile_size = game_scene.stage.map.tile_size;
+            }
+            Asset::Entities => {
+                let npcs = game_scene.stage.load_npcs(&state.constants.base_paths, ctx)?;
+
+                // NPCs spawned by scripts or bosses stay, only the ones from the old .pxe are replaced
+                for &id in self.stage_npcs.iter() {
+                    if let Some(npc) = game_scene.npc_list.get_npc(id as usize) {
+                        *npc = NPC::empty();
+                        npc.id = id;
+                    }
+                }
+
+                game_scene.spawn_stage_npcs(state, ctx)?;
+                self.stage_npcs = npcs.iter().map(|npc| npc.id).collect();
+            }
+            Asset::Background => {
+                game_scene.background.load_layers(ctx, &state.constants.base_paths, &game_scene.stage.data.map)?;
+            }
+            Asset::NPCTable => {
+                let npc_tbl = filesystem::open_find(ctx, &state.constants.base_paths, path)?;
+                state.npc_table = NPCTable::load_from(npc_tbl)?;
+                state.npc_table.stage_textures = game_scene.stage_textures.clone();
+            }
+            Asset::Texture(name) => {
+                // gets loaded again the next time it's drawn
+                state.texture_set.tex_map.remove(name);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Updates the known modification times of given files, returns the ones that have changed since
+/// the last check, at most one per asset. Files seen for the first time aren't considered changed.
+/// Changes to scripts are held back while `scripts_running` is set, since swapping the bytecode
+/// of a running script would break it.
+fn detect_changes(
+    timestamps: &mut HashMap<String, Option<SystemTime>>,
+    files: Vec<(String, Asset, Option<SystemTime>)>,
+    scripts_running: bool,
+) -> Vec<(String, Asset)> {
+    let mut changed = Vec::new();
+
+    for (path, asset, modified) in files {
+        let old = match timestamps.insert(path.clone(), modified) {
+            Some(old) if old != modified => old,
+            _ => continue,
+        };
+
+        // try again once the script is done
+        if matches!(asset, Asset::Script(_)) && scripts_running {
+            timestamps.insert(path, old);
+            continue;
+        }
+
+        if !changed.iter().any(|(_, a)| *a == asset) {
+            changed.push((path, asset));
+        }
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(secs: u64) -> Option<SystemTime> {
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    fn files(
+        pxm: Option<SystemTime>,
+        pxa: Option<SystemTime>,
+        tsc: Option<SystemTime>,
+    ) -> Vec<(String, Asset, Option<SystemTime>)> {
+        vec![
+            ("Stage/Cave.pxm".to_owned(), Asset::Map, pxm),
+            ("Stage/Cave.pxa".to_owned(), Asset::Map, pxa),
+            ("Stage/Cave.tsc".to_owned(), Asset::Script(ScriptType::Scene), tsc),
+        ]
+    }
+
+    #[test]
+    fn test_detect_changes() {
+        let mut timestamps = HashMap::new();
+
+        // nothing is reloaded the first time files are seen
+        assert!(detect_changes(&mut timestamps, files(time(1), time(1), None), false).is_empty());
+        assert!(detect_changes(&mut timestamps, files(time(1), time(1), None), false).is_empty());
+
+        // an asset is reloaded once even if several of its files have changed
+        let changed = detect_changes(&mut timestamps, files(time(2), time(2), None), false);
+        assert_eq!(changed, vec![("Stage/Cave.pxm".to_owned(), Asset::Map)]);
+        assert!(detect_changes(&mut timestamps, files(time(2), time(2), None), false).is_empty());
+
+        // files appearing or disappearing count as changes too
+        let changed = detect_changes(&mut timestamps, files(time(2), None, time(3)), false);
+        assert_eq!(changed.len(), 2);
+        assert!(changed.contains(&("Stage/Cave.pxa".to_owned(), Asset::Map)));
+        assert!(changed.contains(&("Stage/Cave.tsc".to_owned(), Asset::Script(ScriptType::Scene))));
+    }
+
+    #[test]
+    fn test_detect_changes_running_script() {
+        let mut timestamps = HashMap::new();
+        detect_changes(&mut timestamps, files(time(1), time(1), time(1)), false);
+
+        // script changes wait until the script has ended, others don't
+        let changed = detect_changes(&mut timestamps, files(time(2), time(1), time(2)), true);
+        assert_eq!(changed, vec![("Stage/Cave.pxm".to_owned(), Asset::Map)]);
+        assert!(detect_changes(&mut timestamps, files(time(2), time(1), time(2)), true).is_empty());
+
+        let changed = detect_changes(&mut timestamps, files(time(2), time(1), time(2)), false);
+        assert_eq!(changed, vec![("Stage/Cave.tsc".to_owned(), Asset::Script(ScriptType::Scene))]);
+    }
+}
@@ -8,8 +8,10 @@ use crate::game::shared_game_state::SharedGameState;
 use crate::scene::game_scene::GameScene;
 
 use self::command_line::CommandLineParser;
+use self::hot_reload::HotReload;
 
 pub mod command_line;
+pub mod hot_reload;
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 #[repr(u8)]
@@ -28,6 +30,7 @@ pub struct LiveDebugger {
     hotkey_list_visible: bool,
     command_line_parser: CommandLineParser,
     command_line_focused: bool,
+    hot_reload: HotReload,
     last_stage_id: usize,
     stages: Vec<ImString>,
     selected_stage: i32,
@@ -48,6 +51,7 @@ impl LiveDebugger {
             hotkey_list_visible: false,
             command_line_parser: CommandLineParser::new(),
             command_line_focused: false,
+            hot_reload: HotReload::new(),
             last_stage_id: usize::MAX,
             stages: Vec::new(),
             selected_stage: -1,
@@ -136,6 +140,15 @@ impl LiveDebugger {
             self.command_line_focused = false;
         }
 
+        match self.hot_reload.poll(game_scene, state, ctx) {
+            Ok(true) => self.events.clear(),
+            Ok(false) => (),
+            Err(e) => {
+                log::error!("Error hot reloading assets: {:?}", e);
+                self.error = Some(ImString::new(e.to_string()));
+            }
+        }
+
         if !state.debugger {
             return Ok(());
         }
@@ -229,9 +242,40 @@ impl LiveDebugger {
                     state.command_line = !state.command_line;
                 }
 
+                ui.same_line();
+                if ui.button("Texture Report") {
+                    let id = u32::MAX;
+                    self.text_windows.retain(|(e, _, _)| *e != id);
+
+                    let mut mismatches = state.texture_set.validate_scales(ctx, &state.constants);
+                    for mismatch in state.texture_set.scale_mismatches.iter() {
+                        if !mismatches.iter().any(|m| m.name == mismatch.name) {
+                            mismatches.push(mismatch.clone());
+                        }
+                    }
+
+                    let report = if mismatches.is_empty() {
+                        "All textures match the original sheets.".to_owned()
+                    } else {
+                        mismatches
+                            .iter()
+                            .map(|m| {
+                                format!(
+                                    "{}: {}x{}, original {}x{}",
+                                    m.path, m.size.0, m.size.1, m.original_size.0, m.original_size.1
+                                )
+                            })
+                            .join("\n")
+                    };
+
+                    self.text_windows.push((id, ImString::new("Texture scale report"), ImString::new(report)));
+                }
+
                 ui.checkbox("noclip", &mut state.settings.noclip);
                 ui.same_line();
                 ui.checkbox("more rust", &mut state.more_rust);
+                ui.same_line();
+                ui.checkbox("hot reload", &mut self.hot_reload.enabled);
             });
 
         if self.map_selector_visible {
@@ -3,10 +3,34 @@
 use std::process::exit;
 
 fn main() {
-    let args = std::env::args();
-    let mut options = doukutsu_rs::game::LaunchOptions { server_mode: false, editor: false };
+    let mut args = std::env::args();
 
-    for arg in args {
+    if std::env::args().nth(1).as_deref() == Some("save") {
+        let args: Vec<String> = args.skip(2).collect();
+        if let Err(e) = doukutsu_rs::game::save_tool::run(&args) {
+            eprintln!("{}", e);
+            exit(1);
+        }
+
+        return;
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("stage") {
+        let args: Vec<String> = args.skip(2).collect();
+        if let Err(e) = doukutsu_rs::game::stage_tool::run(&args) {
+            eprintln!("{}", e);
+            exit(1);
+        }
+
+        return;
+    }
+
+    let mut options =
+        doukutsu_rs::game::LaunchOptions { server_mode: false, editor: false, capture_audio: None, dump_frames: None };
+    let mut dump_path = None;
+    let mut dump_fps = None;
+
+    while let Some(arg) = args.next() {
         if arg == "--server-mode" {
             options.server_mode = true;
         }
@@ -14,8 +38,23 @@ fn main() {
         if arg == "--editor" {
             options.editor = true;
         }
+
+        if arg == "--capture-audio" {
+            options.capture_audio = args.next();
+        }
+
+        if arg == "--dump-frames" {
+            dump_path = args.next();
+        }
+
+        if arg == "--dump-fps" {
+            dump_fps = args.next().and_then(|fps| fps.parse().ok()).or(dump_fps);
+        }
     }
 
+    options.dump_frames =
+        dump_path.map(|path| doukutsu_rs::game::frame_dump::FrameDumpConfig { path: path.into(), fps: dump_fps });
+
     if options.server_mode && options.editor {
         eprintln!("Cannot run in server mode and editor mode at the same time.");
         exit(1);
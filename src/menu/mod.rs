@@ -6,6 +6,7 @@ use crate::framework::context::Context;
 use crate::framework::error::GameResult;
 use crate::framework::graphics;
 use crate::game::shared_game_state::{GameDifficulty, MenuCharacter, SharedGameState};
+use crate::game::weapon::WeaponType;
 use crate::graphics::font::Font;
 use crate::input::combined_menu_controller::CombinedMenuController;
 use crate::menu::save_select_menu::MenuSaveInfo;
@@ -642,6 +643,8 @@ impl<T: std::cmp::PartialEq + std::default::Default + Clone> Menu<T> {
                     let valid_save = state.stages.get(save.current_map as usize).is_some();
                     let name = if valid_save {
                         state.stages.get(save.current_map as usize).unwrap().name.as_str()
+                    } else if let Some(stage_name) = &save.stage_name {
+                        stage_name.as_str()
                     } else {
                         state.loc.t("menus.save_menu.invalid_save")
                     };
@@ -698,10 +701,8 @@ impl<T: std::cmp::PartialEq + std::default::Default + Clone> Menu<T> {
                         for weapon_slot in 0..save.weapon_count {
                             let wtype = save.weapon_id[weapon_slot];
                             let pos_x = weapon_slot as f32 * 16.0 - (16 * save.weapon_count.saturating_sub(4)) as f32;
-                            let mut rect = Rect::new(0, 0, 0, 16);
-                            if wtype != 0 {
-                                rect.left = wtype as u16 * 16;
-                                rect.right = rect.left + 16;
+                            if let Some(wtype) = u8::try_from(wtype).ok().filter(|&id| id != 0) {
+                                let rect = WeaponType::icon_rect(wtype, batch.width(), &state.constants);
                                 batch.add_rect(right_edge + pos_x - 60.0, y + 8.0, &rect);
                             }
                         }
@@ -1,14 +1,23 @@
+use std::cell::RefCell;
+use std::io::{Read, Write};
+
+use crate::common::{Color, Rect};
+use crate::framework::backend::{BackendTexture, SpriteBatchCommand};
 use crate::framework::context::Context;
 use crate::framework::error::GameResult;
 use crate::framework::filesystem;
-use crate::game::profile::GameProfile;
+use crate::framework::graphics;
+use crate::game::profile::{GameProfile, SaveThumbnail};
 use crate::game::shared_game_state::{GameDifficulty, SharedGameState};
 use crate::input::combined_menu_controller::CombinedMenuController;
 use crate::menu::coop_menu::PlayerCountMenu;
 use crate::menu::MenuEntry;
 use crate::menu::{Menu, MenuSelectionResult};
 
-#[derive(Clone, Copy)]
+/// Width of the save thumbnail in the detailed save view, in canvas pixels.
+const THUMBNAIL_DISPLAY_WIDTH: f32 = 64.0;
+
+#[derive(Clone)]
 pub struct MenuSaveInfo {
     pub current_map: u32,
     pub max_life: u16,
@@ -16,11 +25,23 @@ pub struct MenuSaveInfo {
     pub weapon_count: usize,
     pub weapon_id: [u32; 8],
     pub difficulty: u8,
+    /// Play time in seconds, 0 if the save doesn't track it.
+    pub play_time: u32,
+    pub stage_name: Option<String>,
 }
 
 impl Default for MenuSaveInfo {
     fn default() -> Self {
-        MenuSaveInfo { current_map: 0, max_life: 0, life: 0, weapon_count: 0, weapon_id: [0; 8], difficulty: 0 }
+        MenuSaveInfo {
+            current_map: 0,
+            max_life: 0,
+            life: 0,
+            weapon_count: 0,
+            weapon_id: [0; 8],
+            difficulty: 0,
+            play_time: 0,
+            stage_name: None,
+        }
     }
 }
 
@@ -33,6 +54,8 @@ pub enum CurrentMenu {
     PlayerCountMenu,
     DeleteConfirm,
     LoadConfirm,
+    CopyMenu,
+    CopyConfirm,
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -77,6 +100,7 @@ impl Default for DeleteConfirmMenuEntry {
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum LoadConfirmMenuEntry {
     Start,
+    Copy,
     Delete,
     Back,
 }
@@ -87,8 +111,23 @@ impl Default for LoadConfirmMenuEntry {
     }
 }
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CopyMenuEntry {
+    Title,
+    Slot(usize),
+    Back,
+}
+
+impl Default for CopyMenuEntry {
+    fn default() -> Self {
+        CopyMenuEntry::Back
+    }
+}
+
 pub struct SaveSelectMenu {
     pub saves: [MenuSaveInfo; 3],
+    thumbnails: [Option<SaveThumbnail>; 3],
+    thumbnail_textures: RefCell<[Option<Box<dyn BackendTexture>>; 3]>,
     current_menu: CurrentMenu,
     save_menu: Menu<SaveMenuEntry>,
     save_detailed: Menu<usize>,
@@ -96,13 +135,19 @@ pub struct SaveSelectMenu {
     coop_menu: PlayerCountMenu,
     delete_confirm: Menu<DeleteConfirmMenuEntry>,
     load_confirm: Menu<LoadConfirmMenuEntry>,
+    copy_menu: Menu<CopyMenuEntry>,
+    copy_confirm: Menu<DeleteConfirmMenuEntry>,
+    copy_source: usize,
+    copy_target: usize,
     skip_difficulty_menu: bool,
 }
 
 impl SaveSelectMenu {
     pub fn new() -> SaveSelectMenu {
         SaveSelectMenu {
-            saves: [MenuSaveInfo::default(); 3],
+            saves: Default::default(),
+            thumbnails: Default::default(),
+            thumbnail_textures: RefCell::new(Default::default()),
             current_menu: CurrentMenu::SaveMenu,
             save_menu: Menu::new(0, 0, 230, 0),
             coop_menu: PlayerCountMenu::new(),
@@ -110,6 +155,10 @@ impl SaveSelectMenu {
             difficulty_menu: Menu::new(0, 0, 130, 0),
             delete_confirm: Menu::new(0, 0, 75, 0),
             load_confirm: Menu::new(0, 0, 75, 0),
+            copy_menu: Menu::new(0, 0, 230, 0),
+            copy_confirm: Menu::new(0, 0, 75, 0),
+            copy_source: 0,
+            copy_target: 0,
             skip_difficulty_menu: false,
         }
     }
@@ -122,11 +171,17 @@ impl SaveSelectMenu {
         self.difficulty_menu = Menu::new(0, 0, 130, 0);
         self.delete_confirm = Menu::new(0, 0, 75, 0);
         self.load_confirm = Menu::new(0, 0, 75, 0);
+        self.copy_menu = Menu::new(0, 0, 230, 0);
+        self.copy_confirm = Menu::new(0, 0, 75, 0);
         self.skip_difficulty_menu = false;
+        self.thumbnails = Default::default();
+        *self.thumbnail_textures.borrow_mut() = Default::default();
 
         let mut should_mutate_selection = true;
 
         for (iter, save) in self.saves.iter_mut().enumerate() {
+            *save = MenuSaveInfo::default();
+
             if let Ok(data) = filesystem::user_open(ctx, state.get_save_filename(iter + 1).unwrap_or(String::new())) {
                 let loaded_save = GameProfile::load_from_save(data)?;
 
@@ -134,10 +189,16 @@ impl SaveSelectMenu {
                 save.max_life = loaded_save.max_life;
                 save.life = loaded_save.life;
                 save.weapon_count = loaded_save.weapon_data.iter().filter(|weapon| weapon.weapon_id != 0).count();
-                save.weapon_id = loaded_save.weapon_data.map(|weapon| weapon.weapon_id);
+                save.weapon_id = [0; 8];
+                for (out, weapon) in save.weapon_id.iter_mut().zip(loaded_save.weapon_data.iter()) {
+                    *out = weapon.weapon_id;
+                }
                 save.difficulty = loaded_save.difficulty;
+                save.play_time = loaded_save.play_time;
+                save.stage_name = loaded_save.stage_name;
+                self.thumbnails[iter] = loaded_save.thumbnail;
 
-                self.save_menu.push_entry(SaveMenuEntry::Load(iter), MenuEntry::SaveData(*save));
+                self.save_menu.push_entry(SaveMenuEntry::Load(iter), MenuEntry::SaveData(save.clone()));
 
                 if should_mutate_selection {
                     should_mutate_selection = false;
@@ -193,6 +254,8 @@ impl SaveSelectMenu {
             LoadConfirmMenuEntry::Start,
             MenuEntry::Active(state.loc.t("menus.main_menu.start").to_owned()),
         );
+        self.load_confirm
+            .push_entry(LoadConfirmMenuEntry::Copy, MenuEntry::Active(state.loc.t("menus.save_menu.copy").to_owned()));
         self.load_confirm.push_entry(
             LoadConfirmMenuEntry::Delete,
             MenuEntry::Active(state.loc.t("menus.save_menu.delete_confirm").to_owned()),
@@ -200,10 +263,21 @@ impl SaveSelectMenu {
         self.load_confirm
             .push_entry(LoadConfirmMenuEntry::Back, MenuEntry::Active(state.loc.t("common.back").to_owned()));
 
+        self.copy_confirm.push_entry(
+            DeleteConfirmMenuEntry::Title,
+            MenuEntry::Disabled(state.loc.t("menus.save_menu.copy_confirm").to_owned()),
+        );
+        self.copy_confirm
+            .push_entry(DeleteConfirmMenuEntry::Yes, MenuEntry::Active(state.loc.t("common.yes").to_owned()));
+        self.copy_confirm
+            .push_entry(DeleteConfirmMenuEntry::No, MenuEntry::Active(state.loc.t("common.no").to_owned()));
+
+        self.copy_confirm.selected = DeleteConfirmMenuEntry::No;
+
         self.save_detailed.draw_cursor = false;
 
-        if let (_, MenuEntry::SaveData(save)) = self.save_menu.entries[0] {
-            self.save_detailed.push_entry(0, MenuEntry::SaveDataSingle(save));
+        if let (_, MenuEntry::SaveData(save)) = &self.save_menu.entries[0] {
+            self.save_detailed.push_entry(0, MenuEntry::SaveDataSingle(save.clone()));
         }
 
         self.update_sizes(state);
@@ -241,6 +315,55 @@ impl SaveSelectMenu {
         self.save_detailed.update_height(state);
         self.save_detailed.x = ((state.canvas_size.0 - self.save_detailed.width as f32) / 2.0).floor() as isize;
         self.save_detailed.y = -40 + ((state.canvas_size.1 - self.save_detailed.height as f32) / 2.0).floor() as isize;
+
+        self.copy_menu.update_width(state);
+        self.copy_menu.update_height(state);
+        self.copy_menu.x = ((state.canvas_size.0 - self.copy_menu.width as f32) / 2.0).floor() as isize;
+        self.copy_menu.y = 30 + ((state.canvas_size.1 - self.copy_menu.height as f32) / 2.0).floor() as isize;
+
+        self.copy_confirm.update_width(state);
+        self.copy_confirm.update_height(state);
+        self.copy_confirm.x = ((state.canvas_size.0 - self.copy_confirm.width as f32) / 2.0).floor() as isize;
+        self.copy_confirm.y = 30 + ((state.canvas_size.1 - self.copy_confirm.height as f32) / 2.0).floor() as isize;
+    }
+
+    /// Lists the other slots as copy destinations.
+    fn init_copy_menu(&mut self, state: &SharedGameState, source: usize) {
+        self.copy_source = source;
+        self.copy_menu = Menu::new(0, 0, 230, 0);
+        self.copy_menu
+            .push_entry(CopyMenuEntry::Title, MenuEntry::Disabled(state.loc.t("menus.save_menu.copy_to").to_owned()));
+
+        let mut selected = None;
+        for slot in (0..self.saves.len()).filter(|&slot| slot != source) {
+            let entry = match &self.save_menu.entries[slot] {
+                (SaveMenuEntry::Load(_), MenuEntry::SaveData(save)) => MenuEntry::SaveData(save.clone()),
+                _ => MenuEntry::NewSave,
+            };
+
+            self.copy_menu.push_entry(CopyMenuEntry::Slot(slot), entry);
+            selected.get_or_insert(CopyMenuEntry::Slot(slot));
+        }
+
+        self.copy_menu.push_entry(CopyMenuEntry::Back, MenuEntry::Active(state.loc.t("common.back").to_owned()));
+        self.copy_menu.selected = selected.unwrap_or(CopyMenuEntry::Back);
+
+        self.update_sizes(state);
+    }
+
+    fn copy_save(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let source = state.get_save_filename(self.copy_source + 1).unwrap_or(String::new());
+        let target = state.get_save_filename(self.copy_target + 1).unwrap_or(String::new());
+
+        let mut data = Vec::new();
+        filesystem::user_open(ctx, source)?.read_to_end(&mut data)?;
+        filesystem::user_create(ctx, target)?.write_all(&data)?;
+
+        self.init(state, ctx)?;
+        self.save_menu.selected = SaveMenuEntry::Load(self.copy_target);
+        self.current_menu = CurrentMenu::SaveMenu;
+
+        Ok(())
     }
 
     pub fn tick(
@@ -270,7 +393,8 @@ impl SaveSelectMenu {
                     if let Ok(_) =
                         filesystem::user_open(ctx, state.get_save_filename(state.save_slot).unwrap_or(String::new()))
                     {
-                        if let (_, MenuEntry::SaveData(save)) = self.save_menu.entries[slot] {
+                        if let (_, MenuEntry::SaveData(save)) = &self.save_menu.entries[slot] {
+                            let save = save.clone();
                             self.save_detailed.entries.clear();
                             self.save_detailed.push_entry(0, MenuEntry::SaveDataSingle(save));
                         }
@@ -309,6 +433,10 @@ impl SaveSelectMenu {
                         SaveMenuEntry::Load(slot) => {
                             state.sound_manager.play_sfx(17); // Player Death sfx
                             filesystem::user_delete(ctx, state.get_save_filename(slot + 1).unwrap_or(String::new()))?;
+
+                            self.saves[slot] = MenuSaveInfo::default();
+                            self.thumbnails[slot] = None;
+                            self.thumbnail_textures.borrow_mut()[slot] = None;
                         }
                         _ => (),
                     }
@@ -331,6 +459,12 @@ impl SaveSelectMenu {
                 MenuSelectionResult::Selected(LoadConfirmMenuEntry::Start, _) => {
                     self.confirm_save_slot(state, ctx)?;
                 }
+                MenuSelectionResult::Selected(LoadConfirmMenuEntry::Copy, _) => {
+                    if let SaveMenuEntry::Load(slot) = self.save_menu.selected {
+                        self.init_copy_menu(state, slot);
+                        self.current_menu = CurrentMenu::CopyMenu;
+                    }
+                }
                 MenuSelectionResult::Selected(LoadConfirmMenuEntry::Delete, _) => {
                     self.current_menu = CurrentMenu::DeleteConfirm;
                     self.delete_confirm.selected = DeleteConfirmMenuEntry::No;
@@ -340,6 +474,32 @@ impl SaveSelectMenu {
                 }
                 _ => (),
             },
+            CurrentMenu::CopyMenu => match self.copy_menu.tick(controller, state) {
+                MenuSelectionResult::Selected(CopyMenuEntry::Slot(slot), _) => {
+                    self.copy_target = slot;
+
+                    if let (SaveMenuEntry::Load(_), _) = self.save_menu.entries[slot] {
+                        self.current_menu = CurrentMenu::CopyConfirm;
+                        self.copy_confirm.selected = DeleteConfirmMenuEntry::No;
+                    } else {
+                        self.copy_save(state, ctx)?;
+                    }
+                }
+                MenuSelectionResult::Selected(CopyMenuEntry::Back, _) | MenuSelectionResult::Canceled => {
+                    self.current_menu = CurrentMenu::LoadConfirm;
+                    self.load_confirm.selected = LoadConfirmMenuEntry::Copy;
+                }
+                _ => (),
+            },
+            CurrentMenu::CopyConfirm => match self.copy_confirm.tick(controller, state) {
+                MenuSelectionResult::Selected(DeleteConfirmMenuEntry::Yes, _) => {
+                    self.copy_save(state, ctx)?;
+                }
+                MenuSelectionResult::Selected(DeleteConfirmMenuEntry::No, _) | MenuSelectionResult::Canceled => {
+                    self.current_menu = CurrentMenu::CopyMenu;
+                }
+                _ => (),
+            },
         }
 
         Ok(())
@@ -357,17 +517,87 @@ impl SaveSelectMenu {
                 self.coop_menu.draw(state, ctx)?;
             }
             CurrentMenu::DeleteConfirm => {
-                self.save_detailed.draw(state, ctx)?;
+                self.draw_save_details(state, ctx)?;
                 self.delete_confirm.draw(state, ctx)?;
             }
             CurrentMenu::LoadConfirm => {
-                self.save_detailed.draw(state, ctx)?;
+                self.draw_save_details(state, ctx)?;
                 self.load_confirm.draw(state, ctx)?;
             }
+            CurrentMenu::CopyMenu => {
+                self.copy_menu.draw(state, ctx)?;
+            }
+            CurrentMenu::CopyConfirm => {
+                self.copy_menu.draw(state, ctx)?;
+                self.copy_confirm.draw(state, ctx)?;
+            }
         }
         Ok(())
     }
 
+    /// Draws the selected save along with its thumbnail and play time above it.
+    fn draw_save_details(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        self.save_detailed.draw(state, ctx)?;
+
+        let slot = match self.save_menu.selected {
+            SaveMenuEntry::Load(slot) => slot,
+            _ => return Ok(()),
+        };
+
+        let x = self.save_detailed.x as f32 + 8.0;
+        let bottom = self.save_detailed.y as f32 - 4.0;
+        let mut text_x = x;
+
+        if let Some(thumbnail) = &self.thumbnails[slot] {
+            let mut textures = self.thumbnail_textures.borrow_mut();
+            if textures[slot].is_none() {
+                textures[slot] =
+                    Some(graphics::create_texture(ctx, thumbnail.width, thumbnail.height, &thumbnail.data)?);
+            }
+
+            if let Some(texture) = textures[slot].as_mut() {
+                let width = THUMBNAIL_DISPLAY_WIDTH;
+                let height = width * thumbnail.height as f32 / thumbnail.width as f32;
+                let y = bottom - height;
+
+                graphics::draw_rect(
+                    ctx,
+                    Rect::new_size(
+                        ((x - 1.0) * state.scale) as isize,
+                        ((y - 1.0) * state.scale) as isize,
+                        ((width + 2.0) * state.scale) as isize,
+                        ((height + 2.0) * state.scale) as isize,
+                    ),
+                    Color::new(1.0, 1.0, 1.0, 1.0),
+                )?;
+
+                texture.clear();
+                texture.add(SpriteBatchCommand::DrawRect(
+                    Rect::new_size(0.0, 0.0, thumbnail.width as f32, thumbnail.height as f32),
+                    Rect::new_size(x * state.scale, y * state.scale, width * state.scale, height * state.scale),
+                ));
+                texture.draw()?;
+
+                text_x += width + 6.0;
+            }
+        }
+
+        let play_time = self.saves[slot].play_time;
+        if play_time != 0 {
+            let time = format!("{}:{:02}:{:02}", play_time / 3600, play_time / 60 % 60, play_time % 60);
+            let text = state.loc.tt("menus.save_menu.play_time", &[("time", time.as_str())]);
+
+            state.font.builder().position(text_x, bottom - state.font.line_height()).shadow(true).draw(
+                &text,
+                ctx,
+                &state.constants,
+                &mut state.texture_set,
+            )?;
+        }
+
+        Ok(())
+    }
+
     fn confirm_save_slot(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
         if state.constants.supports_two_player {
             self.current_menu = CurrentMenu::PlayerCountMenu;
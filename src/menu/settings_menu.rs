@@ -4,13 +4,15 @@ use crate::framework::context::Context;
 use crate::framework::error::GameResult;
 use crate::framework::graphics::VSyncMode;
 use crate::framework::{filesystem, graphics};
-use crate::game::shared_game_state::{CutsceneSkipMode, ScreenShakeIntensity, SharedGameState, TimingMode, WindowMode};
+use crate::game::shared_game_state::{
+    CutsceneSkipMode, ScreenShakeIntensity, SharedGameState, TimingMode, ViewportSize, WindowMode,
+};
 use crate::graphics::font::Font;
 use crate::input::combined_menu_controller::CombinedMenuController;
 use crate::menu::MenuEntry;
 use crate::menu::{Menu, MenuSelectionResult};
 use crate::scene::title_scene::TitleScene;
-use crate::sound::InterpolationMode;
+use crate::sound::{InterpolationMode, SoundManager};
 use crate::util::browser;
 
 use super::controls_menu::ControlsMenu;
@@ -53,6 +55,7 @@ impl Default for MainMenuEntry {
 enum GraphicsMenuEntry {
     VSyncMode,
     WindowMode,
+    ViewportSize,
     LightingEffects,
     WeaponLightCone,
     ScreenShake,
@@ -74,7 +77,11 @@ impl Default for GraphicsMenuEntry {
 enum SoundMenuEntry {
     MusicVolume,
     EffectsVolume,
+    PositionalSound,
     BGMInterpolation,
+    OutputDevice,
+    SampleRate,
+    BufferSize,
     Soundtrack,
     Back,
 }
@@ -184,6 +191,7 @@ pub struct SettingsMenu {
     advanced: Menu<AdvancedMenuEntry>,
     portable: Menu<PortableMenuEntry>,
     controls_menu: ControlsMenu,
+    audio_devices: Vec<String>,
     pub on_title: bool,
 }
 
@@ -195,6 +203,9 @@ static GENERAL_LINK: &str = "https://discord.gg/cavestory";
 static MODDING_LINK: &str = "https://discord.gg/xRsWpz6";
 static GETPLUS_LINK: &str = "https://www.nicalis.com/games/cavestory+";
 
+static SAMPLE_RATES: [u32; 4] = [22050, 44100, 48000, 96000];
+static BUFFER_SIZES: [u32; 5] = [256, 512, 1024, 2048, 4096];
+
 impl SettingsMenu {
     pub fn new() -> SettingsMenu {
         let main = Menu::new(0, 0, 220, 0);
@@ -221,6 +232,7 @@ impl SettingsMenu {
             advanced,
             controls_menu,
             portable,
+            audio_devices: Vec::new(),
             on_title: false,
         }
     }
@@ -259,6 +271,19 @@ impl SettingsMenu {
                 ],
             ),
         );
+        self.graphics.push_entry(
+            GraphicsMenuEntry::ViewportSize,
+            MenuEntry::Options(
+                state.loc.t("menus.options_menu.graphics_menu.viewport_size.entry").to_owned(),
+                state.settings.viewport_size as usize,
+                vec![
+                    state.loc.t("menus.options_menu.graphics_menu.viewport_size.fill").to_owned(),
+                    state.loc.t("menus.options_menu.graphics_menu.viewport_size.classic").to_owned(),
+                    state.loc.t("menus.options_menu.graphics_menu.viewport_size.widescreen").to_owned(),
+                    state.loc.t("menus.options_menu.graphics_menu.viewport_size.ultrawide").to_owned(),
+                ],
+            ),
+        );
         self.graphics.push_entry(
             GraphicsMenuEntry::LightingEffects,
             MenuEntry::Toggle(
@@ -477,6 +502,14 @@ impl SettingsMenu {
             ),
         );
 
+        self.sound.push_entry(
+            SoundMenuEntry::PositionalSound,
+            MenuEntry::Toggle(
+                state.loc.t("menus.options_menu.sound_menu.positional_sound").to_owned(),
+                state.settings.positional_sfx,
+            ),
+        );
+
         self.sound.push_entry(
             SoundMenuEntry::BGMInterpolation,
             MenuEntry::DescriptiveOptions(
@@ -498,6 +531,66 @@ impl SettingsMenu {
                 ],
             ),
         );
+
+        self.audio_devices = SoundManager::output_device_names();
+        if let Some(device) = &state.settings.audio_device {
+            if !self.audio_devices.contains(device) {
+                self.audio_devices.push(device.clone());
+            }
+        }
+
+        let default_option = state.loc.t("menus.options_menu.sound_menu.default").to_owned();
+
+        let mut device_options = vec![default_option.clone()];
+        device_options.extend(self.audio_devices.iter().cloned());
+        let device_index = match &state.settings.audio_device {
+            Some(device) => self.audio_devices.iter().position(|d| d == device).map_or(0, |i| i + 1),
+            None => 0,
+        };
+
+        self.sound.push_entry(
+            SoundMenuEntry::OutputDevice,
+            MenuEntry::Options(
+                state.loc.t("menus.options_menu.sound_menu.output_device").to_owned(),
+                device_index,
+                device_options,
+            ),
+        );
+
+        let mut sample_rate_options = vec![default_option.clone()];
+        sample_rate_options.extend(SAMPLE_RATES.iter().map(|rate| format!("{} Hz", rate)));
+        let sample_rate_index = state
+            .settings
+            .audio_sample_rate
+            .and_then(|r| SAMPLE_RATES.iter().position(|&x| x == r))
+            .map_or(0, |i| i + 1);
+
+        self.sound.push_entry(
+            SoundMenuEntry::SampleRate,
+            MenuEntry::Options(
+                state.loc.t("menus.options_menu.sound_menu.sample_rate").to_owned(),
+                sample_rate_index,
+                sample_rate_options,
+            ),
+        );
+
+        let mut buffer_size_options = vec![default_option];
+        buffer_size_options.extend(BUFFER_SIZES.iter().map(|size| size.to_string()));
+        let buffer_size_index = state
+            .settings
+            .audio_buffer_size
+            .and_then(|s| BUFFER_SIZES.iter().position(|&x| x == s))
+            .map_or(0, |i| i + 1);
+
+        self.sound.push_entry(
+            SoundMenuEntry::BufferSize,
+            MenuEntry::Options(
+                state.loc.t("menus.options_menu.sound_menu.buffer_size").to_owned(),
+                buffer_size_index,
+                buffer_size_options,
+            ),
+        );
+
         self.sound.push_entry(
             SoundMenuEntry::Soundtrack,
             MenuEntry::Active(state.loc.tt(
@@ -651,6 +744,31 @@ impl SettingsMenu {
         self.portable.y = 30 + ((state.canvas_size.1 - self.portable.height as f32) / 2.0).floor() as isize;
     }
 
+    /// Applies an option picked in one of the audio output entries, index 0 is always the system default.
+    fn set_audio_output_option(
+        entry: SoundMenuEntry,
+        index: usize,
+        audio_devices: &[String],
+        state: &mut SharedGameState,
+        ctx: &mut Context,
+    ) {
+        let index = index.checked_sub(1);
+
+        match entry {
+            SoundMenuEntry::OutputDevice => state.settings.audio_device = index.map(|i| audio_devices[i].clone()),
+            SoundMenuEntry::SampleRate => state.settings.audio_sample_rate = index.map(|i| SAMPLE_RATES[i]),
+            SoundMenuEntry::BufferSize => state.settings.audio_buffer_size = index.map(|i| BUFFER_SIZES[i]),
+            _ => return,
+        }
+
+        let _ = state.settings.save(ctx);
+
+        let output_config = state.settings.audio_output_config();
+        if let Err(err) = state.sound_manager.set_output_config(output_config, &state.constants, &state.settings, ctx) {
+            log::error!("Failed to switch audio output: {}", err);
+        }
+    }
+
     pub fn tick(
         &mut self,
         exit_action: &mut dyn FnMut(),
@@ -703,6 +821,39 @@ impl SettingsMenu {
                         let _ = state.settings.save(ctx);
                     }
                 }
+                MenuSelectionResult::Selected(GraphicsMenuEntry::ViewportSize, toggle)
+                | MenuSelectionResult::Right(GraphicsMenuEntry::ViewportSize, toggle, _) => {
+                    if let MenuEntry::Options(_, value, _) = toggle {
+                        let (new_size, new_value) = match *value {
+                            0 => (ViewportSize::Classic, 1),
+                            1 => (ViewportSize::Widescreen, 2),
+                            2 => (ViewportSize::Ultrawide, 3),
+                            _ => (ViewportSize::Fill, 0),
+                        };
+
+                        *value = new_value;
+                        state.settings.viewport_size = new_size;
+                        state.handle_resize(ctx)?;
+
+                        let _ = state.settings.save(ctx);
+                    }
+                }
+                MenuSelectionResult::Left(GraphicsMenuEntry::ViewportSize, toggle, _) => {
+                    if let MenuEntry::Options(_, value, _) = toggle {
+                        let (new_size, new_value) = match *value {
+                            0 => (ViewportSize::Ultrawide, 3),
+                            1 => (ViewportSize::Fill, 0),
+                            2 => (ViewportSize::Classic, 1),
+                            _ => (ViewportSize::Widescreen, 2),
+                        };
+
+                        *value = new_value;
+                        state.settings.viewport_size = new_size;
+                        state.handle_resize(ctx)?;
+
+                        let _ = state.settings.save(ctx);
+                    }
+                }
                 MenuSelectionResult::Selected(GraphicsMenuEntry::VSyncMode, toggle)
                 | MenuSelectionResult::Right(GraphicsMenuEntry::VSyncMode, toggle, _) => {
                     if let MenuEntry::DescriptiveOptions(_, value, _, _) = toggle {
@@ -847,6 +998,14 @@ impl SettingsMenu {
                         let _ = state.settings.save(ctx);
                     }
                 }
+                MenuSelectionResult::Selected(SoundMenuEntry::PositionalSound, toggle) => {
+                    if let MenuEntry::Toggle(_, value) = toggle {
+                        state.settings.positional_sfx = !state.settings.positional_sfx;
+                        let _ = state.settings.save(ctx);
+
+                        *value = state.settings.positional_sfx;
+                    }
+                }
                 MenuSelectionResult::Selected(SoundMenuEntry::BGMInterpolation, toggle)
                 | MenuSelectionResult::Right(SoundMenuEntry::BGMInterpolation, toggle, _) => {
                     if let MenuEntry::DescriptiveOptions(_, value, _, _) = toggle {
@@ -880,6 +1039,32 @@ impl SettingsMenu {
                         let _ = state.settings.save(ctx);
                     }
                 }
+                MenuSelectionResult::Selected(
+                    entry @ (SoundMenuEntry::OutputDevice | SoundMenuEntry::SampleRate | SoundMenuEntry::BufferSize),
+                    toggle,
+                )
+                | MenuSelectionResult::Right(
+                    entry @ (SoundMenuEntry::OutputDevice | SoundMenuEntry::SampleRate | SoundMenuEntry::BufferSize),
+                    toggle,
+                    _,
+                ) => {
+                    if let MenuEntry::Options(_, value, options) = toggle {
+                        *value = (*value + 1) % options.len();
+
+                        Self::set_audio_output_option(entry, *value, &self.audio_devices, state, ctx);
+                    }
+                }
+                MenuSelectionResult::Left(
+                    entry @ (SoundMenuEntry::OutputDevice | SoundMenuEntry::SampleRate | SoundMenuEntry::BufferSize),
+                    toggle,
+                    _,
+                ) => {
+                    if let MenuEntry::Options(_, value, options) = toggle {
+                        *value = (*value + options.len() - 1) % options.len();
+
+                        Self::set_audio_output_option(entry, *value, &self.audio_devices, state, ctx);
+                    }
+                }
                 MenuSelectionResult::Selected(SoundMenuEntry::Soundtrack, _) => {
                     let mut active_soundtrack = SoundtrackMenuEntry::Soundtrack(0);
 
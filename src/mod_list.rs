@@ -1,13 +1,149 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::io::{BufRead, BufReader};
 use std::iter::Peekable;
+use std::path::PathBuf;
 use std::str::Chars;
 
+use serde::Deserialize;
+
 use crate::framework::context::Context;
+use crate::framework::error::GameError::InvalidValue;
 use crate::framework::error::GameResult;
 use crate::framework::filesystem;
 use crate::mod_requirements::ModRequirements;
 
+/// Directory scanned for mods that only come with a `mod.json` manifest.
+const MODS_DIR: &str = "/mods/";
+
+/// Version of the engine the mods are checked against.
+const ENGINE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A `major.minor.patch` version, missing components are zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ModVersion(pub u32, pub u32, pub u32);
+
+impl ModVersion {
+    pub fn parse(version: &str) -> Option<ModVersion> {
+        let mut parts = version.trim().splitn(3, '.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().map_or(Some(0), |p| p.parse().ok())?;
+        let patch = parts.next().map_or(Some(0), |p| p.parse().ok())?;
+
+        Some(ModVersion(major, minor, patch))
+    }
+}
+
+impl fmt::Display for ModVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+/// A comma separated list of version comparisons that all have to match, eg. `>=1.2, <2`.
+/// Supports `=`, `>`, `>=`, `<`, `<=`, `^` (same major version) and `~` (same minor version),
+/// an empty string or `*` matches any version.
+#[derive(Debug, Clone, Default)]
+pub struct VersionReq {
+    comparators: Vec<(VersionOp, ModVersion)>,
+    source: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VersionOp {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Caret,
+    Tilde,
+}
+
+impl VersionReq {
+    pub fn parse(req: &str) -> Option<VersionReq> {
+        let mut comparators = Vec::new();
+
+        for part in req.split(',').map(str::trim).filter(|p| !p.is_empty() && *p != "*") {
+            let (op, version) = [
+                (">=", VersionOp::GreaterEq),
+                ("<=", VersionOp::LessEq),
+                (">", VersionOp::Greater),
+                ("<", VersionOp::Less),
+                ("=", VersionOp::Exact),
+                ("^", VersionOp::Caret),
+                ("~", VersionOp::Tilde),
+            ]
+            .iter()
+            .find_map(|(prefix, op)| part.strip_prefix(prefix).map(|v| (*op, v)))
+            .unwrap_or((VersionOp::Caret, part));
+
+            comparators.push((op, ModVersion::parse(version)?));
+        }
+
+        Some(VersionReq { comparators, source: req.trim().to_owned() })
+    }
+
+    pub fn matches(&self, version: ModVersion) -> bool {
+        self.comparators.iter().all(|&(op, req)| match op {
+            VersionOp::Exact => version == req,
+            VersionOp::Greater => version > req,
+            VersionOp::GreaterEq => version >= req,
+            VersionOp::Less => version < req,
+            VersionOp::LessEq => version <= req,
+            VersionOp::Caret if req.0 == 0 => version >= req && version.0 == 0 && version.1 == req.1,
+            VersionOp::Caret => version >= req && version.0 == req.0,
+            VersionOp::Tilde => version >= req && version.0 == req.0 && version.1 == req.1,
+        })
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.source.is_empty() {
+            f.write_str("*")
+        } else {
+            f.write_str(&self.source)
+        }
+    }
+}
+
+/// Contents of `mod.json`, either next to `mod.txt` of a mod listed in `mods.txt` or in a directory under `/mods/`.
+///
+/// ```json
+/// {
+///   "id": "better_balrog",
+///   "name": "Better Balrog",
+///   "version": "1.2.0",
+///   "engine_version": ">=0.101",
+///   "dependencies": { "balrog_assets": "^1.0" },
+///   "load_after": ["hd_textures"]
+/// }
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct ModManifest {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Range of engine versions the mod works with.
+    #[serde(default)]
+    pub engine_version: Option<String>,
+    /// Ids of mods that are loaded below this one, along with the required version range.
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, String>,
+    /// Ids of mods this one has to be stacked on top of if both are loaded, without requiring them.
+    #[serde(default)]
+    pub load_after: Vec<String>,
+    #[serde(default)]
+    pub priority: Option<u32>,
+    #[serde(default)]
+    pub save_slot: Option<i32>,
+}
+
 #[derive(Debug)]
 pub struct ModInfo {
     pub id: String,
@@ -18,6 +154,17 @@ pub struct ModInfo {
     pub name: String,
     pub description: String,
     pub valid: bool,
+    pub version: Option<ModVersion>,
+    pub engine_version: VersionReq,
+    pub dependencies: Vec<(String, VersionReq)>,
+    pub load_after: Vec<String>,
+}
+
+/// A file provided by more than one mod of a stack, the last mod wins.
+#[derive(Debug)]
+pub struct ModConflict {
+    pub path: String,
+    pub mods: Vec<String>,
 }
 
 impl ModInfo {
@@ -173,13 +320,228 @@ impl ModList {
                     description = "mod.txt not found".to_string();
                 }
 
-                mods.push(ModInfo { id, requirement, priority, save_slot, path, name, description, valid })
+                let mut info = ModInfo {
+                    id,
+                    requirement,
+                    priority,
+                    save_slot,
+                    path,
+                    name,
+                    description,
+                    valid,
+                    version: None,
+                    engine_version: VersionReq::default(),
+                    dependencies: Vec::new(),
+                    load_after: Vec::new(),
+                };
+
+                // mod.json only extends mod.txt here, it can't make up for a missing one
+                if valid {
+                    if let Some(manifest) = ModList::load_manifest(ctx, &info.path) {
+                        info.apply_manifest(manifest, string_table);
+                    }
+                }
+
+                mods.push(info);
+            }
+        }
+
+        if let Ok(dir) = filesystem::read_dir(ctx, MODS_DIR) {
+            let mut dirs: Vec<PathBuf> = dir.filter(|entry| filesystem::is_dir(ctx, entry)).collect();
+            dirs.sort();
+
+            for dir in dirs {
+                let path = format!("{}/", dir.to_string_lossy().trim_end_matches('/'));
+                if mods.iter().any(|m: &ModInfo| m.path == path) {
+                    continue;
+                }
+
+                let Some(manifest) = ModList::load_manifest(ctx, &path) else {
+                    continue;
+                };
+
+                let mut info = ModInfo {
+                    id: String::new(),
+                    requirement: Requirement::Unlocked,
+                    priority: 1000,
+                    save_slot: -1,
+                    path: path.clone(),
+                    name: path,
+                    description: String::new(),
+                    valid: true,
+                    version: None,
+                    engine_version: VersionReq::default(),
+                    dependencies: Vec::new(),
+                    load_after: Vec::new(),
+                };
+                info.apply_manifest(manifest, string_table);
+
+                mods.push(info);
             }
         }
 
         mods.sort_by(|a, b| a.priority.cmp(&b.priority));
 
-        Ok(ModList { mods })
+        let mut list = ModList { mods };
+        list.validate();
+
+        Ok(list)
+    }
+
+    fn load_manifest(ctx: &mut Context, path: &str) -> Option<ModManifest> {
+        let file = filesystem::open(ctx, [path.trim_end_matches('/'), "/mod.json"].join("")).ok()?;
+
+        match serde_json::from_reader::<_, ModManifest>(file) {
+            Ok(manifest) => Some(manifest),
+            Err(err) => {
+                log::warn!("Failed to deserialize {}mod.json: {}", path, err);
+                None
+            }
+        }
+    }
+
+    /// Disables mods that don't support this engine version or have unmet dependencies.
+    /// Disabling a mod can break the mods depending on it, so it's repeated until nothing changes.
+    fn validate(&mut self) {
+        let engine_version = ModVersion::parse(ENGINE_VERSION).unwrap_or(ModVersion(0, 0, 0));
+
+        loop {
+            let mut changed = false;
+
+            for idx in 0..self.mods.len() {
+                if !self.mods[idx].valid {
+                    continue;
+                }
+
+                let error = if !self.mods[idx].engine_version.matches(engine_version) {
+                    Some(format!("Requires engine version {}.", self.mods[idx].engine_version))
+                } else {
+                    self.resolve_stack(idx).err().map(|err| err.to_string())
+                };
+
+                if let Some(error) = error {
+                    log::warn!("Disabling mod {}: {}", self.mods[idx].id, error);
+
+                    let info = &mut self.mods[idx];
+                    info.valid = false;
+                    info.description = error;
+                    changed = true;
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    /// Finds a valid mod with given id, invalid mods can't be depended on.
+    fn find_by_id(&self, id: &str) -> Option<usize> {
+        self.mods.iter().position(|m| m.valid && m.id == id)
+    }
+
+    /// Returns indices of the mods to load for given mod, dependencies first.
+    fn resolve_stack(&self, idx: usize) -> GameResult<Vec<usize>> {
+        let mut stack = Vec::new();
+        self.collect_dependencies(idx, &mut Vec::new(), &mut stack)?;
+
+        // stable topological sort, honoring load_after between the mods that ended up in the stack
+        let mut ordered: Vec<usize> = Vec::with_capacity(stack.len());
+        let mut remaining = stack;
+        while !remaining.is_empty() {
+            let next = remaining.iter().position(|&candidate| {
+                let info = &self.mods[candidate];
+                let after = info.dependencies.iter().map(|(id, _)| id).chain(info.load_after.iter());
+
+                after.filter_map(|id| self.find_by_id(id)).all(|dep| dep == candidate || !remaining.contains(&dep))
+            });
+
+            match next {
+                Some(pos) => ordered.push(remaining.remove(pos)),
+                None => {
+                    let ids: Vec<&str> = remaining.iter().map(|&i| self.mods[i].id.as_str()).collect();
+                    return Err(InvalidValue(format!("Circular load order between mods {}.", ids.join(", "))));
+                }
+            }
+        }
+
+        Ok(ordered)
+    }
+
+    fn collect_dependencies(&self, idx: usize, visiting: &mut Vec<usize>, out: &mut Vec<usize>) -> GameResult {
+        if out.contains(&idx) {
+            return Ok(());
+        }
+
+        if visiting.contains(&idx) {
+            return Err(InvalidValue(format!("Circular dependency on mod {}.", self.mods[idx].id)));
+        }
+
+        visiting.push(idx);
+
+        for (dep_id, req) in &self.mods[idx].dependencies {
+            let dep = self.find_by_id(dep_id).ok_or_else(|| InvalidValue(format!("Missing dependency {}.", dep_id)))?;
+            let dep_info = &self.mods[dep];
+
+            match dep_info.version {
+                Some(version) if req.matches(version) => {}
+                None if req.comparators.is_empty() => {}
+                version => {
+                    let version = version.map_or("unknown".to_owned(), |v| v.to_string());
+                    return Err(InvalidValue(format!(
+                        "Dependency {} version {} doesn't match {}.",
+                        dep_id, version, req
+                    )));
+                }
+            }
+
+            self.collect_dependencies(dep, visiting, out)?;
+        }
+
+        visiting.pop();
+        out.push(idx);
+
+        Ok(())
+    }
+
+    /// Paths of the mods to stack for the mod at given path, in load order with the selected mod last.
+    pub fn load_order(&self, mod_path: &str) -> Vec<String> {
+        let Some(idx) = self.mods.iter().position(|m| m.path == mod_path) else {
+            return vec![mod_path.to_owned()];
+        };
+
+        match self.resolve_stack(idx) {
+            Ok(stack) => stack.into_iter().map(|i| self.mods[i].path.clone()).collect(),
+            Err(err) => {
+                log::warn!("Failed to resolve dependencies of {}: {}", mod_path, err);
+                vec![mod_path.to_owned()]
+            }
+        }
+    }
+
+    /// Lists files provided by more than one of the mods in the stack.
+    pub fn find_conflicts(&self, ctx: &Context, stack: &[String]) -> Vec<ModConflict> {
+        if stack.len() < 2 {
+            return Vec::new();
+        }
+
+        let mut providers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+        for mod_path in stack {
+            let name = self.mods.iter().find(|m| &m.path == mod_path).map_or(mod_path.as_str(), |m| m.id.as_str());
+            let mut files = Vec::new();
+            list_files(ctx, mod_path.trim_end_matches('/'), "", &mut files);
+
+            for file in files {
+                providers.entry(file).or_default().push(name.to_owned());
+            }
+        }
+
+        providers
+            .into_iter()
+            .filter(|(path, mods)| mods.len() > 1 && path != "/mod.json" && path != "/mod.txt")
+            .map(|(path, mods)| ModConflict { path, mods })
+            .collect()
     }
 
     pub fn get_save_from_path(&self, mod_path: String) -> i32 {
@@ -198,3 +560,165 @@ impl ModList {
         }
     }
 }
+
+impl ModInfo {
+    fn apply_manifest(&mut self, manifest: ModManifest, string_table: &HashMap<String, String>) {
+        self.id = manifest.id;
+
+        if let Some(name) = manifest.name {
+            self.name = string_table.get(&name).cloned().unwrap_or(name);
+        }
+        if let Some(description) = manifest.description {
+            self.description = description;
+        }
+        if let Some(priority) = manifest.priority {
+            self.priority = priority;
+        }
+        if let Some(save_slot) = manifest.save_slot {
+            self.save_slot = save_slot;
+        }
+
+        self.version = manifest.version.as_deref().and_then(|v| {
+            let version = ModVersion::parse(v);
+            if version.is_none() {
+                log::warn!("Mod {} has invalid version {:?}.", self.id, v);
+            }
+            version
+        });
+
+        if let Some(engine_version) = manifest.engine_version {
+            match VersionReq::parse(&engine_version) {
+                Some(req) => self.engine_version = req,
+                None => {
+                    log::warn!("Mod {} has invalid engine version range {:?}.", self.id, engine_version);
+                    self.valid = false;
+                }
+            }
+        }
+
+        for (id, req) in manifest.dependencies {
+            match VersionReq::parse(&req) {
+                Some(req) => self.dependencies.push((id, req)),
+                None => {
+                    log::warn!("Mod {} has invalid version range {:?} for dependency {}.", self.id, req, id);
+                    self.valid = false;
+                }
+            }
+        }
+
+        self.load_after = manifest.load_after;
+    }
+}
+
+/// Recursively lists files under a directory of the VFS, relative to `root`.
+fn list_files(ctx: &Context, root: &str, dir: &str, out: &mut Vec<String>) {
+    let Ok(entries) = filesystem::read_dir(ctx, [root, dir, "/"].join("")) else {
+        return;
+    };
+
+    for entry in entries {
+        let Some(name) = entry.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+            continue;
+        };
+        let path = [dir, "/", &name].join("");
+
+        if filesystem::is_dir(ctx, &entry) {
+            list_files(ctx, root, &path, out);
+        } else {
+            out.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mod_info(id: &str, dependencies: &[(&str, &str)]) -> ModInfo {
+        ModInfo {
+            id: id.to_owned(),
+            requirement: Requirement::Unlocked,
+            priority: 1000,
+            save_slot: -1,
+            path: format!("/mods/{}/", id),
+            name: id.to_owned(),
+            description: String::new(),
+            valid: true,
+            version: Some(ModVersion(1, 0, 0)),
+            engine_version: VersionReq::default(),
+            dependencies: dependencies
+                .iter()
+                .map(|(id, req)| (id.to_string(), VersionReq::parse(req).unwrap()))
+                .collect(),
+            load_after: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_mod_version_parse() {
+        assert_eq!(ModVersion::parse("1.2.3"), Some(ModVersion(1, 2, 3)));
+        assert_eq!(ModVersion::parse(" 1.2 "), Some(ModVersion(1, 2, 0)));
+        assert_eq!(ModVersion::parse("7"), Some(ModVersion(7, 0, 0)));
+        assert_eq!(ModVersion::parse(""), None);
+        assert_eq!(ModVersion::parse("1.x"), None);
+        assert_eq!(ModVersion::parse("1.2.3.4"), None);
+        assert_eq!(ModVersion::parse("-1"), None);
+        assert_eq!(ModVersion(1, 2, 3).to_string(), "1.2.3");
+    }
+
+    #[test]
+    fn test_version_req() {
+        let req = |s: &str| VersionReq::parse(s).unwrap();
+
+        assert!(req("").matches(ModVersion(0, 0, 1)));
+        assert!(req("*").matches(ModVersion(9, 9, 9)));
+        assert_eq!(req("").to_string(), "*");
+
+        assert!(req(">=1.2, <2").matches(ModVersion(1, 2, 0)));
+        assert!(req(">=1.2, <2").matches(ModVersion(1, 9, 9)));
+        assert!(!req(">=1.2, <2").matches(ModVersion(2, 0, 0)));
+        assert!(!req(">=1.2, <2").matches(ModVersion(1, 1, 9)));
+
+        assert!(req("=1.0.1").matches(ModVersion(1, 0, 1)));
+        assert!(!req("=1.0.1").matches(ModVersion(1, 0, 2)));
+        assert!(req(">1").matches(ModVersion(1, 0, 1)));
+        assert!(req("<=1.5").matches(ModVersion(1, 5, 0)));
+
+        // bare versions behave like ^
+        assert!(req("1.2").matches(ModVersion(1, 3, 0)));
+        assert!(!req("1.2").matches(ModVersion(2, 0, 0)));
+        assert!(req("^0.3").matches(ModVersion(0, 3, 5)));
+        assert!(!req("^0.3").matches(ModVersion(0, 4, 0)));
+        assert!(req("~1.2").matches(ModVersion(1, 2, 9)));
+        assert!(!req("~1.2").matches(ModVersion(1, 3, 0)));
+
+        assert!(VersionReq::parse(">=one").is_none());
+        assert!(VersionReq::parse("1.0, <").is_none());
+    }
+
+    #[test]
+    fn test_validate_disables_dependents_of_invalid_mods() {
+        let mut base = mod_info("base", &[]);
+        base.engine_version = VersionReq::parse(">=9999").unwrap();
+
+        // listed before the mods they depend on, so a single pass would miss them
+        let mut list =
+            ModList { mods: vec![mod_info("top", &[("middle", "^1")]), mod_info("middle", &[("base", "")]), base] };
+        list.validate();
+
+        assert!(list.mods.iter().all(|m| !m.valid));
+        assert_eq!(list.find_by_id("base"), None);
+    }
+
+    #[test]
+    fn test_load_order() {
+        let mut list = ModList {
+            mods: vec![mod_info("top", &[("base", ">=1")]), mod_info("base", &[]), mod_info("other", &[("gone", "")])],
+        };
+        list.validate();
+
+        assert!(list.mods[0].valid && list.mods[1].valid);
+        assert!(!list.mods[2].valid);
+        assert_eq!(list.load_order("/mods/top/"), vec!["/mods/base/".to_owned(), "/mods/top/".to_owned()]);
+    }
+}
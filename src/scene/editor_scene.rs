@@ -6,7 +6,8 @@ use imgui::{Condition, MenuItem, TabItem, TabItemFlags, Window};
 
 use crate::editor::{CurrentTool, EditorInstance};
 use crate::framework::context::Context;
-use crate::framework::error::GameResult;
+use crate::framework::error::{GameError, GameResult};
+use crate::framework::filesystem;
 use crate::framework::keyboard;
 use crate::framework::keyboard::ScanCode;
 use crate::framework::ui::Components;
@@ -107,6 +108,25 @@ impl EditorScene {
         });
     }
 
+    /// Writes the map of the current stage to the user directory, as files within game data can't be written to.
+    fn export_map(&mut self, ctx: &mut Context) {
+        catch(self.error_list.clone(), || {
+            if let Some(instance) = self.instances.get(self.selected_instance) {
+                if instance.stage.data.pxpack_data.is_some() {
+                    return Err(GameError::InvalidValue("Exporting PxPack maps is not supported.".to_owned()));
+                }
+
+                let path = ["/editor/", &instance.stage.data.map, ".pxm"].join("");
+                filesystem::user_create_dir(ctx, "/editor")?;
+                instance.stage.map.write_pxm(filesystem::user_create(ctx, &path)?)?;
+
+                log::info!("Exported map to {}", path);
+            }
+
+            Ok(())
+        });
+    }
+
     fn perform_actions(&mut self, state: &mut SharedGameState, ctx: &mut Context) {
         let actions = std::mem::take(&mut self.stage_list.actions);
         for action in actions.iter() {
@@ -156,6 +176,7 @@ impl Scene for EditorScene {
             if let Some(next_scene) = next_scene {
                 *subscene_ref = if let Ok(game_scene) = next_scene.downcast() {
                     let mut game_scene: Box<GameScene> = game_scene;
+                    state.sound_manager.set_sfx_listener(None);
                     game_scene.init(state, ctx)?;
                     Some(game_scene)
                 } else {
@@ -226,6 +247,10 @@ impl Scene for EditorScene {
                     self.stage_list.show();
                 }
 
+                if MenuItem::new("Export map").enabled(!self.instances.is_empty()).build(ui) {
+                    self.export_map(ctx);
+                }
+
                 ui.separator();
 
                 if MenuItem::new("Exit editor").build(ui) {
@@ -33,6 +33,7 @@ use crate::framework::{filesystem, gamepad, graphics};
 use crate::game::caret::CaretType;
 use crate::game::frame::{Frame, UpdateTarget};
 use crate::game::inventory::{Inventory, TakeExperienceResult};
+use crate::game::lighting::LightSource;
 use crate::game::map::WaterParams;
 use crate::game::npc::boss::BossNPC;
 use crate::game::npc::list::NPCList;
@@ -52,6 +53,7 @@ use crate::input::touch_controls::TouchControlType;
 use crate::menu::pause_menu::PauseMenu;
 use crate::scene::title_scene::TitleScene;
 use crate::scene::Scene;
+use crate::sound::SfxListener;
 use crate::util::rng::RNG;
 
 pub struct GameScene {
@@ -132,6 +134,11 @@ impl GameScene {
             }
         }
 
+        let mut background = Background::new();
+        if let Err(err) = background.load_layers(ctx, &state.constants.base_paths, &stage.data.map) {
+            log::warn!("{}, falling back to the stage background.", err);
+        }
+
         let stage_textures = {
             let mut textures = StageTexturePaths::new();
             textures.update(&stage);
@@ -166,7 +173,7 @@ impl GameScene {
             hud_player2: HUD::new(Alignment::Right),
             nikumaru: NikumaruCounter::new(),
             whimsical_star: WhimsicalStar::new(),
-            background: Background::new(),
+            background,
             tilemap,
             text_boxes: TextBoxes::new(),
             fade: Fade::new(),
@@ -209,6 +216,31 @@ impl GameScene {
         self.player2.cond.set_alive(false);
     }
 
+    /// Spawns NPCs from the stage's entity list, except the ones hidden by their flags.
+    pub fn spawn_stage_npcs(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let npcs = self.stage.load_npcs(&state.constants.base_paths, ctx)?;
+        for npc_data in npcs.iter() {
+            log::info!("creating npc: {:?}", npc_data);
+
+            let mut npc = NPC::create_from_data(npc_data, &state.npc_table, state.tile_size);
+            if npc.npc_flags.appear_when_flag_set() {
+                if state.get_flag(npc_data.flag_num as _) {
+                    npc.cond.set_alive(true);
+                }
+            } else if npc.npc_flags.hide_unless_flag_set() {
+                if !state.get_flag(npc_data.flag_num as _) {
+                    npc.cond.set_alive(true);
+                }
+            } else {
+                npc.cond.set_alive(true);
+            }
+
+            self.npc_list.spawn_at_slot(npc_data.id, npc)?;
+        }
+
+        Ok(())
+    }
+
     fn draw_npc_layer(&self, state: &mut SharedGameState, ctx: &mut Context, layer: NPCLayer) -> GameResult {
         for npc in self.npc_list.iter_alive() {
             if npc.layer != layer
@@ -498,6 +530,33 @@ impl GameScene {
         }
     }
 
+    fn draw_light_source(
+        &self,
+        light: &LightSource,
+        (prev_x, prev_y, x, y): (i32, i32, i32, i32),
+        (seed, anim_num): (u16, u16),
+        tile_size: TileSize,
+        frame_time: f64,
+        batch: &mut Box<dyn SpriteBatch>,
+    ) {
+        let color = light.color(seed, anim_num);
+        let (off_x, off_y) = light.offset;
+
+        if light.shadows {
+            let x = x + (off_x * 512.0) as i32;
+            let y = y + (off_y * 512.0) as i32;
+            self.draw_light_raycast(tile_size, x, y, color, light.attenuation(), 0..360, batch);
+        } else {
+            self.draw_light(
+                interpolate_fix9_scale(prev_x - self.frame.prev_x, x - self.frame.x, frame_time) + off_x,
+                interpolate_fix9_scale(prev_y - self.frame.prev_y, y - self.frame.y, frame_time) + off_y,
+                light.radius / 32.0,
+                color,
+                batch,
+            );
+        }
+    }
+
     fn draw_light_map(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
         {
             let maybe_canvas = state.lightmap_canvas.as_ref();
@@ -511,7 +570,8 @@ impl GameScene {
 
         graphics::set_blend_mode(ctx, BlendMode::Add)?;
 
-        graphics::clear(ctx, Color::from_rgb(100, 100, 110));
+        let (r, g, b) = self.stage.data.ambient_light.unwrap_or((100, 100, 110));
+        graphics::clear(ctx, Color::from_rgb(r, g, b));
 
         for npc in self.npc_list.iter_alive() {
             if npc.x < (self.frame.x - 128 * 0x200 - npc.display_bounds.width() as i32 * 0x200)
@@ -586,44 +646,56 @@ impl GameScene {
                 }
             }
 
+            let lights = &state.constants.lights;
+            let frame_time = state.frame_time;
+
             for bullet in self.bullet_manager.bullets.iter() {
-                self.draw_light(
-                    interpolate_fix9_scale(
-                        bullet.prev_x - self.frame.prev_x,
-                        bullet.x - self.frame.x,
-                        state.frame_time,
-                    ),
-                    interpolate_fix9_scale(
-                        bullet.prev_y - self.frame.prev_y,
-                        bullet.y - self.frame.y,
-                        state.frame_time,
-                    ),
-                    0.3,
-                    (200, 200, 200),
-                    batch,
-                );
+                for light in lights.bullet(bullet.btype) {
+                    if light.matches(bullet.anim_num, bullet.action_num, bullet.direction) {
+                        let pos = (bullet.prev_x, bullet.prev_y, bullet.x, bullet.y);
+                        let seed = (bullet.anim_num, bullet.anim_num);
+                        self.draw_light_source(light, pos, seed, state.tile_size, frame_time, batch);
+                    }
+                }
             }
 
             for caret in state.carets.iter() {
-                match caret.ctype {
-                    CaretType::ProjectileDissipation | CaretType::Shoot => {
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                caret.prev_x - self.frame.prev_x,
-                                caret.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                caret.prev_y - self.frame.prev_y,
-                                caret.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            0.5,
-                            (150, 150, 150),
-                            batch,
-                        );
+                let Some(caret_lights) = lights.carets.get(&(caret.ctype as u16)) else {
+                    continue;
+                };
+
+                for light in caret_lights {
+                    if light.matches(caret.anim_num, 0, caret.direction) {
+                        let pos = (caret.prev_x, caret.prev_y, caret.x, caret.y);
+                        let seed = (caret.anim_num, caret.anim_num);
+                        self.draw_light_source(light, pos, seed, state.tile_size, frame_time, batch);
+                    }
+                }
+            }
+
+            if !lights.tiles.is_empty() {
+                let tile_size = state.tile_size.as_int() * 0x200;
+                let left = (self.frame.x - 128 * 0x200) / tile_size;
+                let top = (self.frame.y - 128 * 0x200) / tile_size;
+                let right = (self.frame.x + (state.canvas_size.0 as i32 + 128) * 0x200) / tile_size + 1;
+                let bottom = (self.frame.y + (state.canvas_size.1 as i32 + 128) * 0x200) / tile_size + 1;
+                let seed = (self.tick / 8) as u16;
+                // tiles have no animation of their own, they cycle through 4 frames instead
+                let anim_num = seed & 3;
+
+                for y in top.max(0)..bottom.min(self.stage.map.height as i32) {
+                    for x in left.max(0)..right.min(self.stage.map.width as i32) {
+                        let attrib = self.stage.map.get_attribute(x as usize, y as usize);
+                        let Some(tile_lights) = lights.tiles.get(&attrib) else {
+                            continue;
+                        };
+
+                        let pos = (x * tile_size, y * tile_size, x * tile_size, y * tile_size);
+                        let seed = seed.wrapping_add((x + y) as u16);
+                        for light in tile_lights.iter().filter(|light| light.matches_tile(anim_num)) {
+                            self.draw_light_source(light, pos, (seed, anim_num), state.tile_size, frame_time, batch);
+                        }
                     }
-                    _ => {}
                 }
             }
 
@@ -643,359 +715,19 @@ impl GameScene {
                     continue;
                 }
 
-                // NPC lighting
-                match npc.npc_type {
-                    1 => {
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            0.33,
-                            (255, 255, 50),
-                            batch,
-                        );
-                    }
-                    4 if npc.direction == Direction::Up => self.draw_light(
-                        interpolate_fix9_scale(npc.prev_x - self.frame.prev_x, npc.x - self.frame.x, state.frame_time),
-                        interpolate_fix9_scale(npc.prev_y - self.frame.prev_y, npc.y - self.frame.y, state.frame_time),
-                        1.0,
-                        (200, 100, 0),
-                        batch,
-                    ),
-                    7 => self.draw_light(
-                        interpolate_fix9_scale(npc.prev_x - self.frame.prev_x, npc.x - self.frame.x, state.frame_time),
-                        interpolate_fix9_scale(npc.prev_y - self.frame.prev_y, npc.y - self.frame.y, state.frame_time),
-                        1.0,
-                        (100, 100, 100),
-                        batch,
-                    ),
-                    17 if npc.anim_num == 0 => {
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            1.25,
-                            (100, 0, 0),
-                            batch,
-                        );
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            0.5,
-                            (255, 10, 10),
-                            batch,
-                        );
-                    }
-                    20 if npc.direction == Direction::Right => {
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            1.5,
-                            (30, 30, 130),
-                            batch,
-                        );
-
-                        if npc.anim_num < 2 {
-                            self.draw_light(
-                                interpolate_fix9_scale(
-                                    npc.prev_x - self.frame.prev_x,
-                                    npc.x - self.frame.x,
-                                    state.frame_time,
-                                ),
-                                interpolate_fix9_scale(
-                                    npc.prev_y - self.frame.prev_y,
-                                    npc.y - self.frame.y,
-                                    state.frame_time,
-                                ),
-                                1.0,
-                                (0, 0, 20),
-                                batch,
-                            );
-                        }
-                    }
-                    22 if npc.action_num == 1 && npc.anim_num == 1 => self.draw_light(
-                        interpolate_fix9_scale(npc.prev_x - self.frame.prev_x, npc.x - self.frame.x, state.frame_time),
-                        interpolate_fix9_scale(npc.prev_y - self.frame.prev_y, npc.y - self.frame.y, state.frame_time),
-                        3.0,
-                        (0, 0, 255),
-                        batch,
-                    ),
-                    32 | 87 => {
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            0.75,
-                            (255, 30, 30),
-                            batch,
-                        );
-                    }
-                    211 => {
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            1.0,
-                            (90, 0, 0),
-                            batch,
-                        );
-                    }
-                    27 => {
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ) + 0.5,
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            3.0,
-                            (96, 0, 0),
-                            batch,
-                        );
-                    }
-                    38 => {
-                        let flicker = ((npc.anim_num.wrapping_add(npc.id) ^ 5) & 3) as u8 * 24;
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            3.5,
-                            (150 + flicker, 60 + flicker, 0),
-                            batch,
-                        );
-                    }
-                    69 | 81 => {
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            if npc.npc_type == 69 { 0.5 } else { 1.0 },
-                            (200, 200, 200),
-                            batch,
-                        );
-                    }
-                    70 => {
-                        let flicker = 50 + npc.anim_num as u8 * 15;
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            2.0,
-                            (flicker, flicker, flicker),
-                            batch,
-                        );
-                    }
-                    85 if npc.action_num == 1 => {
-                        let (color, color2) = if npc.direction == Direction::Left {
-                            if state.constants.is_cs_plus {
-                                ((20, 100, 20), (20, 50, 20))
-                            } else {
-                                ((20, 20, 100), (20, 20, 50))
-                            }
-                        } else {
-                            ((150, 0, 0), (50, 0, 0))
-                        };
-
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            0.75,
-                            color,
-                            batch,
-                        );
+                if let Some(npc_lights) = lights.npcs.get(&npc.npc_type) {
+                    let seed = (npc.anim_num.wrapping_add(npc.id), npc.anim_num);
 
-                        if npc.anim_num < 2 && npc.direction == Direction::Right {
-                            self.draw_light(
-                                interpolate_fix9_scale(
-                                    npc.prev_x - self.frame.prev_x,
-                                    npc.x - self.frame.x,
-                                    state.frame_time,
-                                ),
-                                interpolate_fix9_scale(
-                                    npc.prev_y - self.frame.prev_y,
-                                    npc.y - self.frame.y,
-                                    state.frame_time,
-                                ) - 8.0,
-                                2.1,
-                                color2,
-                                batch,
-                            );
+                    for light in npc_lights {
+                        if light.matches(npc.anim_num, npc.action_num, npc.direction) {
+                            let pos = (npc.prev_x, npc.prev_y, npc.x, npc.y);
+                            self.draw_light_source(light, pos, seed, state.tile_size, frame_time, batch);
                         }
                     }
-                    101 | 102 => self.draw_light(
-                        interpolate_fix9_scale(npc.prev_x - self.frame.prev_x, npc.x - self.frame.x, state.frame_time),
-                        interpolate_fix9_scale(npc.prev_y - self.frame.prev_y, npc.y - self.frame.y, state.frame_time),
-                        1.0,
-                        (100, 100, 200),
-                        batch,
-                    ),
-                    175 if npc.action_num < 10 => {
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            1.0,
-                            (128, 175, 200),
-                            batch,
-                        );
-                    }
-                    189 => self.draw_light(
-                        interpolate_fix9_scale(npc.prev_x - self.frame.prev_x, npc.x - self.frame.x, state.frame_time),
-                        interpolate_fix9_scale(npc.prev_y - self.frame.prev_y, npc.y - self.frame.y, state.frame_time),
-                        1.0,
-                        (10, 50, 255),
-                        batch,
-                    ),
-                    270 => self.draw_light(
-                        interpolate_fix9_scale(npc.prev_x - self.frame.prev_x, npc.x - self.frame.x, state.frame_time),
-                        interpolate_fix9_scale(npc.prev_y - self.frame.prev_y, npc.y - self.frame.y, state.frame_time),
-                        0.4,
-                        (192, 0, 0),
-                        batch,
-                    ),
-                    285 | 287 => self.draw_light(
-                        interpolate_fix9_scale(npc.prev_x - self.frame.prev_x, npc.x - self.frame.x, state.frame_time),
-                        interpolate_fix9_scale(npc.prev_y - self.frame.prev_y, npc.y - self.frame.y, state.frame_time),
-                        1.0,
-                        (150, 90, 0),
-                        batch,
-                    ),
-                    293 => self.draw_light(
-                        interpolate_fix9_scale(npc.prev_x - self.frame.prev_x, npc.x - self.frame.x, state.frame_time),
-                        interpolate_fix9_scale(npc.prev_y - self.frame.prev_y, npc.y - self.frame.y, state.frame_time),
-                        4.0,
-                        (255, 255, 255),
-                        batch,
-                    ),
-                    311 => {
-                        let size = if npc.anim_num % 7 == 2 || npc.anim_num % 7 == 5 { 1.0 } else { 0.0 };
-
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            size,
-                            (255, 255, 255),
-                            batch,
-                        )
-                    }
-                    312 => self.draw_light(
-                        interpolate_fix9_scale(npc.prev_x - self.frame.prev_x, npc.x - self.frame.x, state.frame_time),
-                        interpolate_fix9_scale(npc.prev_y - self.frame.prev_y, npc.y - self.frame.y, state.frame_time),
-                        0.5,
-                        (255, 255, 255),
-                        batch,
-                    ),
-                    319 => {
-                        let color = if npc.anim_num == 2 { (255, 29, 0) } else { (234, 157, 68) };
+                }
 
-                        self.draw_light(
-                            interpolate_fix9_scale(
-                                npc.prev_x - self.frame.prev_x,
-                                npc.x - self.frame.x,
-                                state.frame_time,
-                            ),
-                            interpolate_fix9_scale(
-                                npc.prev_y - self.frame.prev_y,
-                                npc.y - self.frame.y,
-                                state.frame_time,
-                            ),
-                            1.0,
-                            color,
-                            batch,
-                        )
-                    }
+                // lights depending on more than the animation or action state
+                match npc.npc_type {
                     180 => {
                         if state.settings.light_cone {
                             // Curly's looking upward frames
@@ -1022,6 +754,25 @@ impl GameScene {
                             );
                         }
                     }
+                    311 => {
+                        let size = if npc.anim_num % 7 == 2 || npc.anim_num % 7 == 5 { 1.0 } else { 0.0 };
+
+                        self.draw_light(
+                            interpolate_fix9_scale(
+                                npc.prev_x - self.frame.prev_x,
+                                npc.x - self.frame.x,
+                                state.frame_time,
+                            ),
+                            interpolate_fix9_scale(
+                                npc.prev_y - self.frame.prev_y,
+                                npc.y - self.frame.y,
+                                state.frame_time,
+                            ),
+                            size,
+                            (255, 255, 255),
+                            batch,
+                        )
+                    }
                     320 => {
                         if state.settings.light_cone {
                             let range = match npc.direction() {
@@ -1166,6 +917,13 @@ impl GameScene {
                 if npc.npc_flags.shootable() {
                     npc.life = (npc.life as i32).saturating_sub(bullet.damage as i32).clamp(0, u16::MAX as i32) as u16;
 
+                    #[cfg(feature = "scripting")]
+                    if let Err(err) =
+                        npc.hit_scripted(state, [&self.player1, &self.player2], &self.npc_list, bullet.damage as i32)
+                    {
+                        log::warn!("NPC hit handler failed: {}", err);
+                    }
+
                     if npc.life == 0 {
                         if npc.npc_flags.show_damage() {
                             npc.popup.add_value(-bullet.damage);
@@ -1181,7 +939,7 @@ impl GameScene {
                     } else {
                         if npc.shock < 14 {
                             if let Some(table_entry) = state.npc_table.get_entry(npc.npc_type) {
-                                state.sound_manager.play_sfx(table_entry.hurt_sound);
+                                state.sound_manager.play_sfx_at(table_entry.hurt_sound, npc.x, npc.y);
                             }
 
                             npc.shock = 16;
@@ -1277,7 +1035,7 @@ impl GameScene {
                             state.control_flags.set_interactions_disabled(true);
                             state.textscript_vm.start_script(npc.event_num);
                         } else {
-                            state.sound_manager.play_sfx(self.boss.death_sound[idx]);
+                            state.sound_manager.play_sfx_at(self.boss.death_sound[idx], npc.x, npc.y);
 
                             let destroy_count = 4usize * (2usize).pow((npc.size as u32).saturating_sub(1));
 
@@ -1296,7 +1054,7 @@ impl GameScene {
                             for _ in 0..3 {
                                 state.create_caret(bullet.x, bullet.y, CaretType::HurtParticles, Direction::Left);
                             }
-                            state.sound_manager.play_sfx(self.boss.hurt_sound[idx]);
+                            state.sound_manager.play_sfx_at(self.boss.hurt_sound[idx], npc.x, npc.y);
                         }
 
                         npc.shock = 8;
@@ -1334,14 +1092,14 @@ impl GameScene {
 
         self.player1.current_weapon = {
             if let Some(weapon) = self.inventory_player1.get_current_weapon_mut() {
-                weapon.wtype as u8
+                weapon.wtype.arms_sprite(&state.constants)
             } else {
                 0
             }
         };
         self.player2.current_weapon = {
             if let Some(weapon) = self.inventory_player2.get_current_weapon_mut() {
-                weapon.wtype as u8
+                weapon.wtype.arms_sprite(&state.constants)
             } else {
                 0
             }
@@ -1350,6 +1108,16 @@ impl GameScene {
         self.player2.tick(state, &self.npc_list)?;
         state.textscript_vm.reset_invicibility = false;
 
+        let sfx_listener = if state.settings.positional_sfx {
+            let half_width = (state.canvas_size.0 * 256.0) as i32;
+            let half_height = (state.canvas_size.1 * 256.0) as i32;
+
+            Some(SfxListener { x: self.frame.x + half_width, y: self.frame.y + half_height, half_width, half_height })
+        } else {
+            None
+        };
+        state.sound_manager.set_sfx_listener(sfx_listener);
+
         self.whimsical_star.tick(state, (&self.player1, &mut self.bullet_manager))?;
 
         if self.player1.damage > 0 {
@@ -1476,10 +1244,14 @@ impl GameScene {
                 }
 
                 if self.player2.cond.alive() && !self.player2.cond.hidden() {
-                    if self.player2.x + 0x1000 < self.frame.x
-                        || self.player2.x - 0x1000 > self.frame.x + state.canvas_size.0 as i32 * 0x200
-                        || self.player2.y + 0x1000 < self.frame.y
-                        || self.player2.y - 0x1000 > self.frame.y + state.canvas_size.1 as i32 * 0x200
+                    let view_x = self.frame.x + (state.viewport_offset.0 * 512.0) as i32;
+                    let view_y = self.frame.y + (state.viewport_offset.1 * 512.0) as i32;
+                    let (view_width, view_height) = state.view_size();
+
+                    if self.player2.x + 0x1000 < view_x
+                        || self.player2.x - 0x1000 > view_x + view_width as i32 * 0x200
+                        || self.player2.y + 0x1000 < view_y
+                        || self.player2.y - 0x1000 > view_y + view_height as i32 * 0x200
                     {
                         self.player2.update_teleport_counter(state);
 
@@ -1669,25 +1441,7 @@ impl Scene for GameScene {
         self.player1.controller = state.settings.create_player1_controller();
         self.player2.controller = state.settings.create_player2_controller();
 
-        let npcs = self.stage.load_npcs(&state.constants.base_paths, ctx)?;
-        for npc_data in npcs.iter() {
-            log::info!("creating npc: {:?}", npc_data);
-
-            let mut npc = NPC::create_from_data(npc_data, &state.npc_table, state.tile_size);
-            if npc.npc_flags.appear_when_flag_set() {
-                if state.get_flag(npc_data.flag_num as _) {
-                    npc.cond.set_alive(true);
-                }
-            } else if npc.npc_flags.hide_unless_flag_set() {
-                if !state.get_flag(npc_data.flag_num as _) {
-                    npc.cond.set_alive(true);
-                }
-            } else {
-                npc.cond.set_alive(true);
-            }
-
-            self.npc_list.spawn_at_slot(npc_data.id, npc)?;
-        }
+        self.spawn_stage_npcs(state, ctx)?;
 
         state.npc_table.stage_textures = self.stage_textures.clone();
 
@@ -1711,6 +1465,7 @@ impl Scene for GameScene {
 
         self.lighting_mode = match () {
             _ if self.intro_mode => LightingMode::None,
+            _ if self.stage.data.ambient_light.is_some() => LightingMode::Ambient,
             _ if !state.constants.is_switch
                 && (self.stage.data.background_type == BackgroundType::Black
                     || self.stage.data.background.name() == "bkBlack") =>
@@ -1804,6 +1559,10 @@ impl Scene for GameScene {
             return Ok(());
         }
 
+        if !self.intro_mode {
+            state.play_time = state.play_time.saturating_add(1);
+        }
+
         if state.replay_state == ReplayState::Recording {
             self.replay.tick(state, (ctx, &mut self.player1))?;
         }
@@ -2050,8 +1809,15 @@ impl Scene for GameScene {
                     if self.player2.teleport_counter < state.settings.timing_mode.get_tps() as u16 * 3
                         || self.player2.teleport_counter % 5 != 0
                     {
-                        if self.player2.y + 0x1000 < self.frame.y {
-                            let scale = 1.0 + (self.frame.y as f32 / self.player2.y as f32 / 2.0 - 0.5).clamp(0.0, 2.0);
+                        // the marker is kept within the viewport, out of the black bars around it
+                        let view_x = self.frame.x + (state.viewport_offset.0 * 512.0) as i32;
+                        let view_y = self.frame.y + (state.viewport_offset.1 * 512.0) as i32;
+                        let (view_width, view_height) = state.view_size();
+                        let (left, top, right, bottom) = state.viewport_insets(ctx);
+                        let (canvas_width, canvas_height) = state.canvas_size;
+
+                        if self.player2.y + 0x1000 < view_y {
+                            let scale = 1.0 + (view_y as f32 / self.player2.y as f32 / 2.0 - 0.5).clamp(0.0, 2.0);
 
                             let x = interpolate_fix9_scale(
                                 self.player2.prev_x - self.frame.prev_x,
@@ -2059,21 +1825,20 @@ impl Scene for GameScene {
                                 state.frame_time,
                             );
 
-                            let x = x.clamp(8.0, state.canvas_size.0 - 8.0 * scale - state.font.line_height());
+                            let x = x.clamp(left + 8.0, canvas_width - right - 8.0 * scale - state.font.line_height());
 
                             state
                                 .font
                                 .builder()
-                                .position(x, 8.0)
+                                .position(x, top + 8.0)
                                 .scale(scale)
                                 .shadow_color((0, 0, 130, 255))
                                 .color((96, 96, 255, 255))
                                 .shadow(true)
                                 .draw(P2_OFFSCREEN_TEXT, ctx, &state.constants, &mut state.texture_set)?;
-                        } else if self.player2.y - 0x1000 > self.frame.y + state.canvas_size.1 as i32 * 0x200 {
+                        } else if self.player2.y - 0x1000 > view_y + view_height as i32 * 0x200 {
                             let scale = 1.0
-                                + (self.player2.y as f32 / (self.frame.y as f32 + state.canvas_size.1 * 0x200 as f32)
-                                    - 0.5)
+                                + (self.player2.y as f32 / (view_y as f32 + view_height * 0x200 as f32) - 0.5)
                                     .clamp(0.0, 2.0);
 
                             let x = interpolate_fix9_scale(
@@ -2082,40 +1847,39 @@ impl Scene for GameScene {
                                 state.frame_time,
                             );
 
-                            let x = x.clamp(8.0, state.canvas_size.0 - 8.0 * scale - state.font.line_height());
+                            let x = x.clamp(left + 8.0, canvas_width - right - 8.0 * scale - state.font.line_height());
 
                             state
                                 .font
                                 .builder()
-                                .position(x, state.canvas_size.1 - 8.0 * scale - state.font.line_height())
+                                .position(x, canvas_height - bottom - 8.0 * scale - state.font.line_height())
                                 .scale(scale)
                                 .shadow_color((0, 0, 130, 255))
                                 .color((96, 96, 255, 255))
                                 .shadow(true)
                                 .draw(P2_OFFSCREEN_TEXT, ctx, &state.constants, &mut state.texture_set)?;
-                        } else if self.player2.x + 0x1000 < self.frame.x {
-                            let scale = 1.0 + (self.frame.x as f32 / self.player2.x as f32 / 2.0 - 0.5).clamp(0.0, 2.0);
+                        } else if self.player2.x + 0x1000 < view_x {
+                            let scale = 1.0 + (view_x as f32 / self.player2.x as f32 / 2.0 - 0.5).clamp(0.0, 2.0);
 
                             let y = interpolate_fix9_scale(
                                 self.player2.prev_y - self.frame.prev_y,
                                 self.player2.y - self.frame.y,
                                 state.frame_time,
                             );
-                            let y = y.clamp(8.0, state.canvas_size.1 - 8.0 * scale - state.font.line_height());
+                            let y = y.clamp(top + 8.0, canvas_height - bottom - 8.0 * scale - state.font.line_height());
 
                             state
                                 .font
                                 .builder()
-                                .position(8.0, y)
+                                .position(left + 8.0, y)
                                 .scale(scale)
                                 .shadow_color((0, 0, 130, 255))
                                 .color((96, 96, 255, 255))
                                 .shadow(true)
                                 .draw(P2_OFFSCREEN_TEXT, ctx, &state.constants, &mut state.texture_set)?;
-                        } else if self.player2.x - 0x1000 > self.frame.x + state.canvas_size.0 as i32 * 0x200 {
+                        } else if self.player2.x - 0x1000 > view_x + view_width as i32 * 0x200 {
                             let scale = 1.0
-                                + (self.player2.x as f32 / (self.frame.x as f32 + state.canvas_size.0 * 0x200 as f32)
-                                    - 0.5)
+                                + (self.player2.x as f32 / (view_x as f32 + view_width * 0x200 as f32) - 0.5)
                                     .clamp(0.0, 2.0);
 
                             let y = interpolate_fix9_scale(
@@ -2123,7 +1887,7 @@ impl Scene for GameScene {
                                 self.player2.y - self.frame.y,
                                 state.frame_time,
                             );
-                            let y = y.clamp(8.0, state.canvas_size.1 - 8.0 * scale - state.font.line_height());
+                            let y = y.clamp(top + 8.0, canvas_height - bottom - 8.0 * scale - state.font.line_height());
 
                             let width = state.font.builder().compute_width(P2_OFFSCREEN_TEXT);
 
@@ -2133,7 +1897,7 @@ impl Scene for GameScene {
                                 .shadow_color((0, 0, 130, 255))
                                 .color((96, 96, 255, 255))
                                 .shadow(true)
-                                .position(state.canvas_size.0 - width - 8.0 * scale, y)
+                                .position(canvas_width - right - width - 8.0 * scale, y)
                                 .scale(scale)
                                 .draw(P2_OFFSCREEN_TEXT, ctx, &state.constants, &mut state.texture_set)?;
                         }
@@ -2316,6 +2080,18 @@ impl Scene for GameScene {
             return Ok(());
         }
 
+        if key_code == ScanCode::F4 && ctx.keyboard_context.active_mods().ctrl() {
+            if state.sound_manager.is_capturing() {
+                state.sound_manager.stop_capture()?;
+            } else {
+                let _ = filesystem::user_create_dir(ctx, "/capture");
+                let path = format!("/capture/{}.wav", chrono::Local::now().format("%Y-%m-%d_%H-%M-%S"));
+                let file = filesystem::user_create(ctx, &path)?;
+                state.sound_manager.start_capture(file, &state.constants, &state.settings, ctx, true)?;
+            }
+            return Ok(());
+        }
+
         if key_code == ScanCode::S && ctx.keyboard_context.active_mods().ctrl() {
             let _ = state.save_game(self, ctx, None);
             state.sound_manager.play_sfx(18);
@@ -14,6 +14,7 @@ use crate::game::shared_game_state::{SharedGameState, TileSize};
 use crate::game::stage::{BackgroundType, NpcType, Stage, StageData, StageTexturePaths, Tileset};
 use crate::graphics::font::Font;
 use crate::input::combined_menu_controller::CombinedMenuController;
+use crate::scene::organya_scene::OrganyaScene;
 use crate::scene::title_scene::TitleScene;
 use crate::scene::Scene;
 
@@ -66,7 +67,14 @@ pub struct JukeboxScene {
 impl JukeboxScene {
     pub fn new() -> JukeboxScene {
         let fake_stage = Stage {
-            map: Map { width: 0, height: 0, tiles: vec![], attrib: [0; 0x100], tile_size: TileSize::Tile16x16 },
+            map: Map {
+                width: 0,
+                height: 0,
+                tiles: vec![],
+                attrib: [0; 0x100],
+                tile_size: TileSize::Tile16x16,
+                layers: Vec::new(),
+            },
             data: StageData {
                 name: String::new(),
                 name_jp: String::new(),
@@ -79,6 +87,8 @@ impl JukeboxScene {
                 background_color: Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 },
                 npc1: NpcType::new("0"),
                 npc2: NpcType::new("0"),
+                ambient_light: None,
+                camera_bounds: None,
             },
         };
 
@@ -191,6 +201,19 @@ impl Scene for JukeboxScene {
             state.sound_manager.play_song(song_id, &state.constants, &state.settings, ctx, false)?;
         }
 
+        if self.controller.trigger_inventory() {
+            let song_id = state
+                .constants
+                .music_table
+                .iter()
+                .position(|song_comp| song_comp == &self.song_list[song as usize])
+                .unwrap_or(0);
+
+            state.settings.pause_on_focus_loss = self.previous_pause_on_focus_loss_setting;
+            state.next_scene = Some(Box::new(OrganyaScene::new(song_id)));
+            return Ok(());
+        }
+
         if self.controller.trigger_shift_left() {
             self.selected_soundtrack = self.selected_soundtrack.checked_sub(1).unwrap_or(self.soundtracks.len() - 1);
             state.settings.soundtrack = self.soundtracks[self.selected_soundtrack].to_id();
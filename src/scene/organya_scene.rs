@@ -0,0 +1,524 @@
+use crate::common::{Color, Rect};
+use crate::framework::context::Context;
+use crate::framework::error::GameResult;
+use crate::framework::filesystem;
+use crate::framework::graphics;
+use crate::game::shared_game_state::SharedGameState;
+use crate::input::combined_menu_controller::CombinedMenuController;
+use crate::scene::jukebox_scene::JukeboxScene;
+use crate::scene::Scene;
+use crate::sound::organya::{Note, Song};
+
+const KEY_COUNT: u8 = 96;
+const KEY_HEIGHT: f32 = 4.0;
+const STEP_WIDTH: f32 = 4.0;
+const ROLL_LEFT: f32 = 8.0;
+const ROLL_TOP: f32 = 36.0;
+const ROLL_BOTTOM_MARGIN: f32 = 40.0;
+
+const TRACK_COLORS: [(u8, u8, u8); 16] = [
+    (255, 96, 96),
+    (255, 160, 64),
+    (255, 232, 64),
+    (128, 255, 96),
+    (64, 232, 192),
+    (64, 176, 255),
+    (144, 112, 255),
+    (232, 96, 255),
+    (224, 224, 224),
+    (192, 160, 128),
+    (160, 192, 128),
+    (128, 160, 192),
+    (192, 128, 160),
+    (160, 160, 96),
+    (96, 160, 160),
+    (160, 96, 160),
+];
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum TrackState {
+    Normal,
+    Muted,
+    Solo,
+}
+
+impl TrackState {
+    fn next(self) -> TrackState {
+        match self {
+            TrackState::Normal => TrackState::Muted,
+            TrackState::Muted => TrackState::Solo,
+            TrackState::Solo => TrackState::Normal,
+        }
+    }
+}
+
+/// Piano roll view of an Organya song with basic editing capabilities, opened from the jukebox.
+pub struct OrganyaScene {
+    song_id: usize,
+    song_name: String,
+    song: Option<Song>,
+    controller: CombinedMenuController,
+    track_states: [TrackState; 16],
+    selected_track: usize,
+    cursor_pos: i32,
+    cursor_key: u8,
+    note_length: u8,
+    play_pos: i32,
+    playing: bool,
+    modified: bool,
+    status: Option<(String, u16)>,
+    previous_pause_on_focus_loss_setting: bool,
+}
+
+impl OrganyaScene {
+    pub fn new(song_id: usize) -> OrganyaScene {
+        OrganyaScene {
+            song_id,
+            song_name: String::new(),
+            song: None,
+            controller: CombinedMenuController::new(),
+            track_states: [TrackState::Normal; 16],
+            selected_track: 0,
+            cursor_pos: 0,
+            cursor_key: 48,
+            note_length: 1,
+            play_pos: 0,
+            playing: false,
+            modified: false,
+            status: None,
+            previous_pause_on_focus_loss_setting: true,
+        }
+    }
+
+    fn track_mask(&self) -> u16 {
+        let any_solo = self.track_states.iter().any(|s| *s == TrackState::Solo);
+
+        self.track_states.iter().enumerate().fold(0, |mask, (i, s)| {
+            let audible = match s {
+                TrackState::Normal => !any_solo,
+                TrackState::Muted => false,
+                TrackState::Solo => true,
+            };
+
+            if audible {
+                mask | (1 << i)
+            } else {
+                mask
+            }
+        })
+    }
+
+    fn start_playback(&mut self, state: &mut SharedGameState) -> GameResult {
+        if let Some(song) = &self.song {
+            state.sound_manager.play_organya_song(self.song_id, song.clone(), &state.settings)?;
+            state.sound_manager.set_org_track_mask(self.track_mask())?;
+            state.sound_manager.set_org_position(self.cursor_pos)?;
+            self.play_pos = self.cursor_pos;
+            self.playing = true;
+        }
+
+        Ok(())
+    }
+
+    fn stop_playback(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        state.sound_manager.play_song(0, &state.constants, &state.settings, ctx, false)?;
+        state.sound_manager.set_org_track_mask(0xffff)?;
+        self.playing = false;
+
+        Ok(())
+    }
+
+    fn song_changed(&mut self, state: &mut SharedGameState) -> GameResult {
+        self.modified = true;
+
+        if let (true, Some(song)) = (self.playing, &self.song) {
+            state.sound_manager.update_organya_song(song.clone())?;
+        }
+
+        Ok(())
+    }
+
+    fn toggle_note(&mut self) {
+        let (pos, key, len) = (self.cursor_pos, self.cursor_key, self.note_length);
+
+        if let Some(song) = &mut self.song {
+            let track = &mut song.tracks[self.selected_track];
+
+            if let Some(idx) = track.notes.iter().position(|n| n.pos == pos) {
+                if track.notes[idx].key == key {
+                    track.notes.remove(idx);
+                } else {
+                    track.notes[idx].key = key;
+                }
+            } else {
+                let idx = track.notes.iter().position(|n| n.pos > pos).unwrap_or(track.notes.len());
+                track.notes.insert(idx, Note { pos, key, len, vol: 200, pan: 6 });
+            }
+
+            track.inst.notes = track.notes.len() as u16;
+        }
+    }
+
+    fn change_note_length(&mut self, delta: i16) {
+        self.note_length = (self.note_length as i16 + delta).clamp(1, 255) as u8;
+
+        let (pos, len) = (self.cursor_pos, self.note_length);
+        if let Some(song) = &mut self.song {
+            if let Some(note) = song.tracks[self.selected_track].notes.iter_mut().find(|n| n.pos == pos) {
+                note.len = len;
+            }
+        }
+    }
+
+    fn save(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let song = if let Some(song) = &self.song { song } else { return Ok(()) };
+
+        let _ = filesystem::user_create_dir(ctx, "/organya/");
+        let path = format!("/organya/{}.org", self.song_name);
+        let file = filesystem::user_create(ctx, &path)?;
+        song.write_to(file)?;
+
+        log::info!("Saved Organya song to {}", path);
+        self.modified = false;
+        self.status = Some((state.tt("jukebox.editor.saved", &[("path", path.as_str())]), 150));
+
+        Ok(())
+    }
+}
+
+impl Scene for OrganyaScene {
+    fn init(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        self.controller.add(state.settings.create_player1_controller());
+        self.controller.add(state.settings.create_player2_controller());
+
+        self.song_name = state.constants.music_table.get(self.song_id).cloned().unwrap_or_default();
+
+        if let Some(path) = state.sound_manager.find_organya_path(self.song_id, &state.constants, &state.settings, ctx)
+        {
+            match filesystem::open(ctx, &path).and_then(Song::load_from) {
+                Ok(song) => self.song = Some(song),
+                Err(err) => log::warn!("Failed to load Organya song {}: {}", path, err),
+            }
+        }
+
+        self.previous_pause_on_focus_loss_setting = state.settings.pause_on_focus_loss;
+        state.settings.pause_on_focus_loss = false;
+
+        self.start_playback(state)?;
+
+        Ok(())
+    }
+
+    fn tick(&mut self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        self.controller.update(state, ctx)?;
+        self.controller.update_trigger();
+
+        if let Some((_, timer)) = &mut self.status {
+            *timer = timer.saturating_sub(1);
+
+            if *timer == 0 {
+                self.status = None;
+            }
+        }
+
+        if self.controller.trigger_back() {
+            self.stop_playback(state, ctx)?;
+            state.settings.pause_on_focus_loss = self.previous_pause_on_focus_loss_setting;
+            state.next_scene = Some(Box::new(JukeboxScene::new()));
+            return Ok(());
+        }
+
+        if self.song.is_none() {
+            return Ok(());
+        }
+
+        if self.playing {
+            self.play_pos = state.sound_manager.org_position();
+        }
+
+        if self.controller.strafe() {
+            // hold strafe to change the tempo and the length of placed notes
+            let wait_delta = if self.controller.trigger_up() {
+                -1
+            } else if self.controller.trigger_down() {
+                1
+            } else {
+                0
+            };
+
+            if wait_delta != 0 {
+                if let Some(song) = &mut self.song {
+                    song.time.wait = (song.time.wait as i32 + wait_delta).clamp(1, 2000) as u16;
+                }
+                self.song_changed(state)?;
+            }
+
+            if self.controller.trigger_left() {
+                self.change_note_length(-1);
+                self.song_changed(state)?;
+            } else if self.controller.trigger_right() {
+                self.change_note_length(1);
+                self.song_changed(state)?;
+            }
+        } else {
+            if self.controller.trigger_up() {
+                self.cursor_key = (self.cursor_key + 1).min(KEY_COUNT - 1);
+            } else if self.controller.trigger_down() {
+                self.cursor_key = self.cursor_key.saturating_sub(1);
+            }
+
+            if self.controller.trigger_left() {
+                self.cursor_pos = (self.cursor_pos - 1).max(0);
+            } else if self.controller.trigger_right() {
+                self.cursor_pos += 1;
+            }
+        }
+
+        if self.controller.trigger_shift_left() {
+            self.selected_track = self.selected_track.checked_sub(1).unwrap_or(15);
+        }
+
+        if self.controller.trigger_shift_right() {
+            self.selected_track = (self.selected_track + 1) % 16;
+        }
+
+        if self.controller.trigger_map() {
+            self.track_states[self.selected_track] = self.track_states[self.selected_track].next();
+            state.sound_manager.set_org_track_mask(self.track_mask())?;
+        }
+
+        if self.controller.trigger_ok() {
+            self.toggle_note();
+            self.song_changed(state)?;
+        }
+
+        if self.controller.trigger_inventory() {
+            if self.playing {
+                self.stop_playback(state, ctx)?;
+            } else {
+                self.start_playback(state)?;
+            }
+        }
+
+        if self.controller.trigger_skip() {
+            if let Err(err) = self.save(state, ctx) {
+                log::error!("Failed to save Organya song: {}", err);
+                self.status = Some((state.loc.t("jukebox.editor.save_failed").to_owned(), 150));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw(&self, state: &mut SharedGameState, ctx: &mut Context) -> GameResult {
+        let scale = state.scale;
+        let scaled = |x: f32, y: f32, w: f32, h: f32| {
+            Rect::new_size(
+                (x * scale) as isize,
+                (y * scale) as isize,
+                (w * scale).max(1.0) as isize,
+                (h * scale).max(1.0) as isize,
+            )
+        };
+
+        graphics::draw_rect(
+            ctx,
+            scaled(0.0, 0.0, state.canvas_size.0, state.canvas_size.1),
+            Color::from_rgb(0, 0, 32),
+        )?;
+
+        let song = if let Some(song) = &self.song {
+            song
+        } else {
+            state.font.builder().center(state.canvas_size.0).y(state.canvas_size.1 / 2.0).shadow(true).draw(
+                state.loc.t("jukebox.editor.no_organya"),
+                ctx,
+                &state.constants,
+                &mut state.texture_set,
+            )?;
+
+            return Ok(());
+        };
+
+        let roll_width = state.canvas_size.0 - ROLL_LEFT * 2.0;
+        let roll_height = state.canvas_size.1 - ROLL_TOP - ROLL_BOTTOM_MARGIN;
+        let visible_steps = ((roll_width / STEP_WIDTH) as i32).max(1);
+        let visible_keys = ((roll_height / KEY_HEIGHT) as i32).clamp(1, KEY_COUNT as i32);
+
+        // scroll by whole pages so the view doesn't shift on every step
+        let focus = if self.playing { self.play_pos } else { self.cursor_pos };
+        let first_step = (focus / visible_steps) * visible_steps;
+        let first_key =
+            (self.cursor_key as i32 - visible_keys / 2).clamp(0, (KEY_COUNT as i32 - visible_keys).max(0)) as u8;
+
+        let step_x = |pos: i32| ROLL_LEFT + (pos - first_step) as f32 * STEP_WIDTH;
+        let key_y = |key: u8| ROLL_TOP + roll_height - (key as i32 - first_key as i32 + 1) as f32 * KEY_HEIGHT;
+
+        for row in 0..visible_keys {
+            let key = first_key + row as u8;
+            let color = match key % 12 {
+                1 | 3 | 6 | 8 | 10 => Color::from_rgb(16, 16, 40),
+                _ => Color::from_rgb(28, 28, 56),
+            };
+
+            graphics::draw_rect(ctx, scaled(ROLL_LEFT, key_y(key), roll_width, KEY_HEIGHT), color)?;
+        }
+
+        let steps_per_beat = song.display.steps.max(1) as i32;
+        let steps_per_measure = steps_per_beat * song.display.beats.max(1) as i32;
+
+        for pos in first_step..first_step + visible_steps {
+            let color = if pos % steps_per_measure == 0 {
+                Color::from_rgb(96, 96, 128)
+            } else if pos % steps_per_beat == 0 {
+                Color::from_rgb(56, 56, 88)
+            } else {
+                continue;
+            };
+
+            graphics::draw_rect(ctx, scaled(step_x(pos), ROLL_TOP, 1.0 / scale, roll_height), color)?;
+        }
+
+        for pos in [song.time.loop_range.start, song.time.loop_range.end] {
+            if pos >= first_step && pos < first_step + visible_steps {
+                graphics::draw_rect(
+                    ctx,
+                    scaled(step_x(pos), ROLL_TOP, 1.0, roll_height),
+                    Color::from_rgb(96, 255, 96),
+                )?;
+            }
+        }
+
+        // draw the selected track last so it's on top of others
+        let track_order = (0..16).filter(|&t| t != self.selected_track).chain(std::iter::once(self.selected_track));
+
+        for track_id in track_order {
+            let track = &song.tracks[track_id];
+            let (r, g, b) = TRACK_COLORS[track_id];
+            let alpha = if track_id == self.selected_track {
+                255
+            } else if self.track_mask() & (1 << track_id) == 0 {
+                32
+            } else {
+                96
+            };
+            let color = Color::from_rgba(r, g, b, alpha);
+
+            for note in track.notes.iter() {
+                let len = if track_id >= 8 { 1 } else { note.len.max(1) as i32 };
+
+                if note.key == 255
+                    || note.key < first_key
+                    || note.key as i32 >= first_key as i32 + visible_keys
+                    || note.pos + len <= first_step
+                    || note.pos >= first_step + visible_steps
+                {
+                    continue;
+                }
+
+                let start = note.pos.max(first_step);
+                let end = (note.pos + len).min(first_step + visible_steps);
+
+                graphics::draw_rect(
+                    ctx,
+                    scaled(step_x(start), key_y(note.key), (end - start) as f32 * STEP_WIDTH - 1.0, KEY_HEIGHT - 1.0),
+                    color,
+                )?;
+            }
+        }
+
+        if self.cursor_pos >= first_step && self.cursor_pos < first_step + visible_steps {
+            graphics::draw_outline_rect(
+                ctx,
+                scaled(
+                    step_x(self.cursor_pos),
+                    key_y(self.cursor_key),
+                    STEP_WIDTH * self.note_length as f32,
+                    KEY_HEIGHT,
+                ),
+                1,
+                Color::from_rgb(255, 255, 255),
+            )?;
+        }
+
+        if self.playing && self.play_pos >= first_step && self.play_pos < first_step + visible_steps {
+            graphics::draw_rect(
+                ctx,
+                scaled(step_x(self.play_pos), ROLL_TOP, 1.0, roll_height),
+                Color::from_rgb(255, 64, 64),
+            )?;
+        }
+
+        // track selector
+        let track_box = 10.0;
+        let tracks_x = (state.canvas_size.0 - track_box * 16.0) / 2.0;
+        let tracks_y = state.canvas_size.1 - ROLL_BOTTOM_MARGIN + 8.0;
+
+        for (track_id, track_state) in self.track_states.iter().enumerate() {
+            let (r, g, b) = TRACK_COLORS[track_id];
+            let x = tracks_x + track_id as f32 * track_box;
+            let color = match track_state {
+                TrackState::Normal => Color::from_rgb(r, g, b),
+                TrackState::Muted => Color::from_rgb(r / 4, g / 4, b / 4),
+                TrackState::Solo => Color::from_rgb(255, 255, 255),
+            };
+
+            graphics::draw_rect(ctx, scaled(x + 1.0, tracks_y + 1.0, track_box - 2.0, track_box - 2.0), color)?;
+
+            if track_id == self.selected_track {
+                graphics::draw_outline_rect(
+                    ctx,
+                    scaled(x, tracks_y, track_box, track_box),
+                    1,
+                    Color::from_rgb(255, 255, 255),
+                )?;
+            }
+        }
+
+        state.font.builder().center(state.canvas_size.0).y(8.0).shadow(true).draw(
+            &format!("{}{}", self.song_name, if self.modified { "*" } else { "" }),
+            ctx,
+            &state.constants,
+            &mut state.texture_set,
+        )?;
+
+        let track_state = match self.track_states[self.selected_track] {
+            TrackState::Normal => "",
+            TrackState::Muted => state.loc.t("jukebox.editor.muted"),
+            TrackState::Solo => state.loc.t("jukebox.editor.solo"),
+        };
+        let info = format!(
+            "{} {} {}",
+            state.tt("jukebox.editor.track", &[("track", (self.selected_track + 1).to_string().as_str())]),
+            track_state,
+            state.tt("jukebox.editor.tempo", &[("wait", song.time.wait.to_string().as_str())]),
+        );
+
+        state.font.builder().position(ROLL_LEFT, 20.0).shadow(true).draw(
+            &info,
+            ctx,
+            &state.constants,
+            &mut state.texture_set,
+        )?;
+
+        let position = format!("{}:{}", self.cursor_pos, self.cursor_key);
+        let position_width = state.font.builder().compute_width(&position);
+        state.font.builder().position(state.canvas_size.0 - ROLL_LEFT - position_width, 20.0).shadow(true).draw(
+            &position,
+            ctx,
+            &state.constants,
+            &mut state.texture_set,
+        )?;
+
+        if let Some((text, _)) = &self.status {
+            state.font.builder().center(state.canvas_size.0).y(state.canvas_size.1 - 18.0).shadow(true).draw(
+                text,
+                ctx,
+                &state.constants,
+                &mut state.texture_set,
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -97,7 +97,14 @@ pub struct TitleScene {
 impl TitleScene {
     pub fn new() -> Self {
         let fake_stage = Stage {
-            map: Map { width: 0, height: 0, tiles: vec![], attrib: [0; 0x100], tile_size: TileSize::Tile16x16 },
+            map: Map {
+                width: 0,
+                height: 0,
+                tiles: vec![],
+                attrib: [0; 0x100],
+                tile_size: TileSize::Tile16x16,
+                layers: Vec::new(),
+            },
             data: StageData {
                 name: String::new(),
                 name_jp: String::new(),
@@ -110,6 +117,8 @@ impl TitleScene {
                 background_color: Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 },
                 npc1: NpcType::new("0"),
                 npc2: NpcType::new("0"),
+                ambient_light: None,
+                camera_bounds: None,
             },
         };
         let mut textures = StageTexturePaths::new();
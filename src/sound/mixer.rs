@@ -0,0 +1,361 @@
+use std::sync::mpsc::Receiver;
+
+use num_traits::clamp;
+
+#[cfg(feature = "ogg-playback")]
+use crate::sound::ogg_playback::{OggPlaybackEngine, SavedOggPlaybackState};
+use crate::sound::org_playback::{OrgPlaybackEngine, SavedOrganyaPlaybackState};
+use crate::sound::pixtone::PixTonePlayback;
+use crate::sound::wave_bank::SoundBank;
+use crate::sound::PlaybackMessage;
+
+#[derive(PartialEq, Eq)]
+enum PlaybackState {
+    Stopped,
+    PlayingOrg,
+    #[cfg(feature = "ogg-playback")]
+    PlayingOgg,
+}
+
+enum PlaybackStateType {
+    None,
+    Organya(SavedOrganyaPlaybackState),
+    #[cfg(feature = "ogg-playback")]
+    Ogg(SavedOggPlaybackState),
+}
+
+impl Default for PlaybackStateType {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+/// Mixes the background music and sound effects into a single stereo stream.
+/// Driven either by the audio device callback or by the game loop while capturing audio.
+pub(in crate::sound) struct Mixer {
+    bank: SoundBank,
+    sample_rate: f32,
+    state: PlaybackState,
+    saved_state: PlaybackStateType,
+    speed: f32,
+    org_engine: Box<OrgPlaybackEngine>,
+    #[cfg(feature = "ogg-playback")]
+    ogg_engine: Box<OggPlaybackEngine>,
+    pixtone: Box<PixTonePlayback>,
+    bgm_buf: Vec<u16>,
+    pxt_buf: Vec<u16>,
+    bgm_index: usize,
+    pxt_index: usize,
+    samples: usize,
+    bgm_vol: f32,
+    bgm_vol_saved: f32,
+    sfx_vol: f32,
+    bgm_fadeout: bool,
+}
+
+impl Mixer {
+    pub fn new(bank: SoundBank, sample_rate: u32) -> Mixer {
+        let sample_rate = sample_rate as f32;
+        let mut org_engine = Box::new(OrgPlaybackEngine::new());
+        #[cfg(feature = "ogg-playback")]
+        let mut ogg_engine = Box::new(OggPlaybackEngine::new());
+        let mut pixtone = Box::new(PixTonePlayback::new());
+        pixtone.create_samples();
+
+        org_engine.set_sample_rate(sample_rate as usize);
+        #[cfg(feature = "ogg-playback")]
+        {
+            org_engine.loops = usize::MAX;
+            ogg_engine.set_sample_rate(sample_rate as usize);
+        }
+
+        let buf_size = sample_rate as usize * 10 / 1000;
+        let mut pxt_buf = vec![0x8000; buf_size * 2];
+        pixtone.mix(&mut pxt_buf, sample_rate);
+
+        Mixer {
+            bank,
+            sample_rate,
+            state: PlaybackState::Stopped,
+            saved_state: PlaybackStateType::None,
+            speed: 1.0,
+            org_engine,
+            #[cfg(feature = "ogg-playback")]
+            ogg_engine,
+            pixtone,
+            bgm_buf: vec![0x8080; buf_size * 2],
+            pxt_buf,
+            bgm_index: 0,
+            pxt_index: 0,
+            samples: 0,
+            bgm_vol: 1.0,
+            bgm_vol_saved: 1.0,
+            sfx_vol: 1.0,
+            bgm_fadeout: false,
+        }
+    }
+
+    /// Handles all pending messages, also advancing the song fade out.
+    pub fn process_messages(&mut self, rx: &Receiver<PlaybackMessage>) {
+        loop {
+            if self.bgm_fadeout && self.bgm_vol > 0.0 {
+                self.bgm_vol -= 0.02;
+            }
+
+            if self.bgm_vol < 0.0 {
+                self.bgm_vol = 0.0;
+            }
+
+            match rx.try_recv() {
+                Ok(message) => self.handle_message(message),
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Returns the position of currently playing Organya song, if any.
+    pub fn org_position(&self) -> Option<i32> {
+        if self.state == PlaybackState::PlayingOrg {
+            Some(self.org_engine.get_position())
+        } else {
+            None
+        }
+    }
+
+    fn clear_bgm_buf(&mut self) {
+        for i in &mut self.bgm_buf[0..self.samples] {
+            *i = 0x8000
+        }
+    }
+
+    fn stop_fadeout(&mut self) {
+        if self.bgm_fadeout {
+            self.bgm_fadeout = false;
+            self.bgm_vol = self.bgm_vol_saved;
+        }
+    }
+
+    fn handle_message(&mut self, message: PlaybackMessage) {
+        match message {
+            PlaybackMessage::PlayOrganyaSong(song) => {
+                if self.state == PlaybackState::Stopped {
+                    self.saved_state = PlaybackStateType::None;
+                }
+
+                self.stop_fadeout();
+
+                self.org_engine.start_song(*song, &self.bank);
+
+                self.clear_bgm_buf();
+                self.samples = self.org_engine.render_to(&mut self.bgm_buf);
+                self.bgm_index = 0;
+
+                self.state = PlaybackState::PlayingOrg;
+            }
+            PlaybackMessage::UpdateOrganyaSong(song) => {
+                self.org_engine.update_song(*song, &self.bank);
+            }
+            #[cfg(feature = "ogg-playback")]
+            PlaybackMessage::PlayOggSongSinglePart(data) => {
+                if self.state == PlaybackState::Stopped {
+                    self.saved_state = PlaybackStateType::None;
+                }
+
+                self.stop_fadeout();
+
+                self.ogg_engine.start_single(data);
+
+                self.clear_bgm_buf();
+                self.samples = self.ogg_engine.render_to(&mut self.bgm_buf);
+                self.bgm_index = 0;
+
+                self.state = PlaybackState::PlayingOgg;
+            }
+            #[cfg(feature = "ogg-playback")]
+            PlaybackMessage::PlayOggSongMultiPart(data_intro, data_loop) => {
+                if self.state == PlaybackState::Stopped {
+                    self.saved_state = PlaybackStateType::None;
+                }
+
+                self.stop_fadeout();
+
+                self.ogg_engine.start_multi(data_intro, data_loop);
+
+                self.clear_bgm_buf();
+                self.samples = self.ogg_engine.render_to(&mut self.bgm_buf);
+                self.bgm_index = 0;
+
+                self.state = PlaybackState::PlayingOgg;
+            }
+            PlaybackMessage::PlaySample(id) => {
+                self.pixtone.play_sfx(id);
+            }
+            PlaybackMessage::PlaySampleAt(id, volume, pan) => {
+                self.pixtone.play_sfx_positional(id, volume, pan);
+            }
+            PlaybackMessage::LoopSample(id) => {
+                self.pixtone.loop_sfx(id);
+            }
+            PlaybackMessage::LoopSampleFreq(id, freq) => {
+                self.pixtone.loop_sfx_freq(id, freq);
+            }
+            PlaybackMessage::StopSample(id) => {
+                self.pixtone.stop_sfx(id);
+            }
+            PlaybackMessage::Stop => {
+                if self.state == PlaybackState::Stopped {
+                    self.saved_state = PlaybackStateType::None;
+                }
+
+                self.state = PlaybackState::Stopped;
+            }
+            PlaybackMessage::SetSpeed(new_speed) => {
+                assert!(new_speed > 0.0);
+                self.speed = new_speed;
+                #[cfg(feature = "ogg-playback")]
+                self.ogg_engine.set_sample_rate((self.sample_rate / new_speed) as usize);
+                self.org_engine.set_sample_rate((self.sample_rate / new_speed) as usize);
+            }
+            PlaybackMessage::SetSongVolume(new_volume) => {
+                assert!(self.bgm_vol >= 0.0);
+                if self.bgm_fadeout {
+                    self.bgm_vol_saved = new_volume;
+                } else {
+                    self.bgm_vol = new_volume;
+                }
+            }
+            PlaybackMessage::SetSampleVolume(new_volume) => {
+                assert!(self.sfx_vol >= 0.0);
+                self.sfx_vol = new_volume;
+            }
+            PlaybackMessage::FadeoutSong => {
+                self.bgm_fadeout = true;
+                self.bgm_vol_saved = self.bgm_vol;
+            }
+            PlaybackMessage::SaveState => {
+                self.saved_state = match self.state {
+                    PlaybackState::Stopped => PlaybackStateType::None,
+                    PlaybackState::PlayingOrg => PlaybackStateType::Organya(self.org_engine.get_state()),
+                    #[cfg(feature = "ogg-playback")]
+                    PlaybackState::PlayingOgg => PlaybackStateType::Ogg(self.ogg_engine.get_state()),
+                };
+            }
+            PlaybackMessage::RestoreState => {
+                let saved_state_loc = std::mem::take(&mut self.saved_state);
+
+                match saved_state_loc {
+                    PlaybackStateType::None => {
+                        self.state = PlaybackState::Stopped;
+                    }
+                    PlaybackStateType::Organya(playback_state) => {
+                        self.org_engine.set_state(playback_state, &self.bank);
+
+                        if self.state == PlaybackState::Stopped {
+                            self.org_engine.rewind();
+                        }
+
+                        self.clear_bgm_buf();
+                        self.samples = self.org_engine.render_to(&mut self.bgm_buf);
+                        self.bgm_index = 0;
+
+                        self.stop_fadeout();
+
+                        self.state = PlaybackState::PlayingOrg;
+                    }
+                    #[cfg(feature = "ogg-playback")]
+                    PlaybackStateType::Ogg(playback_state) => {
+                        self.ogg_engine.set_state(playback_state);
+
+                        if self.state == PlaybackState::Stopped {
+                            self.ogg_engine.rewind();
+                        }
+
+                        self.clear_bgm_buf();
+                        self.samples = self.ogg_engine.render_to(&mut self.bgm_buf);
+                        self.bgm_index = 0;
+
+                        self.stop_fadeout();
+
+                        self.state = PlaybackState::PlayingOgg;
+                    }
+                }
+            }
+            PlaybackMessage::SetSampleParams(id, params) => {
+                self.pixtone.set_sample_parameters(id, params);
+            }
+            PlaybackMessage::SetOrgInterpolation(interpolation) => {
+                self.org_engine.interpolation = interpolation;
+            }
+            PlaybackMessage::SetOrgPosition(position) => {
+                self.org_engine.set_position(position);
+
+                if self.state == PlaybackState::PlayingOrg {
+                    self.clear_bgm_buf();
+                    self.samples = self.org_engine.render_to(&mut self.bgm_buf);
+                    self.bgm_index = 0;
+                }
+            }
+            PlaybackMessage::SetOrgTrackMask(mask) => {
+                self.org_engine.track_mask = mask;
+            }
+            PlaybackMessage::SetSampleData(id, data) => {
+                self.pixtone.set_sample_data(id, data);
+            }
+        }
+    }
+
+    /// Mixes a single stereo frame, samples are unsigned 16-bit.
+    pub fn next_frame(&mut self) -> (u16, u16) {
+        let (bgm_sample_l, bgm_sample_r): (u16, u16) = {
+            if self.state == PlaybackState::Stopped {
+                (0x8000, 0x8000)
+            } else if self.bgm_index < self.samples {
+                let samples = (self.bgm_buf[self.bgm_index], self.bgm_buf[self.bgm_index + 1]);
+                self.bgm_index += 2;
+                samples
+            } else {
+                self.clear_bgm_buf();
+
+                match self.state {
+                    PlaybackState::PlayingOrg => {
+                        self.samples = self.org_engine.render_to(&mut self.bgm_buf);
+                    }
+                    #[cfg(feature = "ogg-playback")]
+                    PlaybackState::PlayingOgg => {
+                        self.samples = self.ogg_engine.render_to(&mut self.bgm_buf);
+                    }
+                    _ => unreachable!(),
+                }
+                self.bgm_index = 2;
+                (self.bgm_buf[0], self.bgm_buf[1])
+            }
+        };
+
+        let (pxt_sample_l, pxt_sample_r): (u16, u16) = (self.pxt_buf[self.pxt_index], self.pxt_buf[self.pxt_index + 1]);
+
+        if self.pxt_index < (self.pxt_buf.len() - 2) {
+            self.pxt_index += 2;
+        } else {
+            self.pxt_index = 0;
+            self.pxt_buf.fill(0x8000);
+            self.pixtone.mix(&mut self.pxt_buf, self.sample_rate / self.speed);
+        }
+
+        let sample_l = clamp(
+            (((bgm_sample_l ^ 0x8000) as i16) as f32 * self.bgm_vol) as isize
+                + (((pxt_sample_l ^ 0x8000) as i16) as f32 * self.sfx_vol) as isize,
+            -0x7fff,
+            0x7fff,
+        ) as u16
+            ^ 0x8000;
+        let sample_r = clamp(
+            (((bgm_sample_r ^ 0x8000) as i16) as f32 * self.bgm_vol) as isize
+                + (((pxt_sample_r ^ 0x8000) as i16) as f32 * self.sfx_vol) as isize,
+            -0x7fff,
+            0x7fff,
+        ) as u16
+            ^ 0x8000;
+
+        (sample_l, sample_r)
+    }
+}
@@ -1,13 +1,14 @@
+use std::collections::{HashMap, VecDeque};
 use std::io;
-use std::io::{BufRead, BufReader, Lines};
-use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 #[cfg(feature = "ogg-playback")]
 use lewton::inside_ogg::OggStreamReader;
-use num_traits::clamp;
 
 use crate::engine_constants::EngineConstants;
 use crate::framework::context::Context;
@@ -16,18 +17,19 @@ use crate::framework::error::{GameError, GameResult};
 use crate::framework::filesystem;
 use crate::framework::filesystem::File;
 use crate::game::settings::Settings;
-#[cfg(feature = "ogg-playback")]
-use crate::sound::ogg_playback::{OggPlaybackEngine, SavedOggPlaybackState};
-use crate::sound::org_playback::{OrgPlaybackEngine, SavedOrganyaPlaybackState};
+use crate::sound::mixer::Mixer;
 use crate::sound::organya::Song;
-use crate::sound::pixtone::{PixToneParameters, PixTonePlayback};
+use crate::sound::pixtone::PixToneParameters;
+use crate::sound::pixtone_sfx::DEFAULT_PIXTONE_TABLE;
+use crate::sound::wav::{WavFormat, WavWriter};
 use crate::sound::wave_bank::SoundBank;
 
 mod fir;
+mod mixer;
 #[cfg(feature = "ogg-playback")]
 mod ogg_playback;
 mod org_playback;
-mod organya;
+pub mod organya;
 pub mod pixtone;
 mod pixtone_sfx;
 mod stuff;
@@ -42,6 +44,106 @@ pub struct SoundManager {
     no_audio: bool,
     load_failed: bool,
     stream: Option<cpal::Stream>,
+    stream_state: Arc<StreamState>,
+    output_config: AudioOutputConfig,
+    playback_settings: PlaybackSettings,
+    next_recovery: Option<Instant>,
+    sample_rate: u32,
+    capture: Option<AudioCapture>,
+    sfx_listener: Option<SfxListener>,
+    sfx_emitter: Option<(i32, i32)>,
+    /// Sound effects of the game edition that differ from the freeware ones, restored when mods are unloaded.
+    base_sample_params: HashMap<u8, PixToneParameters>,
+}
+
+/// Selects the audio output device and stream parameters, `None` values use the defaults of the host.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AudioOutputConfig {
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+}
+
+/// State shared between the sound manager and the audio stream callbacks.
+#[derive(Default)]
+struct StreamState {
+    org_position: AtomicI32,
+    failed: AtomicBool,
+    /// While set, the game loop drives the mixer and the stream only plays back `capture_buf`.
+    capturing: AtomicBool,
+    capture_buf: Mutex<VecDeque<u16>>,
+    mixer: Mutex<Option<SharedMixer>>,
+}
+
+/// Mixer of the audio stream along with its message queue, shared so the game loop can take over
+/// mixing while audio is being captured.
+struct SharedMixer {
+    rx: Receiver<PlaybackMessage>,
+    mixer: Box<Mixer>,
+}
+
+/// Sample rate used for capturing audio when there's no output device.
+const CAPTURE_SAMPLE_RATE: u32 = 44100;
+
+/// Where the captured audio comes from.
+enum CaptureSource {
+    /// Mixer of the audio stream, the mixed frames are also played back if the game runs in real time.
+    Stream { realtime: bool },
+    /// Mixer driven by the game loop, used when there's no audio device. Keeps the sender and state
+    /// the sound manager had before the capture, so it can be restored afterwards.
+    Mixer {
+        rx: Receiver<PlaybackMessage>,
+        mixer: Box<Mixer>,
+        prev_tx: Sender<PlaybackMessage>,
+        no_audio: bool,
+        load_failed: bool,
+    },
+}
+
+struct AudioCapture {
+    writer: WavWriter<Box<dyn WriteSeek>>,
+    source: CaptureSource,
+    frame_remainder: f64,
+    frame_buf: Vec<u16>,
+}
+
+trait WriteSeek: io::Write + io::Seek {}
+
+impl<T: io::Write + io::Seek> WriteSeek for T {}
+
+/// Everything sent to the playback thread that has to survive reopening the audio stream.
+#[derive(Default)]
+struct PlaybackSettings {
+    song_volume: Option<f32>,
+    sfx_volume: Option<f32>,
+    org_interpolation: Option<InterpolationMode>,
+    sample_params: HashMap<u8, PixToneParameters>,
+    sample_data: HashMap<u8, Vec<i16>>,
+}
+
+/// Describes the area sound effects are heard from, usually the visible part of the stage.
+/// All coordinates are in world units (fix9).
+#[derive(Copy, Clone, Debug)]
+pub struct SfxListener {
+    pub x: i32,
+    pub y: i32,
+    pub half_width: i32,
+    pub half_height: i32,
+}
+
+impl SfxListener {
+    /// Returns the volume and stereo pan of a sound emitted at given position.
+    /// Sounds are played at full volume anywhere on screen and fade out within two more screens.
+    pub fn spatialize(&self, x: i32, y: i32) -> (f32, f32) {
+        let dx = (x - self.x) as f32 / self.half_width.max(1) as f32;
+        let dy = (y - self.y) as f32 / self.half_height.max(1) as f32;
+        let distance = dx.abs().max(dy.abs());
+
+        let volume = (1.0 - (distance - 1.0) / 4.0).clamp(0.0, 1.0);
+        let pan = (dx * 0.75).clamp(-1.0, 1.0);
+
+        (volume, pan)
+    }
 }
 
 enum SongFormat {
@@ -62,7 +164,7 @@ pub enum InterpolationMode {
 }
 
 impl SoundManager {
-    pub fn new(ctx: &mut Context) -> GameResult<SoundManager> {
+    pub fn new(ctx: &mut Context, output_config: AudioOutputConfig) -> GameResult<SoundManager> {
         let (tx, rx): (Sender<PlaybackMessage>, Receiver<PlaybackMessage>) = mpsc::channel();
 
         if ctx.headless {
@@ -76,17 +178,27 @@ impl SoundManager {
                 no_audio: true,
                 load_failed: false,
                 stream: None,
+                stream_state: Arc::new(StreamState::default()),
+                output_config,
+                playback_settings: PlaybackSettings::default(),
+                next_recovery: None,
+                sample_rate: 0,
+                capture: None,
+                sfx_listener: None,
+                sfx_emitter: None,
+                base_sample_params: HashMap::new(),
             });
         }
 
         let bnk = wave_bank::SoundBank::load_from(filesystem::open(ctx, "/builtin/organya-wavetable-doukutsu.bin")?)?;
-        Ok(SoundManager::bootstrap(&bnk, tx, rx)?)
+        Ok(SoundManager::bootstrap(&bnk, tx, rx, output_config)?)
     }
 
     fn bootstrap(
         soundbank: &SoundBank,
         tx: Sender<PlaybackMessage>,
         rx: Receiver<PlaybackMessage>,
+        output_config: AudioOutputConfig,
     ) -> GameResult<SoundManager> {
         let mut sound_manager = SoundManager {
             soundbank: Some(soundbank.to_owned()),
@@ -96,12 +208,21 @@ impl SoundManager {
             no_audio: false,
             load_failed: false,
             stream: None,
+            stream_state: Arc::new(StreamState::default()),
+            output_config,
+            playback_settings: PlaybackSettings::default(),
+            next_recovery: None,
+            sample_rate: 0,
+            capture: None,
+            sfx_listener: None,
+            sfx_emitter: None,
+            base_sample_params: HashMap::new(),
         };
 
         let host = cpal::default_host();
 
-        let device_result =
-            host.default_output_device().ok_or_else(|| AudioError("Error initializing audio device.".to_owned()));
+        let device_result = SoundManager::find_output_device(&host, sound_manager.output_config.device.as_deref())
+            .ok_or_else(|| AudioError("Error initializing audio device.".to_owned()));
 
         if device_result.is_err() {
             log::error!("{}", device_result.err().unwrap().to_string());
@@ -119,24 +240,53 @@ impl SoundManager {
             return Ok(sound_manager);
         }
 
-        let config = config_result.unwrap();
+        let mut config = config_result.unwrap();
+
+        if let Some(sample_rate) = sound_manager.output_config.sample_rate {
+            let sample_rate = cpal::SampleRate(sample_rate);
+            let supported = device.supported_output_configs().ok().and_then(|mut configs| {
+                configs.find(|c| {
+                    c.channels() == config.channels()
+                        && c.sample_format() == config.sample_format()
+                        && c.min_sample_rate() <= sample_rate
+                        && c.max_sample_rate() >= sample_rate
+                })
+            });
 
-        let res = match config.sample_format() {
-            cpal::SampleFormat::I8 => run::<i8>(rx, soundbank.to_owned(), device, config.into()),
-            cpal::SampleFormat::I16 => run::<i16>(rx, soundbank.to_owned(), device, config.into()),
-            cpal::SampleFormat::I32 => run::<i32>(rx, soundbank.to_owned(), device, config.into()),
-            cpal::SampleFormat::I64 => run::<i64>(rx, soundbank.to_owned(), device, config.into()),
-            cpal::SampleFormat::U8 => run::<u8>(rx, soundbank.to_owned(), device, config.into()),
-            cpal::SampleFormat::U16 => run::<u16>(rx, soundbank.to_owned(), device, config.into()),
-            cpal::SampleFormat::U32 => run::<u32>(rx, soundbank.to_owned(), device, config.into()),
-            cpal::SampleFormat::U64 => run::<u64>(rx, soundbank.to_owned(), device, config.into()),
-            cpal::SampleFormat::F32 => run::<f32>(rx, soundbank.to_owned(), device, config.into()),
-            cpal::SampleFormat::F64 => run::<f64>(rx, soundbank.to_owned(), device, config.into()),
+            match supported {
+                Some(supported) => config = supported.with_sample_rate(sample_rate),
+                None => log::warn!("Sample rate {} is not supported by the device, using default.", sample_rate.0),
+            }
+        }
+
+        let sample_format = config.sample_format();
+        let mut stream_config: cpal::StreamConfig = config.into();
+
+        if let Some(buffer_size) = sound_manager.output_config.buffer_size {
+            stream_config.buffer_size = cpal::BufferSize::Fixed(buffer_size);
+        }
+
+        let config = stream_config;
+        let stream_state = sound_manager.stream_state.clone();
+        sound_manager.sample_rate = config.sample_rate.0;
+
+        let res = match sample_format {
+            cpal::SampleFormat::I8 => run::<i8>(rx, soundbank.to_owned(), device, config, stream_state),
+            cpal::SampleFormat::I16 => run::<i16>(rx, soundbank.to_owned(), device, config, stream_state),
+            cpal::SampleFormat::I32 => run::<i32>(rx, soundbank.to_owned(), device, config, stream_state),
+            cpal::SampleFormat::I64 => run::<i64>(rx, soundbank.to_owned(), device, config, stream_state),
+            cpal::SampleFormat::U8 => run::<u8>(rx, soundbank.to_owned(), device, config, stream_state),
+            cpal::SampleFormat::U16 => run::<u16>(rx, soundbank.to_owned(), device, config, stream_state),
+            cpal::SampleFormat::U32 => run::<u32>(rx, soundbank.to_owned(), device, config, stream_state),
+            cpal::SampleFormat::U64 => run::<u64>(rx, soundbank.to_owned(), device, config, stream_state),
+            cpal::SampleFormat::F32 => run::<f32>(rx, soundbank.to_owned(), device, config, stream_state),
+            cpal::SampleFormat::F64 => run::<f64>(rx, soundbank.to_owned(), device, config, stream_state),
             _ => Err(AudioError("Unsupported sample format.".to_owned())),
         };
 
         if let Err(res) = &res {
             log::error!("Error initializing audio: {}", res);
+            sound_manager.load_failed = true;
         }
 
         sound_manager.stream = res.ok();
@@ -149,15 +299,285 @@ impl SoundManager {
             return Ok(());
         }
 
+        if let Some(AudioCapture { source: CaptureSource::Mixer { .. }, .. }) = &self.capture {
+            log::info!("Skipping sound manager reload because audio is being captured without a device.");
+            return Ok(());
+        }
+
         log::info!("Reloading sound manager.");
 
         let (tx, rx): (Sender<PlaybackMessage>, Receiver<PlaybackMessage>) = mpsc::channel();
         let soundbank = self.soundbank.take().unwrap();
-        *self = SoundManager::bootstrap(&soundbank, tx, rx)?;
+        let playback_settings = std::mem::take(&mut self.playback_settings);
+        let sfx_listener = self.sfx_listener;
+        let capture = self.capture.take();
+        *self = SoundManager::bootstrap(&soundbank, tx, rx, self.output_config.clone())?;
+        self.sfx_listener = sfx_listener;
+
+        if let Some(capture) = capture {
+            if capture.writer.format().sample_rate == self.sample_rate {
+                self.stream_state.capturing.store(true, Ordering::Relaxed);
+                self.capture = Some(capture);
+            } else {
+                log::warn!("Audio capture stopped because the output sample rate has changed.");
+            }
+        }
+
+        self.restore_playback_settings(playback_settings)
+    }
+
+    fn restore_playback_settings(&mut self, playback_settings: PlaybackSettings) -> GameResult {
+        if let Some(volume) = playback_settings.song_volume {
+            self.set_song_volume(volume);
+        }
+        if let Some(volume) = playback_settings.sfx_volume {
+            self.set_sfx_volume(volume);
+        }
+        if let Some(interpolation) = playback_settings.org_interpolation {
+            self.set_org_interpolation(interpolation);
+        }
+        for (id, params) in playback_settings.sample_params {
+            self.set_sample_params(id, params)?;
+        }
+        for (id, data) in playback_settings.sample_data {
+            self.set_sfx_samples(id, data);
+        }
+
+        Ok(())
+    }
+
+    /// Starts writing the mixed audio to a WAV file, [`SoundManager::capture_tick`] has to be called
+    /// every game tick afterwards, as the mixer is driven by the game loop while capturing. The audio
+    /// device plays back the captured frames if the game runs in real time and is silent otherwise.
+    /// If there's no audio device, a mixer is created just for the capture.
+    pub fn start_capture<W: io::Write + io::Seek + 'static>(
+        &mut self,
+        writer: W,
+        constants: &EngineConstants,
+        settings: &Settings,
+        ctx: &mut Context,
+        realtime: bool,
+    ) -> GameResult {
+        self.stop_capture()?;
+
+        let source = if self.stream.is_some() {
+            CaptureSource::Stream { realtime }
+        } else {
+            if self.soundbank.is_none() {
+                let bnk = SoundBank::load_from(filesystem::open(ctx, "/builtin/organya-wavetable-doukutsu.bin")?)?;
+                self.soundbank = Some(bnk);
+            }
+
+            let (tx, rx): (Sender<PlaybackMessage>, Receiver<PlaybackMessage>) = mpsc::channel();
+            let mixer = Box::new(Mixer::new(self.soundbank.clone().unwrap(), CAPTURE_SAMPLE_RATE));
+            let prev_tx = std::mem::replace(&mut self.tx, tx);
+            let no_audio = std::mem::replace(&mut self.no_audio, false);
+            let load_failed = std::mem::replace(&mut self.load_failed, false);
+            self.sample_rate = CAPTURE_SAMPLE_RATE;
+
+            let playback_settings = std::mem::take(&mut self.playback_settings);
+            self.restore_playback_settings(playback_settings)?;
+            self.restart_song(constants, settings, ctx)?;
+
+            CaptureSource::Mixer { rx, mixer, prev_tx, no_audio, load_failed }
+        };
+
+        let format = WavFormat { channels: 2, sample_rate: self.sample_rate, bit_depth: 16 };
+        let writer = WavWriter::new(Box::new(writer) as Box<dyn WriteSeek>, format)?;
+
+        log::info!("Started audio capture: {}", format);
+
+        if let Ok(mut buf) = self.stream_state.capture_buf.lock() {
+            buf.clear();
+        }
+        self.stream_state.capturing.store(true, Ordering::Relaxed);
+        self.capture = Some(AudioCapture { writer, source, frame_remainder: 0.0, frame_buf: Vec::new() });
 
         Ok(())
     }
 
+    /// Finishes the WAV file being captured, if any.
+    pub fn stop_capture(&mut self) -> GameResult {
+        let capture = match self.capture.take() {
+            Some(capture) => capture,
+            None => return Ok(()),
+        };
+
+        self.stream_state.capturing.store(false, Ordering::Relaxed);
+
+        let mut writer = capture.writer;
+        writer.finish()?;
+
+        log::info!("Finished audio capture.");
+
+        if let Ok(mut buf) = self.stream_state.capture_buf.lock() {
+            buf.clear();
+        }
+
+        if let CaptureSource::Mixer { prev_tx, no_audio, load_failed, .. } = capture.source {
+            // the mixer and its receiver are dropped along with the capture, don't leave a sender to them behind
+            self.tx = prev_tx;
+            self.no_audio = no_audio;
+            self.load_failed = load_failed;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    /// Mixes the audio of a single game tick and writes it to the capture file, so the length of captured
+    /// audio always matches the game time, regardless of the audio device latency or the lack of one.
+    pub fn capture_tick(&mut self, tps: usize) -> GameResult {
+        let capture = match &mut self.capture {
+            Some(capture) => capture,
+            None => return Ok(()),
+        };
+
+        capture.frame_remainder += self.sample_rate as f64 / tps.max(1) as f64;
+        let frames = capture.frame_remainder as usize;
+        capture.frame_remainder -= frames as f64;
+
+        capture.frame_buf.clear();
+
+        match &mut capture.source {
+            CaptureSource::Stream { realtime } => {
+                if let Ok(mut shared) = self.stream_state.mixer.lock() {
+                    if let Some(SharedMixer { rx, mixer }) = shared.as_mut() {
+                        mix_frames(rx, mixer, frames, &mut capture.frame_buf, &self.stream_state);
+                    }
+                }
+
+                capture.frame_buf.resize(frames * 2, 0x8000);
+
+                if *realtime {
+                    if let Ok(mut buf) = self.stream_state.capture_buf.lock() {
+                        buf.extend(capture.frame_buf.iter());
+
+                        // don't let the playback lag behind if the device consumes samples slower than ticks go
+                        let max_queued = frames * 2 * 8;
+                        if buf.len() > max_queued {
+                            let excess = buf.len() - max_queued;
+                            buf.drain(..excess);
+                        }
+                    }
+                }
+            }
+            CaptureSource::Mixer { rx, mixer, .. } => {
+                mix_frames(rx, mixer, frames, &mut capture.frame_buf, &self.stream_state);
+            }
+        }
+
+        if let Err(err) = capture.writer.write_samples(&capture.frame_buf) {
+            log::error!("Stopping audio capture: {}", err);
+            return self.stop_capture();
+        }
+
+        Ok(())
+    }
+
+    /// Reopens the audio stream and resumes the song that was playing.
+    pub fn reopen(&mut self, constants: &EngineConstants, settings: &Settings, ctx: &mut Context) -> GameResult {
+        let song_id = self.current_song_id;
+
+        self.reload()?;
+        self.current_song_id = song_id;
+        self.restart_song(constants, settings, ctx)
+    }
+
+    /// Plays the current song again from the start, used after the playback engine has been replaced.
+    pub fn restart_song(&mut self, constants: &EngineConstants, settings: &Settings, ctx: &mut Context) -> GameResult {
+        let song_id = self.current_song_id;
+
+        self.current_song_id = 0;
+        self.play_song(song_id, constants, settings, ctx, false)
+    }
+
+    /// Switches to another output device or stream configuration, reopening the audio stream if it changed.
+    pub fn set_output_config(
+        &mut self,
+        output_config: AudioOutputConfig,
+        constants: &EngineConstants,
+        settings: &Settings,
+        ctx: &mut Context,
+    ) -> GameResult {
+        if self.output_config == output_config {
+            return Ok(());
+        }
+
+        self.output_config = output_config;
+        self.reopen(constants, settings, ctx)
+    }
+
+    /// Reopens the audio stream if the output device has been lost (eg. it was unplugged) or none was available yet,
+    /// the default device is used if the selected one is no longer available.
+    pub fn poll_device(&mut self, constants: &EngineConstants, settings: &Settings, ctx: &mut Context) -> GameResult {
+        if self.no_audio {
+            return Ok(());
+        }
+
+        // no stream could be opened (eg. there was no device at startup), which is retried like a failed one
+        let capturing_without_device =
+            matches!(&self.capture, Some(AudioCapture { source: CaptureSource::Mixer { .. }, .. }));
+        let no_stream = self.stream.is_none() && !capturing_without_device;
+
+        if !no_stream && !self.stream_state.failed.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        if let Some(next_recovery) = self.next_recovery {
+            if Instant::now() < next_recovery {
+                return Ok(());
+            }
+        }
+
+        if no_stream {
+            log::info!("No audio output stream, trying to open audio device.");
+        } else {
+            log::warn!("Audio output stream failed, reopening audio device.");
+        }
+        self.reopen(constants, settings, ctx)?;
+
+        if self.stream.is_none() {
+            self.stream_state.failed.store(true, Ordering::Relaxed);
+            self.next_recovery = Some(Instant::now() + Duration::from_secs(3));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the names of all audio output devices available on the host.
+    pub fn output_device_names() -> Vec<String> {
+        let host = cpal::default_host();
+
+        match host.output_devices() {
+            Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+            Err(err) => {
+                log::warn!("Failed to enumerate audio devices: {}", err);
+                Vec::new()
+            }
+        }
+    }
+
+    fn find_output_device(host: &cpal::Host, name: Option<&str>) -> Option<cpal::Device> {
+        if let Some(name) = name {
+            let device = host
+                .output_devices()
+                .ok()
+                .and_then(|mut devices| devices.find(|d| d.name().map(|n| n == name).unwrap_or(false)));
+
+            if device.is_some() {
+                return device;
+            }
+
+            log::warn!("Audio device {} not found, using default.", name);
+        }
+
+        host.default_output_device()
+    }
+
     fn send(&mut self, message: PlaybackMessage) -> GameResult<()> {
         if self.no_audio {
             return Ok(());
@@ -190,9 +610,42 @@ impl SoundManager {
             return;
         }
 
+        if let Some((x, y)) = self.sfx_emitter {
+            return self.play_sfx_at(id, x, y);
+        }
+
         self.send(PlaybackMessage::PlaySample(id)).unwrap();
     }
 
+    /// Plays a sound effect emitted at given world position. If positional sound is disabled
+    /// (there's no listener set), it's played the same way as [`SoundManager::play_sfx`].
+    pub fn play_sfx_at(&mut self, id: u8, x: i32, y: i32) {
+        if self.no_audio {
+            return;
+        }
+
+        if let Some(listener) = self.sfx_listener {
+            let (volume, pan) = listener.spatialize(x, y);
+
+            if volume > 0.0 {
+                self.send(PlaybackMessage::PlaySampleAt(id, volume, pan)).unwrap();
+            }
+        } else {
+            self.send(PlaybackMessage::PlaySample(id)).unwrap();
+        }
+    }
+
+    /// Sets the area from which positional sound effects are heard, `None` disables positional sound.
+    pub fn set_sfx_listener(&mut self, listener: Option<SfxListener>) {
+        self.sfx_listener = listener;
+    }
+
+    /// Sets the position all following [`SoundManager::play_sfx`] calls are emitted from,
+    /// used to make sounds of entities that don't know their own position positional.
+    pub fn set_sfx_emitter(&mut self, emitter: Option<(i32, i32)>) {
+        self.sfx_emitter = emitter;
+    }
+
     pub fn loop_sfx(&self, id: u8) {
         if self.no_audio {
             return;
@@ -216,6 +669,7 @@ impl SoundManager {
     }
 
     pub fn set_org_interpolation(&mut self, interpolation: InterpolationMode) {
+        self.playback_settings.org_interpolation = Some(interpolation);
         if self.no_audio {
             return;
         }
@@ -223,6 +677,7 @@ impl SoundManager {
     }
 
     pub fn set_song_volume(&mut self, volume: f32) {
+        self.playback_settings.song_volume = Some(volume);
         if self.no_audio {
             return;
         }
@@ -230,6 +685,7 @@ impl SoundManager {
     }
 
     pub fn set_sfx_volume(&mut self, volume: f32) {
+        self.playback_settings.sfx_volume = Some(volume);
         if self.no_audio {
             return;
         }
@@ -237,6 +693,8 @@ impl SoundManager {
     }
 
     pub fn set_sfx_samples(&mut self, id: u8, data: Vec<i16>) {
+        self.playback_settings.sample_params.remove(&id);
+        self.playback_settings.sample_data.insert(id, data.clone());
         if self.no_audio {
             return;
         }
@@ -282,14 +740,7 @@ impl SoundManager {
                 self.send(PlaybackMessage::Stop).unwrap();
             }
         } else if let Some(song_name) = constants.music_table.get(song_id) {
-            let mut paths = constants.organya_paths.clone();
-
-            paths.insert(0, "/Soundtracks/".to_owned() + &settings.soundtrack + "/");
-
-            if let Some(soundtrack) = constants.soundtracks.iter().find(|s| s.available && s.id == settings.soundtrack)
-            {
-                paths.insert(0, soundtrack.path.clone());
-            }
+            let paths = SoundManager::song_path_prefixes(constants, settings);
 
             let songs_paths = paths.iter().map(|prefix| {
                 [
@@ -401,125 +852,197 @@ impl SoundManager {
         Ok(())
     }
 
-    pub fn save_state(&mut self) -> GameResult {
+    fn song_path_prefixes(constants: &EngineConstants, settings: &Settings) -> Vec<String> {
+        let mut paths = constants.organya_paths.clone();
+
+        paths.insert(0, "/Soundtracks/".to_owned() + &settings.soundtrack + "/");
+
+        if let Some(soundtrack) = constants.soundtracks.iter().find(|s| s.available && s.id == settings.soundtrack) {
+            paths.insert(0, soundtrack.path.clone());
+        }
+
+        paths
+    }
+
+    /// Returns the path of the Organya file that would be used for given song, ignoring any Ogg replacements.
+    pub fn find_organya_path(
+        &self,
+        song_id: usize,
+        constants: &EngineConstants,
+        settings: &Settings,
+        ctx: &mut Context,
+    ) -> Option<String> {
+        let song_name = constants.music_table.get(song_id)?;
+
+        SoundManager::song_path_prefixes(constants, settings)
+            .iter()
+            .map(|prefix| format!("{}{}.org", prefix, song_name))
+            .find(|path| filesystem::exists(ctx, path))
+    }
+
+    /// Plays an already loaded Organya song, used by the song editor to play back modified data.
+    pub fn play_organya_song(&mut self, song_id: usize, song: Song, settings: &Settings) -> GameResult {
         if self.no_audio {
             return Ok(());
         }
 
-        self.send(PlaybackMessage::SaveState).unwrap();
         self.prev_song_id = self.current_song_id;
+        self.current_song_id = song_id;
+        self.send(PlaybackMessage::SetOrgInterpolation(settings.organya_interpolation)).unwrap();
+        self.send(PlaybackMessage::SaveState).unwrap();
+        self.send(PlaybackMessage::PlayOrganyaSong(Box::new(song))).unwrap();
 
         Ok(())
     }
 
-    pub fn restore_state(&mut self) -> GameResult {
+    /// Replaces the data of currently playing Organya song without restarting the playback.
+    pub fn update_organya_song(&mut self, song: Song) -> GameResult {
         if self.no_audio {
             return Ok(());
         }
 
-        self.send(PlaybackMessage::RestoreState).unwrap();
-        self.current_song_id = self.prev_song_id;
+        self.send(PlaybackMessage::UpdateOrganyaSong(Box::new(song))).unwrap();
 
         Ok(())
     }
 
-    pub fn set_speed(&mut self, speed: f32) -> GameResult {
+    pub fn set_org_position(&mut self, position: i32) -> GameResult {
         if self.no_audio {
             return Ok(());
         }
 
-        if speed <= 0.0 {
-            return Err(InvalidValue("Speed must be bigger than 0.0!".to_owned()));
+        self.send(PlaybackMessage::SetOrgPosition(position)).unwrap();
+
+        Ok(())
+    }
+
+    pub fn set_org_track_mask(&mut self, mask: u16) -> GameResult {
+        if self.no_audio {
+            return Ok(());
         }
 
-        self.send(PlaybackMessage::SetSpeed(speed)).unwrap();
+        self.send(PlaybackMessage::SetOrgTrackMask(mask)).unwrap();
 
         Ok(())
     }
 
-    pub fn current_song(&self) -> usize {
-        self.current_song_id
+    /// Current playback position of the Organya engine, in song steps.
+    pub fn org_position(&self) -> i32 {
+        self.stream_state.org_position.load(Ordering::Relaxed)
     }
 
-    pub fn set_sample_params_from_file<R: io::Read>(&mut self, id: u8, data: R) -> GameResult {
+    pub fn save_state(&mut self) -> GameResult {
         if self.no_audio {
             return Ok(());
         }
 
-        let mut reader = BufReader::new(data).lines();
-        let mut params = PixToneParameters::empty();
+        self.send(PlaybackMessage::SaveState).unwrap();
+        self.prev_song_id = self.current_song_id;
 
-        fn next_string<T: FromStr, R: io::Read>(reader: &mut Lines<BufReader<R>>) -> GameResult<T> {
-            while let Some(Ok(str)) = reader.next() {
-                let str = str.trim();
-                if str.is_empty() || str.starts_with('#') {
-                    continue;
-                }
+        Ok(())
+    }
 
-                let mut splits = str.split(':');
+    pub fn restore_state(&mut self) -> GameResult {
+        if self.no_audio {
+            return Ok(());
+        }
 
-                let _ = splits.next();
-                if let Some(str) = splits.next() {
-                    return str.trim().parse::<T>().map_err(|_| {
-                        GameError::ParseError("failed to parse the value as specified type.".to_string())
-                    });
-                } else {
-                    break;
-                }
-            }
+        self.send(PlaybackMessage::RestoreState).unwrap();
+        self.current_song_id = self.prev_song_id;
 
-            Err(GameError::ParseError("unexpected end.".to_string()))
+        Ok(())
+    }
+
+    pub fn set_speed(&mut self, speed: f32) -> GameResult {
+        if self.no_audio {
+            return Ok(());
         }
 
-        for channel in &mut params.channels {
-            channel.enabled = next_string::<u8, R>(&mut reader)? != 0;
-            channel.length = next_string::<u32, R>(&mut reader)?;
+        if speed <= 0.0 {
+            return Err(InvalidValue("Speed must be bigger than 0.0!".to_owned()));
+        }
 
-            channel.carrier.waveform_type = next_string::<u8, R>(&mut reader)?;
-            channel.carrier.pitch = next_string::<f32, R>(&mut reader)?;
-            channel.carrier.level = next_string::<i32, R>(&mut reader)?;
-            channel.carrier.offset = next_string::<i32, R>(&mut reader)?;
+        self.send(PlaybackMessage::SetSpeed(speed)).unwrap();
 
-            channel.frequency.waveform_type = next_string::<u8, R>(&mut reader)?;
-            channel.frequency.pitch = next_string::<f32, R>(&mut reader)?;
-            channel.frequency.level = next_string::<i32, R>(&mut reader)?;
-            channel.frequency.offset = next_string::<i32, R>(&mut reader)?;
+        Ok(())
+    }
 
-            channel.amplitude.waveform_type = next_string::<u8, R>(&mut reader)?;
-            channel.amplitude.pitch = next_string::<f32, R>(&mut reader)?;
-            channel.amplitude.level = next_string::<i32, R>(&mut reader)?;
-            channel.amplitude.offset = next_string::<i32, R>(&mut reader)?;
+    pub fn current_song(&self) -> usize {
+        self.current_song_id
+    }
 
-            channel.envelope.initial = next_string::<i32, R>(&mut reader)?;
-            channel.envelope.time_a = next_string::<i32, R>(&mut reader)?;
-            channel.envelope.value_a = next_string::<i32, R>(&mut reader)?;
-            channel.envelope.time_b = next_string::<i32, R>(&mut reader)?;
-            channel.envelope.value_b = next_string::<i32, R>(&mut reader)?;
-            channel.envelope.time_c = next_string::<i32, R>(&mut reader)?;
-            channel.envelope.value_c = next_string::<i32, R>(&mut reader)?;
-        }
+    pub fn set_sample_params_from_file<R: io::Read>(&mut self, id: u8, data: R) -> GameResult {
+        self.set_sample_params(id, PixToneParameters::from_pxt(data)?)
+    }
 
+    /// Replaces a sound effect for the whole session, unlike the ones loaded from mods.
+    pub fn set_base_sample_params(&mut self, id: u8, params: PixToneParameters) -> GameResult {
+        self.base_sample_params.insert(id, params);
         self.set_sample_params(id, params)
     }
 
+    fn base_sample_params(&self, id: u8) -> PixToneParameters {
+        match self.base_sample_params.get(&id) {
+            Some(params) => *params,
+            None => DEFAULT_PIXTONE_TABLE.get(id as usize).copied().unwrap_or_else(PixToneParameters::empty),
+        }
+    }
+
     pub fn set_sample_params(&mut self, id: u8, params: PixToneParameters) -> GameResult {
-        if self.no_audio {
+        // already synthesized, reloading the same sound effects shouldn't do it again
+        if self.playback_settings.sample_params.get(&id) == Some(&params) {
             return Ok(());
         }
 
+        self.playback_settings.sample_data.remove(&id);
+        self.playback_settings.sample_params.insert(id, params);
+
+        if self.no_audio {
+            return Ok(());
+        }
         self.send(PlaybackMessage::SetSampleParams(id, params)).unwrap();
 
         Ok(())
     }
 
     pub fn load_custom_sound_effects(&mut self, ctx: &mut Context, roots: &Vec<String>) -> GameResult {
+        for i in 0..0xffu8 {
+            let paths = [format!("pxt/fx{:02x}.pxt", i), format!("PixTone/{:03}.pxt", i)];
+            let file = paths.iter().find_map(|path| Some((path, filesystem::open_find(ctx, roots, path).ok()?)));
+
+            // sound effects of previously loaded mods are reset, same as the ones that fail to load
+            let params = match file.map(|(path, file)| (path, PixToneParameters::from_pxt(file))) {
+                Some((_, Ok(params))) => params,
+                Some((path, Err(err))) => {
+                    log::warn!("Failed to load {}: {}", path, err);
+                    self.base_sample_params(i)
+                }
+                None => self.base_sample_params(i),
+            };
+
+            // the playback thread synthesizes the default ones on its own
+            let replaced = self.playback_settings.sample_params.contains_key(&i)
+                || self.playback_settings.sample_data.contains_key(&i);
+            if !replaced && params == self.base_sample_params(i) {
+                continue;
+            }
+
+            self.set_sample_params(i, params)?;
+        }
+
         for path in roots.iter().rev() {
             let wavs = filesystem::read_dir(ctx, [path, "sfx/"].join(""))?
                 .filter(|f| f.to_string_lossy().to_lowercase().ends_with(".wav"));
 
             for filename in wavs {
                 if let Ok(mut file) = filesystem::open(ctx, &filename) {
-                    let wav = wav::WavSample::read_from(&mut file)?;
+                    let wav = match wav::WavSample::read_from(&mut file) {
+                        Ok(wav) => wav,
+                        Err(err) => {
+                            log::warn!("Failed to load {}: {}", filename.display(), err);
+                            continue;
+                        }
+                    };
                     let id = filename
                         .file_stem()
                         .unwrap_or_default()
@@ -550,11 +1073,13 @@ impl SoundManager {
 pub(in crate::sound) enum PlaybackMessage {
     Stop,
     PlayOrganyaSong(Box<Song>),
+    UpdateOrganyaSong(Box<Song>),
     #[cfg(feature = "ogg-playback")]
     PlayOggSongSinglePart(Box<OggStreamReader<File>>),
     #[cfg(feature = "ogg-playback")]
     PlayOggSongMultiPart(Box<OggStreamReader<File>>, Box<OggStreamReader<File>>),
     PlaySample(u8),
+    PlaySampleAt(u8, f32, f32),
     LoopSample(u8),
     LoopSampleFreq(u8, f32),
     StopSample(u8),
@@ -566,27 +1091,41 @@ pub(in crate::sound) enum PlaybackMessage {
     RestoreState,
     SetSampleParams(u8, PixToneParameters),
     SetOrgInterpolation(InterpolationMode),
+    SetOrgPosition(i32),
+    SetOrgTrackMask(u16),
     SetSampleData(u8, Vec<i16>),
 }
 
-#[derive(PartialEq, Eq)]
-enum PlaybackState {
-    Stopped,
-    PlayingOrg,
-    #[cfg(feature = "ogg-playback")]
-    PlayingOgg,
-}
+fn write_frame<T: cpal::SizedSample + cpal::FromSample<u16>>(frame: &mut [T], sample_l: u16, sample_r: u16) {
+    if frame.len() >= 2 {
+        frame[0] = T::from_sample(sample_l);
+        frame[1] = T::from_sample(sample_r);
+    } else {
+        let sample =
+            ((((sample_l ^ 0x8000) as i16 as i32) + ((sample_r ^ 0x8000) as i16 as i32)) / 2) as i16 as u16 ^ 0x8000;
 
-enum PlaybackStateType {
-    None,
-    Organya(SavedOrganyaPlaybackState),
-    #[cfg(feature = "ogg-playback")]
-    Ogg(SavedOggPlaybackState),
+        frame[0] = T::from_sample(sample);
+    }
 }
 
-impl Default for PlaybackStateType {
-    fn default() -> Self {
-        Self::None
+/// Mixes given number of frames on the game loop, appending them to `out`.
+fn mix_frames(
+    rx: &Receiver<PlaybackMessage>,
+    mixer: &mut Mixer,
+    frames: usize,
+    out: &mut Vec<u16>,
+    stream_state: &StreamState,
+) {
+    mixer.process_messages(rx);
+
+    if let Some(position) = mixer.org_position() {
+        stream_state.org_position.store(position, Ordering::Relaxed);
+    }
+
+    for _ in 0..frames {
+        let (sample_l, sample_r) = mixer.next_frame();
+        out.push(sample_l);
+        out.push(sample_r);
     }
 }
 
@@ -595,302 +1134,61 @@ fn run<T>(
     bank: SoundBank,
     device: cpal::Device,
     config: cpal::StreamConfig,
+    stream_state: Arc<StreamState>,
 ) -> GameResult<cpal::Stream>
 where
     T: cpal::SizedSample + cpal::FromSample<u16>,
 {
-    let sample_rate = config.sample_rate.0 as f32;
+    let sample_rate = config.sample_rate.0;
     let channels = config.channels as usize;
-    let mut state = PlaybackState::Stopped;
-    let mut saved_state: PlaybackStateType = PlaybackStateType::None;
-    let mut speed = 1.0;
-    let mut org_engine = Box::new(OrgPlaybackEngine::new());
-    #[cfg(feature = "ogg-playback")]
-    let mut ogg_engine = Box::new(OggPlaybackEngine::new());
-    let mut pixtone = Box::new(PixTonePlayback::new());
-    pixtone.create_samples();
+    let mixer = Box::new(Mixer::new(bank, sample_rate));
 
-    log::info!("Audio format: {} {}", sample_rate, channels);
-    org_engine.set_sample_rate(sample_rate as usize);
-    #[cfg(feature = "ogg-playback")]
-    {
-        org_engine.loops = usize::MAX;
-        ogg_engine.set_sample_rate(sample_rate as usize);
+    if let Ok(mut shared) = stream_state.mixer.lock() {
+        *shared = Some(SharedMixer { rx, mixer });
     }
 
-    let buf_size = sample_rate as usize * 10 / 1000;
-    let mut bgm_buf = vec![0x8080; buf_size * 2];
-    let mut pxt_buf = vec![0x8000; buf_size];
-    let mut bgm_index = 0;
-    let mut pxt_index = 0;
-    let mut samples = 0;
-    let mut bgm_vol = 1.0_f32;
-    let mut bgm_vol_saved = 1.0_f32;
-    let mut sfx_vol = 1.0_f32;
-    let mut bgm_fadeout = false;
-    pixtone.mix(&mut pxt_buf, sample_rate);
+    log::info!("Audio format: {} {}", sample_rate, channels);
 
-    let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+    let error_state = stream_state.clone();
+    let err_fn = move |err| {
+        log::error!("An error occurred on audio stream: {}", err);
+        error_state.failed.store(true, Ordering::Relaxed);
+    };
 
     let stream_result = device.build_output_stream(
         &config,
         move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-            loop {
-                if bgm_fadeout && bgm_vol > 0.0 {
-                    bgm_vol -= 0.02;
+            if stream_state.capturing.load(Ordering::Relaxed) {
+                // the game loop mixes while capturing, play back what it has queued, or silence
+                let mut buf = stream_state.capture_buf.lock().ok();
+
+                for frame in data.chunks_mut(channels) {
+                    let sample_l = buf.as_mut().and_then(|b| b.pop_front()).unwrap_or(0x8000);
+                    let sample_r = buf.as_mut().and_then(|b| b.pop_front()).unwrap_or(0x8000);
+                    write_frame(frame, sample_l, sample_r);
                 }
 
-                if bgm_vol < 0.0 {
-                    bgm_vol = 0.0;
-                }
-
-                match rx.try_recv() {
-                    Ok(PlaybackMessage::PlayOrganyaSong(song)) => {
-                        if state == PlaybackState::Stopped {
-                            saved_state = PlaybackStateType::None;
-                        }
-
-                        if bgm_fadeout {
-                            bgm_fadeout = false;
-                            bgm_vol = bgm_vol_saved;
-                        }
-
-                        org_engine.start_song(*song, &bank);
-
-                        for i in &mut bgm_buf[0..samples] {
-                            *i = 0x8000
-                        }
-                        samples = org_engine.render_to(&mut bgm_buf);
-                        bgm_index = 0;
-
-                        state = PlaybackState::PlayingOrg;
-                    }
-                    #[cfg(feature = "ogg-playback")]
-                    Ok(PlaybackMessage::PlayOggSongSinglePart(data)) => {
-                        if state == PlaybackState::Stopped {
-                            saved_state = PlaybackStateType::None;
-                        }
-
-                        if bgm_fadeout {
-                            bgm_fadeout = false;
-                            bgm_vol = bgm_vol_saved;
-                        }
-
-                        ogg_engine.start_single(data);
-
-                        for i in &mut bgm_buf[0..samples] {
-                            *i = 0x8000
-                        }
-                        samples = ogg_engine.render_to(&mut bgm_buf);
-                        bgm_index = 0;
-
-                        state = PlaybackState::PlayingOgg;
-                    }
-                    #[cfg(feature = "ogg-playback")]
-                    Ok(PlaybackMessage::PlayOggSongMultiPart(data_intro, data_loop)) => {
-                        if state == PlaybackState::Stopped {
-                            saved_state = PlaybackStateType::None;
-                        }
-
-                        if bgm_fadeout {
-                            bgm_fadeout = false;
-                            bgm_vol = bgm_vol_saved;
-                        }
-
-                        ogg_engine.start_multi(data_intro, data_loop);
-
-                        for i in &mut bgm_buf[0..samples] {
-                            *i = 0x8000
-                        }
-                        samples = ogg_engine.render_to(&mut bgm_buf);
-                        bgm_index = 0;
-
-                        state = PlaybackState::PlayingOgg;
-                    }
-                    Ok(PlaybackMessage::PlaySample(id)) => {
-                        pixtone.play_sfx(id);
-                    }
-
-                    Ok(PlaybackMessage::LoopSample(id)) => {
-                        pixtone.loop_sfx(id);
-                    }
-                    Ok(PlaybackMessage::LoopSampleFreq(id, freq)) => {
-                        pixtone.loop_sfx_freq(id, freq);
-                    }
-                    Ok(PlaybackMessage::StopSample(id)) => {
-                        pixtone.stop_sfx(id);
-                    }
-                    Ok(PlaybackMessage::Stop) => {
-                        if state == PlaybackState::Stopped {
-                            saved_state = PlaybackStateType::None;
-                        }
-
-                        state = PlaybackState::Stopped;
-                    }
-                    Ok(PlaybackMessage::SetSpeed(new_speed)) => {
-                        assert!(new_speed > 0.0);
-                        speed = new_speed;
-                        #[cfg(feature = "ogg-playback")]
-                        ogg_engine.set_sample_rate((sample_rate / new_speed) as usize);
-                        org_engine.set_sample_rate((sample_rate / new_speed) as usize);
-                    }
-                    Ok(PlaybackMessage::SetSongVolume(new_volume)) => {
-                        assert!(bgm_vol >= 0.0);
-                        if bgm_fadeout {
-                            bgm_vol_saved = new_volume;
-                        } else {
-                            bgm_vol = new_volume;
-                        }
-                    }
-                    Ok(PlaybackMessage::SetSampleVolume(new_volume)) => {
-                        assert!(sfx_vol >= 0.0);
-                        sfx_vol = new_volume;
-                    }
-                    Ok(PlaybackMessage::FadeoutSong) => {
-                        bgm_fadeout = true;
-                        bgm_vol_saved = bgm_vol;
-                    }
-                    Ok(PlaybackMessage::SaveState) => {
-                        saved_state = match state {
-                            PlaybackState::Stopped => PlaybackStateType::None,
-                            PlaybackState::PlayingOrg => PlaybackStateType::Organya(org_engine.get_state()),
-                            #[cfg(feature = "ogg-playback")]
-                            PlaybackState::PlayingOgg => PlaybackStateType::Ogg(ogg_engine.get_state()),
-                        };
-                    }
-                    Ok(PlaybackMessage::RestoreState) => {
-                        let saved_state_loc = std::mem::take(&mut saved_state);
-
-                        match saved_state_loc {
-                            PlaybackStateType::None => {
-                                state = PlaybackState::Stopped;
-                            }
-                            PlaybackStateType::Organya(playback_state) => {
-                                org_engine.set_state(playback_state, &bank);
-
-                                if state == PlaybackState::Stopped {
-                                    org_engine.rewind();
-                                }
-
-                                for i in &mut bgm_buf[0..samples] {
-                                    *i = 0x8000
-                                }
-                                samples = org_engine.render_to(&mut bgm_buf);
-                                bgm_index = 0;
-
-                                if bgm_fadeout {
-                                    bgm_fadeout = false;
-                                    bgm_vol = bgm_vol_saved;
-                                }
-
-                                state = PlaybackState::PlayingOrg;
-                            }
-                            #[cfg(feature = "ogg-playback")]
-                            PlaybackStateType::Ogg(playback_state) => {
-                                ogg_engine.set_state(playback_state);
-
-                                if state == PlaybackState::Stopped {
-                                    ogg_engine.rewind();
-                                }
+                return;
+            }
 
-                                for i in &mut bgm_buf[0..samples] {
-                                    *i = 0x8000
-                                }
-                                samples = ogg_engine.render_to(&mut bgm_buf);
-                                bgm_index = 0;
+            let mut shared = stream_state.mixer.lock().ok();
+            let shared = match shared.as_mut().and_then(|s| s.as_mut()) {
+                Some(shared) => shared,
+                None => {
+                    data.fill(T::from_sample(0x8000u16));
+                    return;
+                }
+            };
 
-                                if bgm_fadeout {
-                                    bgm_fadeout = false;
-                                    bgm_vol = bgm_vol_saved;
-                                }
+            shared.mixer.process_messages(&shared.rx);
 
-                                state = PlaybackState::PlayingOgg;
-                            }
-                        }
-                    }
-                    Ok(PlaybackMessage::SetSampleParams(id, params)) => {
-                        pixtone.set_sample_parameters(id, params);
-                    }
-                    Ok(PlaybackMessage::SetOrgInterpolation(interpolation)) => {
-                        org_engine.interpolation = interpolation;
-                    }
-                    Ok(PlaybackMessage::SetSampleData(id, data)) => {
-                        pixtone.set_sample_data(id, data);
-                    }
-                    Err(_) => {
-                        break;
-                    }
-                }
+            if let Some(position) = shared.mixer.org_position() {
+                stream_state.org_position.store(position, Ordering::Relaxed);
             }
 
             for frame in data.chunks_mut(channels) {
-                let (bgm_sample_l, bgm_sample_r): (u16, u16) = {
-                    if state == PlaybackState::Stopped {
-                        (0x8000, 0x8000)
-                    } else if bgm_index < samples {
-                        let samples = (bgm_buf[bgm_index], bgm_buf[bgm_index + 1]);
-                        bgm_index += 2;
-                        samples
-                    } else {
-                        for i in &mut bgm_buf[0..samples] {
-                            *i = 0x8000
-                        }
-
-                        match state {
-                            PlaybackState::PlayingOrg => {
-                                samples = org_engine.render_to(&mut bgm_buf);
-                            }
-                            #[cfg(feature = "ogg-playback")]
-                            PlaybackState::PlayingOgg => {
-                                samples = ogg_engine.render_to(&mut bgm_buf);
-                            }
-                            _ => unreachable!(),
-                        }
-                        bgm_index = 2;
-                        (bgm_buf[0], bgm_buf[1])
-                    }
-                };
-
-                let pxt_sample: u16 = pxt_buf[pxt_index];
-
-                if pxt_index < (pxt_buf.len() - 1) {
-                    pxt_index += 1;
-                } else {
-                    pxt_index = 0;
-                    pxt_buf.fill(0x8000);
-                    pixtone.mix(&mut pxt_buf, sample_rate / speed);
-                }
-
-                if frame.len() >= 2 {
-                    let sample_l = clamp(
-                        (((bgm_sample_l ^ 0x8000) as i16) as f32 * bgm_vol) as isize
-                            + (((pxt_sample ^ 0x8000) as i16) as f32 * sfx_vol) as isize,
-                        -0x7fff,
-                        0x7fff,
-                    ) as u16
-                        ^ 0x8000;
-                    let sample_r = clamp(
-                        (((bgm_sample_r ^ 0x8000) as i16) as f32 * bgm_vol) as isize
-                            + (((pxt_sample ^ 0x8000) as i16) as f32 * sfx_vol) as isize,
-                        -0x7fff,
-                        0x7fff,
-                    ) as u16
-                        ^ 0x8000;
-
-                    frame[0] = T::from_sample(sample_l);
-                    frame[1] = T::from_sample(sample_r);
-                } else {
-                    let sample = clamp(
-                        ((((bgm_sample_l ^ 0x8000) as i16) + ((bgm_sample_r ^ 0x8000) as i16)) as f32 * bgm_vol / 2.0)
-                            as isize
-                            + (((pxt_sample ^ 0x8000) as i16) as f32 * sfx_vol) as isize,
-                        -0x7fff,
-                        0x7fff,
-                    ) as u16
-                        ^ 0x8000;
-
-                    frame[0] = T::from_sample(sample);
-                }
+                let (sample_l, sample_r) = shared.mixer.next_frame();
+                write_frame(frame, sample_l, sample_r);
             }
         },
         err_fn,
@@ -906,3 +1204,26 @@ where
 
     Ok(stream)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spatialize() {
+        let listener = SfxListener { x: 1000, y: 1000, half_width: 100, half_height: 50 };
+
+        assert_eq!(listener.spatialize(1000, 1000), (1.0, 0.0));
+        assert_eq!(listener.spatialize(1050, 1000), (1.0, 0.375));
+        assert_eq!(listener.spatialize(900, 1050), (1.0, -0.75));
+
+        // fades out over two more screens in each direction
+        assert_eq!(listener.spatialize(1300, 1000), (0.5, 1.0));
+        assert_eq!(listener.spatialize(1000, 1150), (0.5, 0.0));
+        assert_eq!(listener.spatialize(500, 1000), (0.0, -1.0));
+        assert_eq!(listener.spatialize(1000, -100000), (0.0, 0.0));
+
+        let empty = SfxListener { x: 0, y: 0, half_width: 0, half_height: 0 };
+        assert_eq!(empty.spatialize(0, 0), (1.0, 0.0));
+    }
+}
@@ -48,6 +48,8 @@ pub(crate) struct OrgPlaybackEngine {
     frames_per_tick: usize,
     pub loops: usize,
     pub interpolation: InterpolationMode,
+    /// Bit mask of audible tracks, bit 0 is the first melody track and bit 8 is the first drum track.
+    pub track_mask: u16,
 }
 
 #[derive(Clone)]
@@ -77,6 +79,7 @@ impl OrgPlaybackEngine {
             frames_per_tick,
             loops: 1,
             interpolation: InterpolationMode::Linear,
+            track_mask: 0xffff,
         }
     }
 
@@ -101,34 +104,16 @@ impl OrgPlaybackEngine {
     }
 
     pub fn start_song(&mut self, song: Organya, samples: &SoundBank) {
-        for i in 0..8 {
-            let sound_index = song.tracks[i].inst.inst as usize;
-            let sound = samples.get_wave(sound_index).iter().map(|&x| x ^ 128).collect();
-
-            let format = WavFormat { channels: 1, sample_rate: 22050, bit_depth: 8 };
-
-            let rbuf = RenderBuffer::new_organya(WavSample { format, data: sound });
+        self.song = song;
 
-            for j in 0..8 {
-                for &k in &[0, 64] {
-                    self.track_buffers[i + (j * 8) + k] = rbuf.clone();
-                }
-            }
+        for track in 0..8 {
+            self.load_melody_track(track, samples);
         }
 
-        // Initialize drums
-        for (idx, (track, buf)) in song.tracks[8..].iter().zip(self.track_buffers[128..].iter_mut()).enumerate() {
-            if song.version == Version::Extended {
-                // Check for OOB track count, instruments outside of the sample range will be set to the last valid sample
-                let index = if track.inst.inst as usize >= samples.samples.len() {samples.samples.len() - 1} else {track.inst.inst as usize} ;
-                *buf = RenderBuffer::new(samples.samples[index].clone());
-            } else {
-                let index = if idx >= samples.samples.len() {samples.samples.len() - 1} else {idx};
-                *buf = RenderBuffer::new(samples.samples[index].clone());
-            }
+        for track in 0..8 {
+            self.load_drum_track(track, samples);
         }
 
-        self.song = song;
         self.play_pos = 0;
         self.frames_per_tick = (self.output_format.sample_rate as usize / 1000) * self.song.time.wait as usize;
         self.frames_this_tick = 0;
@@ -137,10 +122,66 @@ impl OrgPlaybackEngine {
         self.keys.fill(255);
     }
 
+    fn load_melody_track(&mut self, track: usize, samples: &SoundBank) {
+        let sound_index = self.song.tracks[track].inst.inst as usize;
+        let sound = samples.get_wave(sound_index).iter().map(|&x| x ^ 128).collect();
+
+        let format = WavFormat { channels: 1, sample_rate: 22050, bit_depth: 8 };
+
+        let rbuf = RenderBuffer::new_organya(WavSample { format, data: sound });
+
+        for j in 0..8 {
+            for &k in &[0, 64] {
+                self.track_buffers[track + (j * 8) + k] = rbuf.clone();
+            }
+        }
+    }
+
+    fn load_drum_track(&mut self, track: usize, samples: &SoundBank) {
+        let index = if self.song.version == Version::Extended {
+            // Check for OOB track count, instruments outside of the sample range will be set to the last valid sample
+            self.song.tracks[8 + track].inst.inst as usize
+        } else {
+            track
+        };
+        let index = index.min(samples.samples.len() - 1);
+
+        self.track_buffers[128 + track] = RenderBuffer::new(samples.samples[index].clone());
+    }
+
+    /// Replaces the song data without interrupting playback, used when editing a song live.
+    /// Only tracks whose instrument has changed are reloaded, which stops the notes they were playing.
+    pub fn update_song(&mut self, song: Organya, samples: &SoundBank) {
+        let old_song = std::mem::replace(&mut self.song, song);
+        let drums_changed = old_song.version != self.song.version;
+
+        for track in 0..8 {
+            if old_song.tracks[track].inst.inst != self.song.tracks[track].inst.inst {
+                self.load_melody_track(track, samples);
+                self.lengths[track] = 0;
+                self.swaps[track] = 0;
+                self.keys[track] = 255;
+            }
+
+            if drums_changed || old_song.tracks[8 + track].inst.inst != self.song.tracks[8 + track].inst.inst {
+                self.load_drum_track(track, samples);
+            }
+        }
+
+        if old_song.time.wait != self.song.time.wait {
+            self.frames_per_tick = (self.output_format.sample_rate as usize / 1000) * self.song.time.wait as usize;
+            self.frames_this_tick = self.frames_this_tick.min(self.frames_per_tick.saturating_sub(1));
+        }
+    }
+
     pub fn set_position(&mut self, position: i32) {
         self.play_pos = position;
     }
 
+    pub fn get_position(&self) -> i32 {
+        self.play_pos
+    }
+
     pub fn rewind(&mut self) {
         self.set_position(0);
     }
@@ -304,8 +345,9 @@ impl OrgPlaybackEngine {
                 self.update_play_state()
             }
 
-            for buf in &mut self.track_buffers {
+            for (idx, buf) in self.track_buffers.iter_mut().enumerate() {
                 if buf.playing {
+                    let track = if idx >= 128 { idx - 120 } else { idx % 8 };
                     let is_16bit = buf.sample.format.bit_depth == 16;
                     let is_stereo = buf.sample.format.channels == 2;
 
@@ -339,7 +381,7 @@ impl OrgPlaybackEngine {
                     // index into sound samples
                     let advance = buf.frequency as f64 / freq;
 
-                    let vol = buf.vol_cent;
+                    let vol = if self.track_mask & (1 << track) != 0 { buf.vol_cent } else { 0.0 };
                     let (pan_l, pan_r) = buf.pan_cent;
 
                     if self.interpolation == InterpolationMode::Polyphase {
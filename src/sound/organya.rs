@@ -1,6 +1,6 @@
 use std::io;
 
-use byteorder::{LE, ReadBytesExt};
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
 
 use crate::framework::error::{GameError, GameResult};
 
@@ -68,6 +68,7 @@ pub struct Note {
 #[derive(Debug, Clone)]
 pub struct Song {
     pub version: Version,
+    pub display: Display,
     pub time: Timing,
     pub tracks: [Track; 16],
 }
@@ -76,6 +77,7 @@ impl Song {
     pub fn empty() -> Song {
         Song {
             version: Version::Main,
+            display: Display { beats: 4, steps: 4 },
             time: Timing { wait: 8, loop_range: LoopRange { start: 0, end: 1 } },
             tracks: [
                 Track { inst: Instrument { freq: 1000, inst: 0, pipi: 0, notes: 0 }, notes: vec![] },
@@ -112,8 +114,8 @@ impl Song {
             };
 
         let wait = f.read_u16::<LE>()?;
-        let _bpm = f.read_u8()?;
-        let _spb = f.read_u8()?;
+        let beats = f.read_u8()?;
+        let steps = f.read_u8()?;
         let start = f.read_i32::<LE>()?;
         let end = f.read_i32::<LE>()?;
 
@@ -194,6 +196,7 @@ impl Song {
 
         let song = Song {
             version,
+            display: Display { beats, steps },
             time: Timing {
                 wait,
                 loop_range: LoopRange {
@@ -206,4 +209,90 @@ impl Song {
 
         Ok(song)
     }
+
+    pub fn write_to<W: io::Write>(&self, mut f: W) -> GameResult {
+        let magic = match self.version {
+            Version::Beta => b"Org-01",
+            Version::Main => b"Org-02",
+            Version::Extended => b"Org-03",
+        };
+
+        f.write_all(magic)?;
+        f.write_u16::<LE>(self.time.wait)?;
+        f.write_u8(self.display.beats)?;
+        f.write_u8(self.display.steps)?;
+        f.write_i32::<LE>(self.time.loop_range.start)?;
+        f.write_i32::<LE>(self.time.loop_range.end)?;
+
+        for track in &self.tracks {
+            let notes = u16::try_from(track.notes.len())
+                .map_err(|_| GameError::InvalidValue("Organya tracks can't have more than 65535 notes.".to_owned()))?;
+
+            f.write_u16::<LE>(track.inst.freq)?;
+            f.write_u8(track.inst.inst)?;
+            f.write_u8(track.inst.pipi)?;
+            f.write_u16::<LE>(notes)?;
+        }
+
+        for track in &self.tracks {
+            for note in &track.notes {
+                f.write_i32::<LE>(note.pos)?;
+            }
+
+            for note in &track.notes {
+                f.write_u8(note.key)?;
+            }
+
+            for note in &track.notes {
+                f.write_u8(note.len)?;
+            }
+
+            for note in &track.notes {
+                f.write_u8(note.vol)?;
+            }
+
+            for note in &track.notes {
+                f.write_u8(note.pan)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_to_round_trip() {
+        let mut song = Song::empty();
+        song.version = Version::Extended;
+        song.display = Display { beats: 3, steps: 8 };
+        song.time = Timing { wait: 120, loop_range: LoopRange { start: 16, end: 256 } };
+        song.tracks[0].inst = Instrument { freq: 1100, inst: 42, pipi: 1, notes: 0 };
+        song.tracks[0].notes = vec![
+            Note { pos: 0, key: 36, len: 4, vol: 200, pan: 6 },
+            Note { pos: 8, key: 255, len: 255, vol: 100, pan: 255 },
+        ];
+        song.tracks[9].inst = Instrument { freq: 1000, inst: 40, pipi: 0, notes: 0 };
+        song.tracks[9].notes = vec![Note { pos: 2, key: 50, len: 1, vol: 255, pan: 0 }];
+
+        let mut data = Vec::new();
+        song.write_to(&mut data).unwrap();
+
+        let loaded = Song::load_from(&data[..]).unwrap();
+        assert_eq!(loaded.version, Version::Extended);
+        assert_eq!((loaded.display.beats, loaded.display.steps), (3, 8));
+        assert_eq!(loaded.time.wait, 120);
+        assert_eq!((loaded.time.loop_range.start, loaded.time.loop_range.end), (16, 256));
+        assert_eq!(loaded.tracks[0].inst.inst, 42);
+        assert_eq!(loaded.tracks[0].notes.len(), 2);
+        assert_eq!(loaded.tracks[0].notes[1].pos, 8);
+        assert_eq!(loaded.tracks[9].notes[0].key, 50);
+
+        let mut rewritten = Vec::new();
+        loaded.write_to(&mut rewritten).unwrap();
+        assert_eq!(data, rewritten);
+    }
 }
@@ -1,8 +1,13 @@
 use std::collections::HashMap;
+use std::fmt::Write;
+use std::io;
+use std::io::{BufRead, BufReader, Lines};
+use std::str::FromStr;
 
 use lazy_static::lazy_static;
 use vec_mut_scan::VecMutScan;
 
+use crate::framework::error::{GameError, GameResult};
 use crate::sound::pixtone_sfx::DEFAULT_PIXTONE_TABLE;
 use crate::sound::stuff::cubic_interp;
 
@@ -30,7 +35,7 @@ lazy_static! {
     };
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub struct Waveform {
     pub pitch: f32,
     pub level: i32,
@@ -44,7 +49,7 @@ impl Waveform {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub struct Envelope {
     pub initial: i32,
     pub time_a: i32,
@@ -94,7 +99,7 @@ impl Envelope {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub struct Channel {
     pub carrier: Waveform,
     pub frequency: Waveform,
@@ -117,7 +122,7 @@ impl Channel {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub struct PixToneParameters {
     pub channels: [Channel; 4],
 }
@@ -129,6 +134,94 @@ impl PixToneParameters {
         }
     }
 
+    /// Parses the text format used by PixTone's `.pxt` files, the counterpart of [`PixToneParameters::to_pxt`].
+    pub fn from_pxt<R: io::Read>(data: R) -> GameResult<PixToneParameters> {
+        let mut reader = BufReader::new(data).lines();
+        let mut params = PixToneParameters::empty();
+
+        fn next_string<T: FromStr, R: io::Read>(reader: &mut Lines<BufReader<R>>) -> GameResult<T> {
+            while let Some(Ok(str)) = reader.next() {
+                let str = str.trim();
+                if str.is_empty() || str.starts_with('#') {
+                    continue;
+                }
+
+                let mut splits = str.split(':');
+
+                let _ = splits.next();
+                if let Some(str) = splits.next() {
+                    return str.trim().parse::<T>().map_err(|_| {
+                        GameError::ParseError("failed to parse the value as specified type.".to_string())
+                    });
+                } else {
+                    break;
+                }
+            }
+
+            Err(GameError::ParseError("unexpected end.".to_string()))
+        }
+
+        for channel in &mut params.channels {
+            channel.enabled = next_string::<u8, R>(&mut reader)? != 0;
+            channel.length = next_string::<u32, R>(&mut reader)?;
+
+            channel.carrier.waveform_type = next_string::<u8, R>(&mut reader)?;
+            channel.carrier.pitch = next_string::<f32, R>(&mut reader)?;
+            channel.carrier.level = next_string::<i32, R>(&mut reader)?;
+            channel.carrier.offset = next_string::<i32, R>(&mut reader)?;
+
+            channel.frequency.waveform_type = next_string::<u8, R>(&mut reader)?;
+            channel.frequency.pitch = next_string::<f32, R>(&mut reader)?;
+            channel.frequency.level = next_string::<i32, R>(&mut reader)?;
+            channel.frequency.offset = next_string::<i32, R>(&mut reader)?;
+
+            channel.amplitude.waveform_type = next_string::<u8, R>(&mut reader)?;
+            channel.amplitude.pitch = next_string::<f32, R>(&mut reader)?;
+            channel.amplitude.level = next_string::<i32, R>(&mut reader)?;
+            channel.amplitude.offset = next_string::<i32, R>(&mut reader)?;
+
+            channel.envelope.initial = next_string::<i32, R>(&mut reader)?;
+            channel.envelope.time_a = next_string::<i32, R>(&mut reader)?;
+            channel.envelope.value_a = next_string::<i32, R>(&mut reader)?;
+            channel.envelope.time_b = next_string::<i32, R>(&mut reader)?;
+            channel.envelope.value_b = next_string::<i32, R>(&mut reader)?;
+            channel.envelope.time_c = next_string::<i32, R>(&mut reader)?;
+            channel.envelope.value_c = next_string::<i32, R>(&mut reader)?;
+        }
+
+        Ok(params)
+    }
+
+    /// Serializes the parameters into the text format used by PixTone's `.pxt` files.
+    pub fn to_pxt(&self) -> String {
+        let mut out = String::new();
+
+        for channel in self.channels.iter() {
+            let _ = writeln!(out, "use  :{}", channel.enabled as u8);
+            let _ = writeln!(out, "size :{}", channel.length);
+
+            let waveforms = [("main", &channel.carrier), ("pitch", &channel.frequency), ("volume", &channel.amplitude)];
+            for (name, waveform) in waveforms {
+                let _ = writeln!(out, "{:<13}:{}", [name, "_model"].join(""), waveform.waveform_type);
+                let _ = writeln!(out, "{:<13}:{}", [name, "_freq"].join(""), waveform.pitch);
+                let _ = writeln!(out, "{:<13}:{}", [name, "_top"].join(""), waveform.level);
+                let _ = writeln!(out, "{:<13}:{}", [name, "_offset"].join(""), waveform.offset);
+            }
+
+            let envelope = &channel.envelope;
+            let _ = writeln!(out, "initialY:{}", envelope.initial);
+            let _ = writeln!(out, "ax      :{}", envelope.time_a);
+            let _ = writeln!(out, "ay      :{}", envelope.value_a);
+            let _ = writeln!(out, "bx      :{}", envelope.time_b);
+            let _ = writeln!(out, "by      :{}", envelope.value_b);
+            let _ = writeln!(out, "cx      :{}", envelope.time_c);
+            let _ = writeln!(out, "cy      :{}", envelope.value_c);
+            out.push('\n');
+        }
+
+        out
+    }
+
     pub fn synth(&self) -> Vec<i16> {
         let length = self.channels.iter().map(|c| c.length as usize).max().unwrap_or(0);
         if length == 0 {
@@ -183,6 +276,8 @@ pub struct PlaybackState {
     pos: f32,
     tag: u32,
     freq: f32,
+    gain_l: f32,
+    gain_r: f32,
 }
 
 pub struct PixTonePlayback {
@@ -219,15 +314,25 @@ impl PixTonePlayback {
     }
 
     pub fn play_sfx(&mut self, id: u8) {
+        self.play_sfx_positional(id, 1.0, 0.0);
+    }
+
+    /// Plays a sound effect with given volume (0.0 to 1.0) and stereo pan (-1.0 is left, 1.0 is right).
+    pub fn play_sfx_positional(&mut self, id: u8, volume: f32, pan: f32) {
+        let gain_l = volume * (1.0 - pan).min(1.0);
+        let gain_r = volume * (1.0 + pan).min(1.0);
+
         for state in &mut self.playback_state {
             if state.id == id && state.tag == 0 {
                 state.pos = 0.0;
                 state.looping = false;
+                state.gain_l = gain_l;
+                state.gain_r = gain_r;
                 return;
             }
         }
 
-        self.playback_state.push(PlaybackState { id, pos: 0.0, tag: 0, looping: false, freq: 1.0 });
+        self.playback_state.push(PlaybackState { id, pos: 0.0, tag: 0, looping: false, freq: 1.0, gain_l, gain_r });
     }
 
     pub fn loop_sfx(&mut self, id: u8) {
@@ -238,7 +343,15 @@ impl PixTonePlayback {
             }
         }
 
-        self.playback_state.push(PlaybackState { id, pos: 0.0, tag: 0, looping: true, freq: 1.0 });
+        self.playback_state.push(PlaybackState {
+            id,
+            pos: 0.0,
+            tag: 0,
+            looping: true,
+            freq: 1.0,
+            gain_l: 1.0,
+            gain_r: 1.0,
+        });
     }
 
     pub fn loop_sfx_freq(&mut self, id: u8, freq: f32) {
@@ -249,7 +362,7 @@ impl PixTonePlayback {
             }
         }
 
-        self.playback_state.push(PlaybackState { id, pos: 0.0, tag: 0, looping: true, freq });
+        self.playback_state.push(PlaybackState { id, pos: 0.0, tag: 0, looping: true, freq, gain_l: 1.0, gain_r: 1.0 });
     }
 
     pub fn stop_sfx(&mut self, id: u8) {
@@ -259,9 +372,18 @@ impl PixTonePlayback {
     }
 
     pub fn play_concurrent(&mut self, id: u8, tag: u32) {
-        self.playback_state.push(PlaybackState { id, pos: 0.0, tag, looping: false, freq: 1.0 });
+        self.playback_state.push(PlaybackState {
+            id,
+            pos: 0.0,
+            tag,
+            looping: false,
+            freq: 1.0,
+            gain_l: 1.0,
+            gain_r: 1.0,
+        });
     }
 
+    /// Mixes playing sound effects into an interleaved stereo buffer.
     pub fn mix(&mut self, dst: &mut [u16], sample_rate: f32) {
         let mut scan = VecMutScan::new(&mut self.playback_state);
         let delta = 22050.0 / sample_rate;
@@ -276,7 +398,7 @@ impl PixTonePlayback {
                     continue;
                 };
 
-                for result in dst.iter_mut() {
+                for frame in dst.chunks_exact_mut(2) {
                     if state.pos >= sample.len() as f32 {
                         if state.looping {
                             state.pos = 0.0;
@@ -294,8 +416,10 @@ impl PixTonePlayback {
 
                     let s = cubic_interp(s1, s2, s4, s3, state.pos.fract()) * 32768.0;
                     // let s = sample[pos] as f32;
-                    let sam = (*result ^ 0x8000) as i16;
-                    *result = sam.saturating_add(s as i16) as u16 ^ 0x8000;
+                    let sam_l = (frame[0] ^ 0x8000) as i16;
+                    let sam_r = (frame[1] ^ 0x8000) as i16;
+                    frame[0] = sam_l.saturating_add((s * state.gain_l) as i16) as u16 ^ 0x8000;
+                    frame[1] = sam_r.saturating_add((s * state.gain_r) as i16) as u16 ^ 0x8000;
 
                     state.pos += delta * state.freq;
                 }
@@ -309,3 +433,24 @@ impl PixTonePlayback {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pxt_round_trip() {
+        let mut custom = DEFAULT_PIXTONE_TABLE[0];
+        custom.channels[1].enabled = true;
+        custom.channels[1].length = 4000;
+        custom.channels[1].carrier.pitch = 12.75;
+        custom.channels[1].frequency.pitch = 0.1;
+        custom.channels[1].amplitude.offset = -32;
+        custom.channels[1].envelope.value_c = 17;
+
+        for params in DEFAULT_PIXTONE_TABLE.iter().chain(std::iter::once(&custom)) {
+            let parsed = PixToneParameters::from_pxt(params.to_pxt().as_bytes()).unwrap();
+            assert!(parsed == *params);
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use std::fmt;
 use std::io;
-use std::io::ErrorKind;
+use std::io::{ErrorKind, SeekFrom};
 
-use byteorder::{LE, ReadBytesExt};
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct RiffChunk {
@@ -119,3 +119,119 @@ impl WavSample {
         Ok(WavSample { format: WavFormat { channels, sample_rate: samples, bit_depth: bits }, data: buf })
     }
 }
+
+/// Largest data chunk that still fits the 32-bit RIFF chunk length along with the rest of the header.
+const MAX_DATA_LENGTH: u32 = u32::MAX - 36;
+
+/// Writes 16-bit PCM audio to a WAV file, the chunk lengths are filled in once the writer is finished or dropped.
+pub struct WavWriter<W: io::Write + io::Seek> {
+    writer: W,
+    format: WavFormat,
+    data_length: u32,
+}
+
+impl<W: io::Write + io::Seek> WavWriter<W> {
+    pub fn new(mut writer: W, format: WavFormat) -> io::Result<WavWriter<W>> {
+        let block_align = format.channels * (format.bit_depth / 8);
+
+        writer.write_all(b"RIFF")?;
+        writer.write_u32::<LE>(0)?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_u32::<LE>(16)?;
+        writer.write_u16::<LE>(1)?;
+        writer.write_u16::<LE>(format.channels)?;
+        writer.write_u32::<LE>(format.sample_rate)?;
+        writer.write_u32::<LE>(format.sample_rate * block_align as u32)?;
+        writer.write_u16::<LE>(block_align)?;
+        writer.write_u16::<LE>(format.bit_depth)?;
+
+        writer.write_all(b"data")?;
+        writer.write_u32::<LE>(0)?;
+
+        Ok(WavWriter { writer, format, data_length: 0 })
+    }
+
+    pub fn format(&self) -> WavFormat {
+        self.format
+    }
+
+    /// Appends unsigned 16-bit samples (as produced by the mixer), interleaved if there's more than one channel.
+    /// Fails without writing anything once the file would exceed the 4 GiB limit of the format.
+    pub fn write_samples(&mut self, samples: &[u16]) -> io::Result<()> {
+        let length = u32::try_from(samples.len() * 2)
+            .ok()
+            .and_then(|length| self.data_length.checked_add(length))
+            .filter(|&length| length <= MAX_DATA_LENGTH)
+            .ok_or_else(|| io::Error::new(ErrorKind::Other, "WAV file size limit reached.".to_owned()))?;
+
+        let mut buf = Vec::with_capacity(samples.len() * 2);
+        for &sample in samples {
+            buf.write_i16::<LE>((sample ^ 0x8000) as i16)?;
+        }
+
+        self.writer.write_all(&buf)?;
+        self.data_length = length;
+
+        Ok(())
+    }
+
+    /// Updates the chunk lengths in the header, the file stays valid for further writes.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_u32::<LE>(36 + self.data_length)?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_u32::<LE>(self.data_length)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+impl<W: io::Write + io::Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_wav_writer_header() {
+        let format = WavFormat { channels: 2, sample_rate: 44100, bit_depth: 16 };
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), format).unwrap();
+        writer.write_samples(&[0x8000, 0xffff, 0x0000, 0x8001]).unwrap();
+        writer.finish().unwrap();
+
+        let data = writer.writer.get_ref().clone();
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()), 36 + 8);
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 8);
+
+        let sample = WavSample::read_from(Cursor::new(&data)).unwrap();
+        assert_eq!(sample.format.channels, 2);
+        assert_eq!(sample.format.sample_rate, 44100);
+        assert_eq!(sample.format.bit_depth, 16);
+        assert_eq!(sample.data, [0x00, 0x00, 0xff, 0x7f, 0x00, 0x80, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn test_wav_writer_size_limit() {
+        let format = WavFormat { channels: 1, sample_rate: 8000, bit_depth: 16 };
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), format).unwrap();
+        writer.data_length = MAX_DATA_LENGTH - 2;
+
+        writer.write_samples(&[0x8000]).unwrap();
+        assert!(writer.write_samples(&[0x8000]).is_err());
+        assert_eq!(writer.data_length, MAX_DATA_LENGTH);
+        writer.finish().unwrap();
+
+        let data = writer.writer.get_ref();
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()), u32::MAX);
+    }
+}
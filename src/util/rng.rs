@@ -4,8 +4,11 @@ use std::ops::Range;
 pub trait RNG {
     fn next(&self) -> i32;
 
+    /// Returns a number between `range.start` and `range.end`, both inclusive.
     fn range(&self, range: Range<i32>) -> i32 {
-        range.start + ((self.next() & 0x7fffffff) % (range.len() as i32 + 1))
+        // computed in i64, the span of a full i32 range doesn't fit in an i32
+        let span = (range.end as i64 - range.start as i64).max(0) + 1;
+        (range.start as i64 + (self.next() & 0x7fffffff) as i64 % span) as i32
     }
 }
 